@@ -59,9 +59,7 @@ fn iter_vec(data: Vec<BlockData>) {
             || {
                 let mut d = vec![];
                 for _ in 0..CHUNK_SIZE_I32 * CHUNK_SIZE_I32 * CHUNK_SIZE_I32 {
-                    d.push(BlockData {
-                        block_type: BlockId(0),
-                    });
+                    d.push(BlockData::new(BlockId(0)));
                 }
                 d
             },
@@ -1,14 +1,18 @@
 use std::sync::Arc;
 
-use bevy::utils::default;
-use criterion::{criterion_group, criterion_main, Criterion};
+use bevy::{math::IVec3, utils::default};
+use bracket_noise::prelude::FastNoise;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use new_voxel_testing::{
     chunk::ChunkData,
     chunks_refs::ChunksRefs,
-    greedy_mesher_optimized,
+    greedy_mesher_optimized::{self, OcclusionTable},
     lod::Lod,
+    utils::index_to_ivec3_bounds,
     voxel::{BlockData, BlockFlags, BlockId, BlockRegistry},
 };
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
 /*fn binary_mesh_optimized(chunks_refs: ChunksRefs) {
     let block_registry = Arc::new(BlockRegistry {
@@ -16,7 +20,7 @@ use new_voxel_testing::{
         ..default()
     });
 
-    let m = greedy_mesher_optimized::build_chunk_mesh(&chunks_refs, Lod::L32, block_registry, BlockFlags::SOLID, true, false);
+    let m = greedy_mesher_optimized::build_chunk_mesh(&chunks_refs, Lod::L32, block_registry, BlockFlags::SOLID, true, false, false, None);
 }*/
 
 // helper for incrementing and constructing chunksrefs
@@ -28,25 +32,110 @@ use new_voxel_testing::{
 fn make_empty() -> ChunksRefs {
     let mut chunks = vec![];
     for _i in 0..3 * 3 * 3 {
-        chunks.push(Arc::new(ChunkData {
-            voxels: vec![BlockData {
-                block_type: BlockId(0),
-            }],
-        }));
+        chunks.push(Arc::new(ChunkData::Filled(BlockData::new(BlockId(0)))));
     }
-    ChunksRefs { chunks }
+    ChunksRefs::new(chunks)
 }
 
 fn make_filled() -> ChunksRefs {
     let mut chunks = vec![];
     for _i in 0..3 * 3 * 3 {
-        chunks.push(Arc::new(ChunkData {
-            voxels: vec![BlockData {
-                block_type: BlockId(2),
-            }],
-        }));
+        chunks.push(Arc::new(ChunkData::Filled(BlockData::new(BlockId(2)))));
     }
-    ChunksRefs { chunks }
+    ChunksRefs::new(chunks)
+}
+
+// an empty chunk above filled ones, like the sky right above the ground.
+fn make_air_above_ground() -> ChunksRefs {
+    let mut chunks = vec![];
+    for i in 0..3 * 3 * 3 {
+        let below = (i / 3) % 3 == 0;
+        chunks.push(Arc::new(ChunkData::Filled(BlockData::new(BlockId(if below { 2 } else { 0 })))));
+    }
+    ChunksRefs::new(chunks)
+}
+
+// bottom half solid, like terrain at the surface.
+fn make_half_filled() -> ChunksRefs {
+    make_uncompressed(|i| (i >> 5) & 31 < 16)
+}
+
+// every other block solid, the worst case where no row is a single block.
+fn make_checkerboard() -> ChunksRefs {
+    make_uncompressed(|i| (i ^ (i >> 5)) & 1 == 0)
+}
+
+// columns of varying height, roughly as many faces as rolling terrain.
+fn make_terrain() -> ChunksRefs {
+    make_uncompressed(|i| {
+        let (x, y, z) = (i & 31, (i >> 5) & 31, i >> 10);
+        y < 4 + (x * 7 + z * 13) % 23
+    })
+}
+
+// the 27 chunks around the origin from a function of the world voxel position, so the terrain continues across chunk borders.
+fn make_world(mut block: impl FnMut(IVec3) -> BlockId) -> ChunksRefs {
+    let chunks = (0..3 * 3 * 3)
+        .map(|i| {
+            let origin = (index_to_ivec3_bounds(i, 3) - IVec3::ONE) * 32;
+            Arc::new(ChunkData::Dense(
+                (0..32 * 32 * 32)
+                    .map(|i| BlockData::new(block(origin + index_to_ivec3_bounds(i, 32))))
+                    .collect(),
+            ))
+        })
+        .collect();
+    ChunksRefs::new(chunks)
+}
+
+fn terrain_height(noise: &FastNoise, pos: IVec3) -> i32 {
+    (noise.get_noise(pos.x as f32, pos.z as f32) * 24.0) as i32
+}
+
+// smooth hills with grass on top of stone, the surface crosses the middle chunk.
+fn make_heightmap_terrain() -> ChunksRefs {
+    let mut noise = FastNoise::seeded(7);
+    noise.set_frequency(0.02);
+    make_world(|pos| {
+        let height = terrain_height(&noise, pos);
+        BlockId(if pos.y < height - 3 { 2 } else if pos.y < height { 1 } else { 0 })
+    })
+}
+
+// underground riddled with small caves, lots of small faces & the worst case for the quad count.
+fn make_caves() -> ChunksRefs {
+    let mut noise = FastNoise::seeded(11);
+    noise.set_frequency(0.15);
+    make_world(|pos| BlockId(if noise.get_noise3d(pos.x as f32, pos.y as f32, pos.z as f32) > 0.0 { 2 } else { 0 }))
+}
+
+// every block randomly air, grass or stone, nothing to merge.
+fn make_random_noise() -> ChunksRefs {
+    let mut rng = ChaCha8Rng::seed_from_u64(3);
+    make_world(|_| BlockId(rng.random_range(0..3)))
+}
+
+fn make_uncompressed(solid: impl Fn(usize) -> bool) -> ChunksRefs {
+    let mut chunks = vec![];
+    for _i in 0..3 * 3 * 3 {
+        chunks.push(Arc::new(ChunkData::Dense(
+            (0..32 * 32 * 32)
+                .map(|i| BlockData::new(BlockId(if solid(i) { 2 } else { 0 })))
+                .collect(),
+        )));
+    }
+    ChunksRefs::new(chunks)
+}
+
+fn block_registry() -> Arc<BlockRegistry> {
+    Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::SOLID],
+        ..default()
+    })
+}
+
+fn row_masks(chunk: &ChunkData, occludes: &OcclusionTable, row_mask: fn(&[BlockData], &OcclusionTable) -> u32) -> u32 {
+    chunk.rows().fold(0, |acc, row| acc ^ row_mask(&row, occludes))
 }
 
 fn slicer(data: [u32; 32]) {
@@ -90,6 +179,48 @@ fn criterion_benchmark(c: &mut Criterion) {
     // });
 
     // let group = c.benchmark_group("yes");
+
+    let registry = block_registry();
+    let occludes = registry.occlusion_table(BlockFlags::SOLID);
+    for (name, chunks_refs) in [("half filled", make_half_filled()), ("checkerboard", make_checkerboard())] {
+        let chunk = &chunks_refs.chunks[13];
+        let mut group = c.benchmark_group(format!("axis columns, {name}"));
+        group.bench_function("scalar", |b| {
+            b.iter(|| row_masks(black_box(chunk), &occludes, greedy_mesher_optimized::solid_row_mask_scalar))
+        });
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        group.bench_function("simd", |b| {
+            b.iter(|| row_masks(black_box(chunk), &occludes, greedy_mesher_optimized::solid_row_mask_simd))
+        });
+        group.finish();
+    }
+
+    // uniform chunks never reach the per voxel loops.
+    let mut group = c.benchmark_group("GREEDY meshing OPTIMIZED: 1 chunk [ao] uniform");
+    for (name, chunks_refs) in [("empty", make_empty()), ("filled", make_filled()), ("air above ground", make_air_above_ground())] {
+        group.bench_function(name, |b| {
+            b.iter(|| greedy_mesher_optimized::build_chunk_mesh(black_box(&chunks_refs), Lod::L32, registry.clone(), BlockFlags::SOLID, true, false, false, None))
+        });
+    }
+    group.finish();
+
+    // compare with & without the `parallel_meshing` feature to see where meshing the face directions in parallel pays off.
+    let mut group = c.benchmark_group("GREEDY meshing OPTIMIZED: 1 chunk [ao]");
+    for (name, chunks_refs) in [("half filled", make_half_filled()), ("terrain", make_terrain()), ("checkerboard", make_checkerboard())] {
+        group.bench_function(name, |b| {
+            b.iter(|| greedy_mesher_optimized::build_chunk_mesh(black_box(&chunks_refs), Lod::L32, registry.clone(), BlockFlags::SOLID, true, false, false, None))
+        });
+    }
+    group.finish();
+
+    // generated the way a world would be rather than patterns, for before/after numbers of meshing optimizations.
+    let mut group = c.benchmark_group("GREEDY meshing OPTIMIZED: 1 chunk [ao] generated");
+    for (name, chunks_refs) in [("heightmap terrain", make_heightmap_terrain()), ("caves", make_caves()), ("random noise", make_random_noise())] {
+        group.bench_function(name, |b| {
+            b.iter(|| greedy_mesher_optimized::build_chunk_mesh(black_box(&chunks_refs), Lod::L32, registry.clone(), BlockFlags::SOLID, true, false, false, None))
+        });
+    }
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
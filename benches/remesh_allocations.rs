@@ -0,0 +1,99 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use bevy::math::ivec3;
+use new_voxel_testing::{
+    chunk::ChunkData,
+    chunk_mesh::DirtySlices,
+    chunks_refs::ChunksRefs,
+    greedy_mesher_optimized,
+    lod::Lod,
+    voxel::{BlockData, BlockFlags, BlockId, BlockRegistry},
+};
+
+// counts every allocation, including the ones that grow a `Vec`.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// rolling terrain, shifted per chunk so the storm doesn't mesh the same chunk over & over.
+fn make_terrain(seed: usize) -> ChunksRefs {
+    let chunks = (0..3 * 3 * 3)
+        .map(|_| {
+            Arc::new(ChunkData::Dense(
+                (0..32 * 32 * 32)
+                    .map(|i| {
+                        let (x, y, z) = (i & 31, (i >> 5) & 31, i >> 10);
+                        let solid = y < 4 + (x * 7 + z * 13 + seed * 5) % 23;
+                        BlockData::new(BlockId(if solid { 1 + (x + z) as u16 % 2 } else { 0 }))
+                    })
+                    .collect(),
+            ))
+        })
+        .collect();
+    ChunksRefs::new(chunks)
+}
+
+fn measure(name: &str, chunks: usize, mut storm: impl FnMut()) {
+    // the first remesh fills the pools, like the first frames of a game would.
+    storm();
+    let (allocations, bytes) = (ALLOCATIONS.load(Ordering::Relaxed), ALLOCATED_BYTES.load(Ordering::Relaxed));
+    storm();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes;
+    println!("{name}: {} allocations, {} KiB per chunk", allocations / chunks, bytes / chunks / 1024);
+}
+
+/// Counts the allocations of remeshing many chunks at once, as happens when moving into unmeshed terrain.
+fn main() {
+    let registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::SOLID],
+        ..Default::default()
+    });
+    let chunks: Vec<ChunksRefs> = (0..64).map(make_terrain).collect();
+
+    measure("full remesh", chunks.len(), || {
+        for chunks_refs in &chunks {
+            let mesh = greedy_mesher_optimized::build_chunk_mesh(chunks_refs, Lod::L32, registry.clone(), BlockFlags::SOLID, true, false, false, None);
+            std::hint::black_box(mesh);
+        }
+    });
+
+    let mut slices: Vec<_> = chunks.iter()
+        .map(|chunks_refs| greedy_mesher_optimized::build_chunk_mesh_slices(chunks_refs, Lod::L32, registry.clone(), BlockFlags::SOLID, true, false, false, &DirtySlices::ALL, None))
+        .collect();
+    let mut dirty = DirtySlices::default();
+    dirty.mark_voxel(ivec3(16, 8, 16));
+    measure("incremental remesh", chunks.len(), || {
+        for (chunks_refs, slices) in chunks.iter().zip(&mut slices) {
+            greedy_mesher_optimized::rebuild_chunk_mesh_slices(slices, chunks_refs, Lod::L32, registry.clone(), BlockFlags::SOLID, true, false, false, &dirty);
+        }
+    });
+}
@@ -13,10 +13,12 @@ use bevy_screen_diagnostics::{
 
 use bracket_noise::prelude::FastNoise;
 use new_voxel_testing::{
-    chunk::{self, ChunkData, ChunkGenerator, NoiseDownSampler2D, NoiseDownSampler3D}, constants::CHUNK_SIZE3, diagnostics::VoxelDiagnosticsPlugin, rendering::{
+    block_registry_asset::BlockRegistryAssetPlugin, chunk::{self, ChunkData, ChunkGenerator, NoiseDownSampler2D, NoiseDownSampler3D}, constants::CHUNK_SIZE3, diagnostics::VoxelDiagnosticsPlugin, rendering::{
+        ChunkBoundsGizmos,
         ChunkMaterial,
+        RemeshGizmos,
         RenderingPlugin,
-    }, scanner::{DataScanner, MeshScanner, Scanner}, utils::{index_to_ivec3, world_to_chunk}, voxel::*, voxel_engine::{ChunkModification, VoxelEngine, VoxelEnginePlugin}
+    }, meshing::{ChunkRenderLayer, ChunkRenderLayers}, scanner::{DataScanner, MeshScanner, Scanner, VisualScanner}, utils::{index_to_ivec3, world_to_chunk}, voxel::*, voxel_engine::{ChunkModification, VoxelEngine, VoxelEnginePlugin}
 };
 
 use bevy_flycam::prelude::*;
@@ -46,10 +48,18 @@ fn main() {
         .add_plugins(WorldInspectorPlugin::new())
         .add_plugins(AssetInspectorPlugin::<ChunkMaterial>::default())
         .add_plugins(VoxelEnginePlugin)
+        .add_plugins(BlockRegistryAssetPlugin { path: "example.blocks.ron".to_string() })
         .add_systems(Startup, setup)
         // camera plugin
         .add_plugins(NoCameraPlayerPlugin)
         .add_plugins(RenderingPlugin)
+        // Leaves & grass are cut out instead of blended, so they sort & cast shadows like solid blocks.
+        // Grass tufts are flat, so their layer is drawn from both sides.
+        .insert_resource(ChunkRenderLayers(vec![
+            ChunkRenderLayer::new("Opaque", BlockFlags::SOLID),
+            ChunkRenderLayer::new("Transparent", BlockFlags::TRANSPARENT).with_alpha_mode(AlphaMode::Premultiplied),
+            ChunkRenderLayer::new("Foliage", BlockFlags::CUTOUT).with_alpha_mode(AlphaMode::Mask(0.5)).with_double_sided(),
+        ]))
         .add_plugins((
             ScreenDiagnosticsPlugin::default(),
             VoxelDiagnosticsPlugin,
@@ -61,38 +71,34 @@ fn main() {
             speed: 64.0 * 2.0,    // default: 12.0
                                   // speed: 32.0 * 12.0,   // default: 12.0
         })
-        .add_systems(Update, modify_current_terrain)
-        .add_systems(PreStartup, load_block_registry)
+        .add_systems(Update, (modify_current_terrain, toggle_chunk_bounds, toggle_remesh_gizmos))
         .run();
 }
 
-fn load_block_registry(
-    mut commands: Commands,
-) {
-    // TODO: Actually load a block registry from assets. For now, just add some dummy blocks.
-    let mut block_registry = BlockRegistry::default();
-    let _ = block_registry.add_block(
-        BlockStringIdentifier(Box::from("air")),
-        &Block { visibility: BlockVisibilty::Invisible, collision: false, ..default() },
-    );
-    let _ = block_registry.add_block(BlockStringIdentifier(Box::from("dirt")), &Block { visibility: BlockVisibilty::Solid, color: Color::srgb(0.0, 1.0, 0.0), ..default() });
-    let _ = block_registry.add_block(BlockStringIdentifier(Box::from("grass")), &Block { visibility: BlockVisibilty::Solid, color: Color::srgb(0.3, 0.4, 0.0), ..default() });
-
-    let _ = block_registry.add_block(BlockStringIdentifier(Box::from("glass")), &Block { visibility: BlockVisibilty::Transparent, color: Color::srgba(0.3, 0.3, 0.3, 0.5), ..default() });
-
-    let _ = block_registry.add_block(BlockStringIdentifier(Box::from("stone")), &Block { visibility: BlockVisibilty::Solid, color: Color::srgba(1.0, 1.0, 1.0, 1.0), ..default() });
+pub fn toggle_chunk_bounds(key: Res<ButtonInput<KeyCode>>, mut bounds: ResMut<ChunkBoundsGizmos>) {
+    if key.just_pressed(KeyCode::KeyB) {
+        bounds.enabled = !bounds.enabled;
+    }
+}
 
-    commands.insert_resource(BlockRegistryResource(Arc::new(block_registry)));
+pub fn toggle_remesh_gizmos(key: Res<ButtonInput<KeyCode>>, mut remesh: ResMut<RemeshGizmos>) {
+    if key.just_pressed(KeyCode::KeyM) {
+        remesh.enabled = !remesh.enabled;
+    }
 }
 
 pub fn modify_current_terrain(
     query: Query<&Transform, With<Camera>>,
     key: Res<ButtonInput<KeyCode>>,
     mut voxel_engine: ResMut<VoxelEngine>,
+    block_registry: Option<Res<BlockRegistryResource>>,
 ) {
     if !key.pressed(KeyCode::KeyN) {
         return;
     }
+    let Some(block_registry) = block_registry else {
+        return;
+    };
     let cam_transform = query.single();
     let cam_chunk = world_to_chunk(cam_transform.translation + (cam_transform.forward() * 64.0));
 
@@ -104,9 +110,9 @@ pub fn modify_current_terrain(
             rng.random_range(0..32),
             rng.random_range(0..32),
         );
-        mods.push(ChunkModification(pos, BlockId(0)));
+        mods.push(ChunkModification(pos, block_registry.0.air().into()));
     }
-    voxel_engine.chunk_modifications.insert(cam_chunk, mods);
+    voxel_engine.chunk_modifications.entry(cam_chunk).or_default().extend(mods);
 }
 
 pub fn setup(
@@ -137,8 +143,10 @@ pub fn setup(
 
     commands
         .spawn((
-            Scanner::<DataScanner>::new(16, Some(7)),
-            Scanner::<MeshScanner>::new(15, Some(6)), 
+            Scanner::<DataScanner>::new(16, Some(7)).with_extra_depth(4),
+            Scanner::<MeshScanner>::new(15, Some(6)).with_extra_depth(4), 
+            // distant chunks keep their meshes after their data unloads.
+            Scanner::<VisualScanner>::new(24, Some(8)).with_extra_depth(4),
             Camera3d::default(),
             Transform::from_xyz(0.0, 2.0, 0.5),
             Msaa::Off,
@@ -154,7 +162,7 @@ pub fn setup(
     ));
 
     commands.insert_resource(ChunkGenerator {
-        generate: Arc::new(generate)
+        generate: Arc::new(|chunk_pos| generate(chunk_pos).into())
     });
 }
 
@@ -166,19 +174,11 @@ pub fn generate(chunk_pos: IVec3) -> ChunkData {
     let chunk_height_limit = 3;
 
     if chunk_pos.y > chunk_height_limit {
-        return ChunkData {
-            voxels: vec![BlockData {
-                block_type: BlockId(0),
-            }],
-        };
+        return ChunkData::Filled(BlockData::new(BlockId(0)));
     }
     // hardcoded extremity check
     if chunk_pos.y < -chunk_height_limit {
-        return ChunkData {
-            voxels: vec![BlockData {
-                block_type: BlockId(2),
-            }],
-        };
+        return ChunkData::Filled(BlockData::new(BlockId(2)));
     }
 
     let _span = info_span!("Generating chunk data").entered();
@@ -221,12 +221,16 @@ pub fn generate(chunk_pos: IVec3) -> ChunkData {
                 y if y > 1.0 => BlockId(1), // Dirt
                 _ => BlockId(2), // Grass
             },
+            // scattered bushes on the surface
+            false if voxel_pos.y as f32 - surface_height < 1.0 && (voxel_pos.x * 7 + voxel_pos.z * 13).rem_euclid(61) == 0 => BlockId(5),
+            // & tufts of grass
+            false if voxel_pos.y as f32 - surface_height < 1.0 && (voxel_pos.x * 5 + voxel_pos.z * 3).rem_euclid(17) == 0 => BlockId(6),
             false => {
                 BlockId(0)
             },
         };
-        voxels.push(BlockData { block_type });
+        voxels.push(BlockData::new(block_type));
     }
 
-    ChunkData { voxels }
+    ChunkData::Dense(voxels)
 }
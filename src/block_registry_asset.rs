@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext, LoadState},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::voxel::{Block, BlockRegistry, BlockRegistryBuilder, BlockRegistryResource, BlockShape, BlockStringIdentifier, BlockVisibilty, RegistryError};
+
+/// Loads a [`BlockRegistryAsset`] from `path` & inserts it as the [`BlockRegistryResource`] once it has loaded.
+///
+/// Data & mesh tasks aren't started until the registry exists.
+/// Changes to the asset after that are ignored, since block ids are baked into the chunk data.
+pub struct BlockRegistryAssetPlugin {
+    pub path: String,
+}
+
+impl Plugin for BlockRegistryAssetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<BlockRegistryAsset>()
+            .register_asset_loader(BlockRegistryAssetLoader);
+
+        let path = self.path.clone();
+        app.add_systems(Startup, move |mut commands: Commands, asset_server: Res<AssetServer>| {
+            commands.insert_resource(BlockRegistryHandle(asset_server.load(path.clone())));
+        });
+        app.add_systems(
+            PreUpdate,
+            insert_block_registry
+                .run_if(resource_exists::<BlockRegistryHandle>)
+                .run_if(not(resource_exists::<BlockRegistryResource>)),
+        );
+    }
+}
+
+/// The blocks of a registry in id order, loaded from a `.blocks.ron` file.
+///
+/// ```ron
+/// (
+///     blocks: [
+///         (identifier: "air", visibility: Invisible, collision: false),
+///         (identifier: "stone", color: (0.5, 0.5, 0.5, 1.0)),
+///     ],
+/// )
+/// ```
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct BlockRegistryAsset {
+    pub blocks: Vec<BlockDefinition>,
+}
+impl BlockRegistryAsset {
+    /// Builds the registry, each block's id is its index in [`BlockRegistryAsset::blocks`].
+    pub fn build_registry(&self) -> Result<Arc<BlockRegistry>, RegistryError> {
+        let mut builder = BlockRegistryBuilder::new();
+        for definition in &self.blocks {
+            builder.add_block(BlockStringIdentifier(Box::from(definition.identifier.as_str())), &Block::from(definition))?;
+        }
+        Ok(builder.build())
+    }
+}
+
+/// A [`Block`] as written in a [`BlockRegistryAsset`], missing fields are the same as [`Block::default`].
+/// Colors are sRGBA.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BlockDefinition {
+    pub identifier: String,
+    pub visibility: BlockVisibilty,
+    pub collision: bool,
+    pub color: [f32; 4],
+    pub emissive: [f32; 4],
+    pub animated_emissive: bool,
+    pub shape: BlockShape,
+    pub no_greedy_merge: bool,
+    pub opacity: u8,
+}
+impl Default for BlockDefinition {
+    fn default() -> Self {
+        let block = Block::default();
+        Self {
+            identifier: String::new(),
+            visibility: block.visibility,
+            collision: block.collision,
+            color: block.color.to_srgba().to_f32_array(),
+            emissive: block.emissive_color.to_srgba().to_f32_array(),
+            animated_emissive: block.animated_emissive,
+            shape: block.shape,
+            no_greedy_merge: block.no_greedy_merge,
+            opacity: block.opacity,
+        }
+    }
+}
+impl From<&BlockDefinition> for Block {
+    fn from(definition: &BlockDefinition) -> Self {
+        Self {
+            visibility: definition.visibility,
+            collision: definition.collision,
+            color: Color::Srgba(Srgba::from_f32_array(definition.color)),
+            emissive_color: Color::Srgba(Srgba::from_f32_array(definition.emissive)),
+            animated_emissive: definition.animated_emissive,
+            shape: definition.shape,
+            no_greedy_merge: definition.no_greedy_merge,
+            opacity: definition.opacity,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockRegistryAssetError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+impl std::fmt::Display for BlockRegistryAssetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockRegistryAssetError::Io(err) => write!(f, "failed to read block registry: {err}"),
+            BlockRegistryAssetError::Ron(err) => write!(f, "failed to parse block registry: {err}"),
+        }
+    }
+}
+impl std::error::Error for BlockRegistryAssetError {}
+
+#[derive(Default)]
+pub struct BlockRegistryAssetLoader;
+
+impl AssetLoader for BlockRegistryAssetLoader {
+    type Asset = BlockRegistryAsset;
+    type Settings = ();
+    type Error = BlockRegistryAssetError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await.map_err(BlockRegistryAssetError::Io)?;
+        ron::de::from_bytes(&bytes).map_err(BlockRegistryAssetError::Ron)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["blocks.ron"]
+    }
+}
+
+/// The registry asset being loaded, removed once the registry has been inserted or failed to load.
+#[derive(Resource)]
+struct BlockRegistryHandle(Handle<BlockRegistryAsset>);
+
+fn insert_block_registry(
+    mut commands: Commands,
+    handle: Res<BlockRegistryHandle>,
+    assets: Res<Assets<BlockRegistryAsset>>,
+    asset_server: Res<AssetServer>,
+) {
+    if let Some(asset) = assets.get(&handle.0) {
+        match asset.build_registry() {
+            Ok(registry) => commands.insert_resource(BlockRegistryResource(registry)),
+            Err(err) => error!("Invalid block registry {:?}: {err}", handle.0.path()),
+        }
+        commands.remove_resource::<BlockRegistryHandle>();
+    } else if let LoadState::Failed(err) = asset_server.load_state(&handle.0) {
+        error!("Failed to load block registry: {err}");
+        commands.remove_resource::<BlockRegistryHandle>();
+    }
+}
+
+#[test]
+fn parse_example_block_registry() {
+    use crate::voxel::BlockId;
+
+    let asset: BlockRegistryAsset = ron::from_str(include_str!("../assets/example.blocks.ron")).unwrap();
+    let registry = asset.build_registry().unwrap();
+    assert_eq!(registry.get_id("air"), Some(BlockId(0)));
+    assert_eq!(registry.get_id("stone"), Some(BlockId(4)));
+    assert!(!registry.is_solid(BlockId(0)));
+    assert!(registry.is_solid(BlockId(4)));
+
+    let duplicate: BlockRegistryAsset = ron::from_str(r#"(blocks: [(identifier: "air"), (identifier: "air")])"#).unwrap();
+    assert!(matches!(duplicate.build_registry(), Err(RegistryError::DuplicateIdentifier(_))));
+}
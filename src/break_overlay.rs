@@ -0,0 +1,178 @@
+use bevy::{
+    asset::load_internal_asset, pbr::{MaterialPipeline, MaterialPipelineKey}, prelude::*, render::{
+        mesh::MeshVertexBufferLayoutRef,
+        render_resource::{AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError},
+    }, utils::HashSet
+};
+
+use crate::{
+    chunk_mesh::{ChunkMesh, ATTRIBUTE_VOXEL}, events::{ChunkGenerated, ChunkModified}, face_direction::FaceDir, greedy_mesher_optimized::GreedyQuad, lod::Lod, utils::{chunks_in_aabb, generate_indices}, voxel::{BlockFlags, BlockRegistry, BlockRegistryResource}, voxel_engine::VoxelEngine
+};
+
+pub const BREAK_OVERLAY_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(91562740017837263093846150277128834569);
+
+/// Draws cracks over the visible faces of the block at `block` in world voxel coordinates, growing with `progress` from 0 to 1.
+///
+/// The overlay is a small mesh of its own with a [`BreakOverlayMaterial`], the chunk meshes aren't touched.
+/// It follows changes to the block & its neighbors, an air block has no faces & nothing is drawn.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+#[require(Transform, Visibility)]
+pub struct BreakProgress {
+    pub block: IVec3,
+    pub progress: f32,
+}
+
+pub struct BreakOverlayPlugin;
+
+impl Plugin for BreakOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<BreakOverlayMaterial>::default());
+
+        load_internal_asset!(
+            app,
+            BREAK_OVERLAY_SHADER_HANDLE,
+            "break_overlay.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.add_systems(PostUpdate, (update_break_overlay_meshes.run_if(resource_exists::<BlockRegistryResource>), update_break_overlay_progress));
+    }
+}
+
+/// Cracks drawn over a block, see [`BreakProgress`].
+/// Faces are unpacked from [`ATTRIBUTE_VOXEL`] like the chunk meshes, their position on the face is used as the UV of the cracks.
+#[derive(Asset, Reflect, AsBindGroup, Debug, Clone)]
+pub struct BreakOverlayMaterial {
+    #[uniform(0)]
+    pub color: LinearRgba,
+    #[uniform(0)]
+    pub progress: f32,
+}
+
+impl Material for BreakOverlayMaterial {
+    fn vertex_shader() -> ShaderRef {
+        BREAK_OVERLAY_SHADER_HANDLE.into()
+    }
+    fn fragment_shader() -> ShaderRef {
+        BREAK_OVERLAY_SHADER_HANDLE.into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.0.get_layout(&[ATTRIBUTE_VOXEL.at_shader_location(0)])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
+}
+
+/// Mesh of the faces of a single block at the origin for which `visible` returns true, packed like the chunk meshes.
+pub fn break_overlay_mesh(visible: impl Fn(FaceDir) -> bool) -> ChunkMesh {
+    let mut vertices = Vec::new();
+    for face in [FaceDir::Down, FaceDir::Up, FaceDir::Left, FaceDir::Right, FaceDir::Forward, FaceDir::Back] {
+        if visible(face) {
+            GreedyQuad { x: 0, y: 0, w: 1, h: 1 }.append_vertices(&mut vertices, face, 0, &Lod::L32, 0, 0);
+        }
+    }
+    ChunkMesh { indices: generate_indices(vertices.len()), vertices, light: Vec::new() }
+}
+
+/// Faces of the block at `block` that aren't hidden by a neighbor, none if the block is invisible or not loaded.
+/// Unloaded neighbors don't hide faces.
+pub fn visible_block_faces(voxel_engine: &VoxelEngine, block_registry: &BlockRegistry, block: IVec3) -> impl Fn(FaceDir) -> bool {
+    let visible_flags = BlockFlags::SOLID | BlockFlags::TRANSPARENT | BlockFlags::CUTOUT;
+    let is_visible = voxel_engine.get_block(block).is_some_and(|data| block_registry.block_flags[data.block_type.0 as usize].intersects(visible_flags));
+    let occluded: Vec<(FaceDir, bool)> = [FaceDir::Down, FaceDir::Up, FaceDir::Left, FaceDir::Right, FaceDir::Forward, FaceDir::Back]
+        .into_iter()
+        .map(|face| {
+            let neighbor = voxel_engine.get_block(block + face.air_sample_dir());
+            (face, neighbor.is_some_and(|neighbor| block_registry.occludes(neighbor.block_type, BlockFlags::SOLID)))
+        })
+        .collect();
+    move |face| is_visible && occluded.iter().any(|(occluded_face, occluded)| *occluded_face == face && !occluded)
+}
+
+/// Rebuilds the overlay meshes of blocks that changed, or whose chunk or a neighbor of it was generated or modified.
+#[allow(clippy::too_many_arguments)]
+fn update_break_overlay_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<BreakOverlayMaterial>>,
+    overlays: Query<(Entity, Ref<BreakProgress>, Has<MeshMaterial3d<BreakOverlayMaterial>>)>,
+    mut chunk_generated: EventReader<ChunkGenerated>,
+    mut chunk_modified: EventReader<ChunkModified>,
+    voxel_engine: Res<VoxelEngine>,
+    block_registry: Res<BlockRegistryResource>,
+) {
+    let changed_chunks: HashSet<IVec3> = chunk_generated.read().map(|event| event.0)
+        .chain(chunk_modified.read().map(|event| event.0))
+        .collect();
+
+    for (entity, overlay, has_material) in overlays.iter() {
+        // faces on the chunk border depend on the neighboring chunk.
+        let block_changed = chunks_in_aabb(overlay.block - IVec3::ONE, overlay.block + IVec3::ONE).any(|chunk| changed_chunks.contains(&chunk));
+        if !overlay.is_changed() && !block_changed {
+            continue;
+        }
+
+        let mesh = break_overlay_mesh(visible_block_faces(&voxel_engine, &block_registry.0, overlay.block));
+        let mut entity_commands = commands.entity(entity);
+        entity_commands.insert(Transform::from_translation(overlay.block.as_vec3()));
+        if mesh.vertices.is_empty() {
+            entity_commands.remove::<Mesh3d>();
+        } else {
+            entity_commands.insert(Mesh3d(meshes.add(mesh.to_bevy_mesh())));
+        }
+        if !has_material {
+            entity_commands.insert(MeshMaterial3d(materials.add(BreakOverlayMaterial {
+                color: LinearRgba::new(0.0, 0.0, 0.0, 0.8),
+                progress: overlay.progress,
+            })));
+        }
+    }
+}
+
+fn update_break_overlay_progress(
+    overlays: Query<(&BreakProgress, &MeshMaterial3d<BreakOverlayMaterial>), Changed<BreakProgress>>,
+    mut materials: ResMut<Assets<BreakOverlayMaterial>>,
+) {
+    for (overlay, material) in overlays.iter() {
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.progress = overlay.progress.clamp(0.0, 1.0);
+        }
+    }
+}
+
+#[test]
+fn overlay_only_covers_visible_faces() {
+    use std::sync::Arc;
+
+    use crate::{chunk::ChunkData, constants::CHUNK_SIZE3, voxel::{BlockData, BlockId}};
+
+    let block_registry = BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        ..Default::default()
+    };
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for pos in [IVec3::new(4, 4, 4), IVec3::new(4, 3, 4), IVec3::new(5, 4, 4)] {
+        voxels[crate::utils::vec3_to_index(pos, 32)].block_type = BlockId(1);
+    }
+    let mut voxel_engine = VoxelEngine::default();
+    voxel_engine.world_data.insert(IVec3::ZERO, Arc::new(ChunkData::Dense(voxels)));
+
+    // stone below & to the right hide two faces.
+    let faces = visible_block_faces(&voxel_engine, &block_registry, IVec3::new(4, 4, 4));
+    assert!(!faces(FaceDir::Down) && !faces(FaceDir::Right) && faces(FaceDir::Up));
+    let mesh = break_overlay_mesh(faces);
+    assert_eq!(mesh.vertices.len(), 4 * 4);
+    assert_eq!(mesh.indices.len(), 4 * 6);
+
+    assert!(break_overlay_mesh(visible_block_faces(&voxel_engine, &block_registry, IVec3::new(10, 10, 10))).vertices.is_empty());
+}
@@ -0,0 +1,92 @@
+#import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_clip}
+
+struct BreakOverlayMaterial {
+    color: vec4<f32>,
+    progress: f32,
+};
+
+@group(2) @binding(0) var<uniform> material: BreakOverlayMaterial;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) vert_data: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Same order as FaceDir::normal_index, the overlay has no cross quads.
+var<private> normals: array<vec3<f32>,6> = array<vec3<f32>,6> (
+	vec3<f32>(-1.0, 0.0, 0.0), // Left
+	vec3<f32>(1.0, 0.0, 0.0), // Right
+	vec3<f32>(0.0, -1.0, 0.0), // Down
+	vec3<f32>(0.0, 1.0, 0.0), // Up
+	vec3<f32>(0.0, 0.0, -1.0), // Forward
+	vec3<f32>(0.0, 0.0, 1.0) // Back
+);
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    let position = vec3<f32>(
+        f32(vertex.vert_data & 63u),
+        f32(vertex.vert_data >> 6u & 63u),
+        f32(vertex.vert_data >> 12u & 63u),
+    );
+    let normal = normals[vertex.vert_data >> 21u & 7u];
+
+    // the two axes along the face, the block is a unit cube so they're already 0..1
+    if normal.x != 0.0 {
+        out.uv = position.zy;
+    } else if normal.y != 0.0 {
+        out.uv = position.xz;
+    } else {
+        out.uv = position.xy;
+    }
+
+    // pushed out a little so it doesn't z-fight with the chunk mesh
+    let local_position = vec4<f32>(position + normal * 0.002, 1.0);
+    out.clip_position = mesh_position_local_to_clip(get_world_from_local(vertex.instance_index), local_position);
+    return out;
+}
+
+fn hash2(p: vec2<f32>) -> vec2<f32> {
+    let q = vec2<f32>(dot(p, vec2<f32>(127.1, 311.7)), dot(p, vec2<f32>(269.5, 183.3)));
+    return fract(sin(q) * 43758.5453);
+}
+
+// Distance to the nearest border between voronoi cells, the cracks.
+fn crack_distance(uv: vec2<f32>) -> f32 {
+    let cell = floor(uv);
+    var nearest = 8.0;
+    var second = 8.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = cell + vec2<f32>(f32(x), f32(y));
+            let distance = length(neighbor + hash2(neighbor) - uv);
+            if distance < nearest {
+                second = nearest;
+                nearest = distance;
+            } else if distance < second {
+                second = distance;
+            }
+        }
+    }
+    return second - nearest;
+}
+
+@fragment
+fn fragment(input: VertexOutput) -> @location(0) vec4<f32> {
+    // cracks spread out from the middle of the face as the block breaks
+    let reach = material.progress * 0.75;
+    if material.progress <= 0.0 || length(input.uv - 0.5) > reach {
+        discard;
+    }
+    if crack_distance(input.uv * 4.0) > 0.08 {
+        discard;
+    }
+    return material.color;
+}
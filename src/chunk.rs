@@ -4,38 +4,689 @@ use bevy::prelude::*;
 use bracket_noise::prelude::*;
 
 use crate::{
-    constants::CHUNK_SIZE, voxel::BlockData
+    constants::{CHUNK_SIZE, CHUNK_SIZE3}, face_direction::FaceDir, utils::{index_to_ivec3, index_to_ivec3_bounds, index_to_ivec3_bounds_reverse, vec3_to_index, CoordinateConvention}, voxel::{BlockData, BlockFlags, BlockId, BlockRegistry}
 };
 
+/// Generates the data of a chunk from its position.
+/// Chunk `c` covers world voxels `c * 32..(c + 1) * 32`, local voxel (0, 0, 0) is its corner at world voxel `c * 32`.
 #[derive(Resource)]
 pub struct ChunkGenerator {
-    pub generate: Arc<dyn Fn(IVec3) -> ChunkData + Send + Sync>,
+    pub generate: Arc<dyn Fn(IVec3) -> GeneratedChunk + Send + Sync>,
+}
+impl ChunkGenerator {
+    /// A generator working in another [`CoordinateConvention`].
+    /// `generate` gets chunk positions in `convention` & returns data & structures in it, which are converted to the engine's.
+    pub fn with_convention(convention: CoordinateConvention, generate: impl Fn(IVec3) -> GeneratedChunk + Send + Sync + 'static) -> Self {
+        Self {
+            generate: Arc::new(move |chunk_pos| {
+                let generated = generate(convention.from_engine(chunk_pos));
+                GeneratedChunk {
+                    data: generated.data.into_engine_convention(convention),
+                    structures: generated.structures.into_iter()
+                        .map(|structure| PendingStructure {
+                            world_pos: convention.to_engine(structure.world_pos),
+                            blocks: structure.blocks.into_iter().map(|(offset, block)| (convention.offset_to_engine(offset), block)).collect(),
+                        })
+                        .collect(),
+                }
+            }),
+        }
+    }
 }
 
+/// A generator that can read the chunks around the one it generates, to continue features like rivers across chunk borders.
+/// Used instead of [`ChunkGenerator`] when inserted.
+///
+/// A queued chunk waits while any of its 26 neighbors is still generating, so it can read them once they're done,
+/// for at most [`ChunkGeneratorWithContext::max_wait_frames`] frames. Neighbors that aren't loaded or queued aren't waited for.
+///
+/// Unlike [`ChunkGenerator`] the result isn't only a function of the chunk's position:
+/// which neighbors exist depends on the order chunks load in, so the same chunk can come out differently when regenerated.
+#[derive(Resource)]
+pub struct ChunkGeneratorWithContext {
+    pub generate: Arc<ContextGenerateFn>,
+    /// Frames a chunk waits on generating neighbors before generating with whichever neighbors are loaded.
+    pub max_wait_frames: u32,
+}
+
+pub type ContextGenerateFn = dyn Fn(IVec3, &GenerationContext) -> GeneratedChunk + Send + Sync;
+
+/// Neighbors of a chunk that were loaded when it started generating, see [`ChunkGeneratorWithContext`].
+pub struct GenerationContext {
+    /// The 3x3x3 chunks around the generated one, indexed with [`vec3_to_index`] of the offset + 1. The middle is always `None`.
+    neighbors: Vec<Option<Arc<ChunkData>>>,
+}
+impl GenerationContext {
+    /// Shares the data of the loaded neighbors of `chunk_pos`.
+    pub fn new(world_data: &bevy::utils::HashMap<IVec3, Arc<ChunkData>>, chunk_pos: IVec3) -> Self {
+        let neighbors = (0..3 * 3 * 3)
+            .map(|i| {
+                let offset = index_to_ivec3_bounds(i, 3) - IVec3::ONE;
+                (offset != IVec3::ZERO).then(|| world_data.get(&(chunk_pos + offset)).cloned()).flatten()
+            })
+            .collect();
+        Self { neighbors }
+    }
+
+    /// Data of the neighbor at `offset` from the generated chunk, each axis -1 to 1. `None` if it isn't loaded.
+    pub fn neighbor(&self, offset: IVec3) -> Option<&ChunkData> {
+        if offset.abs().max_element() > 1 {
+            return None;
+        }
+        self.neighbors[vec3_to_index(offset + IVec3::ONE, 3)].as_deref()
+    }
+
+    /// Number of loaded neighbors.
+    pub fn neighbor_count(&self) -> usize {
+        self.neighbors.iter().flatten().count()
+    }
+}
+
+/// Output of a [`ChunkGenerator`] or [`ChunkGeneratorWithContext`].
+pub struct GeneratedChunk {
+    pub data: ChunkData,
+    /// Structures which may extend into neighboring chunks.
+    pub structures: Vec<PendingStructure>,
+}
+impl From<ChunkData> for GeneratedChunk {
+    fn from(data: ChunkData) -> Self {
+        Self {
+            data,
+            structures: Vec::new(),
+        }
+    }
+}
+
+/// A set of blocks placed relative to `world_pos`, which is allowed to cross chunk boundaries.
+/// Blocks destined for chunks which aren't generated yet are applied once they are.
+pub struct PendingStructure {
+    /// World space voxel position the blocks are relative to.
+    pub world_pos: IVec3,
+    pub blocks: Vec<(IVec3, BlockId)>,
+}
+
+/// Seed for randomly placing features like trees & ores in a chunk, the same every run & whatever order chunks generate in.
+/// Neighboring chunks & different world seeds get unrelated seeds.
+///
+/// Seed an RNG per chunk from it inside the generator, e.g. `rand::rngs::StdRng::seed_from_u64(chunk_seed(world_seed, chunk_pos))`,
+/// rather than sharing one across chunks.
+pub fn chunk_seed(world_seed: u64, chunk_pos: IVec3) -> u64 {
+    let mut hash = splitmix64(world_seed);
+    for coord in chunk_pos.to_array() {
+        hash = splitmix64(hash ^ coord as u32 as u64);
+    }
+    hash
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// Blocks of a chunk, indexed with [`crate::utils::vec3_to_index`].
 #[derive(Clone)]
-pub struct ChunkData {
-    pub voxels: Vec<BlockData>,
+pub enum ChunkData {
+    /// Every voxel is the same block.
+    Filled(BlockData),
+    /// Runs of the same block & state in index order, each with the index one past its last voxel.
+    /// Always has more than one run, a single run is [`ChunkData::Filled`].
+    Runs(Vec<(BlockData, u16)>),
+    /// Every voxel stored separately, [`CHUNK_SIZE3`] long.
+    Dense(Vec<BlockData>),
+}
+
+/// Order of the voxels in a flat array, named from the fastest changing axis to the slowest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelOrder {
+    /// x changes fastest, the same as [`crate::utils::vec3_to_index`].
+    XYZ,
+    /// z changes fastest, the same as [`crate::utils::index_to_ivec3_bounds_reverse`].
+    ZYX,
 }
 
 impl ChunkData {
+    /// Builds a chunk from a flat array of [`CHUNK_SIZE3`] block ids, for meshing voxel data from elsewhere.
+    /// Collapsed to [`ChunkData::Filled`] if every block is the same.
+    ///
+    /// Panics if `data` isn't [`CHUNK_SIZE3`] long.
+    pub fn from_slice(data: &[u16], order: VoxelOrder) -> ChunkData {
+        assert_eq!(data.len(), CHUNK_SIZE3, "chunk data has to be {CHUNK_SIZE3} voxels");
+
+        let voxels = match order {
+            VoxelOrder::XYZ => data.iter().map(|&id| BlockData::new(BlockId(id))).collect(),
+            VoxelOrder::ZYX => {
+                let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+                for (i, &id) in data.iter().enumerate() {
+                    let pos = index_to_ivec3_bounds_reverse(i as i32, CHUNK_SIZE as i32);
+                    voxels[vec3_to_index(pos, CHUNK_SIZE as i32)] = BlockData::new(BlockId(id));
+                }
+                voxels
+            }
+        };
+        let mut chunk = ChunkData::Dense(voxels);
+        chunk.compact();
+        chunk
+    }
+
+    /// Reorders a chunk indexed x, then y, then z in `convention` to the engine's [`crate::utils::vec3_to_index`] order.
+    pub fn into_engine_convention(self, convention: CoordinateConvention) -> ChunkData {
+        if convention == CoordinateConvention::YUp || matches!(self, ChunkData::Filled(_)) {
+            return self;
+        }
+        let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+        for i in 0..CHUNK_SIZE3 {
+            voxels[vec3_to_index(convention.index_to_ivec3(i as i32, CHUNK_SIZE as i32), CHUNK_SIZE as i32)] = self.get_block(i);
+        }
+        let mut chunk = ChunkData::Dense(voxels);
+        chunk.compact();
+        chunk
+    }
+
     #[inline]
-    pub fn get_block(&self, index: usize) -> &BlockData {
-        if self.voxels.len() == 1 {
-            &self.voxels[0]
-        } else {
-            &self.voxels[index]
+    pub fn get_block(&self, index: usize) -> BlockData {
+        match self {
+            ChunkData::Filled(block) => *block,
+            ChunkData::Runs(runs) => {
+                let run = runs.partition_point(|(_, end)| (*end as usize) <= index);
+                runs[run].0
+            }
+            ChunkData::Dense(voxels) => voxels[index],
         }
     }
 
     // returns the block type if all voxels are the same
     #[inline]
     pub fn get_block_if_filled(&self) -> Option<&BlockData> {
-        if self.voxels.len() == 1 {
-            Some(&self.voxels[0])
-        } else {
-            None
+        match self {
+            ChunkData::Filled(block) => Some(block),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the chunk is filled with `air`.
+    #[inline]
+    pub fn is_fully_air(&self, air: BlockId) -> bool {
+        self.get_block_if_filled().is_some_and(|block| block.block_type == air)
+    }
+
+    /// Returns true if the chunk is filled with a block that has all of `flags`.
+    #[inline]
+    pub fn is_fully_solid(&self, registry: &BlockRegistry, flags: BlockFlags) -> bool {
+        self.get_block_if_filled().is_some_and(|block| registry.has_flag(block.block_type, flags))
+    }
+
+    /// Expands the chunk to [`ChunkData::Dense`] if it isn't already, for changing individual voxels.
+    pub fn make_dense(&mut self) -> &mut Vec<BlockData> {
+        if !matches!(self, ChunkData::Dense(_)) {
+            let voxels = (0..CHUNK_SIZE3).map(|i| self.get_block(i)).collect();
+            *self = ChunkData::Dense(voxels);
+        }
+        let ChunkData::Dense(voxels) = self else {
+            unreachable!()
+        };
+        voxels
+    }
+
+    /// Sets the block at a chunk local position.
+    /// Compressed chunks are only expanded once a voxel actually changes, see [`ChunkData::compact`] for going back.
+    pub fn set_block(&mut self, local_pos: IVec3, block: impl Into<BlockData>) {
+        let block = block.into();
+        let i = vec3_to_index(local_pos, CHUNK_SIZE as i32);
+        if self.get_block(i) == block {
+            return;
+        }
+        self.make_dense()[i] = block;
+    }
+
+    /// Returns true if any voxel of the chunk is `block`.
+    pub fn contains_block(&self, block: BlockId) -> bool {
+        match self {
+            ChunkData::Filled(filled) => filled.block_type == block,
+            ChunkData::Runs(runs) => runs.iter().any(|(run, _)| run.block_type == block),
+            ChunkData::Dense(voxels) => voxels.iter().any(|voxel| voxel.block_type == block),
+        }
+    }
+
+    /// Replaces every `from` block with `to` without changing how the chunk is stored, states are kept.
+    /// Returns true if any block was replaced.
+    pub fn replace_block(&mut self, from: BlockId, to: BlockId) -> bool {
+        if from == to {
+            return false;
+        }
+        let replace = |block: &mut BlockData| {
+            let matches = block.block_type == from;
+            if matches {
+                block.block_type = to;
+            }
+            matches
+        };
+        match self {
+            ChunkData::Filled(block) => replace(block),
+            ChunkData::Runs(runs) => {
+                let replaced = runs.iter_mut().fold(false, |replaced, (block, _)| replace(block) | replaced);
+                if replaced {
+                    // neighboring runs may hold the same block now.
+                    runs.dedup_by(|next, run| {
+                        let same = next.0 == run.0;
+                        if same {
+                            run.1 = next.1;
+                        }
+                        same
+                    });
+                    if runs.len() == 1 {
+                        *self = ChunkData::Filled(runs[0].0);
+                    }
+                }
+                replaced
+            }
+            ChunkData::Dense(voxels) => voxels.iter_mut().fold(false, |replaced, block| replace(block) | replaced),
+        }
+    }
+
+    /// Copies out every row of [`CHUNK_SIZE`] voxels along x in index order, without expanding the chunk.
+    pub fn rows(&self) -> impl Iterator<Item = [BlockData; CHUNK_SIZE]> + '_ {
+        let mut run = 0;
+        (0..CHUNK_SIZE3).step_by(CHUNK_SIZE).map(move |start| match self {
+            ChunkData::Filled(block) => [*block; CHUNK_SIZE],
+            ChunkData::Runs(runs) => std::array::from_fn(|x| {
+                // rows are visited in index order, so the runs can be walked along instead of searched.
+                while runs[run].1 as usize <= start + x {
+                    run += 1;
+                }
+                runs[run].0
+            }),
+            ChunkData::Dense(voxels) => voxels[start..start + CHUNK_SIZE].try_into().unwrap(),
+        })
+    }
+
+    /// Collapses the chunk back to a single voxel if every voxel is the same.
+    /// Returns true if the chunk is collapsed afterwards.
+    ///
+    /// Cheap for chunks that aren't uniform, as it stops at the first differing voxel. [`ChunkData::compress`] also tries runs.
+    pub fn compact(&mut self) -> bool {
+        let first = self.get_block(0);
+        let uniform = match self {
+            ChunkData::Filled(_) => return true,
+            // runs never hold a single block.
+            ChunkData::Runs(_) => false,
+            ChunkData::Dense(voxels) => voxels.iter().all(|voxel| *voxel == first),
+        };
+        if uniform {
+            *self = ChunkData::Filled(first);
+        }
+        uniform
+    }
+
+    /// Stores the chunk in whichever form takes the least memory.
+    pub fn compress(&mut self) {
+        let ChunkData::Dense(voxels) = self else {
+            return;
+        };
+
+        let mut runs: Vec<(BlockData, u16)> = Vec::new();
+        for (i, voxel) in voxels.iter().enumerate() {
+            match runs.last_mut() {
+                Some((block, end)) if block == voxel => *end = i as u16 + 1,
+                _ => {
+                    // no point in continuing once the runs are as large as the dense voxels.
+                    if (runs.len() + 1) * std::mem::size_of::<(BlockData, u16)>() >= voxels.len() * std::mem::size_of::<BlockData>() {
+                        return;
+                    }
+                    runs.push((*voxel, i as u16 + 1));
+                }
+            }
+        }
+
+        *self = match runs.as_slice() {
+            [(block, _)] => ChunkData::Filled(*block),
+            _ => {
+                runs.shrink_to_fit();
+                ChunkData::Runs(runs)
+            }
+        };
+    }
+
+    /// Bytes of heap memory used by the voxels, not counting the size of [`ChunkData`] itself.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            ChunkData::Filled(_) => 0,
+            ChunkData::Runs(runs) => runs.capacity() * std::mem::size_of::<(BlockData, u16)>(),
+            ChunkData::Dense(voxels) => voxels.capacity() * std::mem::size_of::<BlockData>(),
         }
     }
+
+    /// Iterates over every block in the chunk along with its chunk-local position.
+    /// Filled chunks yield the fill block for every position.
+    pub fn iter_blocks(&self) -> impl Iterator<Item = (IVec3, BlockId)> + '_ {
+        (0..CHUNK_SIZE3).map(|i| (index_to_ivec3(i), self.get_block(i).block_type))
+    }
+
+    /// Like [`ChunkData::iter_blocks`] but skips blocks of type `air`.
+    /// A chunk filled with `air` yields nothing without visiting every position.
+    pub fn iter_non_air(&self, air: BlockId) -> impl Iterator<Item = (IVec3, BlockId)> + '_ {
+        let len = if self.is_fully_air(air) { 0 } else { CHUNK_SIZE3 };
+
+        (0..len).filter_map(move |i| {
+            let block_type = self.get_block(i).block_type;
+            (block_type != air).then(|| (index_to_ivec3(i), block_type))
+        })
+    }
+
+    /// Changes turning this chunk into `other`, or a compressed copy of `other` if there are so many that it takes less memory.
+    pub fn diff(&self, other: &ChunkData) -> ChunkPatch {
+        let mut snapshot = other.clone();
+        snapshot.compress();
+        let max_changes = snapshot.memory_usage() / std::mem::size_of::<(u16, BlockData)>();
+
+        let mut changes = Vec::new();
+        for (row_index, (row, other_row)) in self.rows().zip(other.rows()).enumerate() {
+            for (x, (block, other_block)) in row.iter().zip(other_row.iter()).enumerate() {
+                if block == other_block {
+                    continue;
+                }
+                if changes.len() == max_changes {
+                    return ChunkPatch::Snapshot(snapshot);
+                }
+                changes.push(((row_index * CHUNK_SIZE + x) as u16, *other_block));
+            }
+        }
+        ChunkPatch::Changes(changes)
+    }
+
+    /// Applies a patch made with [`ChunkData::diff`].
+    /// Changes expand the chunk like [`ChunkData::set_block`], a chunk they leave uniform is collapsed to [`ChunkData::Filled`].
+    pub fn apply_patch(&mut self, patch: ChunkPatch) {
+        match patch {
+            ChunkPatch::Snapshot(chunk) => *self = chunk,
+            ChunkPatch::Changes(changes) => {
+                let changes: Vec<_> = changes.into_iter().filter(|(i, block)| self.get_block(*i as usize) != *block).collect();
+                if changes.is_empty() {
+                    return;
+                }
+                let voxels = self.make_dense();
+                for (i, block) in changes {
+                    voxels[i as usize] = block;
+                }
+                self.compact();
+            }
+        }
+    }
+
+    /// Number of voxels of each block type in the chunk, without visiting every voxel of filled chunks or runs.
+    pub fn block_histogram(&self) -> bevy::utils::HashMap<BlockId, u32> {
+        let mut histogram = bevy::utils::HashMap::new();
+        match self {
+            ChunkData::Filled(block) => {
+                histogram.insert(block.block_type, CHUNK_SIZE3 as u32);
+            }
+            ChunkData::Runs(runs) => {
+                let mut start = 0;
+                for (block, end) in runs {
+                    *histogram.entry(block.block_type).or_default() += (*end - start) as u32;
+                    start = *end;
+                }
+            }
+            ChunkData::Dense(voxels) => {
+                for voxel in voxels {
+                    *histogram.entry(voxel.block_type).or_default() += 1;
+                }
+            }
+        }
+        histogram
+    }
+}
+
+/// Changes turning one chunk into another, see [`ChunkData::diff`] & [`ChunkData::apply_patch`].
+/// Small enough to send over the network when a few blocks of a chunk change, such as for each [`crate::events::ChunkBlocksChanged`].
+#[derive(Clone)]
+pub enum ChunkPatch {
+    /// Voxels that changed in index order, as (index, new block). States are included.
+    Changes(Vec<(u16, BlockData)>),
+    /// The whole new chunk, when it's smaller than the changes would be.
+    Snapshot(ChunkData),
+}
+
+/// Which of a chunk's 6 boundary faces are entirely covered by opaque full blocks,
+/// as bits indexed by [`FaceDir::normal_index`].
+///
+/// Nothing can be seen through a solid face, which makes it a cheap way to tell whether chunks are hidden behind others.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FaceSolidity(pub u8);
+impl FaceSolidity {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(0b111111);
+
+    /// Blocks count as opaque if they [`BlockRegistry::occludes`] with [`BlockFlags::SOLID`].
+    pub fn of_chunk(chunk: &ChunkData, registry: &BlockRegistry) -> Self {
+        if let Some(block) = chunk.get_block_if_filled() {
+            return if registry.occludes(block.block_type, BlockFlags::SOLID) { Self::ALL } else { Self::NONE };
+        }
+
+        let last = CHUNK_SIZE as i32 - 1;
+        let mut solidity = Self::NONE;
+        for face in FaceDir::ALL {
+            let dir = face.air_sample_dir();
+            // The axis the face is perpendicular to is fixed, the other two span the face.
+            let fixed = dir.max(IVec3::ZERO) * last;
+            let (u, v) = match face {
+                FaceDir::Up | FaceDir::Down => (IVec3::X, IVec3::Z),
+                FaceDir::Left | FaceDir::Right => (IVec3::Y, IVec3::Z),
+                FaceDir::Forward | FaceDir::Back => (IVec3::X, IVec3::Y),
+            };
+            let solid = (0..CHUNK_SIZE as i32).all(|a| (0..CHUNK_SIZE as i32).all(|b| {
+                let block = chunk.get_block(vec3_to_index(fixed + u * a + v * b, CHUNK_SIZE as i32));
+                registry.occludes(block.block_type, BlockFlags::SOLID)
+            }));
+            if solid {
+                solidity.0 |= 1 << face.normal_index();
+            }
+        }
+        solidity
+    }
+
+    #[inline]
+    pub fn is_solid(&self, face: FaceDir) -> bool {
+        self.0 & (1 << face.normal_index()) != 0
+    }
+}
+
+/// Stable hash of a chunk's blocks, for checking that generation is deterministic.
+///
+/// Only depends on the block & state at each position, so filled & expanded chunks with the same blocks hash the same.
+/// Uses FNV-1a rather than [`std::hash::Hash`] so hashes can be compared across runs, platforms & Rust versions.
+pub fn hash_chunk(chunk: &ChunkData) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET_BASIS;
+    for i in 0..CHUNK_SIZE3 {
+        let block = chunk.get_block(i);
+        for byte in block.block_type.0.to_le_bytes().into_iter().chain(block.state.to_le_bytes()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
+#[test]
+fn collapse_uniform_chunks() {
+    let mut voxels = vec![BlockData::new(BlockId(2)); CHUNK_SIZE3];
+    voxels[100].block_type = BlockId(0);
+    let mut chunk = ChunkData::Dense(voxels);
+    assert!(!chunk.compact());
+    assert!(matches!(chunk, ChunkData::Dense(_)));
+
+    chunk.set_block(index_to_ivec3(100), BlockId(2));
+    let expanded_hash = hash_chunk(&chunk);
+    assert!(chunk.compact());
+    assert_eq!(hash_chunk(&chunk), expanded_hash);
+    assert_eq!(chunk.get_block_if_filled(), Some(&BlockData::new(BlockId(2))));
+}
+
+#[test]
+fn set_block_keeps_chunks_compressed() {
+    let stone = BlockId(2);
+    let mut chunk = ChunkData::Filled(BlockData::new(stone));
+
+    // writing what's already there doesn't expand the chunk.
+    chunk.set_block(IVec3::new(3, 4, 5), stone);
+    assert!(matches!(chunk, ChunkData::Filled(_)));
+
+    chunk.set_block(IVec3::new(3, 4, 5), BlockId(0));
+    assert!(matches!(chunk, ChunkData::Dense(_)));
+    assert_eq!(chunk.get_block(vec3_to_index(IVec3::new(3, 4, 5), 32)).block_type, BlockId(0));
+    assert_eq!(chunk.get_block(0).block_type, stone);
+    assert!(!chunk.compact());
+
+    chunk.set_block(IVec3::new(3, 4, 5), stone);
+    assert!(chunk.compact());
+    assert_eq!(chunk.get_block_if_filled(), Some(&BlockData::new(stone)));
+}
+
+#[test]
+fn chunk_from_slice() {
+    let pos = IVec3::new(1, 2, 3);
+    let mut xyz = vec![0u16; CHUNK_SIZE3];
+    xyz[vec3_to_index(pos, CHUNK_SIZE as i32)] = 5;
+    let mut zyx = vec![0u16; CHUNK_SIZE3];
+    zyx[vec3_to_index(IVec3::new(pos.z, pos.y, pos.x), CHUNK_SIZE as i32)] = 5;
+
+    for chunk in [ChunkData::from_slice(&xyz, VoxelOrder::XYZ), ChunkData::from_slice(&zyx, VoxelOrder::ZYX)] {
+        assert_eq!(chunk.iter_non_air(BlockId(0)).collect::<Vec<_>>(), [(pos, BlockId(5))]);
+    }
+
+    let filled = ChunkData::from_slice(&[3; CHUNK_SIZE3], VoxelOrder::ZYX);
+    assert_eq!(filled.get_block_if_filled(), Some(&BlockData::new(BlockId(3))));
+}
+
+#[test]
+fn z_up_generator_places_blocks_upright() {
+    use crate::utils::world_to_chunk_local;
+
+    // a pillar 3 blocks tall in Z-up, at chunk z 0 in its convention.
+    let generator = ChunkGenerator::with_convention(CoordinateConvention::ZUp, |chunk_pos| {
+        let mut data = vec![0u16; CHUNK_SIZE3];
+        if chunk_pos == IVec3::ZERO {
+            for z in 0..3 {
+                data[vec3_to_index(IVec3::new(4, 5, z), CHUNK_SIZE as i32)] = 1;
+            }
+        }
+        ChunkData::from_slice(&data, VoxelOrder::XYZ).into()
+    });
+
+    let (chunk_pos, _) = world_to_chunk_local(CoordinateConvention::ZUp.to_engine(IVec3::new(4, 5, 0)));
+    let generated = (generator.generate)(chunk_pos);
+    let mut blocks: Vec<IVec3> = generated.data.iter_non_air(BlockId(0)).map(|(pos, _)| pos + chunk_pos * CHUNK_SIZE as i32).collect();
+    blocks.sort_by_key(|pos| pos.y);
+    let expected: Vec<IVec3> = (0..3).map(|z| CoordinateConvention::ZUp.to_engine(IVec3::new(4, 5, z))).collect();
+    assert_eq!(blocks, expected);
+    // stacked along +Y in the engine.
+    assert_eq!(blocks[1] - blocks[0], IVec3::Y);
+}
+
+#[test]
+fn chunk_seeds_are_stable_and_distinct() {
+    assert_eq!(chunk_seed(7, IVec3::new(1, -2, 3)), chunk_seed(7, IVec3::new(1, -2, 3)));
+
+    let mut seeds: Vec<u64> = [IVec3::ZERO, IVec3::X, IVec3::Y, IVec3::Z, IVec3::NEG_X, IVec3::new(1, 1, 0)]
+        .into_iter()
+        .flat_map(|pos| [chunk_seed(7, pos), chunk_seed(8, pos)])
+        .collect();
+    seeds.sort();
+    seeds.dedup();
+    assert_eq!(seeds.len(), 12);
+}
+
+#[test]
+fn chunk_patches_reproduce_the_target() {
+    let stone = BlockData::new(BlockId(1));
+    let air = ChunkData::Filled(BlockData::default());
+    let mut few = ChunkData::Filled(BlockData::default());
+    few.set_block(IVec3::new(1, 2, 3), stone);
+    few.set_block(IVec3::new(31, 0, 0), stone.with_state(2));
+    let mut many = ChunkData::Dense((0..CHUNK_SIZE3).map(|i| BlockData::new(BlockId((i % 5) as u16))).collect());
+
+    let round_trip = |from: &ChunkData, to: &ChunkData| {
+        let mut patched = from.clone();
+        patched.apply_patch(from.diff(to));
+        assert!((0..CHUNK_SIZE3).all(|i| patched.get_block(i) == to.get_block(i)));
+        patched
+    };
+    // uniform to dense & back.
+    assert!(matches!(air.diff(&few), ChunkPatch::Changes(changes) if changes.len() == 2));
+    round_trip(&air, &few);
+    assert!(matches!(round_trip(&few, &air), ChunkData::Filled(_)));
+
+    // too many changes send the new chunk instead.
+    assert!(matches!(few.diff(&many), ChunkPatch::Snapshot(_)));
+    round_trip(&few, &many);
+    assert!(matches!(many.diff(&ChunkData::Filled(stone)), ChunkPatch::Snapshot(ChunkData::Filled(_))));
+    round_trip(&many, &ChunkData::Filled(stone));
+
+    let before = many.clone();
+    many.set_block(IVec3::new(4, 4, 4), stone.with_state(1));
+    assert!(matches!(before.diff(&many), ChunkPatch::Changes(changes) if changes.len() == 1));
+    round_trip(&before, &many);
+}
+
+#[test]
+fn iter_chunk_blocks() {
+    use crate::utils::vec3_to_index;
+
+    let air = ChunkData::Filled(BlockData::default());
+    assert_eq!(air.iter_blocks().count(), CHUNK_SIZE3);
+    assert!(air.iter_blocks().all(|(_, block)| block == BlockId(0)));
+    assert_eq!(air.iter_non_air(BlockId(0)).count(), 0);
+
+    let stone = ChunkData::Filled(BlockData::new(BlockId(3)));
+    assert_eq!(stone.iter_non_air(BlockId(0)).count(), CHUNK_SIZE3);
+
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    let ores = [IVec3::new(0, 0, 0), IVec3::new(31, 0, 7), IVec3::new(4, 20, 31)];
+    for pos in ores {
+        voxels[vec3_to_index(pos, 32)].block_type = BlockId(5);
+    }
+    let chunk = ChunkData::Dense(voxels);
+    assert_eq!(chunk.iter_non_air(BlockId(0)).collect::<Vec<_>>(), ores.map(|pos| (pos, BlockId(5))));
+    for (pos, block) in chunk.iter_blocks() {
+        assert_eq!(chunk.get_block(vec3_to_index(pos, 32)).block_type, block);
+    }
+}
+
+#[test]
+fn compress_to_runs() {
+    // stone below y 10, a layer of dirt & air above, with a hole in the dirt.
+    let mut voxels: Vec<_> = (0..CHUNK_SIZE3)
+        .map(|i| BlockData::new(BlockId(match index_to_ivec3(i).y { 0..10 => 2, 10 => 1, _ => 0 })))
+        .collect();
+    voxels[10 * 32 + 5].block_type = BlockId(0);
+    let mut chunk = ChunkData::Dense(voxels.clone());
+    let dense_hash = hash_chunk(&chunk);
+
+    chunk.compress();
+    let ChunkData::Runs(runs) = &chunk else {
+        panic!("layered chunk should compress to runs");
+    };
+    assert!(chunk.memory_usage() < CHUNK_SIZE3 * std::mem::size_of::<BlockData>() / 10, "{} runs", runs.len());
+    assert_eq!(hash_chunk(&chunk), dense_hash);
+    assert!(chunk.rows().flatten().eq(voxels.iter().copied()));
+
+    // expanding to change a voxel keeps the rest.
+    chunk.set_block(IVec3::ZERO, BlockId(3));
+    voxels[0].block_type = BlockId(3);
+    assert!(chunk.rows().flatten().eq(voxels.iter().copied()));
+
+    // noise doesn't compress.
+    let mut noise = ChunkData::Dense((0..CHUNK_SIZE3).map(|i| BlockData::new(BlockId((i * 7 % 3) as u16))).collect());
+    noise.compress();
+    assert!(matches!(noise, ChunkData::Dense(_)));
+
+    let mut filled = ChunkData::Dense(vec![BlockData::new(BlockId(4)); CHUNK_SIZE3]);
+    filled.compress();
+    assert_eq!(filled.get_block_if_filled(), Some(&BlockData::new(BlockId(4))));
 }
 
 fn bilinear_interpolation(
@@ -251,4 +902,38 @@ impl NoiseDownSampler3D {
             sample_value_111
         )
     }
-}
\ No newline at end of file
+}
+#[test]
+fn block_state_survives_compression() {
+    let stone = BlockData::new(BlockId(2));
+    let mut chunk = ChunkData::Filled(stone);
+
+    chunk.set_block(IVec3::ZERO, stone.with_state(3));
+    assert!(!chunk.compact());
+    chunk.compress();
+    assert!(matches!(chunk, ChunkData::Runs(_)));
+    assert_eq!(chunk.get_block(0), stone.with_state(3));
+    assert_eq!(chunk.get_block(1), stone);
+
+    chunk.set_block(IVec3::ZERO, stone);
+    assert!(chunk.compact());
+}
+
+#[test]
+fn face_solidity_of_partially_solid_chunks() {
+    let registry = BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        ..default()
+    };
+
+    assert_eq!(FaceSolidity::of_chunk(&ChunkData::Filled(BlockData::new(BlockId(1))), &registry), FaceSolidity::ALL);
+    assert_eq!(FaceSolidity::of_chunk(&ChunkData::Filled(BlockData::new(BlockId(0))), &registry), FaceSolidity::NONE);
+
+    // Solid bottom half, so only the bottom face is entirely solid.
+    let mut chunk = ChunkData::Dense((0..CHUNK_SIZE3).map(|i| BlockData::new(BlockId((index_to_ivec3(i).y < 16) as u16))).collect());
+    assert_eq!(FaceSolidity::of_chunk(&chunk, &registry), FaceSolidity(1 << FaceDir::Down.normal_index()));
+
+    // A single hole opens the face up again.
+    chunk.set_block(IVec3::new(7, 0, 31), BlockId(0));
+    assert_eq!(FaceSolidity::of_chunk(&chunk, &registry), FaceSolidity::NONE);
+}
@@ -27,15 +27,27 @@ struct ChunkMaterial {
     reflectance: f32,
     perceptual_roughness: f32,
     metallic: f32,
+    time: f32,
+    alpha_cutoff: f32,
+    // LOD crossfade, see ChunkMaterial::fade.
+    fade: f32,
 };
 
 @group(2) @binding(0) var<uniform> chunk_material: ChunkMaterial;
 @group(2) @binding(1) var<storage, read> block_color: array<vec4<f32>>;
 @group(2) @binding(2) var<storage, read> block_emissive: array<vec4<f32>>;
+@group(2) @binding(3) var<storage, read> block_flags: array<u32>;
+@group(2) @binding(4) var<storage, read> block_lowering: array<f32>;
+
+// Must match BlockFlags::ANIMATED_EMISSIVE.
+const ANIMATED_EMISSIVE: u32 = 8u;
 
 struct Vertex {
     @builtin(instance_index) instance_index: u32,
     @location(0) vert_data: u32,
+#ifdef BLOCK_LIGHT
+    @location(1) block_light: u32,
+#endif
 };
 
 struct VertexOutput {
@@ -52,15 +64,33 @@ var<private> ambient_lerps: vec4<f32> = vec4<f32>(1.0,0.7,0.5,0.15);
 
 // indexing an array has to be in some memory
 // by declaring this as a var instead it works
-var<private> normals: array<vec3<f32>,6> = array<vec3<f32>,6> (
+var<private> normals: array<vec3<f32>,8> = array<vec3<f32>,8> (
 	vec3<f32>(-1.0, 0.0, 0.0), // Left
 	vec3<f32>(1.0, 0.0, 0.0), // Right
 	vec3<f32>(0.0, -1.0, 0.0), // Down
 	vec3<f32>(0.0, 1.0, 0.0), // Up
 	vec3<f32>(0.0, 0.0, -1.0), // Forward
-	vec3<f32>(0.0, 0.0, 1.0) // Back
+	vec3<f32>(0.0, 0.0, 1.0), // Back
+	vec3<f32>(0.70710678, 0.0, -0.70710678), // Cross
+	vec3<f32>(0.70710678, 0.0, 0.70710678) // Cross
+);
+
+// 4x4 ordered dither pattern for LOD crossfades.
+var<private> dither_pattern: array<f32, 16> = array<f32, 16>(
+    0.0, 8.0, 2.0, 10.0,
+    12.0, 4.0, 14.0, 6.0,
+    3.0, 11.0, 1.0, 9.0,
+    15.0, 7.0, 13.0, 5.0
 );
 
+// Whether a fragment is drawn at the material's fade. From 0 to 1 the fade is the share of fragments drawn,
+// from -1 to 0 it draws the ones left out at fade + 1, so the old & new mesh of a chunk never overlap.
+fn fade_keeps(frag_coord: vec2<f32>, fade: f32) -> bool {
+    let pixel = vec2<u32>(frag_coord) % 4u;
+    let threshold = (dither_pattern[pixel.y * 4u + pixel.x] + 0.5) / 16.0;
+    return select(threshold < fade, threshold >= fade + 1.0, fade < 0.0);
+}
+
 fn x_positive_bits(bits: u32) -> u32{
     return (1u << bits) - 1u;
 }
@@ -70,9 +100,11 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     var out: VertexOutput;
 
     let x = f32(vertex.vert_data & x_positive_bits(6u));
-    let y = f32(vertex.vert_data >> 6u & x_positive_bits(6u));
+    // slabs lower vertices by half a voxel, fluids down to their level
+    let half_down = f32(vertex.vert_data >> 20u & 1u) * block_lowering[vertex.vert_data >> 24u];
+    let y = f32(vertex.vert_data >> 6u & x_positive_bits(6u)) - half_down;
     let z = f32(vertex.vert_data >> 12u & x_positive_bits(6u));
-    let ao = vertex.vert_data >> 18u & x_positive_bits(3u);
+    let ao = vertex.vert_data >> 18u & x_positive_bits(2u);
     let normal_index = vertex.vert_data >> 21u & x_positive_bits(3u);
     let block_index = vertex.vert_data >> 24u & x_positive_bits(8u);
 
@@ -85,6 +117,10 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 
     let ambient_lerp = ambient_lerps[ao];
     out.ambient = ambient_lerp;
+#ifdef BLOCK_LIGHT
+    // Same as light_brightness, unlit faces are darkened.
+    out.ambient *= 0.05 + 0.95 * f32(min(vertex.block_light, 15u)) / 15.0;
+#endif
     out.world_position = world_position;
     
 
@@ -93,12 +129,16 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 
     out.blend_color = block_color[block_index];
     out.blend_emissive = block_emissive[block_index];
+    // Animated emissive blocks pulse between no & full emissive.
+    if (block_flags[block_index] & ANIMATED_EMISSIVE) != 0u {
+        out.blend_emissive *= 0.5 + 0.5 * sin(chunk_material.time);
+    }
     out.instance_index = vertex.instance_index;
     return out;
 }
 
 @fragment
-fn fragment(input: VertexOutput) -> FragmentOutput {
+fn fragment(input: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
     var pbr_input = pbr_input_new();
 
     pbr_input.flags = mesh[input.instance_index].flags;
@@ -107,17 +147,36 @@ fn fragment(input: VertexOutput) -> FragmentOutput {
     pbr_input.frag_coord = input.clip_position;
     pbr_input.world_position = input.world_position;
 
+#ifdef CHUNK_DOUBLE_SIDED
+    pbr_input.world_normal = prepare_world_normal(
+        input.world_normal,
+        true,
+        is_front,
+    );
+#else
     pbr_input.world_normal = prepare_world_normal(
         input.world_normal,
         false,
         false,
     );
+#endif
 #ifdef LOAD_PREPASS_NORMALS
     pbr_input.N = prepass_utils::prepass_normal(input.clip_position, 0u);
 #else
     pbr_input.N = normalize(pbr_input.world_normal);
 #endif
 
+    if chunk_material.fade < 1.0 && !fade_keeps(input.clip_position.xy, chunk_material.fade) {
+        discard;
+    }
+
+#ifdef MAY_DISCARD
+    // AlphaMode::Mask layers, such as foliage.
+    if input.blend_color.w < chunk_material.alpha_cutoff {
+        discard;
+    }
+#endif
+
     pbr_input.material.base_color = vec4<f32>(input.blend_color.xyz * input.ambient, input.blend_color.w);
     pbr_input.material.emissive = input.blend_emissive;
 
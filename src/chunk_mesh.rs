@@ -1,19 +1,62 @@
-use bevy::{asset::RenderAssetUsages, math::{IVec3, Vec3}, render::{mesh::{Indices, Mesh, MeshVertexAttribute, PrimitiveTopology}, primitives::Aabb, render_resource::VertexFormat}};
+use std::f32::consts::FRAC_1_SQRT_2;
 
-use crate::utils::get_pos_from_vertex_u32;
+use bevy::math::{IVec3, Vec3};
+#[cfg(feature = "rendering")]
+use bevy::{asset::RenderAssetUsages, render::{mesh::{Indices, Mesh, MeshVertexAttribute, PrimitiveTopology}, primitives::Aabb, render_resource::VertexFormat}};
+
+use crate::{constants::{CHUNK_SIZE, CHUNK_SIZE_I32}, face_direction::FaceDir, light::ChunkLight, quad::Quad, utils::{generate_indices, generate_indices_with, get_normal_index_from_vertex_u32, FaceWinding, get_offset_pos_from_vertex_u32, get_pos_from_vertex_u32}};
+#[cfg(feature = "rendering")]
+use crate::{light::light_brightness, utils::{get_ao_from_vertex_u32, get_block_type_from_vertex_u32, VERTEX_HALF_DOWN_BIT}, voxel::{BlockId, BlockRegistry}};
 
 // A "high" random id should be used for custom attributes to ensure consistent sorting and avoid collisions with other attributes.
 // See the MeshVertexAttribute docs for more info.
+#[cfg(feature = "rendering")]
 pub const ATTRIBUTE_VOXEL: MeshVertexAttribute =
     MeshVertexAttribute::new("Voxel", 988540919, VertexFormat::Uint32);
+/// Block light level of each vertex, only on meshes with [`ChunkMesh::light`].
+#[cfg(feature = "rendering")]
+pub const ATTRIBUTE_BLOCK_LIGHT: MeshVertexAttribute =
+    MeshVertexAttribute::new("BlockLight", 988540920, VertexFormat::Uint32);
+
+/// Normal of each face, indexed by the normal index packed into the vertex (see [`crate::face_direction::FaceDir::normal_index`]).
+/// The last two are the diagonals of [`crate::voxel::BlockShape::Cross`], see [`crate::utils::CROSS_NORMAL_INDICES`].
+pub const FACE_NORMALS: [[f32; 3]; 8] = [
+    [-1.0, 0.0, 0.0], // Left
+    [1.0, 0.0, 0.0],  // Right
+    [0.0, -1.0, 0.0], // Down
+    [0.0, 1.0, 0.0],  // Up
+    [0.0, 0.0, -1.0], // Forward
+    [0.0, 0.0, 1.0],  // Back
+    [FRAC_1_SQRT_2, 0.0, -FRAC_1_SQRT_2], // Cross
+    [FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2],  // Cross
+];
+
+/// Brightness for each ambient occlusion level, same as in `chunk.wgsl`.
+pub const AMBIENT_OCCLUSION_LEVELS: [f32; 4] = [1.0, 0.7, 0.5, 0.15];
+
+/// Tangent of each face, indexed by the normal index packed into the vertex (see [`crate::face_direction::FaceDir::normal_index`]).
+/// Chosen so the bitangent (`cross(normal, tangent.xyz) * tangent.w`) points up on side faces.
+pub const FACE_TANGENTS: [[f32; 4]; 8] = [
+    [0.0, 0.0, 1.0, 1.0],  // Left
+    [0.0, 0.0, -1.0, 1.0], // Right
+    [1.0, 0.0, 0.0, 1.0],  // Down
+    [1.0, 0.0, 0.0, 1.0],  // Up
+    [-1.0, 0.0, 0.0, 1.0], // Forward
+    [1.0, 0.0, 0.0, 1.0],  // Back
+    [-FRAC_1_SQRT_2, 0.0, -FRAC_1_SQRT_2, 1.0], // Cross
+    [FRAC_1_SQRT_2, 0.0, -FRAC_1_SQRT_2, 1.0],  // Cross
+];
 
 /// gpu ready mesh payload
 #[derive(Default)]
 pub struct ChunkMesh {
     pub indices: Vec<u32>,
     pub vertices: Vec<u32>,
+    /// Block light level of each vertex, empty for meshes without block light, see [`ChunkMesh::apply_block_light`].
+    pub light: Vec<u8>,
 }
 impl ChunkMesh {
+    #[cfg(feature = "rendering")]
     pub fn to_bevy_mesh(self) -> Mesh {
         let mut bevy_mesh = Mesh::new(
             PrimitiveTopology::TriangleList,
@@ -21,12 +64,124 @@ impl ChunkMesh {
         );
         
         bevy_mesh.insert_attribute(ATTRIBUTE_VOXEL, self.vertices);
+        if !self.light.is_empty() {
+            bevy_mesh.insert_attribute(ATTRIBUTE_BLOCK_LIGHT, self.light.into_iter().map(u32::from).collect::<Vec<_>>());
+        }
         bevy_mesh.insert_indices(Indices::U32(self.indices));
 
         bevy_mesh
     }
 
-    pub fn calculate_aabb(&self) -> Aabb {
+    /// Regenerates the indices so `winding` is the front of the quads, see [`generate_indices_with`].
+    pub fn set_winding(&mut self, winding: FaceWinding, double_sided: bool) {
+        self.indices = generate_indices_with(self.vertices.len(), winding, double_sided);
+    }
+
+    /// The quads of the mesh with their fields unpacked, in mesh order.
+    pub fn quads(&self) -> impl Iterator<Item = Quad> + '_ {
+        self.vertices.chunks_exact(4).map(|quad| Quad::from_vertices([quad[0], quad[1], quad[2], quad[3]]))
+    }
+
+    /// Splits the mesh by the direction its quads face, so each direction can be hidden on its own.
+    /// Quads facing along no axis, like cross quads, are under `None`. Empty parts are left out & quads keep their order.
+    pub fn split_by_face(self) -> Vec<(Option<FaceDir>, ChunkMesh)> {
+        let quad_count = self.vertices.len() / 4;
+        if quad_count == 0 {
+            return Vec::new();
+        }
+        // the indices of each quad are next to each other, however many there are.
+        let indices_per_quad = self.indices.len() / quad_count;
+
+        let mut parts: Vec<(Option<FaceDir>, ChunkMesh)> = Vec::new();
+        for quad in 0..quad_count {
+            let vertices = &self.vertices[quad * 4..quad * 4 + 4];
+            let face = FaceDir::from_normal_index(get_normal_index_from_vertex_u32(vertices[0]));
+            let part = match parts.iter().position(|(part_face, _)| *part_face == face) {
+                Some(part) => &mut parts[part].1,
+                None => {
+                    parts.push((face, ChunkMesh::default()));
+                    &mut parts.last_mut().unwrap().1
+                }
+            };
+
+            let (old_start, new_start) = (quad as u32 * 4, part.vertices.len() as u32);
+            part.indices.extend(self.indices[quad * indices_per_quad..(quad + 1) * indices_per_quad].iter().map(|index| index - old_start + new_start));
+            part.vertices.extend_from_slice(vertices);
+            if !self.light.is_empty() {
+                part.light.extend_from_slice(&self.light[quad * 4..quad * 4 + 4]);
+            }
+        }
+        parts
+    }
+
+    /// Lights each quad by the light of the voxel in front of it, cross quads by their own voxel.
+    /// Greedy meshing only merges faces with the same light (see [`crate::chunks_refs::ChunksRefs::block_light`]), so any voxel of the quad will do.
+    pub fn apply_block_light(&mut self, light: &ChunkLight) {
+        self.light = self.vertices.chunks_exact(4).flat_map(|quad| {
+            let corner = quad.iter().map(|vertex| get_pos_from_vertex_u32(*vertex)).reduce(IVec3::min).unwrap();
+            // faces lie on the far side of their voxel, except the ones facing down an axis.
+            let voxel = match get_normal_index_from_vertex_u32(quad[0]) {
+                0 => corner - IVec3::X,
+                2 => corner - IVec3::Y,
+                4 => corner - IVec3::Z,
+                _ => corner,
+            };
+            [light.get(voxel.clamp(IVec3::NEG_ONE, IVec3::splat(CHUNK_SIZE_I32))); 4]
+        }).collect();
+    }
+
+    /// Like [`ChunkMesh::to_bevy_mesh`] but also attaches [`Mesh::ATTRIBUTE_TANGENT`] for normal mapped materials.
+    #[cfg(feature = "rendering")]
+    pub fn to_bevy_mesh_with_tangents(self) -> Mesh {
+        let tangents: Vec<[f32; 4]> = self.vertices.iter().map(|vertex| {
+            FACE_TANGENTS[get_normal_index_from_vertex_u32(*vertex) as usize]
+        }).collect();
+
+        let mut bevy_mesh = self.to_bevy_mesh();
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+
+        bevy_mesh
+    }
+
+    /// Unpacks the vertices into a mesh with the standard position, normal & color attributes,
+    /// so it can be used with materials other than `ChunkMaterial` or exported.
+    ///
+    /// Vertex colors are the block color darkened by ambient occlusion. Vertices aren't deduplicated.
+    #[cfg(feature = "rendering")]
+    pub fn to_standard_mesh(self, block_registry: &BlockRegistry) -> Mesh {
+        self.bake_colors(block_registry)
+    }
+
+    /// Like [`ChunkMesh::to_standard_mesh`] but keeps the packed mesh, for exporting meshes that are also rendered.
+    /// Together with the positions & normals the baked colors are all glTF or OBJ exporters need.
+    #[cfg(feature = "rendering")]
+    pub fn bake_colors(&self, block_registry: &BlockRegistry) -> Mesh {
+        let mut standard = StandardVertices::default();
+        standard.append(self, IVec3::ZERO, block_registry);
+        standard.into_mesh()
+    }
+
+    /// Merges the meshes of several chunks into a single standard mesh, to draw a group of distant chunks in one draw call.
+    /// Each mesh is moved by its `offset` in voxels, such as `(chunk_pos - group_origin) * CHUNK_SIZE_I32`.
+    ///
+    /// The packed vertices only have 6 bits per axis, enough for one chunk & its far edge but not for several,
+    /// so the merged mesh is unpacked like [`ChunkMesh::to_standard_mesh`] & needs a material using vertex colors instead of `ChunkMaterial`.
+    /// It takes about 4 times the memory of the packed meshes, so it's best kept to coarse LODs with few vertices.
+    #[cfg(feature = "rendering")]
+    pub fn merge(meshes: &[(IVec3, ChunkMesh)], block_registry: &BlockRegistry) -> Mesh {
+        let mut standard = StandardVertices::default();
+        for (offset, mesh) in meshes {
+            standard.append(mesh, *offset, block_registry);
+        }
+        standard.into_mesh()
+    }
+
+    /// Bounds of the vertices, `None` for a mesh without any.
+    #[cfg(feature = "rendering")]
+    pub fn calculate_aabb(&self) -> Option<Aabb> {
+        if self.vertices.is_empty() {
+            return None;
+        }
         // Calculate the AABB for the chunk (purely for minorly improved culling, might not be necessary)
         let (min, max) = self.vertices.iter().fold((IVec3::MAX, IVec3::MIN), |(min, max), v| {
             let pos = get_pos_from_vertex_u32(*v);
@@ -34,14 +189,386 @@ impl ChunkMesh {
             (min.min(pos), max.max(pos))
         });
 
-        Aabb::from_min_max(min.as_vec3(), max.as_vec3())
+        Some(Aabb::from_min_max(min.as_vec3(), max.as_vec3()))
+    }
+
+    /// Triangles of the mesh in chunk local positions, from the index triples.
+    /// Lowered fluid vertices are off like in [`ChunkMesh::into_uncompressed_mesh`].
+    pub fn triangles(&self) -> impl Iterator<Item = [Vec3; 3]> + '_ {
+        self.indices.chunks_exact(3).map(|triangle| [0, 1, 2].map(|i| get_offset_pos_from_vertex_u32(self.vertices[triangle[i] as usize])))
     }
 
     /// Converts the chunk mesh into a regular "uncompressed" mesh that can be used for collision or other purposes.
     pub fn into_uncompressed_mesh(self) -> (Vec<u32>, Vec<Vec3>) {
         (
             self.indices,
-            self.vertices.into_iter().map(|vertex| get_pos_from_vertex_u32(vertex).as_vec3()).collect()
+            self.vertices.into_iter().map(get_offset_pos_from_vertex_u32).collect()
         )
     }
 }
+
+/// Result of a successful [`ray_triangle_nearest`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangleHit {
+    /// Chunk local position the ray hit the triangle at.
+    pub position: Vec3,
+    /// Distance along the ray to the hit.
+    pub distance: f32,
+    pub triangle: [Vec3; 3],
+}
+
+/// Casts a ray in chunk local space against the triangles of `mesh` & returns the nearest hit, see [`ChunkMesh::triangles`].
+///
+/// Unlike [`crate::voxel_engine::raycast`] this hits the actual shape of slabs, cross blocks & fluids.
+/// Triangles are hit from either side, so meshes with a flipped winding work the same.
+pub fn ray_triangle_nearest(mesh: &ChunkMesh, origin: Vec3, dir: Vec3) -> Option<TriangleHit> {
+    let dir = dir.normalize_or_zero();
+    if dir == Vec3::ZERO {
+        return None;
+    }
+    mesh.triangles()
+        .filter_map(|triangle| {
+            let distance = ray_triangle_distance(origin, dir, triangle)?;
+            Some(TriangleHit { position: origin + dir * distance, distance, triangle })
+        })
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+/// Möller-Trumbore intersection, the distance along `dir` to the triangle if the ray hits it in front of `origin`.
+fn ray_triangle_distance(origin: Vec3, dir: Vec3, [a, b, c]: [Vec3; 3]) -> Option<f32> {
+    let (edge1, edge2) = (b - a, c - a);
+    let p = dir.cross(edge2);
+    let determinant = edge1.dot(p);
+    // parallel to the triangle, or a degenerate one.
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let t = origin - a;
+    let u = t.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = t.cross(edge1);
+    let v = dir.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(q) * inverse;
+    (distance >= 0.0).then_some(distance)
+}
+
+/// Unpacked vertices of one or more chunk meshes, see [`ChunkMesh::bake_colors`].
+#[cfg(feature = "rendering")]
+#[derive(Default)]
+struct StandardVertices {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+#[cfg(feature = "rendering")]
+impl StandardVertices {
+    fn append(&mut self, mesh: &ChunkMesh, offset: IVec3, block_registry: &BlockRegistry) {
+        let first_vertex = self.positions.len() as u32;
+        self.positions.extend(mesh.vertices.iter().map(|vertex| {
+            let block_type = BlockId(get_block_type_from_vertex_u32(*vertex) as u16);
+            let lowering = if vertex & VERTEX_HALF_DOWN_BIT != 0 { block_registry.shape(block_type).vertex_lowering() } else { 0.0 };
+            ((get_pos_from_vertex_u32(*vertex) + offset).as_vec3() - Vec3::Y * lowering).to_array()
+        }));
+        self.normals.extend(mesh.vertices.iter().map(|vertex| {
+            FACE_NORMALS[get_normal_index_from_vertex_u32(*vertex) as usize]
+        }));
+        self.colors.extend(mesh.vertices.iter().enumerate().map(|(i, vertex)| {
+            let color = block_registry.block_color[get_block_type_from_vertex_u32(*vertex) as usize].to_linear();
+            let light = mesh.light.get(i).map_or(1.0, |level| light_brightness(*level));
+            let ambient = AMBIENT_OCCLUSION_LEVELS[get_ao_from_vertex_u32(*vertex) as usize] * light;
+
+            [color.red * ambient, color.green * ambient, color.blue * ambient, color.alpha]
+        }));
+        self.indices.extend(mesh.indices.iter().map(|index| first_vertex + index));
+    }
+
+    fn into_mesh(self) -> Mesh {
+        let mut bevy_mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        bevy_mesh.insert_indices(Indices::U32(self.indices));
+
+        bevy_mesh
+    }
+}
+
+/// How well greedy meshing merged the faces of a mesh, see [`crate::greedy_mesher_optimized::build_chunk_mesh_with_stats`].
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct MeshStats {
+    pub quads: usize,
+    /// Voxel faces covered by the quads, which is how many quads there would be without merging.
+    pub unit_faces_covered: usize,
+    /// Share of the faces that were merged away, `1 - quads / unit_faces_covered`.
+    pub merge_ratio: f32,
+    pub build_micros: u64,
+}
+impl MeshStats {
+    /// Measures the quads of a mesh's vertices.
+    pub fn from_vertices(vertices: &[u32], build_micros: u64) -> Self {
+        let area: f32 = vertices.chunks_exact(4)
+            .map(|quad| {
+                let positions = quad.iter().map(|vertex| get_offset_pos_from_vertex_u32(*vertex));
+                let extent = positions.clone().reduce(Vec3::max).unwrap() - positions.reduce(Vec3::min).unwrap();
+                if extent.cmpgt(Vec3::ZERO).all() {
+                    // the diagonal quads of cross blocks each cover a face
+                    return extent.y;
+                }
+                // quads are flat, so one of the terms is always 0.
+                extent.x * extent.y + extent.y * extent.z + extent.z * extent.x
+            })
+            .sum();
+
+        Self::new(vertices.len() / 4, area.round() as usize, build_micros)
+    }
+
+    fn new(quads: usize, unit_faces_covered: usize, build_micros: u64) -> Self {
+        Self {
+            quads,
+            unit_faces_covered,
+            merge_ratio: if unit_faces_covered == 0 { 0.0 } else { 1.0 - quads as f32 / unit_faces_covered as f32 },
+            build_micros,
+        }
+    }
+
+    /// Combines the stats of several meshes, like the layers of a chunk.
+    pub fn combine(self, other: Self) -> Self {
+        Self::new(self.quads + other.quads, self.unit_faces_covered + other.unit_faces_covered, self.build_micros + other.build_micros)
+    }
+
+    pub fn average_quad_area(&self) -> f32 {
+        if self.quads == 0 { 0.0 } else { self.unit_faces_covered as f32 / self.quads as f32 }
+    }
+}
+
+/// A chunk mesh where the vertices of every slice are kept separate,
+/// so individual slices can be rebuilt without remeshing the entire chunk.
+#[derive(Clone)]
+pub struct ChunkMeshSlices {
+    /// Vertices indexed by `face_axis * CHUNK_SIZE + slice`, with face axis in the greedy mesher's order
+    /// (down, up, left, right, forward, back).
+    pub vertices: Vec<Vec<u32>>,
+}
+impl Default for ChunkMeshSlices {
+    fn default() -> Self {
+        Self {
+            vertices: vec![Vec::new(); 6 * CHUNK_SIZE],
+        }
+    }
+}
+impl ChunkMeshSlices {
+    #[inline]
+    pub fn slice_mut(&mut self, face_axis: usize, slice: usize) -> &mut Vec<u32> {
+        &mut self.vertices[face_axis * CHUNK_SIZE + slice]
+    }
+
+    /// Swaps the `dirty` slices with the ones from `rebuilt`, leaving the replaced slices in `rebuilt`.
+    pub fn swap(&mut self, rebuilt: &mut ChunkMeshSlices, dirty: &DirtySlices) {
+        for face_axis in 0..6 {
+            let mut mask = dirty.0[face_axis / 2];
+            while mask != 0 {
+                let slice = mask.trailing_zeros() as usize;
+                mask &= mask - 1;
+
+                let i = face_axis * CHUNK_SIZE + slice;
+                std::mem::swap(&mut self.vertices[i], &mut rebuilt.vertices[i]);
+            }
+        }
+    }
+
+    /// Empties every slice, keeping their capacity.
+    pub fn clear(&mut self) {
+        self.vertices.iter_mut().for_each(Vec::clear);
+    }
+
+    /// Flattens the slices into a single mesh. Returns `None` if there are no vertices.
+    pub fn to_chunk_mesh(&self) -> Option<ChunkMesh> {
+        let len = self.vertices.iter().map(Vec::len).sum();
+        if len == 0 {
+            return None;
+        }
+        let mut vertices = Vec::with_capacity(len);
+        self.vertices.iter().for_each(|slice| vertices.extend_from_slice(slice));
+
+        Some(ChunkMesh {
+            indices: generate_indices(vertices.len()),
+            vertices,
+            light: Vec::new(),
+        })
+    }
+}
+
+/// Bitmask of slices per axis: `[y, x, z]`, matching the face axis pairs (down/up, left/right, forward/back).
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirtySlices(pub [u32; 3]);
+impl DirtySlices {
+    pub const ALL: Self = Self([u32::MAX; 3]);
+
+    /// Marks every slice whose faces may depend on the voxel at `local_pos`.
+    /// `local_pos` may lie in the one voxel padding around the chunk (-1 or 32).
+    ///
+    /// Besides the voxel's own slice, the slices on either side are marked as their faces are culled by & sample ambient occlusion from it.
+    pub fn mark_voxel(&mut self, local_pos: IVec3) {
+        for (mask, c) in self.0.iter_mut().zip([local_pos.y, local_pos.x, local_pos.z]) {
+            for slice in (c - 1).max(0)..=(c + 1).min(CHUNK_SIZE as i32 - 1) {
+                *mask |= 1 << slice;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|mask| *mask == 0)
+    }
+}
+
+#[test]
+fn face_tangents_match_normals() {
+    use crate::face_direction::FaceDir;
+
+    for face in [FaceDir::Up, FaceDir::Down, FaceDir::Left, FaceDir::Right, FaceDir::Forward, FaceDir::Back] {
+        let tangent = FACE_TANGENTS[face.normal_index() as usize];
+        let tangent = Vec3::new(tangent[0], tangent[1], tangent[2]);
+        let normal = face.air_sample_dir().as_vec3();
+        assert_eq!(Vec3::from_array(FACE_NORMALS[face.normal_index() as usize]), normal);
+
+        assert_eq!(tangent.dot(normal), 0.0);
+        assert_eq!(tangent.length(), 1.0);
+        if normal.y == 0.0 {
+            assert_eq!(normal.cross(tangent), Vec3::Y);
+        }
+    }
+
+    for index in crate::utils::CROSS_NORMAL_INDICES {
+        let normal = Vec3::from_array(FACE_NORMALS[index as usize]);
+        let tangent = bevy::math::Vec4::from_array(FACE_TANGENTS[index as usize]).truncate();
+        assert!(tangent.dot(normal).abs() < 1e-6);
+        assert!(normal.cross(tangent).abs_diff_eq(Vec3::Y, 1e-6));
+    }
+}
+
+#[cfg(feature = "rendering")]
+#[test]
+fn baked_colors_match_vertices() {
+    use bevy::{color::Color, render::mesh::VertexAttributeValues};
+    use crate::{face_direction::FaceDir, greedy_mesher_optimized::GreedyQuad, lod::Lod, utils::generate_indices, voxel::BlockFlags};
+
+    let block_registry = BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        block_color: vec![Color::NONE, Color::srgb(1.0, 0.0, 0.0)],
+        ..Default::default()
+    };
+    let mut vertices = Vec::new();
+    GreedyQuad { x: 0, y: 0, w: 2, h: 3 }.append_vertices(&mut vertices, FaceDir::Up, 4, &Lod::L32, 0, 1);
+    GreedyQuad { x: 5, y: 5, w: 1, h: 1 }.append_vertices(&mut vertices, FaceDir::Left, 7, &Lod::L32, 0b111111111, 1);
+    let mesh = ChunkMesh { indices: generate_indices(vertices.len()), vertices, light: Vec::new() };
+
+    let baked = mesh.bake_colors(&block_registry);
+    let Some(VertexAttributeValues::Float32x4(colors)) = baked.attribute(Mesh::ATTRIBUTE_COLOR) else {
+        panic!("missing colors");
+    };
+    assert_eq!(colors.len(), mesh.vertices.len());
+    assert_eq!(baked.attribute(Mesh::ATTRIBUTE_NORMAL).unwrap().len(), mesh.vertices.len());
+    assert_eq!(colors[0], [1.0, 0.0, 0.0, 1.0]);
+    // fully occluded corners are darkened.
+    assert!(colors[4][0] < 1.0);
+}
+
+#[cfg(feature = "rendering")]
+#[test]
+fn merged_meshes_are_offset() {
+    use bevy::render::mesh::VertexAttributeValues;
+    use crate::{face_direction::FaceDir, greedy_mesher_optimized::GreedyQuad, lod::Lod, utils::generate_indices, voxel::BlockFlags};
+
+    let block_registry = BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        block_color: vec![bevy::color::Color::NONE, bevy::color::Color::WHITE],
+        ..Default::default()
+    };
+    let quad_mesh = |x| {
+        let mut vertices = Vec::new();
+        GreedyQuad { x, y: 0, w: 1, h: 1 }.append_vertices(&mut vertices, FaceDir::Up, 0, &Lod::L32, 0, 1);
+        ChunkMesh { indices: generate_indices(vertices.len()), vertices, light: Vec::new() }
+    };
+    let offset = IVec3::new(CHUNK_SIZE_I32, 0, -CHUNK_SIZE_I32);
+    let merged = ChunkMesh::merge(&[(IVec3::ZERO, quad_mesh(0)), (offset, quad_mesh(31))], &block_registry);
+
+    let Some(VertexAttributeValues::Float32x3(positions)) = merged.attribute(Mesh::ATTRIBUTE_POSITION) else {
+        panic!("missing positions");
+    };
+    assert_eq!(positions.len(), 8);
+    // past what the packed positions can hold.
+    assert!(positions[4..].iter().all(|pos| pos[0] >= 63.0 && pos[2] <= -31.0));
+    let Some(Indices::U32(indices)) = merged.indices() else {
+        panic!("missing indices");
+    };
+    assert_eq!(indices.len(), 12);
+    assert!(indices[6..].iter().all(|index| (4..8).contains(index)));
+}
+
+#[test]
+fn split_by_face_keeps_quads_whole() {
+    use crate::utils::make_vertex_u32;
+
+    let quad = |face: FaceDir, x: i32| [0; 4].map(|_| make_vertex_u32(IVec3::new(x, 0, 0), 0, face.normal_index(), 1));
+    let vertices: Vec<u32> = [quad(FaceDir::Up, 0), quad(FaceDir::Left, 1), quad(FaceDir::Up, 2)].concat();
+    let mut mesh = ChunkMesh { indices: Vec::new(), vertices, light: vec![1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3] };
+    mesh.set_winding(FaceWinding::Clockwise, true);
+
+    let parts = mesh.split_by_face();
+    assert_eq!(parts.len(), 2);
+    let (face, up) = &parts[0];
+    assert_eq!(*face, Some(FaceDir::Up));
+    assert_eq!(up.vertices.len(), 8);
+    assert_eq!(up.light, [1, 1, 1, 1, 3, 3, 3, 3]);
+    // both quads keep their winding & double sided triangles.
+    assert_eq!(up.indices, generate_indices_with(8, FaceWinding::Clockwise, true));
+    assert_eq!(get_pos_from_vertex_u32(up.vertices[4]).x, 2);
+    assert_eq!(parts[1].0, Some(FaceDir::Left));
+    assert_eq!(parts[1].1.indices, generate_indices_with(4, FaceWinding::Clockwise, true));
+}
+
+#[test]
+fn rays_hit_the_nearest_triangle() {
+    use crate::{greedy_mesher_optimized::GreedyQuad, lod::Lod};
+
+    // the tops of a block at the origin & of the block above it.
+    let mut vertices = Vec::new();
+    GreedyQuad { x: 0, y: 0, w: 1, h: 1 }.append_vertices(&mut vertices, FaceDir::Up, 0, &Lod::L32, 0, 0);
+    GreedyQuad { x: 0, y: 0, w: 1, h: 1 }.append_vertices(&mut vertices, FaceDir::Up, 1, &Lod::L32, 0, 0);
+    let mesh = ChunkMesh { indices: generate_indices(vertices.len()), vertices, light: Vec::new() };
+    assert_eq!(mesh.triangles().count(), 4);
+    assert!(mesh.triangles().flatten().all(|pos| pos.y == 1.0 || pos.y == 2.0));
+
+    let hit = ray_triangle_nearest(&mesh, Vec3::new(0.5, 5.0, 0.5), Vec3::NEG_Y * 3.0).unwrap();
+    assert_eq!(hit.distance, 3.0);
+    assert_eq!(hit.position, Vec3::new(0.5, 2.0, 0.5));
+    // from between the two, only the lower one is in front of the ray.
+    let hit = ray_triangle_nearest(&mesh, Vec3::new(0.25, 1.5, 0.75), Vec3::NEG_Y).unwrap();
+    assert_eq!(hit.position.y, 1.0);
+    assert!(ray_triangle_nearest(&mesh, Vec3::new(0.5, 5.0, 0.5), Vec3::Y).is_none());
+    assert!(ray_triangle_nearest(&mesh, Vec3::new(3.5, 5.0, 0.5), Vec3::NEG_Y).is_none());
+}
+
+#[cfg(feature = "rendering")]
+#[test]
+fn empty_meshes_have_no_aabb() {
+    use crate::{greedy_mesher_optimized::GreedyQuad, lod::Lod};
+
+    assert!(ChunkMesh::default().calculate_aabb().is_none());
+
+    let mut mesh = ChunkMesh::default();
+    GreedyQuad { x: 2, y: 3, w: 4, h: 5 }.append_vertices(&mut mesh.vertices, FaceDir::Up, 6, &Lod::L32, 0, 0);
+    let aabb = mesh.calculate_aabb().unwrap();
+    assert!(aabb.min().cmple(aabb.max()).all());
+    assert_eq!(aabb.half_extents.y, 0.0);
+}
@@ -20,10 +20,30 @@ struct ChunkMaterial {
     reflectance: f32,
     perceptual_roughness: f32,
     metallic: f32,
-    // _padding: f32,
+    time: f32,
+    alpha_cutoff: f32,
+    // LOD crossfade, see ChunkMaterial::fade.
+    fade: f32,
 };
 
 @group(2) @binding(0) var<uniform> material: ChunkMaterial;
+@group(2) @binding(4) var<storage, read> block_lowering: array<f32>;
+
+// 4x4 ordered dither pattern for LOD crossfades.
+var<private> dither_pattern: array<f32, 16> = array<f32, 16>(
+    0.0, 8.0, 2.0, 10.0,
+    12.0, 4.0, 14.0, 6.0,
+    3.0, 11.0, 1.0, 9.0,
+    15.0, 7.0, 13.0, 5.0
+);
+
+// Whether a fragment is drawn at the material's fade. From 0 to 1 the fade is the share of fragments drawn,
+// from -1 to 0 it draws the ones left out at fade + 1, so the old & new mesh of a chunk never overlap.
+fn fade_keeps(frag_coord: vec2<f32>, fade: f32) -> bool {
+    let pixel = vec2<u32>(frag_coord) % 4u;
+    let threshold = (dither_pattern[pixel.y * 4u + pixel.x] + 0.5) / 16.0;
+    return select(threshold < fade, threshold >= fade + 1.0, fade < 0.0);
+}
 
 fn x_positive_bits(bits: u32) -> u32{
     return (1u << bits) - 1u;
@@ -48,13 +68,15 @@ struct MyVertexOutput {
 
 // indexing an array has to be in some memory
 // by declaring this as a var instead it works
-var<private> normals: array<vec3<f32>,6> = array<vec3<f32>,6> (
+var<private> normals: array<vec3<f32>,8> = array<vec3<f32>,8> (
 	vec3<f32>(-1.0, 0.0, 0.0), // Left
 	vec3<f32>(1.0, 0.0, 0.0), // Right
 	vec3<f32>(0.0, -1.0, 0.0), // Down
 	vec3<f32>(0.0, 1.0, 0.0), // Up
 	vec3<f32>(0.0, 0.0, -1.0), // Back
-	vec3<f32>(0.0, 0.0, 1.0) // Forward
+	vec3<f32>(0.0, 0.0, 1.0), // Forward
+	vec3<f32>(0.70710678, 0.0, -0.70710678), // Cross
+	vec3<f32>(0.70710678, 0.0, 0.70710678) // Cross
 );
 
 @vertex
@@ -62,9 +84,11 @@ fn vertex(vertex: Vertex) -> MyVertexOutput {
     var out: MyVertexOutput;
 
     let x = f32(vertex.vert_data & x_positive_bits(6u));
-    let y = f32(vertex.vert_data >> 6u & x_positive_bits(6u));
+    // slabs lower vertices by half a voxel, fluids down to their level
+    let half_down = f32(vertex.vert_data >> 20u & 1u) * block_lowering[vertex.vert_data >> 24u];
+    let y = f32(vertex.vert_data >> 6u & x_positive_bits(6u)) - half_down;
     let z = f32(vertex.vert_data >> 12u & x_positive_bits(6u));
-    let ao = vertex.vert_data >> 18u & x_positive_bits(3u);
+    let ao = vertex.vert_data >> 18u & x_positive_bits(2u);
     let normal_index = vertex.vert_data >> 21u & x_positive_bits(3u);
 
     let normal = normals[normal_index];
@@ -93,6 +117,11 @@ fn vertex(vertex: Vertex) -> MyVertexOutput {
 fn fragment(in: MyVertexOutput) -> FragmentOutput {
     var out: FragmentOutput;
 
+    // same as in chunk.wgsl, so the depth matches the fragments drawn.
+    if material.fade < 1.0 && !fade_keeps(in.position.xy, material.fade) {
+        discard;
+    }
+
 #ifdef DEPTH_CLAMP_ORTHO
     out.frag_depth = in.clip_position_unclamped.z;
 #endif // DEPTH_CLAMP_ORTHO
@@ -9,32 +9,116 @@ use rand_chacha::ChaCha8Rng;*/
 
 use crate::{
     chunk::ChunkData,
+    constants::CHUNK_SIZE_I32,
+    greedy_mesher_optimized::{FaceCullRule, GreedyMergeOrder},
+    light::ChunkLight,
+    lod::Lod,
     quad::Direction,
     utils::{index_to_ivec3_bounds, vec3_to_index},
-    voxel::BlockData,
+    voxel::{BlockData, BlockFlags, BlockId, BlockRegistry},
+    voxel_engine::WorldBounds,
 };
 
+/// World position of a chunk [`ChunksRefs::try_new`] needed but has no data yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingNeighbor(pub IVec3);
+impl std::fmt::Display for MissingNeighbor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chunk {} has no data", self.0)
+    }
+}
+impl std::error::Error for MissingNeighbor {}
+
+/// Data of the chunk, or a chunk of the [`WorldBounds::outside`] block if it's outside the bounds.
+fn get_neighbor(world_data: &HashMap<IVec3, Arc<ChunkData>>, chunk: IVec3, bounds: Option<&WorldBounds>) -> Result<Arc<ChunkData>, MissingNeighbor> {
+    match (world_data.get(&chunk), bounds) {
+        (Some(chunk_data), _) => Ok(chunk_data.clone()),
+        (None, Some(bounds)) if !bounds.contains(chunk) => Ok(Arc::new(ChunkData::Filled(BlockData::new(bounds.outside)))),
+        _ => Err(MissingNeighbor(chunk)),
+    }
+}
+
 // pointers to chunk data, a middle one with all their neighbours
 #[derive(Clone)]
 pub struct ChunksRefs {
     pub chunks: Vec<Arc<ChunkData>>,
+    /// LOD the face neighbours are meshed at, indexed by [`crate::face_direction::FaceDir::normal_index`].
+    /// Coarser neighbors are sampled by cell for face culling, see [`ChunksRefs::get_culling_block`].
+    pub neighbor_lods: [Lod; 6],
+    /// Light of the middle chunk, meshes only merge faces with the same light in front of them.
+    /// `None` meshes without block light, see [`crate::voxel_engine::VoxelEngine::block_light`].
+    pub block_light: Option<ChunkLight>,
+    /// Which faces between neighboring blocks are meshed, `None` is [`crate::greedy_mesher_optimized::StandardCull`] without the overhead of asking it per face.
+    pub face_cull_rule: Option<Arc<dyn FaceCullRule>>,
+    /// How the faces of each direction are merged into quads, indexed by [`crate::face_direction::FaceDir::normal_index`].
+    pub merge_orders: [GreedyMergeOrder; 6],
 }
 
 impl ChunksRefs {
+    /// construct a ChunksRefs from the 3x3x3 chunks around the middle chunk,
+    /// with all neighbours at the highest LOD
+    pub fn new(chunks: Vec<Arc<ChunkData>>) -> Self {
+        Self {
+            chunks,
+            neighbor_lods: [Lod::L32; 6],
+            block_light: None,
+            face_cull_rule: None,
+            merge_orders: [GreedyMergeOrder::HeightFirst; 6],
+        }
+    }
+
     /// construct a ChunkRefs at middle_chunk position
-    /// safety: panics if ChunkData doesn't exist in input world_data
+    /// returns the first chunk that doesn't exist in world_data, if any
     pub fn try_new(
         world_data: &HashMap<IVec3, Arc<ChunkData>>,
         middle_chunk: IVec3,
-    ) -> Option<Self> {
+    ) -> Result<Self, MissingNeighbor> {
+        Self::try_new_in_bounds(world_data, middle_chunk, None)
+    }
+
+    /// Like [`ChunksRefs::try_new`], but neighbors outside `bounds` are filled with its [`WorldBounds::outside`] block instead of missing.
+    pub fn try_new_in_bounds(
+        world_data: &HashMap<IVec3, Arc<ChunkData>>,
+        middle_chunk: IVec3,
+        bounds: Option<&WorldBounds>,
+    ) -> Result<Self, MissingNeighbor> {
+        let mut chunks = vec![];
+        for i in 0..3 * 3 * 3 {
+            let offset = index_to_ivec3_bounds(i, 3) + IVec3::splat(-1);
+            chunks.push(get_neighbor(world_data, middle_chunk + offset, bounds)?);
+        }
+        Ok(Self::new(chunks))
+    }
+
+    /// Like [`ChunksRefs::try_new`] but only needs the middle chunk & its face neighbors,
+    /// the edge & corner neighbors are filled with `air`.
+    /// Only meshes the same as [`ChunksRefs::try_new`] without ambient occlusion, see [`crate::constants::ADJACENT_CHUNK_DIRECTIONS`].
+    pub fn try_new_face_neighbors(
+        world_data: &HashMap<IVec3, Arc<ChunkData>>,
+        middle_chunk: IVec3,
+        air: BlockId,
+    ) -> Result<Self, MissingNeighbor> {
+        Self::try_new_face_neighbors_in_bounds(world_data, middle_chunk, air, None)
+    }
+
+    /// Like [`ChunksRefs::try_new_face_neighbors`], with neighbors outside `bounds` filled like [`ChunksRefs::try_new_in_bounds`].
+    pub fn try_new_face_neighbors_in_bounds(
+        world_data: &HashMap<IVec3, Arc<ChunkData>>,
+        middle_chunk: IVec3,
+        air: BlockId,
+        bounds: Option<&WorldBounds>,
+    ) -> Result<Self, MissingNeighbor> {
+        let placeholder = Arc::new(ChunkData::Filled(BlockData::new(air)));
         let mut chunks = vec![];
         for i in 0..3 * 3 * 3 {
             let offset = index_to_ivec3_bounds(i, 3) + IVec3::splat(-1);
-            chunks.push(Arc::clone(
-                world_data.get(&(middle_chunk + offset)).unwrap(),
-            ))
+            if offset.abs().element_sum() > 1 {
+                chunks.push(placeholder.clone());
+            } else {
+                chunks.push(get_neighbor(world_data, middle_chunk + offset, bounds)?);
+            }
         }
-        Some(Self { chunks })
+        Ok(Self::new(chunks))
     }
     // returns if all the voxels are the same
     // this is an incredibly fast approximation (1 sample per chunk) all = voxels[0]
@@ -57,6 +141,23 @@ impl ChunksRefs {
         true
     }
 
+    /// Returns true if meshing the middle chunk for `flag` is guaranteed to produce no faces,
+    /// either because it has no blocks with `flag` or because it & its face neighbours are completely filled with full blocks with it.
+    pub fn is_mesh_empty(&self, registry: &BlockRegistry, flag: BlockFlags) -> bool {
+        let Some(block) = self.chunks[13].get_block_if_filled() else {
+            return false;
+        };
+        if !registry.has_flag(block.block_type, flag) {
+            return true;
+        }
+
+        registry.occludes(block.block_type, flag) && [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z].iter().all(|dir| {
+            self.chunks[vec3_to_index(IVec3::ONE + *dir, 3)]
+                .get_block_if_filled()
+                .is_some_and(|neighbor| registry.occludes(neighbor.block_type, flag))
+        })
+    }
+
     /*/// only use for testing purposes
     pub fn make_dummy_chunk_refs(seed: u64) -> ChunksRefs {
         let mut rng = ChaCha8Rng::seed_from_u64(seed);
@@ -75,7 +176,7 @@ impl ChunksRefs {
 
     /// helper function to get block data that may exceed the bounds of the middle chunk
     /// input position is local pos to middle chunk
-    pub fn get_block(&self, pos: IVec3) -> &BlockData {
+    pub fn get_block(&self, pos: IVec3) -> BlockData {
         let x = (pos.x + 32) as u32;
         let y = (pos.y + 32) as u32;
         let z = (pos.z + 32) as u32;
@@ -89,9 +190,31 @@ impl ChunksRefs {
         chunk_data.get_block(i)
     }
 
+    /// The block face culling sees at `pos`, with the canonical rule for neighbors meshed at a coarser LOD than `lod`:
+    /// a face neighbor coarser than the middle chunk is sampled at the first voxel of the coarse cell containing `pos`,
+    /// the voxel that cell is meshed from in the neighbor's own mesh, so every fine face along the border agrees with the coarse cell in front of it.
+    /// Other positions, including the edge & corner neighbors, are sampled like [`ChunksRefs::get_block`].
+    pub fn get_culling_block(&self, pos: IVec3, lod: Lod) -> BlockData {
+        let outside = pos.cmplt(IVec3::ZERO) | pos.cmpge(IVec3::splat(CHUNK_SIZE_I32));
+        if outside.bitmask().count_ones() != 1 {
+            return self.get_block(pos);
+        }
+        let axis = outside.bitmask().trailing_zeros() as usize;
+        let neighbor_lod = self.neighbor_lods[2 * axis + (pos[axis] >= 0) as usize];
+        let jump = neighbor_lod.jump_index();
+        if jump <= lod.jump_index() {
+            return self.get_block(pos);
+        }
+        self.get_block(pos.div_euclid(IVec3::splat(jump)) * jump)
+    }
+
     /// helper function to get voxels
-    /// panics if the local pos is outside the middle chunk
-    pub fn get_block_no_neighbour(&self, pos: IVec3) -> &BlockData {
+    /// the local pos must be inside the middle chunk, only checked in debug builds as it's called in hot loops.
+    pub fn get_block_no_neighbour(&self, pos: IVec3) -> BlockData {
+        debug_assert!(
+            pos.cmpge(IVec3::ZERO).all() && pos.cmplt(IVec3::splat(CHUNK_SIZE_I32)).all(),
+            "{pos} is outside the middle chunk"
+        );
         let chunk_data = &self.chunks[13];
         let i = vec3_to_index(pos, 32);
         chunk_data.get_block(i)
@@ -102,7 +225,7 @@ impl ChunksRefs {
         &self,
         pos: IVec3,
         // current back, left, down
-    ) -> (&BlockData, &BlockData, &BlockData, &BlockData) {
+    ) -> (BlockData, BlockData, BlockData, BlockData) {
         let current = self.get_block(pos);
         let back = self.get_block(pos + ivec3(0, 0, -1));
         let left = self.get_block(pos + ivec3(-1, 0, 0));
@@ -111,7 +234,7 @@ impl ChunksRefs {
     }
 
     /// helper function to sample adjacent voxels, von neuman include all facing planes
-    pub fn get_von_neumann(&self, pos: IVec3) -> [(Direction, &BlockData);6] {
+    pub fn get_von_neumann(&self, pos: IVec3) -> [(Direction, BlockData);6] {
         [
             (Direction::Back, self.get_block(pos + ivec3(0, 0, -1))),
             (Direction::Forward, self.get_block(pos + ivec3(0, 0, 1))),
@@ -122,9 +245,81 @@ impl ChunksRefs {
         ]
     }
 
-    pub fn get_2(&self, pos: IVec3, offset: IVec3) -> (&BlockData, &BlockData) {
+    pub fn get_2(&self, pos: IVec3, offset: IVec3) -> (BlockData, BlockData) {
         let first = self.get_block(pos);
         let second = self.get_block(pos + offset);
         (first, second)
     }
 }
+
+#[test]
+fn mesh_empty_chunks() {
+    use crate::voxel::{Block, BlockRegistryBuilder, BlockStringIdentifier, BlockVisibilty};
+
+    let mut registry = BlockRegistryBuilder::new();
+    let air = registry.add_block(BlockStringIdentifier(Box::from("air")), &Block { visibility: BlockVisibilty::Invisible, collision: false, ..Default::default() }).unwrap();
+    let stone = registry.add_block(BlockStringIdentifier(Box::from("stone")), &Block::default()).unwrap();
+    let registry = registry.build();
+
+    let filled = |block_type| Arc::new(ChunkData::Filled(BlockData::new(block_type)));
+    let mut chunks_refs = ChunksRefs::new((0..27).map(|_| filled(stone)).collect());
+    chunks_refs.chunks[13] = filled(air);
+    assert!(chunks_refs.chunks[13].is_fully_air(air));
+    assert!(chunks_refs.is_mesh_empty(&registry, BlockFlags::SOLID));
+
+    // Enclosed by solid chunks, the corners don't matter.
+    chunks_refs.chunks[13] = filled(stone);
+    chunks_refs.chunks[0] = filled(air);
+    assert!(chunks_refs.is_mesh_empty(&registry, BlockFlags::SOLID));
+    assert!(chunks_refs.is_mesh_empty(&registry, BlockFlags::TRANSPARENT));
+
+    chunks_refs.chunks[vec3_to_index(IVec3::new(1, 2, 1), 3)] = filled(air);
+    assert!(!chunks_refs.is_mesh_empty(&registry, BlockFlags::SOLID));
+
+    let mut voxels = vec![BlockData::new(stone); 32 * 32 * 32];
+    voxels[0].block_type = air;
+    chunks_refs.chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    assert!(!chunks_refs.is_mesh_empty(&registry, BlockFlags::SOLID));
+}
+
+#[test]
+fn try_new_reports_missing_neighbor() {
+    let mut world_data = HashMap::new();
+    for i in 0..3 * 3 * 3 {
+        world_data.insert(index_to_ivec3_bounds(i, 3), Arc::new(ChunkData::Filled(BlockData::default())));
+    }
+    assert!(ChunksRefs::try_new(&world_data, IVec3::ONE).is_ok());
+
+    world_data.remove(&ivec3(2, 2, 0));
+    assert_eq!(ChunksRefs::try_new(&world_data, IVec3::ONE).err(), Some(MissingNeighbor(ivec3(2, 2, 0))));
+    // only face neighbors are needed here.
+    assert!(ChunksRefs::try_new_face_neighbors(&world_data, IVec3::ONE, BlockId(0)).is_ok());
+}
+
+#[test]
+fn world_bounds_fill_missing_neighbors_outside() {
+    let bounds = WorldBounds { min: IVec3::ZERO, max: IVec3::splat(3), outside: BlockId(1) };
+    let mut world_data = HashMap::new();
+    for i in 0..2 * 2 * 2 {
+        world_data.insert(index_to_ivec3_bounds(i, 2), Arc::new(ChunkData::Filled(BlockData::default())));
+    }
+
+    // the corner of the world only has neighbors inside it.
+    assert!(ChunksRefs::try_new(&world_data, IVec3::ZERO).is_err());
+    let chunks_refs = ChunksRefs::try_new_in_bounds(&world_data, IVec3::ZERO, Some(&bounds)).unwrap();
+    assert_eq!(chunks_refs.get_block(ivec3(-1, 5, 5)).block_type, BlockId(1));
+    assert_eq!(chunks_refs.get_block(ivec3(32, 5, 5)).block_type, BlockId(0));
+
+    // missing chunks inside the bounds are still waited for.
+    assert_eq!(ChunksRefs::try_new_in_bounds(&world_data, IVec3::ONE, Some(&bounds)).err(), Some(MissingNeighbor(ivec3(2, 0, 0))));
+    assert!(ChunksRefs::try_new_face_neighbors_in_bounds(&world_data, IVec3::ZERO, BlockId(0), Some(&bounds)).is_ok());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn get_block_no_neighbour_rejects_neighbor_positions() {
+    let chunks = (0..3 * 3 * 3).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    // would wrap around to x = 0 of the next row in release builds.
+    ChunksRefs::new(chunks).get_block_no_neighbour(ivec3(32, 0, 0));
+}
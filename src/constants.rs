@@ -12,6 +12,11 @@ pub const CHUNK_SIZE2: usize = CHUNK_SIZE * CHUNK_SIZE;
 pub const CHUNK_SIZE2_I32: i32 = CHUNK_SIZE2 as i32;
 pub const CHUNK_SIZE3: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
 
+/// The chunk itself followed by all 26 of its neighbors, the chunks in a [`crate::chunks_refs::ChunksRefs`].
+///
+/// Meshing with ambient occlusion needs all of them, since AO samples the 3x3 voxels in front of a face.
+/// For faces along a chunk's edges & corners those cross into the edge & corner neighbors.
+/// Without AO only [`FACE_ADJACENT_CHUNK_DIRECTIONS`] are needed, face culling only looks at the voxel directly in front of a face.
 pub const ADJACENT_CHUNK_DIRECTIONS: [IVec3; 27] = [
     IVec3 { x: 0, y: 0, z: 0 },
     // moore neighbours in the negative direction
@@ -48,6 +53,9 @@ pub const ADJACENT_CHUNK_DIRECTIONS: [IVec3; 27] = [
     IVec3 { x: 0, y: 0, z: 1 },
 ];
 
+/// The 6 neighbors sharing a face with a chunk, indexed by [`crate::face_direction::FaceDir::normal_index`].
+pub const FACE_ADJACENT_CHUNK_DIRECTIONS: [IVec3; 6] = [IVec3::NEG_X, IVec3::X, IVec3::NEG_Y, IVec3::Y, IVec3::NEG_Z, IVec3::Z];
+
 pub const ADJACENT_AO_DIRS: [IVec2; 9] = [
     ivec2(-1, -1),
     ivec2(-1, 0),
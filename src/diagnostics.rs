@@ -1,7 +1,7 @@
 use bevy::{app::{App, Plugin, Startup, Update}, diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic}, ecs::system::{Res, ResMut}};
 use bevy_screen_diagnostics::{Aggregate, ScreenDiagnostics};
 
-use crate::{rendering::MeshingPipeline, voxel_engine::VoxelEngine};
+use crate::{chunk::ChunkData, chunk_mesh::MeshStats, meshing::MeshingPipeline, voxel_engine::VoxelEngine};
 
 const DIAG_LOAD_DATA_QUEUE: DiagnosticPath = DiagnosticPath::const_new("load_data_queue");
 const DIAG_UNLOAD_DATA_QUEUE: DiagnosticPath = DiagnosticPath::const_new("unload_data_queue");
@@ -10,6 +10,14 @@ const DIAG_UNLOAD_MESH_QUEUE: DiagnosticPath = DiagnosticPath::const_new("unload
 const DIAG_VERTEX_COUNT: DiagnosticPath = DiagnosticPath::const_new("vertex_count");
 const DIAG_MESH_TASKS: DiagnosticPath = DiagnosticPath::const_new("mesh_tasks");
 const DIAG_DATA_TASKS: DiagnosticPath = DiagnosticPath::const_new("data_tasks");
+const DIAG_SKIPPED_MESH_TASKS: DiagnosticPath = DiagnosticPath::const_new("skipped_mesh_tasks");
+const DIAG_DATA_MEMORY_BYTES: DiagnosticPath = DiagnosticPath::const_new("data_memory_bytes");
+const DIAG_COMPRESSED_CHUNK_COUNT: DiagnosticPath = DiagnosticPath::const_new("compressed_chunk_count");
+const DIAG_MESHES_FINALIZED: DiagnosticPath = DiagnosticPath::const_new("meshes_finalized");
+const DIAG_MERGE_RATIO: DiagnosticPath = DiagnosticPath::const_new("merge_ratio");
+const DIAG_MESH_BUILD_MICROS: DiagnosticPath = DiagnosticPath::const_new("mesh_build_micros");
+const DIAG_MESH_BLOCKED_ON_NEIGHBOR: DiagnosticPath = DiagnosticPath::const_new("mesh_blocked_on_neighbor");
+const DIAG_SATURATED_FRAMES: DiagnosticPath = DiagnosticPath::const_new("saturated_frames");
 
 pub struct VoxelDiagnosticsPlugin;
 impl Plugin for VoxelDiagnosticsPlugin {
@@ -22,6 +30,14 @@ impl Plugin for VoxelDiagnosticsPlugin {
         app.register_diagnostic(Diagnostic::new(DIAG_VERTEX_COUNT));
         app.register_diagnostic(Diagnostic::new(DIAG_MESH_TASKS));
         app.register_diagnostic(Diagnostic::new(DIAG_DATA_TASKS));
+        app.register_diagnostic(Diagnostic::new(DIAG_SKIPPED_MESH_TASKS));
+        app.register_diagnostic(Diagnostic::new(DIAG_DATA_MEMORY_BYTES));
+        app.register_diagnostic(Diagnostic::new(DIAG_COMPRESSED_CHUNK_COUNT));
+        app.register_diagnostic(Diagnostic::new(DIAG_MESHES_FINALIZED));
+        app.register_diagnostic(Diagnostic::new(DIAG_MERGE_RATIO));
+        app.register_diagnostic(Diagnostic::new(DIAG_MESH_BUILD_MICROS));
+        app.register_diagnostic(Diagnostic::new(DIAG_MESH_BLOCKED_ON_NEIGHBOR));
+        app.register_diagnostic(Diagnostic::new(DIAG_SATURATED_FRAMES));
         app.add_systems(Update, diagnostics_count);
     }
 }
@@ -55,6 +71,38 @@ fn setup_diagnostics(mut onscreen: ResMut<ScreenDiagnostics>) {
         .add("data_tasks".to_string(), DIAG_DATA_TASKS)
         .aggregate(Aggregate::Value)
         .format(|v| format!("{v:0>2.0}"));
+    onscreen
+        .add("skipped_mesh_tasks".to_string(), DIAG_SKIPPED_MESH_TASKS)
+        .aggregate(Aggregate::Value)
+        .format(|v| format!("{v:0>5.0}"));
+    onscreen
+        .add("mesh_blocked_on_neighbor".to_string(), DIAG_MESH_BLOCKED_ON_NEIGHBOR)
+        .aggregate(Aggregate::Value)
+        .format(|v| format!("{v:0>4.0}"));
+    onscreen
+        .add("saturated_frames".to_string(), DIAG_SATURATED_FRAMES)
+        .aggregate(Aggregate::Value)
+        .format(|v| format!("{v:0>4.0}"));
+    onscreen
+        .add("data_memory".to_string(), DIAG_DATA_MEMORY_BYTES)
+        .aggregate(Aggregate::Value)
+        .format(|v| format!("{:0>7.2}MiB", v / (1024.0 * 1024.0)));
+    onscreen
+        .add("compressed_chunks".to_string(), DIAG_COMPRESSED_CHUNK_COUNT)
+        .aggregate(Aggregate::Value)
+        .format(|v| format!("{v:0>4.0}"));
+    onscreen
+        .add("meshes_finalized".to_string(), DIAG_MESHES_FINALIZED)
+        .aggregate(Aggregate::Value)
+        .format(|v| format!("{v:0>3.0}"));
+    onscreen
+        .add("merge_ratio".to_string(), DIAG_MERGE_RATIO)
+        .aggregate(Aggregate::Value)
+        .format(|v| format!("{:0>5.1}%", v * 100.0));
+    onscreen
+        .add("mesh_build".to_string(), DIAG_MESH_BUILD_MICROS)
+        .aggregate(Aggregate::Value)
+        .format(|v| format!("{v:0>5.0}us"));
 }
 
 fn diagnostics_count(mut diagnostics: Diagnostics, voxel_engine: Res<VoxelEngine>, mesh_pipeline: Res<MeshingPipeline>) {
@@ -72,6 +120,30 @@ fn diagnostics_count(mut diagnostics: Diagnostics, voxel_engine: Res<VoxelEngine
     });
     diagnostics.add_measurement(&DIAG_MESH_TASKS, || mesh_pipeline.mesh_tasks.len() as f64);
     diagnostics.add_measurement(&DIAG_DATA_TASKS, || voxel_engine.data_tasks.len() as f64);
+    diagnostics.add_measurement(&DIAG_MESHES_FINALIZED, || mesh_pipeline.meshes_finalized as f64);
+    diagnostics.add_measurement(&DIAG_SKIPPED_MESH_TASKS, || mesh_pipeline.skipped_mesh_tasks as f64);
+    diagnostics.add_measurement(&DIAG_MESH_BLOCKED_ON_NEIGHBOR, || mesh_pipeline.mesh_blocked_on_neighbor as f64);
+    diagnostics.add_measurement(&DIAG_SATURATED_FRAMES, || voxel_engine.saturated_frames() as f64);
+    diagnostics.add_measurement(&DIAG_DATA_MEMORY_BYTES, || {
+        voxel_engine
+            .world_data
+            .values()
+            .map(|chunk| chunk.memory_usage())
+            .sum::<usize>() as f64
+    });
+    diagnostics.add_measurement(&DIAG_COMPRESSED_CHUNK_COUNT, || {
+        voxel_engine
+            .world_data
+            .values()
+            .filter(|chunk| !matches!(chunk.as_ref(), ChunkData::Dense(_)))
+            .count() as f64
+    });
+    // Over all meshed chunks, so it reflects the whole visible world.
+    let total_stats = || mesh_pipeline.mesh_stats.values().copied().fold(MeshStats::default(), MeshStats::combine);
+    diagnostics.add_measurement(&DIAG_MERGE_RATIO, || total_stats().merge_ratio as f64);
+    diagnostics.add_measurement(&DIAG_MESH_BUILD_MICROS, || {
+        total_stats().build_micros as f64 / mesh_pipeline.mesh_stats.len().max(1) as f64
+    });
     diagnostics.add_measurement(&DIAG_VERTEX_COUNT, || {
         mesh_pipeline
             .vertex_diagnostic
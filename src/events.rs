@@ -1,11 +1,19 @@
 use bevy::{app::{App, Plugin}, ecs::event::Event, math::IVec3};
 
+use crate::{chunk_mesh::ChunkMesh, lod::Lod, surface_nets::SmoothMesh, voxel::BlockId, voxel_engine::Prewarm};
+
 pub struct ChunkEventsPlugin;
 impl Plugin for ChunkEventsPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<ChunkGenerated>()
             .add_event::<ChunkUnloaded>()
-            .add_event::<ChunkModified>();
+            .add_event::<ChunkModified>()
+            .add_event::<ChunkVoxelsModified>()
+            .add_event::<ChunkBlocksChanged>()
+            .add_event::<ChunkMeshed>()
+            .add_event::<ChunkMeshUnloaded>()
+            .add_event::<VoxelEngineSaturated>()
+            .add_event::<PrewarmCompleted>();
     }
 }
 
@@ -17,6 +25,59 @@ pub struct ChunkGenerated(pub IVec3);
 #[derive(Event)]
 pub struct ChunkUnloaded(pub IVec3);
 
-/// Fired when a chunk is modified
+/// Fired when a chunk needs to be remeshed because it or a neighbor was modified.
+/// See [`ChunkBlocksChanged`] for reacting to the blocks that actually changed.
+#[derive(Event)]
+pub struct ChunkModified(pub IVec3);
+
+/// Fired alongside [`ChunkModified`] with the positions that changed, local to `chunk`.
+/// Positions may lie in the one voxel padding around the chunk (-1 or 32) when a neighbor was modified.
+/// `voxels` is empty if the whole chunk should be considered changed, such as when it or a neighbor was filled.
+#[derive(Event)]
+pub struct ChunkVoxelsModified {
+    pub chunk: IVec3,
+    pub voxels: Vec<IVec3>,
+}
+
+/// Fired when blocks of a chunk were replaced by a different type of block, unlike [`ChunkModified`] it isn't sent for neighbors.
+/// Changes to only the state of a block aren't included.
+#[derive(Event)]
+pub struct ChunkBlocksChanged {
+    pub pos: IVec3,
+    /// Chunk local position, old & new block, in the order they were changed.
+    pub changed: Vec<(IVec3, BlockId, BlockId)>,
+}
+
+/// Fired when a chunk has been (re)meshed.
+/// Meshes are left out if they weren't requested or ended up empty.
+///
+/// The rendering plugin takes the `layers` meshes out of the event,
+/// use an `EventMutator` that runs before it if you want those.
+#[derive(Event)]
+pub struct ChunkMeshed {
+    pub chunk: IVec3,
+    /// Meshes of the [`crate::meshing::ChunkRenderLayers`], with the index of their layer.
+    pub layers: Vec<(usize, ChunkMesh)>,
+    pub collision: Option<ChunkMesh>,
+    /// Surface of the chunk with [`crate::voxel_engine::MeshingMethod::SmoothDensity`], taken out by the rendering plugin like `layers`.
+    pub smooth: Option<SmoothMesh>,
+    /// LOD the chunk was meshed at.
+    pub lod: Lod,
+}
+
+/// Fired when a chunk's mesh is no longer wanted by any mesh scanner.
+#[derive(Event)]
+pub struct ChunkMeshUnloaded(pub IVec3);
+
+/// Fired when generation has been at its task limit for [`crate::voxel_engine::VoxelEngineConfig::saturation_frames`] frames in a row.
+/// Sent once each time it becomes saturated, see [`crate::voxel_engine::VoxelEngine::is_saturated`].
+#[derive(Event)]
+pub struct VoxelEngineSaturated {
+    /// Chunks waiting to be generated.
+    pub queued: usize,
+}
+
+/// Fired once the chunks of a [`crate::voxel_engine::VoxelEngine::prewarm`] region are all meshed,
+/// or all loaded if there's no [`crate::meshing::MeshingPipeline`].
 #[derive(Event)]
-pub struct ChunkModified(pub IVec3);
\ No newline at end of file
+pub struct PrewarmCompleted(pub Prewarm);
@@ -13,6 +13,19 @@ pub enum FaceDir {
 }
 
 impl FaceDir {
+    pub const ALL: [FaceDir; 6] = [FaceDir::Up, FaceDir::Down, FaceDir::Left, FaceDir::Right, FaceDir::Forward, FaceDir::Back];
+
+    pub fn opposite(&self) -> FaceDir {
+        match self {
+            FaceDir::Up => FaceDir::Down,
+            FaceDir::Down => FaceDir::Up,
+            FaceDir::Left => FaceDir::Right,
+            FaceDir::Right => FaceDir::Left,
+            FaceDir::Forward => FaceDir::Back,
+            FaceDir::Back => FaceDir::Forward,
+        }
+    }
+
     /// normal data is packed in the shader
     pub fn normal_index(&self) -> u32 {
         match self {
@@ -25,6 +38,19 @@ impl FaceDir {
         }
     }
 
+    /// Inverse of [`FaceDir::normal_index`], `None` for the normals of cross quads.
+    pub fn from_normal_index(normal_index: u32) -> Option<FaceDir> {
+        match normal_index {
+            0 => Some(FaceDir::Left),
+            1 => Some(FaceDir::Right),
+            2 => Some(FaceDir::Down),
+            3 => Some(FaceDir::Up),
+            4 => Some(FaceDir::Forward),
+            5 => Some(FaceDir::Back),
+            _ => None,
+        }
+    }
+
     /// direction to sample face culling
     pub fn air_sample_dir(&self) -> IVec3 {
         match self {
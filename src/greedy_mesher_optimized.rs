@@ -1,24 +1,118 @@
 use std::{
-    collections::VecDeque, sync::Arc
+    cell::RefCell, ops::Range, sync::Arc, thread::LocalKey
 };
 
-use bevy::{math::ivec3, prelude::*, utils::HashMap};
+use bevy::{math::ivec3, prelude::*, utils::{FixedState, Instant}};
+use indexmap::IndexMap;
 
 use crate::{
-    chunk_mesh::ChunkMesh,
+    chunk_mesh::{ChunkMesh, ChunkMeshSlices, DirtySlices, MeshStats},
     chunks_refs::ChunksRefs,
-    constants::{ADJACENT_AO_DIRS, CHUNK_SIZE, CHUNK_SIZE3, CHUNK_SIZE_P},
+    constants::{ADJACENT_AO_DIRS, CHUNK_SIZE, CHUNK_SIZE3, CHUNK_SIZE_I32, CHUNK_SIZE_P},
     face_direction::FaceDir,
     lod::Lod,
-    utils::{generate_indices, make_vertex_u32, vec3_to_index}, voxel::{BlockFlags, BlockRegistry},
+    quad::Quad,
+    utils::{get_pos_from_vertex_u32, make_vertex_u32, CROSS_NORMAL_INDICES, vec3_to_index, VERTEX_HALF_DOWN_BIT}, voxel::{BlockFlags, BlockId, BlockRegistry, BlockShape},
 };
 
 /// Builds a greedy mesh
 /// `flag_to_build`
-pub fn build_chunk_mesh(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<BlockRegistry>, flag_to_build: BlockFlags, calculate_ao: bool, ignore_block_type: bool) -> Option<ChunkMesh> {
-    // early exit, if all faces are culled
-    if chunks_refs.is_all_voxels_same() {
-        return None;
+/// `generate_skirts` adds skirts along borders with coarser neighbours, see [`ChunksRefs::neighbor_lods`].
+/// `y_range` only meshes the chunk local layers in the range, capping the surfaces at its ends as if everything outside of it was air.
+/// Vertex positions stay chunk local, so they pack the same as without a range.
+#[allow(clippy::too_many_arguments)]
+pub fn build_chunk_mesh(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<BlockRegistry>, flag_to_build: BlockFlags, calculate_ao: bool, ignore_block_type: bool, generate_skirts: bool, y_range: Option<Range<i32>>) -> Option<ChunkMesh> {
+    with_scratch(&SCRATCH_SLICES, |slices| {
+        build_chunk_mesh_slices_into(slices, chunks_refs, lod, block_registry, flag_to_build, calculate_ao, ignore_block_type, generate_skirts, &DirtySlices::ALL, y_range, PARALLEL_MESHING_MIN_FACES);
+        let mesh = slices.to_chunk_mesh();
+        slices.clear();
+        mesh
+    })
+}
+
+/// Like [`build_chunk_mesh`] but with the quads unpacked into plain [`Quad`]s, in the same order as the mesh's.
+#[allow(clippy::too_many_arguments)]
+pub fn build_chunk_quads(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<BlockRegistry>, flag_to_build: BlockFlags, calculate_ao: bool, ignore_block_type: bool, generate_skirts: bool, y_range: Option<Range<i32>>) -> Vec<Quad> {
+    build_chunk_mesh(chunks_refs, lod, block_registry, flag_to_build, calculate_ao, ignore_block_type, generate_skirts, y_range)
+        .map_or_else(Vec::new, |mesh| mesh.quads().collect())
+}
+
+/// Like [`build_chunk_mesh`], also measuring how well the faces were merged & how long it took.
+#[allow(clippy::too_many_arguments)]
+pub fn build_chunk_mesh_with_stats(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<BlockRegistry>, flag_to_build: BlockFlags, calculate_ao: bool, ignore_block_type: bool, generate_skirts: bool, y_range: Option<Range<i32>>) -> (Option<ChunkMesh>, MeshStats) {
+    let start = Instant::now();
+    let mesh = build_chunk_mesh(chunks_refs, lod, block_registry, flag_to_build, calculate_ao, ignore_block_type, generate_skirts, y_range);
+    let build_micros = start.elapsed().as_micros() as u64;
+
+    let stats = MeshStats::from_vertices(mesh.as_ref().map_or(&[], |mesh| mesh.vertices.as_slice()), build_micros);
+    (mesh, stats)
+}
+
+/// Rebuilds only the `dirty` slices of an existing mesh, leaving the other slices untouched.
+#[allow(clippy::too_many_arguments)]
+pub fn rebuild_chunk_mesh_slices(mesh: &mut ChunkMeshSlices, chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<BlockRegistry>, flag_to_build: BlockFlags, calculate_ao: bool, ignore_block_type: bool, generate_skirts: bool, dirty: &DirtySlices) {
+    with_scratch(&SCRATCH_SLICES, |rebuilt| {
+        build_chunk_mesh_slices_into(rebuilt, chunks_refs, lod, block_registry, flag_to_build, calculate_ao, ignore_block_type, generate_skirts, dirty, None, PARALLEL_MESHING_MIN_FACES);
+        // the replaced slices go back to the pool.
+        mesh.swap(rebuilt, dirty);
+        rebuilt.clear();
+    });
+}
+
+thread_local! {
+    /// Slices meshes are built into before they're flattened or swapped into an existing mesh.
+    static SCRATCH_SLICES: RefCell<Vec<ChunkMeshSlices>> = const { RefCell::new(Vec::new()) };
+    /// Binary planes & quads of a face direction, see [`mesh_face_direction`].
+    static SCRATCH_PLANES: RefCell<Vec<PlaneScratch>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Default)]
+struct PlaneScratch {
+    /// key(block + ao + light, slice) -> binary plane.
+    /// Iterated in insertion order, so the vertices don't depend on how far the reused map has grown.
+    planes: IndexMap<(u32, u32), [u32; 32], FixedState>,
+    quads: Vec<GreedyQuad>,
+}
+
+/// Lends `f` a buffer from the thread's `pool`, so meshing many chunks in a row doesn't allocate every buffer anew.
+/// `f` has to leave the buffer empty, it keeps its capacity for the next mesh built on the thread.
+///
+/// Meshes are built on the task pool's threads, each of them keeps its own buffers, as big as the largest mesh it has built.
+/// The pool is a stack since meshes can be built within each other, such as the face directions with `parallel_meshing`.
+fn with_scratch<T: Default + 'static, R>(pool: &'static LocalKey<RefCell<Vec<T>>>, f: impl FnOnce(&mut T) -> R) -> R {
+    let mut scratch = pool.with_borrow_mut(Vec::pop).unwrap_or_default();
+    let result = f(&mut scratch);
+    pool.with_borrow_mut(|pool| pool.push(scratch));
+    result
+}
+
+/// With the `parallel_meshing` feature, chunks with at least this many faces mesh their 6 face directions in parallel.
+/// Below it spreading the work over threads costs more than it saves, compare the `meshing` bench with & without the feature.
+/// Chunks are already meshed in parallel with each other, so this mostly helps when few chunks are remeshed at once.
+pub const PARALLEL_MESHING_MIN_FACES: u32 = 1024;
+
+/// Builds a greedy mesh of the given `slices`, keeping the vertices of each slice separate.
+/// Slices which aren't included are left empty.
+/// See [`build_chunk_mesh`] for `y_range`.
+#[allow(clippy::too_many_arguments)]
+pub fn build_chunk_mesh_slices(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<BlockRegistry>, flag_to_build: BlockFlags, calculate_ao: bool, ignore_block_type: bool, generate_skirts: bool, slices: &DirtySlices, y_range: Option<Range<i32>>) -> ChunkMeshSlices {
+    let mut mesh = ChunkMeshSlices::default();
+    build_chunk_mesh_slices_into(&mut mesh, chunks_refs, lod, block_registry, flag_to_build, calculate_ao, ignore_block_type, generate_skirts, slices, y_range, PARALLEL_MESHING_MIN_FACES);
+    mesh
+}
+
+/// [`build_chunk_mesh_slices`] into the empty `mesh`, with a different threshold than [`PARALLEL_MESHING_MIN_FACES`].
+#[allow(clippy::too_many_arguments)]
+#[cfg_attr(not(feature = "parallel_meshing"), allow(unused_variables))]
+fn build_chunk_mesh_slices_into(mesh: &mut ChunkMeshSlices, chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<BlockRegistry>, flag_to_build: BlockFlags, calculate_ao: bool, ignore_block_type: bool, generate_skirts: bool, slices: &DirtySlices, y_range: Option<Range<i32>>, parallel_min_faces: u32) {
+    let y_range = y_range
+        .map(|range| range.start.clamp(0, CHUNK_SIZE_I32)..range.end.clamp(0, CHUNK_SIZE_I32))
+        .filter(|range| *range != (0..CHUNK_SIZE_I32));
+
+    // early exit for uniform chunks without faces, such as sky or deep underground, skipping every per voxel loop.
+    // a range cuts through the chunk, leaving caps.
+    if y_range.is_none() && chunks_refs.is_mesh_empty(&block_registry, flag_to_build) {
+        return;
     }
     
     /*  When we ignore block type:
@@ -29,8 +123,6 @@ pub fn build_chunk_mesh(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<
      */
     let ignore_block_type_mask = -(!ignore_block_type as i32) as u32;
 
-    let mut mesh = ChunkMesh::default();
-
     // solid binary for each x,y,z axis (3)
     let mut axis_cols = [[[0u64; CHUNK_SIZE_P]; CHUNK_SIZE_P]; 3];
 
@@ -47,7 +139,8 @@ pub fn build_chunk_mesh(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<
         block_registry: &Arc<BlockRegistry>,
         flag: BlockFlags
     ) {
-        if block_registry.has_flag(b.block_type, flag) {
+        // only full blocks & fluids are greedy meshed & hide their neighbors
+        if block_registry.is_greedy_meshed(b.block_type, flag) {
             // x,z - y axis
             axis_cols[0][z][x] |= 1u64 << y as u64;
             // z,y - x axis
@@ -58,21 +151,35 @@ pub fn build_chunk_mesh(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<
     }
 
     // inner chunk voxels.
+    // a whole row along x is tested at once, which directly gives the x axis column.
     let chunk = &*chunks_refs.chunks[vec3_to_index(IVec3::new(1, 1, 1), 3)];
-    assert!(chunk.voxels.len() == CHUNK_SIZE3 || chunk.voxels.len() == 1);
+    let occlusion = block_registry.occlusion_table(flag_to_build);
+    let filled_row_mask = chunk.get_block_if_filled().map(|block| {
+        if occlusion.occludes[block.block_type.0 as usize] { u32::MAX } else { 0 }
+    });
+    let mut rows = chunk.rows();
     for z in 0..CHUNK_SIZE {
         for y in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                let i = match chunk.voxels.len() {
-                    1 => 0,
-                    _ => (z * CHUNK_SIZE + y) * CHUNK_SIZE + x,
-                };
-                add_voxel_to_axis_cols(&chunk.voxels[i], x + 1, y + 1, z + 1, &mut axis_cols, &block_registry, flag_to_build);
+            let row_mask = match filled_row_mask {
+                Some(row_mask) => row_mask,
+                None => solid_row_mask(&rows.next().unwrap(), &occlusion),
+            };
+            // z,y - x axis
+            axis_cols[1][y + 1][z + 1] |= (row_mask as u64) << 1;
+
+            let mut row = row_mask;
+            while row != 0 {
+                let x = row.trailing_zeros() as usize;
+                row &= row - 1;
+                // x,z - y axis
+                axis_cols[0][z + 1][x + 1] |= 1u64 << (y + 1);
+                // x,y - z axis
+                axis_cols[2][y + 1][x + 1] |= 1u64 << (z + 1);
             }
         }
     }
 
-    // neighbor chunk voxels.
+    // neighbor chunk voxels, coarser neighbors by cell.
     // note(leddoo): couldn't be bothered to optimize these.
     //  might be worth it though. together, they take
     //  almost as long as the entire "inner chunk" loop.
@@ -80,7 +187,7 @@ pub fn build_chunk_mesh(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<
         for y in 0..CHUNK_SIZE_P {
             for x in 0..CHUNK_SIZE_P {
                 let pos = ivec3(x as i32, y as i32, z as i32) - IVec3::ONE;
-                add_voxel_to_axis_cols(chunks_refs.get_block(pos), x, y, z, &mut axis_cols, &block_registry, flag_to_build);
+                add_voxel_to_axis_cols(&chunks_refs.get_culling_block(pos, lod), x, y, z, &mut axis_cols, &block_registry, flag_to_build);
             }
         }
     }
@@ -88,7 +195,7 @@ pub fn build_chunk_mesh(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<
         for y in [0, CHUNK_SIZE_P - 1] {
             for x in 0..CHUNK_SIZE_P {
                 let pos = ivec3(x as i32, y as i32, z as i32) - IVec3::ONE;
-                add_voxel_to_axis_cols(chunks_refs.get_block(pos), x, y, z, &mut axis_cols, &block_registry, flag_to_build);
+                add_voxel_to_axis_cols(&chunks_refs.get_culling_block(pos, lod), x, y, z, &mut axis_cols, &block_registry, flag_to_build);
             }
         }
     }
@@ -96,11 +203,24 @@ pub fn build_chunk_mesh(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<
         for x in [0, CHUNK_SIZE_P - 1] {
             for y in 0..CHUNK_SIZE_P {
                 let pos = ivec3(x as i32, y as i32, z as i32) - IVec3::ONE;
-                add_voxel_to_axis_cols(chunks_refs.get_block(pos), x, y, z, &mut axis_cols, &block_registry, flag_to_build);
+                add_voxel_to_axis_cols(&chunks_refs.get_culling_block(pos, lod), x, y, z, &mut axis_cols, &block_registry, flag_to_build);
             }
         }
     }
 
+    // clear the layers outside of the range, the neighbor's padding is kept.
+    if let Some(range) = &y_range {
+        let range_bits = (((1u64 << range.len()) - 1) << range.start) << 1;
+        let keep = !((u32::MAX as u64) << 1) | range_bits;
+        for col in axis_cols[0].iter_mut().flatten() {
+            *col &= keep;
+        }
+        for y in (0..CHUNK_SIZE_I32).filter(|y| !range.contains(y)) {
+            axis_cols[1][y as usize + 1] = [0; CHUNK_SIZE_P];
+            axis_cols[2][y as usize + 1] = [0; CHUNK_SIZE_P];
+        }
+    }
+
     // face culling
     for axis in 0..3 {
         for z in 0..CHUNK_SIZE_P {
@@ -116,112 +236,491 @@ pub fn build_chunk_mesh(chunks_refs: &ChunksRefs, lod: Lod, block_registry: Arc<
         }
     }
 
-    // greedy meshing planes for every axis (6)
-    // key(block + ao) -> HashMap<axis(0-32), binary_plane>
-    // note(leddoo): don't ask me how this isn't a massive blottleneck.
-    //  might become an issue in the future, when there are more block types.
-    //  consider using a single hashmap with key (axis, block_hash, y).
-    let mut data: [HashMap<u32, HashMap<u32, [u32; 32]>>; 6];
-    data = [
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-    ];
+    // faces between two greedy meshed blocks are up to the cull rule.
+    // the standard one only adds faces around transparent blocks & fluids, which the masks above are skipped for without them.
+    let transparent = flag_to_build.contains(BlockFlags::TRANSPARENT);
+    let is_fluid = |block_type: BlockId| matches!(block_registry.shape(block_type), BlockShape::Fluid { .. });
+    let any_fluid = (0..block_registry.block_flags.len()).any(|id| occlusion.occludes[id] && is_fluid(BlockId(id as u16)));
+    if ignore_block_type_mask != 0 {
+        if let Some(rule) = &chunks_refs.face_cull_rule {
+            add_faces_between_blocks(&axis_cols, &mut col_face_masks, chunks_refs, lod, |current, neighbor| rule.emit_face(current, neighbor, &block_registry));
+        } else if (transparent || any_fluid) && occlusion.occludes.iter().filter(|occludes| **occludes).count() > 1 {
+            add_faces_between_blocks(&axis_cols, &mut col_face_masks, chunks_refs, lod, |current, neighbor| StandardCull.emit_face(current, neighbor, &block_registry));
+        }
+    }
+
+    // every face direction is meshed independently, the slices are laid out in the same order as the mesh's.
+    let mesh_direction = |(axis, slice_vertices): (usize, &mut [Vec<u32>])| {
+        with_scratch(&SCRATCH_PLANES, |scratch| {
+            mesh_face_direction(axis, slice_vertices, scratch, &col_face_masks[axis], chunks_refs, &block_registry, calculate_ao, ignore_block_type_mask, slices, &y_range, lod)
+        })
+    };
+    #[cfg(feature = "parallel_meshing")]
+    if count_faces(&col_face_masks) >= parallel_min_faces {
+        use rayon::prelude::*;
+        mesh.vertices.par_chunks_mut(CHUNK_SIZE).enumerate().for_each(mesh_direction);
+    } else {
+        mesh.vertices.chunks_mut(CHUNK_SIZE).enumerate().for_each(mesh_direction);
+    }
+    #[cfg(not(feature = "parallel_meshing"))]
+    mesh.vertices.chunks_mut(CHUNK_SIZE).enumerate().for_each(mesh_direction);
+
+    if generate_skirts {
+        append_lod_skirts(mesh, chunks_refs, lod, &col_face_masks, ignore_block_type_mask, slices, &y_range);
+    }
+
+    append_partial_blocks(mesh, chunks_refs, &block_registry, flag_to_build, ignore_block_type_mask, slices, &y_range);
+}
+
+/// Adds the faces between neighboring blocks in `axis_cols` for which `emit_face(current, neighbor)` returns true,
+/// checked for each of the two blocks.
+fn add_faces_between_blocks(
+    axis_cols: &[[[u64; CHUNK_SIZE_P]; CHUNK_SIZE_P]; 3],
+    col_face_masks: &mut [[[u64; CHUNK_SIZE_P]; CHUNK_SIZE_P]; 6],
+    chunks_refs: &ChunksRefs,
+    lod: Lod,
+    emit_face: impl Fn(BlockId, BlockId) -> bool,
+) {
+    for axis in 0..3 {
+        for i in 1..=CHUNK_SIZE {
+            for j in 1..=CHUNK_SIZE {
+                let col = axis_cols[axis][i][j];
+                // set where both the voxel & the next one along the axis are in the column
+                let mut pairs = col & (col >> 1);
+                while pairs != 0 {
+                    let k = pairs.trailing_zeros() as usize;
+                    pairs &= pairs - 1;
+
+                    // padded position of the voxel, in the same layout as axis_cols
+                    let pos = |k: usize| match axis {
+                        0 => ivec3(j as i32, k as i32, i as i32),
+                        1 => ivec3(k as i32, i as i32, j as i32),
+                        _ => ivec3(j as i32, i as i32, k as i32),
+                    } - IVec3::ONE;
+                    let (a, b) = (chunks_refs.get_culling_block(pos(k), lod).block_type, chunks_refs.get_culling_block(pos(k + 1), lod).block_type);
+                    // ascending face of the first, descending face of the second
+                    if emit_face(a, b) {
+                        col_face_masks[2 * axis + 1][i][j] |= 1u64 << k;
+                    }
+                    if emit_face(b, a) {
+                        col_face_masks[2 * axis][i][j] |= 1u64 << (k + 1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Decides which faces between two neighboring blocks are meshed, see [`crate::chunks_refs::ChunksRefs::face_cull_rule`].
+///
+/// Only asked about blocks that are both greedy meshed in the mesh being built, see [`BlockRegistry::is_greedy_meshed`].
+/// Faces towards any other block, such as air or slabs, are always meshed.
+pub trait FaceCullRule: Send + Sync {
+    /// Whether the face of `current` towards `neighbor` is meshed.
+    fn emit_face(&self, current: BlockId, neighbor: BlockId, registry: &BlockRegistry) -> bool;
+}
+
+/// The default culling, blocks hide the faces of their neighbors except for transparent blocks of another kind,
+/// so water is seen through glass & the other way around, & fluids, which don't fill their block.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StandardCull;
+
+impl FaceCullRule for StandardCull {
+    fn emit_face(&self, current: BlockId, neighbor: BlockId, registry: &BlockRegistry) -> bool {
+        let is_fluid = |block_type: BlockId| matches!(registry.shape(block_type), BlockShape::Fluid { .. });
+        current != neighbor && (registry.has_flag(current, BlockFlags::TRANSPARENT) || is_fluid(current) || is_fluid(neighbor))
+    }
+}
+
+/// Greedy meshes the faces in `face_masks` facing one direction (`axis`, in the order of [`ChunkMeshSlices`]).
+/// Appends the vertices of each of its slices to `slice_vertices`, `scratch` is left empty.
+#[allow(clippy::too_many_arguments)]
+fn mesh_face_direction(
+    axis: usize,
+    slice_vertices: &mut [Vec<u32>],
+    scratch: &mut PlaneScratch,
+    face_masks: &[[u64; CHUNK_SIZE_P]; CHUNK_SIZE_P],
+    chunks_refs: &ChunksRefs,
+    block_registry: &BlockRegistry,
+    calculate_ao: bool,
+    ignore_block_type_mask: u32,
+    slices: &DirtySlices,
+    y_range: &Option<Range<i32>>,
+    lod: Lod,
+) {
+    let PlaneScratch { planes: data, quads } = scratch;
+    // greedy meshing planes for this direction
+    // key(block + ao, axis(0-32)) -> binary_plane
+
+    let facedir = match axis {
+        0 => FaceDir::Down,
+        1 => FaceDir::Up,
+        2 => FaceDir::Left,
+        3 => FaceDir::Right,
+        4 => FaceDir::Forward,
+        _ => FaceDir::Back,
+    };
+    // collision meshes aren't lit.
+    let block_light = chunks_refs.block_light.as_ref().filter(|_| ignore_block_type_mask != 0);
+    let merge_order = chunks_refs.merge_orders[facedir.normal_index() as usize];
 
     // find faces and build binary planes based on the voxel block+ao etc...
-    for axis in 0..6 {
-        for z in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                // skip padded by adding 1(for x padding) and (z+1) for (z padding)
-                let mut col = col_face_masks[axis][z + 1][x + 1];
-
-                // removes the right most padding value, because it's invalid
-                col >>= 1;
-                // removes the left most padding value, because it's invalid
-                col &= !(1 << CHUNK_SIZE as u64);
-
-                while col != 0 {
-                    let y = col.trailing_zeros();
-                    // clear least significant set bit
-                    col &= col - 1;
-
-                    // get the voxel position based on axis
-                    let voxel_pos = match axis {
-                        0 | 1 => ivec3(x as i32, y as i32, z as i32), // down,up
-                        2 | 3 => ivec3(y as i32, z as i32, x as i32), // left, right
-                        _ => ivec3(x as i32, z as i32, y as i32),     // forward, back
-                    };
-
-                    // calculate ambient occlusion
-                    let mut ao_index = 0;
-                    if calculate_ao {
-                        for (ao_i, ao_offset) in ADJACENT_AO_DIRS.iter().enumerate() {
-                            // ambient occlusion is sampled based on axis(ascent or descent)
-                            let ao_sample_offset = match axis {
-                                0 => ivec3(ao_offset.x, -1, ao_offset.y), // down
-                                1 => ivec3(ao_offset.x, 1, ao_offset.y),  // up
-                                2 => ivec3(-1, ao_offset.y, ao_offset.x), // left
-                                3 => ivec3(1, ao_offset.y, ao_offset.x),  // right
-                                4 => ivec3(ao_offset.x, ao_offset.y, -1), // forward
-                                _ => ivec3(ao_offset.x, ao_offset.y, 1),  // back
-                            };
-                            let ao_voxel_pos = voxel_pos + ao_sample_offset;
-                            let ao_block = chunks_refs.get_block(ao_voxel_pos);
-                            if block_registry.is_solid(ao_block.block_type) {
-                                ao_index |= 1u32 << ao_i;
-                            }
+    for z in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            // skip padded by adding 1(for x padding) and (z+1) for (z padding)
+            let mut col = face_masks[z + 1][x + 1];
+
+            // removes the right most padding value, because it's invalid
+            col >>= 1;
+            // removes the left most padding value, because it's invalid
+            col &= !(1 << CHUNK_SIZE as u64);
+            // only keep the slices we are building
+            col &= slices.0[axis / 2] as u64;
+
+            while col != 0 {
+                let y = col.trailing_zeros();
+                // clear least significant set bit
+                col &= col - 1;
+
+                // get the voxel position based on axis
+                let voxel_pos = match axis {
+                    0 | 1 => ivec3(x as i32, y as i32, z as i32), // down,up
+                    2 | 3 => ivec3(y as i32, z as i32, x as i32), // left, right
+                    _ => ivec3(x as i32, z as i32, y as i32),     // forward, back
+                };
+
+                // calculate ambient occlusion
+                let mut ao_index = 0;
+                if calculate_ao {
+                    for (ao_i, ao_offset) in ADJACENT_AO_DIRS.iter().enumerate() {
+                        // ambient occlusion is sampled based on axis(ascent or descent)
+                        let ao_sample_offset = match axis {
+                            0 => ivec3(ao_offset.x, -1, ao_offset.y), // down
+                            1 => ivec3(ao_offset.x, 1, ao_offset.y),  // up
+                            2 => ivec3(-1, ao_offset.y, ao_offset.x), // left
+                            3 => ivec3(1, ao_offset.y, ao_offset.x),  // right
+                            4 => ivec3(ao_offset.x, ao_offset.y, -1), // forward
+                            _ => ivec3(ao_offset.x, ao_offset.y, 1),  // back
+                        };
+                        let ao_voxel_pos = voxel_pos + ao_sample_offset;
+                        let ao_block = chunks_refs.get_block(ao_voxel_pos);
+                        if block_registry.is_solid(ao_block.block_type) && in_y_range(y_range, ao_voxel_pos.y) {
+                            ao_index |= 1u32 << ao_i;
                         }
                     }
+                }
 
-                    let current_voxel = chunks_refs.get_block_no_neighbour(voxel_pos);
+                let current_voxel = chunks_refs.get_block_no_neighbour(voxel_pos);
 
-                    // we can only greedy mesh same block types + same ambient occlusion
+                // we can only greedy mesh same block types + same ambient occlusion + same light
 
-                    let block_type = current_voxel.block_type.0 as u32 & ignore_block_type_mask;
-                    let block_hash = ao_index | (block_type << 9);
-                    let data = data[axis]
-                        .entry(block_hash)
-                        .or_default()
-                        .entry(y)
-                        .or_default();
-                    data[x] |= 1u32 << z as u32;
+                let block_type = current_voxel.block_type.0 as u32 & ignore_block_type_mask;
+
+                // blocks that tile their texture per block get a face each, leaving a gap in the plane.
+                if ignore_block_type_mask != 0 && block_registry.has_flag(current_voxel.block_type, BlockFlags::NO_GREEDY_MERGE) {
+                    let vertices = &mut slice_vertices[y as usize];
+                    let start = vertices.len();
+                    GreedyQuad { x: x as u32, y: z as u32, w: 1, h: 1 }
+                        .append_vertices(vertices, facedir, y, &Lod::L32, ao_index, block_type);
+                    if block_registry.shape(current_voxel.block_type).is_lowered_fluid() {
+                        lower_fluid_tops(&mut vertices[start..], facedir);
+                    }
+                    continue;
                 }
+
+                let light = block_light.map_or(0, |light| light.get(voxel_pos + facedir.air_sample_dir()) as u32);
+                let block_hash = ao_index | (block_type << 9) | (light << 17);
+                let data = data.entry((block_hash, y)).or_default();
+                data[x] |= 1u32 << z as u32;
+            }
+        }
+    }
+
+    for ((block_ao, axis_pos), plane) in data.drain(..) {
+        let ao = block_ao & 0b111111111;
+        // the light is only part of the key, it's looked up again by `ChunkMesh::apply_block_light`
+        let block_type = (block_ao >> 9) & 0xff;
+        // without block types fluids are meshed as full blocks.
+        let lowered_fluid = ignore_block_type_mask != 0 && block_registry.shape(BlockId(block_type as u16)).is_lowered_fluid();
+        greedy_mesh_binary_plane_into(plane, lod.size() as u32, merge_order, quads);
+
+        let vertices = &mut slice_vertices[axis_pos as usize];
+        let start = vertices.len();
+        quads.drain(..).for_each(|q| {
+            q.append_vertices(vertices, facedir, axis_pos, &Lod::L32, ao, block_type)
+        });
+        if lowered_fluid {
+            lower_fluid_tops(&mut vertices[start..], facedir);
+        }
+    }
+}
+
+/// Lowers the top edge of fluid quads to the fluid's level, see [`BlockShape::Fluid`].
+/// Top faces are lowered entirely & side faces are shortened, bottom faces stay where they are.
+fn lower_fluid_tops(vertices: &mut [u32], facedir: FaceDir) {
+    if facedir == FaceDir::Down {
+        return;
+    }
+    for quad in vertices.chunks_exact_mut(4) {
+        let top = quad.iter().map(|vertex| get_pos_from_vertex_u32(*vertex).y).max().unwrap();
+        for vertex in quad {
+            if get_pos_from_vertex_u32(*vertex).y == top {
+                *vertex |= VERTEX_HALF_DOWN_BIT;
             }
         }
     }
+}
+
+/// Number of faces in the face masks, without the padding.
+#[cfg(feature = "parallel_meshing")]
+fn count_faces(col_face_masks: &[[[u64; CHUNK_SIZE_P]; CHUNK_SIZE_P]; 6]) -> u32 {
+    col_face_masks.iter()
+        .flat_map(|masks| masks[1..=CHUNK_SIZE].iter().flat_map(|row| &row[1..=CHUNK_SIZE]))
+        .map(|col| ((col >> 1) & u32::MAX as u64).count_ones())
+        .sum()
+}
+
+/// Whether a y position local to the middle chunk is meshed.
+/// Positions in the neighbors above & below are always included.
+#[inline]
+fn in_y_range(y_range: &Option<Range<i32>>, y: i32) -> bool {
+    y_range.as_ref().is_none_or(|range| !(0..CHUNK_SIZE_I32).contains(&y) || range.contains(&y))
+}
+
+/// Most ids [`solid_row_mask_simd`] compares each row against, registries needing more use [`solid_row_mask_scalar`].
+pub const SIMD_MAX_COMPARED_IDS: usize = 16;
+
+/// Which block ids are greedy meshed with a flag, see [`BlockRegistry::is_greedy_meshed`].
+/// Built once per flag by [`BlockRegistry::occlusion_table`].
+#[derive(Debug, Clone)]
+pub struct OcclusionTable {
+    /// `true` for every occluding id, padded to at least 256 entries.
+    pub occludes: Vec<bool>,
+    /// The shorter list of the registry's occluding or non-occluding ids, which rows are compared against with SIMD.
+    #[cfg_attr(not(all(feature = "simd", target_arch = "x86_64")), allow(dead_code))]
+    compared_ids: Vec<u16>,
+    /// Whether `compared_ids` are the occluding ones.
+    #[cfg_attr(not(all(feature = "simd", target_arch = "x86_64")), allow(dead_code))]
+    compared_occlude: bool,
+}
+impl OcclusionTable {
+    pub fn new(block_registry: &BlockRegistry, flag: BlockFlags) -> Self {
+        let block_count = block_registry.block_flags.len();
+        Self::from_occludes((0..block_count).map(|id| block_registry.is_greedy_meshed(BlockId(id as u16), flag)).collect())
+    }
 
-    let mut vertices = vec![];
-    for (axis, block_ao_data) in data.into_iter().enumerate() {
-        let facedir = match axis {
-            0 => FaceDir::Down,
-            1 => FaceDir::Up,
-            2 => FaceDir::Left,
-            3 => FaceDir::Right,
-            4 => FaceDir::Forward,
-            _ => FaceDir::Back,
+    /// A table of the ids below `occludes.len()`, the ids past it never occlude.
+    pub fn from_occludes(mut occludes: Vec<bool>) -> Self {
+        let ids = |occluding: bool| occludes.iter().enumerate().filter(move |(_, occludes)| **occludes == occluding).map(|(id, _)| id as u16);
+        let (occluding, other) = (ids(true).count(), ids(false).count());
+        let compared_occlude = occluding <= other;
+        let compared_ids = ids(compared_occlude).collect();
+        occludes.resize(occludes.len().max(256), false);
+        Self { occludes, compared_ids, compared_occlude }
+    }
+}
+
+/// Bitmask of the blocks in a row of up to 32 voxels that occlude according to `table`.
+#[inline]
+pub fn solid_row_mask(row: &[crate::voxel::BlockData], table: &OcclusionTable) -> u32 {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        solid_row_mask_simd(row, table)
+    }
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    {
+        solid_row_mask_scalar(row, table)
+    }
+}
+
+#[inline]
+pub fn solid_row_mask_scalar(row: &[crate::voxel::BlockData], table: &OcclusionTable) -> u32 {
+    let mut mask = 0;
+    for (x, block) in row.iter().enumerate() {
+        mask |= (table.occludes[block.block_type.0 as usize] as u32) << x;
+    }
+    mask
+}
+
+/// Looks up a row of 32 blocks with SSE2, which every x86_64 cpu has.
+/// There's no gather, so the block ids of the row are compared against each of the table's compared ids,
+/// up to [`SIMD_MAX_COMPARED_IDS`] of them. Bigger registries fall back to [`solid_row_mask_scalar`].
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[inline]
+pub fn solid_row_mask_simd(row: &[crate::voxel::BlockData], table: &OcclusionTable) -> u32 {
+    use std::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi16, _mm_loadu_si128, _mm_movemask_epi8, _mm_or_si128, _mm_packs_epi16, _mm_packs_epi32, _mm_set1_epi16,
+        _mm_setzero_si128, _mm_slli_epi32, _mm_srai_epi32,
+    };
+
+    if row.len() != CHUNK_SIZE || table.compared_ids.len() > SIMD_MAX_COMPARED_IDS {
+        return solid_row_mask_scalar(row, table);
+    }
+
+    // SAFETY: SSE2 is always available on x86_64. BlockData is repr(C) with the u16 id first, so each voxel is a 32 bit lane
+    // with the id in its low half. The row is 128 bytes, read with unaligned loads.
+    let mask = unsafe {
+        let voxels = row.as_ptr().cast::<__m128i>();
+        // sign extending the low halves keeps the ids exact when packing them into 16 bit lanes, 8 voxels each.
+        let ids_of = |i: usize| {
+            let low = |v: __m128i| _mm_srai_epi32(_mm_slli_epi32(v, 16), 16);
+            _mm_packs_epi32(low(_mm_loadu_si128(voxels.add(i * 2))), low(_mm_loadu_si128(voxels.add(i * 2 + 1))))
         };
-        for (block_ao, axis_plane) in block_ao_data.into_iter() {
-            let ao = block_ao & 0b111111111;
-            let block_type = block_ao >> 9;
-            for (axis_pos, plane) in axis_plane.into_iter() {
-                let quads_from_axis = greedy_mesh_binary_plane(plane, lod.size() as u32);
-
-                quads_from_axis.into_iter().for_each(|q| {
-                    q.append_vertices(&mut vertices, facedir, axis_pos, &Lod::L32, ao, block_type)
-                });
+        let ids = [ids_of(0), ids_of(1), ids_of(2), ids_of(3)];
+        let mut matches = [_mm_setzero_si128(); 4];
+        for id in &table.compared_ids {
+            let id = _mm_set1_epi16(*id as i16);
+            for (matched, ids) in matches.iter_mut().zip(ids) {
+                *matched = _mm_or_si128(*matched, _mm_cmpeq_epi16(ids, id));
             }
         }
+        let low = _mm_movemask_epi8(_mm_packs_epi16(matches[0], matches[1])) as u32;
+        let high = _mm_movemask_epi8(_mm_packs_epi16(matches[2], matches[3])) as u32;
+        low | (high << 16)
+    };
+
+    if table.compared_occlude { mask } else { !mask }
+}
+
+/// Adds the faces of blocks which aren't greedy meshed, such as slabs, one at a time.
+/// Faces are culled by full neighbors, & by neighbors of the same shape for the faces they share.
+fn append_partial_blocks(
+    mesh: &mut ChunkMeshSlices,
+    chunks_refs: &ChunksRefs,
+    block_registry: &BlockRegistry,
+    flag_to_build: BlockFlags,
+    ignore_block_type_mask: u32,
+    slices: &DirtySlices,
+    y_range: &Option<Range<i32>>,
+) {
+    let is_partial = |block_type| block_registry.has_flag(block_type, flag_to_build) && !block_registry.is_greedy_meshed(block_type, flag_to_build);
+
+    let chunk = &chunks_refs.chunks[13];
+    if chunk.get_block_if_filled().is_some_and(|block| !is_partial(block.block_type)) {
+        return;
     }
 
-    mesh.vertices.extend(vertices);
-    if mesh.vertices.is_empty() {
-        None
-    } else {
-        mesh.indices = generate_indices(mesh.vertices.len());
-        Some(mesh)
+    for i in 0..CHUNK_SIZE3 {
+        let block = chunk.get_block(i);
+        if !is_partial(block.block_type) {
+            continue;
+        }
+        let voxel_pos = crate::utils::index_to_ivec3(i);
+        if !in_y_range(y_range, voxel_pos.y) {
+            continue;
+        }
+        let shape = block_registry.shape(block.block_type);
+        let block_type = block.block_type.0 as u32 & ignore_block_type_mask;
+
+        if shape == BlockShape::Cross {
+            // never culled, both quads go in the block's Up slice
+            if slices.0[0] & (1 << voxel_pos.y) != 0 {
+                append_cross_vertices(mesh.slice_mut(1, voxel_pos.y as usize), voxel_pos, block_type);
+            }
+            continue;
+        }
+
+        for (axis, facedir) in [FaceDir::Down, FaceDir::Up, FaceDir::Left, FaceDir::Right, FaceDir::Forward, FaceDir::Back].into_iter().enumerate() {
+            // the face at the middle of the block is never hidden
+            let inner_face = matches!((shape, facedir), (BlockShape::SlabBottom, FaceDir::Up) | (BlockShape::SlabTop, FaceDir::Down));
+
+            let neighbor_pos = voxel_pos + facedir.air_sample_dir();
+            let neighbor = chunks_refs.get_block(neighbor_pos).block_type;
+            let culled = !inner_face && in_y_range(y_range, neighbor_pos.y) && (block_registry.occludes(neighbor, flag_to_build)
+                || (facedir.air_sample_dir().y == 0 && block_registry.has_flag(neighbor, flag_to_build) && block_registry.shape(neighbor) == shape));
+            if culled {
+                continue;
+            }
+
+            let (axis_pos, x, y) = match axis {
+                0 | 1 => (voxel_pos.y, voxel_pos.x, voxel_pos.z),
+                2 | 3 => (voxel_pos.x, voxel_pos.z, voxel_pos.y),
+                _ => (voxel_pos.z, voxel_pos.x, voxel_pos.y),
+            };
+            if slices.0[axis / 2] & (1 << axis_pos) == 0 {
+                continue;
+            }
+
+            let vertices = mesh.slice_mut(axis, axis_pos as usize);
+            let start = vertices.len();
+            GreedyQuad { x: x as u32, y: y as u32, w: 1, h: 1 }
+                .append_vertices(vertices, facedir, axis_pos as u32, &Lod::L32, 0, block_type);
+
+            // squash the unit quad into the half of the block the slab occupies
+            for vertex in &mut vertices[start..] {
+                let y = get_pos_from_vertex_u32(*vertex).y;
+                match shape {
+                    BlockShape::SlabBottom if y == voxel_pos.y + 1 => *vertex |= VERTEX_HALF_DOWN_BIT,
+                    BlockShape::SlabTop if y == voxel_pos.y => *vertex = (*vertex + (1 << 6)) | VERTEX_HALF_DOWN_BIT,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Adds the two quads of a [`BlockShape::Cross`] block at `pos`, spanning the block's diagonals.
+fn append_cross_vertices(vertices: &mut Vec<u32>, pos: IVec3, block_type: u32) {
+    // counter clockwise seen from the side the normal points to
+    let diagonals = [
+        [ivec3(0, 0, 0), ivec3(0, 1, 0), ivec3(1, 1, 1), ivec3(1, 0, 1)],
+        [ivec3(1, 0, 0), ivec3(1, 1, 0), ivec3(0, 1, 1), ivec3(0, 0, 1)],
+    ];
+    for (corners, normal) in diagonals.into_iter().zip(CROSS_NORMAL_INDICES) {
+        vertices.extend(corners.map(|corner| make_vertex_u32(pos + corner, 0, normal, block_type)));
+    }
+}
+
+/// Adds "skirts" hanging down from the top surface along borders facing a neighbour meshed at a coarser LOD.
+/// The surfaces don't line up across such borders, the skirts cover the cracks this leaves.
+fn append_lod_skirts(
+    mesh: &mut ChunkMeshSlices,
+    chunks_refs: &ChunksRefs,
+    lod: Lod,
+    col_face_masks: &[[[u64; CHUNK_SIZE_P]; CHUNK_SIZE_P]; 6],
+    ignore_block_type_mask: u32,
+    slices: &DirtySlices,
+    y_range: &Option<Range<i32>>,
+) {
+    let lowest_y = y_range.as_ref().map_or(0, |range| range.start as u32);
+    for (facedir, axis, border) in [
+        (FaceDir::Left, 2, 0),
+        (FaceDir::Right, 3, CHUNK_SIZE - 1),
+        (FaceDir::Forward, 4, 0),
+        (FaceDir::Back, 5, CHUNK_SIZE - 1),
+    ] {
+        let neighbor_lod = chunks_refs.neighbor_lods[facedir.normal_index() as usize];
+        if neighbor_lod.jump_index() <= lod.jump_index() || slices.0[axis / 2] & (1 << border) == 0 {
+            continue;
+        }
+        // cracks are at most one of the neighbour's voxels deep
+        let depth = neighbor_lod.jump_index() as u32;
+
+        for along in 0..CHUNK_SIZE {
+            let (x, z) = match axis {
+                2 | 3 => (border, along),
+                _ => (along, border),
+            };
+
+            // top surfaces in this border column, without padding
+            let mut col = (col_face_masks[1][z + 1][x + 1] >> 1) & u32::MAX as u64;
+            while col != 0 {
+                let y = col.trailing_zeros();
+                col &= col - 1;
+
+                let current_voxel = chunks_refs.get_block_no_neighbour(ivec3(x as i32, y as i32, z as i32));
+                let block_type = current_voxel.block_type.0 as u32 & ignore_block_type_mask;
+                let bottom = (y + 1).saturating_sub(depth).max(lowest_y);
+
+                GreedyQuad {
+                    x: along as u32,
+                    y: bottom,
+                    w: 1,
+                    h: y + 1 - bottom,
+                }
+                .append_vertices(mesh.slice_mut(axis, border), facedir, border as u32, &Lod::L32, 0, block_type);
+            }
+        }
     }
 }
 
@@ -297,31 +796,59 @@ impl GreedyQuad {
         );
 
         // the quad vertices to be added
-        let mut new_vertices = VecDeque::from([v1, v2, v3, v4]);
+        let mut new_vertices = [v1, v2, v3, v4];
 
         // triangle vertex order is different depending on the facing direction
         // due to indices always being the same
         if face_dir.reverse_order() {
             // keep first index, but reverse the rest
-            let o = new_vertices.split_off(1);
-            o.into_iter().rev().for_each(|i| new_vertices.push_back(i));
+            new_vertices[1..].reverse();
         }
 
         // anisotropy flip
         if (v1ao > 0) ^ (v3ao > 0) {
             // right shift array, to swap triangle intersection angle
-            let f = new_vertices.pop_front().unwrap();
-            new_vertices.push_back(f);
+            new_vertices.rotate_left(1);
         }
 
-        vertices.extend(new_vertices);
+        vertices.extend_from_slice(&new_vertices);
     }
 }
 
+/// Which way quads grow first when greedy meshing a plane, see [`ChunksRefs::merge_orders`].
+///
+/// The plane's width runs across its rows & its height along the bits of a row ([`GreedyQuad::w`] & [`GreedyQuad::h`]).
+/// Height is world z on up & down faces & world y on side faces, width is world z on left & right faces & world x on the others.
+/// Every order covers the same faces, only the tiling differs, which matters for textures that aren't square or tile along one axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GreedyMergeOrder {
+    /// Take the whole run of faces along the height, then grow across as many rows as have all of it.
+    #[default]
+    HeightFirst,
+    /// Take the whole run of faces across the rows, then grow along the height as far as all of them go.
+    WidthFirst,
+    /// Whichever of the two covers more faces, height first on ties.
+    LargestArea,
+}
+
 /// generate quads of a binary slice
 /// lod not implemented atm
-pub fn greedy_mesh_binary_plane(mut data: [u32; 32], lod_size: u32) -> Vec<GreedyQuad> {
+pub fn greedy_mesh_binary_plane(data: [u32; 32], lod_size: u32) -> Vec<GreedyQuad> {
+    greedy_mesh_binary_plane_with_order(data, lod_size, GreedyMergeOrder::HeightFirst)
+}
+
+/// [`greedy_mesh_binary_plane`] growing the quads in `order`.
+pub fn greedy_mesh_binary_plane_with_order(data: [u32; 32], lod_size: u32, order: GreedyMergeOrder) -> Vec<GreedyQuad> {
     let mut greedy_quads = vec![];
+    greedy_mesh_binary_plane_into(data, lod_size, order, &mut greedy_quads);
+    greedy_quads
+}
+
+/// [`greedy_mesh_binary_plane_with_order`] appending to `greedy_quads`.
+fn greedy_mesh_binary_plane_into(mut data: [u32; 32], lod_size: u32, order: GreedyMergeOrder, greedy_quads: &mut Vec<GreedyQuad>) {
+    // convert height 'num' to positive bits repeated 'num' times aka:
+    // 1 = 0b1, 2 = 0b11, 4 = 0b1111
+    let as_mask = |h: u32| u32::checked_shl(1, h).map_or(!0, |v| v - 1);
     for row in 0..data.len() {
         let mut y = 0;
         while y < lod_size {
@@ -331,24 +858,34 @@ pub fn greedy_mesh_binary_plane(mut data: [u32; 32], lod_size: u32) -> Vec<Greed
                 // reached top
                 continue;
             }
-            let h = (data[row] >> y).trailing_ones();
-            // convert height 'num' to positive bits repeated 'num' times aka:
-            // 1 = 0b1, 2 = 0b11, 4 = 0b1111
-            let h_as_mask = u32::checked_shl(1, h).map_or(!0, |v| v - 1);
-            let mask = h_as_mask << y;
-            // grow horizontally
-            let mut w = 1;
-            while row + w < lod_size as usize {
-                // fetch bits spanning height, in the next row
-                let next_row_h = (data[row + w] >> y) & h_as_mask;
-                if next_row_h != h_as_mask {
-                    break; // can no longer expand horizontally
-                }
 
-                // nuke the bits we expanded into
-                data[row + w] &= !mask;
+            // the whole run up, then as far right as the next rows have all of it
+            let height_first = || {
+                let h = (data[row] >> y).trailing_ones();
+                let h_as_mask = as_mask(h);
+                // fetch bits spanning height, in the next rows
+                let w = 1 + (row + 1..lod_size as usize).take_while(|next| (data[*next] >> y) & h_as_mask == h_as_mask).count();
+                (w, h)
+            };
+            // the whole run right, then as far up as all of those rows go
+            let width_first = || {
+                let w = 1 + (row + 1..lod_size as usize).take_while(|next| (data[*next] >> y) & 1 != 0).count();
+                let h = data[row..row + w].iter().fold(!0, |spanned, next| spanned & (next >> y)).trailing_ones();
+                (w, h)
+            };
+            let (w, h) = match order {
+                GreedyMergeOrder::HeightFirst => height_first(),
+                GreedyMergeOrder::WidthFirst => width_first(),
+                GreedyMergeOrder::LargestArea => {
+                    let (height_first, width_first) = (height_first(), width_first());
+                    if width_first.0 as u32 * width_first.1 > height_first.0 as u32 * height_first.1 { width_first } else { height_first }
+                }
+            };
 
-                w += 1;
+            // nuke the bits we expanded into
+            let mask = as_mask(h) << y;
+            for next in &mut data[row + 1..row + w] {
+                *next &= !mask;
             }
             greedy_quads.push(GreedyQuad {
                 y,
@@ -359,5 +896,634 @@ pub fn greedy_mesh_binary_plane(mut data: [u32; 32], lod_size: u32) -> Vec<Greed
             y += h;
         }
     }
-    greedy_quads
+}
+
+#[test]
+fn incremental_rebuild_matches_full_rebuild() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::SOLID],
+        ..default()
+    });
+
+    // Flat ground with a bit of variation so there are plenty of faces.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for z in 0..CHUNK_SIZE as i32 {
+        for x in 0..CHUNK_SIZE as i32 {
+            for y in 0..(8 + (x ^ z) % 5) {
+                voxels[vec3_to_index(ivec3(x, y, z), 32)].block_type = BlockId(1 + (x % 2) as u16);
+            }
+        }
+    }
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let mut chunks_refs = ChunksRefs::new(chunks);
+
+    let mut slices = build_chunk_mesh_slices(&chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, true, false, false, &DirtySlices::ALL, None);
+
+    let mut dirty = DirtySlices::default();
+    for (pos, block_type) in [(ivec3(4, 9, 4), BlockId(2)), (ivec3(20, 3, 31), BlockId(0)), (ivec3(0, 12, 0), BlockId(1))] {
+        Arc::make_mut(&mut chunks_refs.chunks[13]).set_block(pos, block_type);
+        dirty.mark_voxel(pos);
+    }
+
+    rebuild_chunk_mesh_slices(&mut slices, &chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, true, false, false, &dirty);
+    let full = build_chunk_mesh_slices(&chunks_refs, Lod::L32, block_registry, BlockFlags::SOLID, true, false, false, &DirtySlices::ALL, None);
+
+    for (mut incremental, mut full) in slices.vertices.into_iter().zip(full.vertices) {
+        incremental.sort_unstable();
+        full.sort_unstable();
+        assert_eq!(incremental, full);
+    }
+}
+
+#[test]
+fn reused_buffers_dont_leak_between_meshes() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::SOLID],
+        ..default()
+    });
+    let make_chunks_refs = |height: i32| {
+        let voxels = (0..CHUNK_SIZE3).map(|i| {
+            let pos = crate::utils::index_to_ivec3(i);
+            BlockData::new(BlockId(if pos.y < height + pos.x % 3 { 1 + (pos.z % 2) as u16 } else { 0 }))
+        }).collect();
+        let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+        chunks[13] = Arc::new(ChunkData::Dense(voxels));
+        ChunksRefs::new(chunks)
+    };
+    let (low, high) = (make_chunks_refs(4), make_chunks_refs(20));
+    let build = |chunks_refs: &ChunksRefs| build_chunk_mesh(chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, true, false, false, None).unwrap();
+
+    // the second mesh of `low` is built with the buffers grown by `high`.
+    let first = build(&low);
+    assert_ne!(build(&high).vertices, first.vertices);
+    let second = build(&low);
+    assert_eq!(second.vertices, first.vertices);
+    assert_eq!(second.indices, first.indices);
+}
+
+#[test]
+fn lod_skirts_only_towards_coarser_neighbors() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        ..default()
+    });
+
+    // Flat ground 4 voxels deep, with the neighbours below solid & the rest empty.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for z in 0..CHUNK_SIZE as i32 {
+        for x in 0..CHUNK_SIZE as i32 {
+            for y in 0..4 {
+                voxels[vec3_to_index(ivec3(x, y, z), 32)].block_type = BlockId(1);
+            }
+        }
+    }
+    let chunks: Vec<_> = (0..27).map(|i| {
+        if i == 13 {
+            Arc::new(ChunkData::Dense(voxels.clone()))
+        } else {
+            Arc::new(ChunkData::Filled(BlockData::default()))
+        }
+    }).collect();
+    let mut chunks_refs = ChunksRefs::new(chunks);
+
+    let build = |chunks_refs: &ChunksRefs| build_chunk_mesh_slices(chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, false, false, true, &DirtySlices::ALL, None);
+
+    let without = build(&chunks_refs);
+    chunks_refs.neighbor_lods[FaceDir::Left.normal_index() as usize] = Lod::L8;
+    // finer neighbours don't need skirts.
+    chunks_refs.neighbor_lods[FaceDir::Back.normal_index() as usize] = Lod::L32;
+    let with = build(&chunks_refs);
+
+    for (i, (without, with)) in without.vertices.iter().zip(with.vertices.iter()).enumerate() {
+        if i == 2 * CHUNK_SIZE {
+            // one quad per column along the left border.
+            assert_eq!(with.len(), without.len() + CHUNK_SIZE * 4);
+        } else {
+            assert_eq!(with, without);
+        }
+    }
+}
+
+#[test]
+fn no_greedy_merge_blocks_get_a_face_each() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::SOLID | BlockFlags::NO_GREEDY_MERGE],
+        ..default()
+    });
+    let (stone, ore) = (BlockId(1), BlockId(2));
+
+    // A row of stone next to a row of ore.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for x in 0..8 {
+        voxels[vec3_to_index(ivec3(x, 0, 0), 32)].block_type = stone;
+        voxels[vec3_to_index(ivec3(x, 0, 1), 32)].block_type = ore;
+    }
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let chunks_refs = ChunksRefs::new(chunks);
+
+    let top_quads = |ignore_block_type| {
+        let mut mesh = build_chunk_mesh_slices(&chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, true, ignore_block_type, false, &DirtySlices::ALL, None);
+        mesh.slice_mut(1, 0).len() / 4
+    };
+    assert_eq!(top_quads(false), 1 + 8);
+    // collision meshes don't care about textures.
+    assert_eq!(top_quads(true), 1);
+}
+
+#[test]
+fn faces_between_different_transparent_blocks() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::TRANSPARENT, BlockFlags::TRANSPARENT],
+        ..default()
+    });
+    let (water, glass) = (BlockId(1), BlockId(2));
+
+    // Two columns of water next to a column of glass.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for y in 0..4 {
+        voxels[vec3_to_index(ivec3(0, y, 0), 32)].block_type = water;
+        voxels[vec3_to_index(ivec3(1, y, 0), 32)].block_type = water;
+        voxels[vec3_to_index(ivec3(2, y, 0), 32)].block_type = glass;
+    }
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let chunks_refs = ChunksRefs::new(chunks);
+
+    let mut mesh = build_chunk_mesh_slices(&chunks_refs, Lod::L32, block_registry, BlockFlags::TRANSPARENT, false, false, false, &DirtySlices::ALL, None);
+    let (left, right) = (2, 3);
+    // water against water is culled.
+    assert_eq!(mesh.slice_mut(right, 0).len(), 0);
+    assert_eq!(mesh.slice_mut(left, 1).len(), 0);
+    // water & glass can see each other, as one quad each.
+    assert_eq!(mesh.slice_mut(right, 1).len(), 4);
+    assert_eq!(mesh.slice_mut(left, 2).len(), 4);
+    assert_eq!(mesh.slice_mut(right, 2).len(), 4);
+}
+
+#[test]
+fn face_cull_rules_decide_faces_between_blocks() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    struct NeverCull;
+    impl FaceCullRule for NeverCull {
+        fn emit_face(&self, _: BlockId, _: BlockId, _: &BlockRegistry) -> bool {
+            true
+        }
+    }
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        ..default()
+    });
+    // Two stone blocks next to each other.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    voxels[vec3_to_index(ivec3(4, 4, 4), 32)].block_type = BlockId(1);
+    voxels[vec3_to_index(ivec3(5, 4, 4), 32)].block_type = BlockId(1);
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let mut chunks_refs = ChunksRefs::new(chunks);
+
+    let mesh = |chunks_refs: &ChunksRefs| build_chunk_mesh(chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, false, false, false, None).unwrap();
+    let standard = mesh(&chunks_refs);
+    assert_eq!(standard.vertices.len(), 6 * 4);
+
+    // asking the standard rule per face gives the same mesh.
+    chunks_refs.face_cull_rule = Some(Arc::new(StandardCull));
+    assert_eq!(mesh(&chunks_refs).vertices, standard.vertices);
+
+    // the faces between the two blocks are no longer culled.
+    chunks_refs.face_cull_rule = Some(Arc::new(NeverCull));
+    assert_eq!(mesh(&chunks_refs).vertices.len(), 8 * 4);
+}
+
+#[test]
+fn merge_orders_tile_planes_differently() {
+    // an L: a column of 3 faces along the height of row 0 & a run of 3 rows along its bottom.
+    let mut plane = [0u32; 32];
+    plane[0] = 0b111;
+    plane[1] = 0b1;
+    plane[2] = 0b1;
+
+    let tiling = |plane: [u32; 32], order: GreedyMergeOrder| {
+        let quads = greedy_mesh_binary_plane_with_order(plane, 32, order);
+        // every face is covered by exactly one quad.
+        let mut covered = [0u32; 32];
+        for quad in &quads {
+            for x in quad.x..quad.x + quad.w {
+                let mask = ((1u32 << quad.h) - 1) << quad.y;
+                assert_eq!(covered[x as usize] & mask, 0);
+                covered[x as usize] |= mask;
+            }
+        }
+        assert_eq!(covered, plane);
+        quads.iter().map(|quad| (quad.x, quad.y, quad.w, quad.h)).collect::<Vec<_>>()
+    };
+
+    assert_eq!(tiling(plane, GreedyMergeOrder::HeightFirst), [(0, 0, 1, 3), (1, 0, 2, 1)]);
+    assert_eq!(tiling(plane, GreedyMergeOrder::WidthFirst), [(0, 0, 3, 1), (0, 1, 1, 2)]);
+    // both are as large, height first wins the tie.
+    assert_eq!(tiling(plane, GreedyMergeOrder::LargestArea), tiling(plane, GreedyMergeOrder::HeightFirst));
+
+    plane[3] = 0b1;
+    assert_eq!(tiling(plane, GreedyMergeOrder::LargestArea), [(0, 0, 4, 1), (0, 1, 1, 2)]);
+}
+
+#[test]
+fn fluid_tops_are_lowered() {
+    use crate::{chunk::ChunkData, voxel::BlockData};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::TRANSPARENT, BlockFlags::SOLID],
+        block_shape: vec![BlockShape::Full, BlockShape::Fluid { level: 4 }, BlockShape::Full],
+        ..default()
+    });
+    let (water, stone) = (BlockId(1), BlockId(2));
+
+    // A 4x4 pool 2 deep, with a stone block above one corner.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for z in 0..4 {
+        for x in 0..4 {
+            for y in 0..2 {
+                voxels[vec3_to_index(ivec3(x, y, z), 32)].block_type = water;
+            }
+        }
+    }
+    voxels[vec3_to_index(ivec3(0, 2, 0), 32)].block_type = stone;
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let chunks_refs = ChunksRefs::new(chunks);
+
+    let mut mesh = build_chunk_mesh_slices(&chunks_refs, Lod::L32, block_registry, BlockFlags::TRANSPARENT, false, false, false, &DirtySlices::ALL, None);
+    let (down, up, left) = (0, 1, 2);
+    let lowered = |vertices: &[u32]| vertices.iter().filter(|vertex| *vertex & VERTEX_HALF_DOWN_BIT != 0).count();
+
+    // the surface is still one quad, the stone above doesn't hide it.
+    let top = mesh.slice_mut(up, 1).clone();
+    assert_eq!(top.len(), 4);
+    assert_eq!(lowered(&top), 4);
+    // the sides merge over both layers & only their top edge is lowered.
+    let side = mesh.slice_mut(left, 0).clone();
+    assert_eq!(side.len(), 4);
+    assert_eq!(lowered(&side), 2);
+    assert_eq!(lowered(mesh.slice_mut(down, 0)), 0);
+    // the water between the layers is hidden.
+    assert!(mesh.slice_mut(up, 0).is_empty());
+}
+
+#[test]
+fn cross_blocks_are_two_quads() {
+    use crate::{chunk::ChunkData, voxel::BlockData};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::CUTOUT, BlockFlags::SOLID],
+        block_shape: vec![BlockShape::Full, BlockShape::Cross, BlockShape::Full],
+        ..default()
+    });
+    let (grass, stone) = (BlockId(1), BlockId(2));
+
+    // A tuft of grass on a stone block.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    voxels[vec3_to_index(ivec3(5, 5, 5), 32)].block_type = grass;
+    voxels[vec3_to_index(ivec3(5, 4, 5), 32)].block_type = stone;
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let chunks_refs = ChunksRefs::new(chunks);
+
+    let (cross, stats) = build_chunk_mesh_with_stats(&chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::CUTOUT, true, false, false, None);
+    let cross = cross.unwrap();
+    assert_eq!(cross.vertices.len(), 8);
+    assert_eq!(stats.unit_faces_covered, 2);
+    let normals: Vec<_> = cross.vertices.iter().map(|vertex| crate::utils::get_normal_index_from_vertex_u32(*vertex)).collect();
+    assert_eq!(normals, [6, 6, 6, 6, 7, 7, 7, 7]);
+
+    // the stone's top face isn't culled by the grass.
+    let stone_mesh = build_chunk_mesh(&chunks_refs, Lod::L32, block_registry, BlockFlags::SOLID, true, false, false, None).unwrap();
+    assert_eq!(stone_mesh.vertices.len(), 6 * 4);
+}
+
+#[test]
+fn lit_faces_only_merge_with_the_same_light() {
+    use crate::{chunk::ChunkData, light::{ChunkLight, MAX_LIGHT}, voxel::BlockData};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::SOLID],
+        block_emissive: vec![Color::BLACK, Color::BLACK, Color::WHITE],
+        ..default()
+    });
+
+    // A 20 long row of stone with a lamp at one end.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for x in 0..20 {
+        voxels[vec3_to_index(ivec3(x, 0, 0), 32)].block_type = BlockId(1);
+    }
+    voxels[vec3_to_index(ivec3(0, 0, 0), 32)].block_type = BlockId(2);
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let mut chunks_refs = ChunksRefs::new(chunks);
+    let unlit = build_chunk_mesh(&chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, false, false, false, None).unwrap();
+    let light = ChunkLight::compute(&chunks_refs, &block_registry);
+    chunks_refs.block_light = Some(light.clone());
+
+    let mut mesh = build_chunk_mesh(&chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, false, false, false, None).unwrap();
+    mesh.apply_block_light(&light);
+    assert_eq!(mesh.light.len(), mesh.vertices.len());
+
+    // the top of the row is split by the light fading out, the far end is dark.
+    let top: Vec<_> = mesh.vertices.chunks_exact(4).zip(mesh.light.chunks_exact(4))
+        .filter(|(quad, _)| crate::utils::get_normal_index_from_vertex_u32(quad[0]) == FaceDir::Up.normal_index())
+        .map(|(quad, light)| (quad.iter().map(|vertex| get_pos_from_vertex_u32(*vertex).x).min().unwrap(), light[0]))
+        .collect();
+    assert!(top.len() > 10);
+    assert!(top.contains(&(1, MAX_LIGHT - 2)));
+    assert!(top.contains(&(14, 0)));
+    assert!(unlit.vertices.len() < mesh.vertices.len());
+    // collision meshes aren't split.
+    let collision = build_chunk_mesh(&chunks_refs, Lod::L32, block_registry, BlockFlags::SOLID, false, true, false, None).unwrap();
+    assert_eq!(collision.vertices.len(), 6 * 4);
+}
+
+#[test]
+fn mesh_stats_count_merged_faces() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::SOLID | BlockFlags::NO_GREEDY_MERGE],
+        ..default()
+    });
+
+    // A row of stone next to a row of ore, each 8 long.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for x in 0..8 {
+        voxels[vec3_to_index(ivec3(x, 0, 0), 32)].block_type = BlockId(1);
+        voxels[vec3_to_index(ivec3(x, 0, 1), 32)].block_type = BlockId(2);
+    }
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let chunks_refs = ChunksRefs::new(chunks);
+
+    let (mesh, stats) = build_chunk_mesh_with_stats(&chunks_refs, Lod::L32, block_registry, BlockFlags::SOLID, true, false, false, None);
+    assert_eq!(stats.quads, mesh.unwrap().vertices.len() / 4);
+    // The stone merges into 5 quads, the ore gets a quad per face. Each covers 8 + 8 + 8 + 1 + 1 faces.
+    assert_eq!(stats.quads, 5 + 26);
+    assert_eq!(stats.unit_faces_covered, 26 * 2);
+    assert!((stats.merge_ratio - (1.0 - 31.0 / 52.0)).abs() < 1e-6);
+}
+
+#[test]
+fn slabs_are_meshed_without_occluding() {
+    use crate::{chunk::ChunkData, utils::get_offset_pos_from_vertex_u32, voxel::{Block, BlockData, BlockId, BlockRegistryBuilder, BlockStringIdentifier, BlockVisibilty}};
+
+    let mut block_registry = BlockRegistryBuilder::new();
+    block_registry.add_block(BlockStringIdentifier(Box::from("air")), &Block { visibility: BlockVisibilty::Invisible, collision: false, ..default() }).unwrap();
+    let stone = block_registry.add_block(BlockStringIdentifier(Box::from("stone")), &Block::default()).unwrap();
+    let slab = block_registry.add_block(BlockStringIdentifier(Box::from("slab")), &Block { shape: BlockShape::SlabBottom, ..default() }).unwrap();
+    let block_registry = block_registry.build();
+
+    let air = Arc::new(ChunkData::Filled(BlockData::default()));
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    let set = |voxels: &mut Vec<BlockData>, pos: IVec3, block_type: BlockId| voxels[vec3_to_index(pos, 32)].block_type = block_type;
+    // A slab between two stones, on top of a stone.
+    set(&mut voxels, IVec3::new(5, 5, 5), slab);
+    set(&mut voxels, IVec3::new(4, 5, 5), stone);
+    set(&mut voxels, IVec3::new(6, 5, 5), stone);
+    set(&mut voxels, IVec3::new(5, 4, 5), stone);
+    let mut chunks: Vec<_> = (0..27).map(|_| air.clone()).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let chunks_refs = ChunksRefs::new(chunks);
+
+    let mesh = build_chunk_mesh_slices(&chunks_refs, Lod::L32, block_registry, BlockFlags::SOLID, false, false, false, &DirtySlices::ALL, None);
+    // Faces in the slice lying entirely within the slab's voxel.
+    let slab_faces = |axis: usize, slice: usize| {
+        mesh.vertices[axis * CHUNK_SIZE + slice].chunks(4).filter(|face| {
+            face.iter().all(|v| {
+                let pos = get_offset_pos_from_vertex_u32(*v);
+                pos.cmpge(Vec3::splat(5.0)).all() && pos.cmple(Vec3::splat(6.0)).all()
+            })
+        }).map(|face| face.iter().map(|v| get_offset_pos_from_vertex_u32(*v)).collect::<Vec<_>>()).collect::<Vec<_>>()
+    };
+
+    // The stones facing the slab still have their faces.
+    assert_eq!(slab_faces(3, 4).len(), 1);
+    assert_eq!(slab_faces(2, 6).len(), 1);
+    assert_eq!(slab_faces(1, 4).len(), 1);
+    // The slab's faces towards the stones are culled.
+    assert!(slab_faces(2, 5).is_empty());
+    assert!(slab_faces(3, 5).is_empty());
+    assert!(slab_faces(0, 5).is_empty());
+
+    // The top face is half way up the block, the sides only cover the lower half.
+    let top = slab_faces(1, 5);
+    assert_eq!(top.len(), 1);
+    assert!(top[0].iter().all(|pos| pos.y == 5.5));
+    for axis in [4, 5] {
+        let side = slab_faces(axis, 5);
+        assert_eq!(side.len(), 1);
+        assert!(side[0].iter().all(|pos| pos.y == 5.0 || pos.y == 5.5));
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[test]
+fn simd_row_mask_matches_scalar() {
+    use crate::voxel::{BlockData, BlockId};
+
+    // few occluding ids, few non-occluding ids & too many of both for SIMD.
+    let tables = [(5, 3), (40, 20), (256, 3)].map(|(ids, every)| OcclusionTable::from_occludes((0..ids).map(|id| (id % every == 1) == (every == 3)).collect()));
+    for table in &tables {
+        for seed in 0..64u32 {
+            let row: Vec<BlockData> = (0..32u32).map(|x| BlockData::new(BlockId(((x * 7 + seed * 13) % 5) as u16)).with_state((x * seed) as u16)).collect();
+            assert_eq!(solid_row_mask_simd(&row, table), solid_row_mask_scalar(&row, table));
+        }
+        // uniform rows, and rows differing only in the last block.
+        for id in 0..5u16 {
+            let mut row = vec![BlockData::new(BlockId(id)); 32];
+            assert_eq!(solid_row_mask_simd(&row, table), solid_row_mask_scalar(&row, table));
+            row[31].block_type = BlockId(id + 1);
+            assert_eq!(solid_row_mask_simd(&row, table), solid_row_mask_scalar(&row, table));
+        }
+    }
+}
+
+#[test]
+fn only_ao_reads_edge_and_corner_chunks() {
+    use crate::{chunk::ChunkData, utils::index_to_ivec3_bounds, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        ..default()
+    });
+    // Blocks along the edges of the chunk, where AO samples the edge & corner neighbors.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for i in 0..CHUNK_SIZE as i32 {
+        for pos in [ivec3(i, 0, 0), ivec3(31, i, 31), ivec3(0, 31, i), ivec3(i, 31, 31)] {
+            voxels[vec3_to_index(pos, 32)].block_type = BlockId(1);
+        }
+    }
+    let chunks_refs = |edge_and_corner_block| {
+        let mut chunks: Vec<_> = (0..27)
+            .map(|i| {
+                let block_type = if (index_to_ivec3_bounds(i, 3) - IVec3::ONE).abs().element_sum() > 1 { edge_and_corner_block } else { BlockId(0) };
+                Arc::new(ChunkData::Filled(BlockData::new(block_type)))
+            })
+            .collect();
+        chunks[13] = Arc::new(ChunkData::Dense(voxels.clone()));
+        ChunksRefs::new(chunks)
+    };
+    let mesh = |edge_and_corner_block, calculate_ao| {
+        let mut vertices = build_chunk_mesh(&chunks_refs(edge_and_corner_block), Lod::L32, block_registry.clone(), BlockFlags::SOLID, calculate_ao, false, false, None).unwrap().vertices;
+        vertices.sort_unstable();
+        vertices
+    };
+
+    assert_eq!(mesh(BlockId(0), false), mesh(BlockId(1), false));
+    assert_ne!(mesh(BlockId(0), true), mesh(BlockId(1), true));
+}
+
+#[test]
+fn y_range_caps_the_mesh() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        ..default()
+    });
+    let stone = Arc::new(ChunkData::Filled(BlockData::new(BlockId(1))));
+    let chunks_refs = ChunksRefs::new((0..27).map(|_| stone.clone()).collect());
+    let mesh = |y_range| build_chunk_mesh(&chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, true, false, false, y_range);
+
+    // a solid chunk surrounded by solid chunks has no faces, unless it's cut open.
+    assert!(mesh(None).is_none());
+    assert!(mesh(Some(-4..40)).is_none());
+
+    let vertices = mesh(Some(8..16)).unwrap().vertices;
+    // a single greedy quad for the top & bottom each.
+    assert_eq!(vertices.len(), 8);
+    let mut heights: Vec<_> = vertices.iter().map(|vertex| get_pos_from_vertex_u32(*vertex).y).collect();
+    heights.sort_unstable();
+    heights.dedup();
+    assert_eq!(heights, [8, 16]);
+    // nothing above the cap, so the top isn't darkened.
+    assert!(vertices.iter().all(|vertex| crate::utils::get_ao_from_vertex_u32(*vertex) == 0));
+}
+
+#[cfg(feature = "parallel_meshing")]
+#[test]
+fn parallel_meshing_matches_serial() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::SOLID],
+        ..default()
+    });
+
+    // Noisy columns of two block types, so every direction has faces with varying ambient occlusion.
+    let voxels = (0..CHUNK_SIZE3).map(|i| {
+        let pos = crate::utils::index_to_ivec3(i);
+        let height = 4 + (pos.x * 7 + pos.z * 13) % 23;
+        BlockData::new(BlockId(if pos.y < height { 1 + (pos.x ^ pos.y) as u16 % 2 } else { 0 }))
+    }).collect();
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let chunks_refs = ChunksRefs::new(chunks);
+
+    for calculate_ao in [false, true] {
+        let build = |parallel_min_faces| {
+            let mut mesh = ChunkMeshSlices::default();
+            build_chunk_mesh_slices_into(&mut mesh, &chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, calculate_ao, false, false, &DirtySlices::ALL, None, parallel_min_faces);
+            mesh
+        };
+        let serial = build(u32::MAX);
+        assert!(serial.to_chunk_mesh().is_some());
+        assert_eq!(build(0).vertices, serial.vertices);
+    }
+}
+
+#[test]
+fn chunk_quads_have_plain_fields() {
+    use crate::{chunk::ChunkData, voxel::BlockData};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::CUTOUT],
+        block_shape: vec![BlockShape::Full, BlockShape::Full, BlockShape::Cross],
+        ..default()
+    });
+
+    // a row of 3 stone blocks with a tuft of grass on top.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    for x in 4..7 {
+        voxels[vec3_to_index(ivec3(x, 2, 8), 32)].block_type = BlockId(1);
+    }
+    voxels[vec3_to_index(ivec3(5, 3, 8), 32)].block_type = BlockId(2);
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let chunks_refs = ChunksRefs::new(chunks);
+
+    let quads = build_chunk_quads(&chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, true, false, false, None);
+    assert_eq!(quads.len(), 6);
+    assert!(quads.iter().all(|quad| quad.block == BlockId(1)));
+    let top = quads.iter().find(|quad| quad.face == Some(FaceDir::Up)).unwrap();
+    assert_eq!((top.position, top.size, top.normal), (Vec3::new(4.0, 3.0, 8.0), Vec2::new(3.0, 1.0), Vec3::Y));
+    let left = quads.iter().find(|quad| quad.face == Some(FaceDir::Left)).unwrap();
+    assert_eq!((left.position, left.size), (Vec3::new(4.0, 2.0, 8.0), Vec2::ONE));
+
+    let cross = build_chunk_quads(&chunks_refs, Lod::L32, block_registry, BlockFlags::CUTOUT, true, false, false, None);
+    assert_eq!(cross.len(), 2);
+    assert!(cross.iter().all(|quad| quad.face.is_none() && quad.block == BlockId(2) && quad.size.y == 1.0));
+}
+
+#[test]
+fn coarse_neighbors_are_culled_against_by_cell() {
+    use crate::{chunk::ChunkData, voxel::{BlockData, BlockId}};
+
+    let block_registry = Arc::new(BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        ..default()
+    });
+
+    // A solid chunk with only the neighbor to the right partly empty along the border.
+    // The neighbor is meshed at half the resolution, from the first voxel of each 2x2x2 cell.
+    let right_faces = |first_voxel_solid: bool, neighbor_lod: Lod| {
+        let mut voxels = vec![BlockData::new(BlockId(1)); CHUNK_SIZE3];
+        for z in 0..CHUNK_SIZE as i32 {
+            for y in 0..CHUNK_SIZE as i32 {
+                let first_voxel = y % 2 == 0 && z % 2 == 0;
+                voxels[vec3_to_index(ivec3(0, y, z), 32)].block_type = BlockId((first_voxel == first_voxel_solid) as u16);
+            }
+        }
+        let chunks: Vec<_> = (0..27).map(|i| {
+            if i == vec3_to_index(ivec3(2, 1, 1), 3) {
+                Arc::new(ChunkData::Dense(voxels.clone()))
+            } else {
+                Arc::new(ChunkData::Filled(BlockData::new(BlockId(1))))
+            }
+        }).collect();
+        let mut chunks_refs = ChunksRefs::new(chunks);
+        chunks_refs.neighbor_lods[FaceDir::Right.normal_index() as usize] = neighbor_lod;
+
+        let mesh = build_chunk_mesh_slices(&chunks_refs, Lod::L32, block_registry.clone(), BlockFlags::SOLID, false, false, false, &DirtySlices::ALL, None);
+        for (i, vertices) in mesh.vertices.iter().enumerate() {
+            assert!(vertices.is_empty() || i == 3 * CHUNK_SIZE + CHUNK_SIZE - 1);
+        }
+        mesh.vertices[3 * CHUNK_SIZE + CHUNK_SIZE - 1].len() / 4
+    };
+
+    // every coarse cell is solid, so no faces would be drawn into it.
+    assert_eq!(right_faces(true, Lod::L16), 0);
+    // every coarse cell is empty, so the whole border needs faces, merged into one quad.
+    assert_eq!(right_faces(false, Lod::L16), 1);
+    // at the same LOD each voxel counts, the empty odd rows merge into a quad each.
+    assert_eq!(right_faces(true, Lod::L32), 16 + 16 * 16);
+    assert_eq!(right_faces(false, Lod::L32), 16 * 16);
 }
@@ -1,14 +1,21 @@
+#[cfg(feature = "block_registry_asset")]
+pub mod block_registry_asset;
+#[cfg(feature = "rendering")]
+pub mod break_overlay;
 pub mod chunk;
 pub mod chunk_mesh;
 pub mod chunks_refs;
 pub mod constants;
 pub mod face_direction;
 pub mod greedy_mesher_optimized;
+pub mod light;
 pub mod lod;
+pub mod meshing;
 pub mod quad;
 #[cfg(feature = "rendering")]
 pub mod rendering;
 pub mod scanner;
+pub mod surface_nets;
 pub mod utils;
 pub mod voxel;
 pub mod voxel_engine;
@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+
+use bevy::math::{ivec3, IVec3};
+
+use crate::{
+    chunk::ChunkData,
+    chunks_refs::ChunksRefs,
+    constants::{ADJACENT_CHUNK_DIRECTIONS, CHUNK_SIZE_I32, CHUNK_SIZE_P},
+    utils::{index_to_ivec3_bounds, vec3_to_index},
+    voxel::{BlockFlags, BlockId, BlockRegistry},
+};
+
+/// Brightest block light level, see [`BlockRegistry::light_emission`].
+/// Light drops by at least one per voxel, so it reaches at most `MAX_LIGHT - 1` voxels from its source.
+pub const MAX_LIGHT: u8 = 15;
+
+/// Brightness of faces lit with `level`, unlit faces are still dimly visible. Same as in `chunk.wgsl`.
+pub fn light_brightness(level: u8) -> f32 {
+    0.05 + 0.95 * level.min(MAX_LIGHT) as f32 / MAX_LIGHT as f32
+}
+
+/// How far outside the middle chunk light is flood filled from, enough for light to reach the padding voxels.
+const REACH: i32 = MAX_LIGHT as i32;
+const REGION: i32 = CHUNK_SIZE_I32 + 2 * REACH;
+
+/// Block light levels of a chunk & the voxels bordering it, flood filled from emissive blocks.
+/// Light entering a block drops by its [`BlockRegistry::light_opacity`], full solid blocks stop it. There is no skylight.
+///
+/// Light never reaches further than a neighboring chunk, so it's computed from the same 3x3x3 chunks a chunk is meshed from
+/// & is always up to date with them, without passing light between chunks.
+#[derive(Clone, Default, Debug)]
+pub struct ChunkLight {
+    /// [`CHUNK_SIZE_P`]^3 levels of the padded chunk, empty if it's entirely dark.
+    levels: Vec<u8>,
+}
+impl ChunkLight {
+    /// Flood fills the light of the middle chunk from the emissive blocks within reach.
+    pub fn compute(chunks_refs: &ChunksRefs, registry: &BlockRegistry) -> Self {
+        let emission: Vec<u8> = (0..registry.block_flags.len()).map(|id| registry.light_emission(BlockId(id as u16))).collect();
+        if emission.iter().all(|level| *level == 0) {
+            return Self::default();
+        }
+        // full solid blocks absorb all of the light.
+        let absorption: Vec<u8> = (0..registry.block_flags.len())
+            .map(|id| {
+                let block = BlockId(id as u16);
+                if registry.occludes(block, BlockFlags::SOLID) { MAX_LIGHT } else { registry.light_opacity(block) }
+            })
+            .collect();
+
+        let region_index = |pos: IVec3| vec3_to_index(pos + IVec3::splat(REACH), REGION);
+        let mut levels = vec![0u8; (REGION * REGION * REGION) as usize];
+        let mut queue = VecDeque::new();
+
+        for (i, chunk) in chunks_refs.chunks.iter().enumerate() {
+            let may_emit = match chunk.as_ref() {
+                ChunkData::Filled(block) => emission[block.block_type.0 as usize] > 0,
+                ChunkData::Runs(runs) => runs.iter().any(|(block, _)| emission[block.block_type.0 as usize] > 0),
+                ChunkData::Dense(voxels) => voxels.iter().any(|block| emission[block.block_type.0 as usize] > 0),
+            };
+            if !may_emit {
+                continue;
+            }
+
+            // only the part of the chunk within reach of the middle chunk
+            let chunk_origin = (index_to_ivec3_bounds(i as i32, 3) - IVec3::ONE) * CHUNK_SIZE_I32;
+            let min = chunk_origin.max(IVec3::splat(-REACH));
+            let max = (chunk_origin + IVec3::splat(CHUNK_SIZE_I32)).min(IVec3::splat(CHUNK_SIZE_I32 + REACH));
+            for z in min.z..max.z {
+                for y in min.y..max.y {
+                    for x in min.x..max.x {
+                        let pos = ivec3(x, y, z);
+                        let level = emission[chunk.get_block(vec3_to_index(pos - chunk_origin, CHUNK_SIZE_I32)).block_type.0 as usize];
+                        if level > 0 {
+                            levels[region_index(pos)] = level;
+                            queue.push_back(pos);
+                        }
+                    }
+                }
+            }
+        }
+        if queue.is_empty() {
+            return Self::default();
+        }
+
+        while let Some(pos) = queue.pop_front() {
+            let level = levels[region_index(pos)];
+            if level <= 1 {
+                continue;
+            }
+            for dir in [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z] {
+                let next = pos + dir;
+                if next.cmplt(IVec3::splat(-REACH)).any() || next.cmpge(IVec3::splat(CHUNK_SIZE_I32 + REACH)).any() {
+                    continue;
+                }
+                let next_index = region_index(next);
+                let next_level = level.saturating_sub(absorption[chunks_refs.get_block(next).block_type.0 as usize]);
+                if levels[next_index] >= next_level {
+                    continue;
+                }
+                levels[next_index] = next_level;
+                queue.push_back(next);
+            }
+        }
+
+        let padded: Vec<u8> = (0..CHUNK_SIZE_P * CHUNK_SIZE_P * CHUNK_SIZE_P)
+            .map(|i| levels[region_index(index_to_ivec3_bounds(i as i32, CHUNK_SIZE_P as i32) - IVec3::ONE)])
+            .collect();
+        if padded.iter().all(|level| *level == 0) {
+            return Self::default();
+        }
+        Self { levels: padded }
+    }
+
+    /// Light level at a position local to the chunk, from -1 to [`crate::constants::CHUNK_SIZE`] inclusive.
+    pub fn get(&self, pos: IVec3) -> u8 {
+        if self.levels.is_empty() {
+            return 0;
+        }
+        self.levels[vec3_to_index(pos + IVec3::ONE, CHUNK_SIZE_P as i32)]
+    }
+
+    pub fn is_dark(&self) -> bool {
+        self.levels.is_empty()
+    }
+}
+
+/// Neighbors of `chunk` whose light can change when its `voxels` change, or all of them if `voxels` is empty.
+pub fn neighbors_in_light_reach(chunk: IVec3, voxels: &[IVec3]) -> impl Iterator<Item = IVec3> + '_ {
+    let reach = MAX_LIGHT as i32;
+    ADJACENT_CHUNK_DIRECTIONS.into_iter()
+        .filter(|dir| *dir != IVec3::ZERO)
+        .filter(move |dir| voxels.is_empty() || voxels.iter().any(|voxel| {
+            (0..3).all(|axis| match dir[axis] {
+                -1 => voxel[axis] < reach,
+                1 => voxel[axis] >= CHUNK_SIZE_I32 - reach,
+                _ => true,
+            })
+        }))
+        .map(move |dir| chunk + dir)
+}
+
+#[test]
+fn light_spreads_around_walls_and_across_chunks() {
+    use std::sync::Arc;
+
+    use bevy::color::Color;
+
+    use crate::{constants::CHUNK_SIZE3, voxel::BlockData};
+
+    let registry = BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID, BlockFlags::SOLID],
+        block_emissive: vec![Color::BLACK, Color::BLACK, Color::WHITE],
+        ..Default::default()
+    };
+    let (stone, lamp) = (BlockId(1), BlockId(2));
+
+    // A lamp in the corner of the chunk below, with a wall next to it.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    voxels[vec3_to_index(ivec3(0, 31, 0), CHUNK_SIZE_I32)].block_type = lamp;
+    voxels[vec3_to_index(ivec3(1, 31, 0), CHUNK_SIZE_I32)].block_type = stone;
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[vec3_to_index(ivec3(1, 0, 1), 3)] = Arc::new(ChunkData::Dense(voxels));
+    let light = ChunkLight::compute(&ChunksRefs::new(chunks.clone()), &registry);
+
+    assert_eq!(light.get(ivec3(0, -1, 0)), MAX_LIGHT);
+    assert_eq!(light.get(ivec3(0, 0, 0)), MAX_LIGHT - 1);
+    assert_eq!(light.get(ivec3(0, 5, 3)), MAX_LIGHT - 9);
+    // the wall is solid, the light goes around it.
+    assert_eq!(light.get(ivec3(1, -1, 0)), 0);
+    assert_eq!(light.get(ivec3(2, -1, 0)), MAX_LIGHT - 4);
+    assert_eq!(light.get(ivec3(0, 14, 0)), 0);
+
+    // nothing emits, so the chunk is dark.
+    chunks[vec3_to_index(ivec3(1, 0, 1), 3)] = Arc::new(ChunkData::Filled(stone.into()));
+    assert!(ChunkLight::compute(&ChunksRefs::new(chunks), &registry).is_dark());
+}
+
+#[test]
+fn modifications_relight_neighbors_in_reach() {
+    let chunk = IVec3::new(2, 0, -1);
+    assert_eq!(neighbors_in_light_reach(chunk, &[]).count(), 26);
+    assert_eq!(neighbors_in_light_reach(chunk, &[IVec3::splat(16)]).count(), 0);
+
+    let near_corner: Vec<IVec3> = neighbors_in_light_reach(chunk, &[ivec3(2, 16, 30)]).collect();
+    assert_eq!(near_corner.len(), 3);
+    assert!(near_corner.contains(&(chunk + ivec3(-1, 0, 1))));
+}
+
+#[test]
+fn light_dims_through_transparent_blocks() {
+    use std::sync::Arc;
+
+    use bevy::color::Color;
+
+    use crate::{constants::CHUNK_SIZE3, voxel::BlockData};
+
+    let registry = BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::TRANSPARENT, BlockFlags::SOLID],
+        block_emissive: vec![Color::BLACK, Color::BLACK, Color::WHITE],
+        block_opacity: vec![1, 4, 1],
+        ..Default::default()
+    };
+    let (glass, lamp) = (BlockId(1), BlockId(2));
+
+    // Two lamps in the middle chunk, one with a pane of glass in front of it.
+    let mut voxels = vec![BlockData::default(); CHUNK_SIZE3];
+    voxels[vec3_to_index(ivec3(4, 4, 4), CHUNK_SIZE_I32)].block_type = lamp;
+    voxels[vec3_to_index(ivec3(4, 4, 24), CHUNK_SIZE_I32)].block_type = lamp;
+    for x in 0..CHUNK_SIZE_I32 {
+        for y in 0..CHUNK_SIZE_I32 {
+            voxels[vec3_to_index(ivec3(x, y, 26), CHUNK_SIZE_I32)].block_type = glass;
+        }
+    }
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let light = ChunkLight::compute(&ChunksRefs::new(chunks), &registry);
+
+    // 3 voxels from each lamp, one of them through the glass.
+    assert_eq!(light.get(ivec3(4, 4, 7)), MAX_LIGHT - 3);
+    assert_eq!(light.get(ivec3(4, 4, 27)), MAX_LIGHT - 6);
+    assert_eq!(light.get(ivec3(4, 4, 26)), MAX_LIGHT - 5);
+}
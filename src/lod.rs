@@ -1,5 +1,7 @@
+use bevy::reflect::Reflect;
+
 /// level of detail
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Reflect)]
 pub enum Lod {
     L32,
     L16,
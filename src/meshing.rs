@@ -0,0 +1,940 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet, Instant},
+};
+use indexmap::IndexSet;
+
+use crate::{
+    chunk_mesh::{ChunkMesh, ChunkMeshSlices, DirtySlices, MeshStats},
+    chunks_refs::ChunksRefs,
+    constants::{ADJACENT_CHUNK_DIRECTIONS, FACE_ADJACENT_CHUNK_DIRECTIONS},
+    events::{ChunkGenerated, ChunkMeshUnloaded, ChunkMeshed, ChunkModified, ChunkUnloaded, ChunkVoxelsModified},
+    greedy_mesher_optimized::{build_chunk_mesh, build_chunk_mesh_slices, build_chunk_mesh_with_stats, rebuild_chunk_mesh_slices},
+    light::{neighbors_in_light_reach, ChunkLight},
+    lod::Lod,
+    scanner::{ChunkGainedScannerRelevance, ChunkLostScannerRelevance, ChunkPos, GlobalScannerDesiredChunks, MeshScanner, Scanner, VisualScanner},
+    surface_nets::{build_smooth_mesh, is_smooth_mesh_empty, SmoothMesh},
+    utils::FaceWinding,
+    voxel::{BlockFlags, BlockRegistryResource},
+    voxel_engine::{join_data, voxel_engine_joining, voxel_engine_running, ChunkTask, MeshingMethod, VoxelEngine, VoxelEngineConfig},
+};
+
+/// Schedules meshing of chunks in range of a [`Scanner<MeshScanner>`] & sends [`ChunkMeshed`] when done.
+///
+/// Doesn't depend on the `rendering` feature, so it can be used to build collision meshes on a server.
+pub struct MeshingPlugin;
+
+impl Plugin for MeshingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MeshingPipeline>()
+            .init_resource::<ChunkMeshOutputs>()
+            .init_resource::<ChunkRenderLayers>()
+            .init_resource::<VoxelEngineConfig>();
+
+        app.add_systems(PostUpdate, (
+            join_mesh.run_if(voxel_engine_joining),
+            unload_mesh.run_if(voxel_engine_running),
+            drop_unloaded_chunks.run_if(voxel_engine_running),
+            start_mesh_tasks.after(join_data).run_if(voxel_engine_running).run_if(resource_exists::<BlockRegistryResource>),
+        ).chain());
+    }
+}
+
+/// Which meshes are built for each chunk.
+/// Changing this only affects chunks meshed afterwards.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkMeshOutputs {
+    /// Build a mesh for each of the [`ChunkRenderLayers`].
+    pub render: bool,
+    /// Build a mesh of all [`BlockFlags::COLLISION`] blocks, without block types or ambient occlusion.
+    pub collision: bool,
+}
+impl Default for ChunkMeshOutputs {
+    fn default() -> Self {
+        Self {
+            render: true,
+            collision: false,
+        }
+    }
+}
+
+/// A mesh built for each chunk from the blocks with `flags`, which the rendering plugin draws with its own material.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkRenderLayer {
+    pub name: String,
+    /// Blocks with all of these flags are meshed into the layer, full blocks among them hide each others faces.
+    pub flags: BlockFlags,
+    #[cfg(feature = "rendering")]
+    pub alpha_mode: AlphaMode,
+    /// Draw the back of faces too, for [`crate::voxel::BlockShape::Cross`] blocks which can be seen from both sides.
+    #[cfg(feature = "rendering")]
+    pub double_sided: bool,
+    /// Front face of the triangles in the layer's meshes. The rendering plugin culls the back faces to match.
+    pub winding: FaceWinding,
+    /// Index every triangle a second time with the opposite winding, for renderers that always cull back faces.
+    /// Doubles the indices, the rendering plugin draws [`ChunkRenderLayer::double_sided`] layers without it.
+    pub double_sided_indices: bool,
+}
+impl ChunkRenderLayer {
+    /// An opaque layer.
+    pub fn new(name: impl Into<String>, flags: BlockFlags) -> Self {
+        Self {
+            name: name.into(),
+            flags,
+            #[cfg(feature = "rendering")]
+            alpha_mode: AlphaMode::Opaque,
+            #[cfg(feature = "rendering")]
+            double_sided: false,
+            winding: FaceWinding::CounterClockwise,
+            double_sided_indices: false,
+        }
+    }
+
+    #[cfg(feature = "rendering")]
+    pub fn with_alpha_mode(mut self, alpha_mode: AlphaMode) -> Self {
+        self.alpha_mode = alpha_mode;
+        self
+    }
+
+    #[cfg(feature = "rendering")]
+    pub fn with_double_sided(mut self) -> Self {
+        self.double_sided = true;
+        self
+    }
+
+    pub fn with_winding(mut self, winding: FaceWinding) -> Self {
+        self.winding = winding;
+        self
+    }
+
+    pub fn with_double_sided_indices(mut self) -> Self {
+        self.double_sided_indices = true;
+        self
+    }
+}
+
+/// The layers chunks are meshed into when [`ChunkMeshOutputs::render`] is set, see [`ChunkMeshed::layers`].
+/// A block can end up in several layers if it has the flags of each.
+/// Changing this only affects chunks meshed afterwards.
+///
+/// Defaults to a [`BlockFlags::SOLID`] layer & a premultiplied alpha [`BlockFlags::TRANSPARENT`] layer.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct ChunkRenderLayers(pub Vec<ChunkRenderLayer>);
+impl Default for ChunkRenderLayers {
+    fn default() -> Self {
+        let opaque = ChunkRenderLayer::new("Opaque", BlockFlags::SOLID);
+        let transparent = ChunkRenderLayer::new("Transparent", BlockFlags::TRANSPARENT);
+        #[cfg(feature = "rendering")]
+        let transparent = transparent.with_alpha_mode(AlphaMode::Premultiplied);
+
+        Self(vec![opaque, transparent])
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct MeshingPipeline {
+    pub load_mesh_queue: IndexSet<IVec3>,
+    pub unload_mesh_queue: Vec<IVec3>,
+    pub mesh_tasks: Vec<(IVec3, Option<ChunkTask<MeshTask>>)>,
+    /// Finished mesh tasks waiting to be sent, see [`MeshingPipeline::max_meshes_per_frame`].
+    pub finished_meshes: HashMap<IVec3, MeshTask>,
+    /// Caps how many finished mesh tasks are sent as [`ChunkMeshed`] each frame, the ones nearest to a scanner first.
+    /// Spreads out building & spawning meshes when many tasks finish at once, like after teleporting. `None` sends all of them.
+    pub max_meshes_per_frame: Option<usize>,
+    /// Number of finished mesh tasks sent the last time [`join_mesh`] ran.
+    pub meshes_finalized: usize,
+
+    pub vertex_diagnostic: HashMap<IVec3, i32>,
+    /// Greedy meshing stats of each meshed chunk's render layers.
+    pub mesh_stats: HashMap<IVec3, MeshStats>,
+
+    /// Sliced meshes kept for [`MeshingMethod::IncrementalBinaryGreedy`].
+    pub mesh_slices: HashMap<IVec3, IncrementalChunkMesh>,
+    /// Slices to rebuild the next time a chunk is incrementally remeshed, chunks without an entry are rebuilt entirely.
+    pub dirty_slices: HashMap<IVec3, DirtySlices>,
+
+    /// Chunks which have been meshed, including ones whose meshes turned out empty.
+    meshed: HashSet<IVec3>,
+    /// Chunks which lost mesh relevance within a [`VisualScanner`], their meshes are kept without being remeshed.
+    frozen: HashSet<IVec3>,
+
+    /// LOD each chunk was last meshed at.
+    pub chunk_lods: HashMap<IVec3, Lod>,
+
+    /// Chunks that were found to have empty meshes without meshing them, sent as empty [`ChunkMeshed`] events when joining.
+    pub empty_meshes: Vec<IVec3>,
+    /// Total number of mesh tasks skipped because the meshes would've been empty.
+    pub skipped_mesh_tasks: usize,
+    /// Number of queued chunks that couldn't be meshed the last time [`start_mesh_tasks`] ran, because a neighbor they read has no data.
+    /// Chunks stuck here usually mean the data scanners don't reach one chunk past the mesh scanners.
+    pub mesh_blocked_on_neighbor: usize,
+}
+
+impl MeshingPipeline {
+    /// Returns true if the chunk has been meshed & not unloaded since.
+    ///
+    /// Meshing lags behind [`VoxelEngine::is_data_loaded`], a chunk is only meshed once all its neighbors have data.
+    pub fn is_meshed(&self, chunk_pos: IVec3) -> bool {
+        self.meshed.contains(&chunk_pos)
+    }
+
+    /// Returns true if the chunk's mesh is kept as is because it's only in range of a [`VisualScanner`].
+    /// Frozen chunks aren't [`MeshingPipeline::is_meshed`], they're meshed again once a mesh scanner reaches them.
+    pub fn is_frozen(&self, chunk_pos: IVec3) -> bool {
+        self.frozen.contains(&chunk_pos)
+    }
+
+    /// LOD the chunk was last meshed at, `None` if it hasn't been meshed.
+    /// Chunks are remeshed in place when the LOD [`Scanner::with_lod_bands`] or [`VoxelEngine::lod`] give them changes.
+    pub fn meshed_lod(&self, chunk_pos: IVec3) -> Option<Lod> {
+        self.chunk_lods.get(&chunk_pos).copied()
+    }
+
+    /// Positions of all meshed chunks, see [`MeshingPipeline::is_meshed`].
+    pub fn meshed_chunk_positions(&self) -> impl Iterator<Item = IVec3> + '_ {
+        self.meshed.iter().copied()
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct IncrementalChunkMesh {
+    /// Indexed by [`ChunkRenderLayers`].
+    pub layers: Vec<Option<ChunkMeshSlices>>,
+    pub collision: Option<ChunkMeshSlices>,
+}
+
+pub struct MeshTask {
+    layers: Vec<(usize, ChunkMesh)>,
+    collision: Option<ChunkMesh>,
+    smooth: Option<SmoothMesh>,
+    slices: Option<IncrementalChunkMesh>,
+    stats: MeshStats,
+}
+
+/// begin mesh building tasks for chunks in range
+#[allow(clippy::too_many_arguments)]
+pub fn start_mesh_tasks(
+    mut mesh_pipeline: ResMut<MeshingPipeline>,
+    voxel_engine: Res<VoxelEngine>,
+    scanners: Query<(&Scanner<MeshScanner>, &ChunkPos)>,
+    changed_scanners: Query<(Ref<Scanner<MeshScanner>>, Ref<ChunkPos>)>,
+    block_registry: Res<BlockRegistryResource>,
+    outputs: Res<ChunkMeshOutputs>,
+    render_layers: Res<ChunkRenderLayers>,
+    mut chunk_gained_mesh_relevance: EventReader<ChunkGainedScannerRelevance<MeshScanner>>,
+    mut chunk_modified: EventReader<ChunkModified>,
+    mut chunk_voxels_modified: EventReader<ChunkVoxelsModified>,
+    mut chunk_generated: EventReader<ChunkGenerated>,
+    global_mesh_scanner_chunks: Res<GlobalScannerDesiredChunks<MeshScanner>>,
+    config: Res<VoxelEngineConfig>,
+    mut last_default_lod: Local<Option<Lod>>,
+) {
+
+    let VoxelEngine {
+        world_data,
+        lod,
+        generate_skirts,
+        ambient_occlusion,
+        block_light,
+        face_cull_rule,
+        merge_orders,
+        meshing_method,
+        ..
+    } = voxel_engine.as_ref();
+    // light reaches into neighboring chunks, so their meshes need every neighbor too.
+    // Smooth surfaces read the diagonal neighbors for the cells along the chunk's edges.
+    let all_neighbors = *ambient_occlusion || *block_light || *meshing_method == MeshingMethod::SmoothDensity;
+
+    // Chunks which aren't modified themselves but whose light may have changed.
+    let mut relit = Vec::new();
+    for ChunkVoxelsModified { chunk, voxels } in chunk_voxels_modified.read() {
+        if *block_light {
+            relit.extend(neighbors_in_light_reach(*chunk, voxels).filter(|neighbor| global_mesh_scanner_chunks.chunks.contains(neighbor)));
+        }
+        if *meshing_method != MeshingMethod::IncrementalBinaryGreedy || !global_mesh_scanner_chunks.chunks.contains(chunk) {
+            continue;
+        }
+        let dirty = mesh_pipeline.dirty_slices.entry(*chunk).or_default();
+        // changed light can reach any slice.
+        if voxels.is_empty() || *block_light {
+            *dirty = DirtySlices::ALL;
+        } else {
+            voxels.iter().for_each(|voxel| dirty.mark_voxel(*voxel));
+        }
+    }
+    for chunk in &relit {
+        mesh_pipeline.dirty_slices.insert(*chunk, DirtySlices::ALL);
+    }
+    
+    let chunk_lod = |chunk: IVec3| {
+        scanners.iter()
+            .filter_map(|(scanner, scan_pos)| scanner.lod_at(chunk - scan_pos.0))
+            .min_by_key(Lod::jump_index)
+            .unwrap_or(*lod)
+    };
+
+    // Remesh chunks (& their neighbors, for skirts) whose LOD differs from the one they were meshed at,
+    // because a scanner moved, its LOD bands changed or the default LOD changed.
+    let mut lod_changed = false;
+    let default_lod_changed = last_default_lod.replace(*lod).is_some_and(|last| last != *lod);
+    let scanners_changed = changed_scanners.iter().any(|(scanner, scan_pos)| scanner.is_changed() || scan_pos.is_changed());
+    if scanners_changed || default_lod_changed {
+        let changed: Vec<IVec3> = mesh_pipeline.chunk_lods.iter()
+            .filter(|(chunk, chunk_lod_before)| chunk_lod(**chunk) != **chunk_lod_before)
+            .map(|(chunk, _)| *chunk)
+            .collect();
+        for chunk in changed {
+            for dir in [IVec3::ZERO].iter().chain(FACE_ADJACENT_CHUNK_DIRECTIONS.iter()) {
+                let neighbor = chunk + *dir;
+                if mesh_pipeline.chunk_lods.contains_key(&neighbor) {
+                    mesh_pipeline.load_mesh_queue.insert(neighbor);
+                    mesh_pipeline.dirty_slices.insert(neighbor, DirtySlices::ALL);
+                    lod_changed = true;
+                }
+            }
+        }
+    }
+
+    // Chunks dropped by `drop_unloaded_chunks` are queued again once their data is back.
+    // Regenerated chunks replace all their data, so their old slices & their neighbors' borders & AO are stale.
+    let mut regenerated = false;
+    for ChunkGenerated(chunk) in chunk_generated.read() {
+        mesh_pipeline.mesh_slices.remove(chunk);
+        if global_mesh_scanner_chunks.chunks.contains(chunk) {
+            regenerated |= mesh_pipeline.load_mesh_queue.insert(*chunk);
+        }
+        for dir in ADJACENT_CHUNK_DIRECTIONS {
+            let neighbor = *chunk + dir;
+            if dir != IVec3::ZERO && mesh_pipeline.is_meshed(neighbor) {
+                mesh_pipeline.dirty_slices.insert(neighbor, DirtySlices::ALL);
+                regenerated |= mesh_pipeline.load_mesh_queue.insert(neighbor);
+            }
+        }
+    }
+
+    // Order by FURTHEST distance to any scanner.
+    // Closest chunks are at the end.
+    // We do this so we can pop from the end of the list.
+    if !chunk_gained_mesh_relevance.is_empty() || !chunk_modified.is_empty() || lod_changed || regenerated || !relit.is_empty() {
+        mesh_pipeline.load_mesh_queue.extend(chunk_gained_mesh_relevance.read().map(|e| e.chunk));
+        mesh_pipeline.load_mesh_queue.extend(relit);
+
+        mesh_pipeline.load_mesh_queue.extend(chunk_modified.read().map(|e| e.0).filter(|chunk| global_mesh_scanner_chunks.chunks.contains(chunk)));
+
+        // TODO: With many chunks in queue, this is SLOW.
+        let _span = info_span!("Sorting meshing queue by distance to scanners").entered();
+        mesh_pipeline.load_mesh_queue.sort_by_cached_key(|pos| {
+            let mut closest_distance = i32::MAX;
+            // TODO: This could use bevy_spatial for better performance.
+            for (_, scan_pos) in scanners.iter() {
+                let distance = pos.distance_squared(scan_pos.0);
+                if distance < closest_distance {
+                    closest_distance = distance;
+                }
+            }
+
+            // prewarmed chunks go last, so they're meshed first.
+            (voxel_engine.is_prewarming(*pos), -closest_distance)
+        });
+    }
+
+    mesh_pipeline.mesh_blocked_on_neighbor = 0;
+    let mut i = mesh_pipeline.load_mesh_queue.len();
+    while i > 0 && mesh_pipeline.mesh_tasks.len() < config.max_mesh_tasks {
+        i -= 1;
+
+        let world_pos = mesh_pipeline.load_mesh_queue[i];
+
+        // Wait for the previous mesh to finish & be sent, so results can't be joined out of order.
+        if mesh_pipeline.mesh_tasks.iter().any(|(pos, _)| *pos == world_pos) || mesh_pipeline.finished_meshes.contains_key(&world_pos) {
+            continue;
+        }
+
+        // Chunks outside the world never get data.
+        if config.world_bounds.is_some_and(|bounds| !bounds.contains(world_pos)) {
+            mesh_pipeline.load_mesh_queue.swap_remove(&world_pos);
+            continue;
+        }
+
+        // We can only generate a mesh if all neighbors it reads are available, see ADJACENT_CHUNK_DIRECTIONS.
+        let chunks_refs = if all_neighbors {
+            ChunksRefs::try_new_in_bounds(world_data, world_pos, config.world_bounds.as_ref())
+        } else {
+            ChunksRefs::try_new_face_neighbors_in_bounds(world_data, world_pos, block_registry.0.air(), config.world_bounds.as_ref())
+        };
+        let mut chunks_refs = match chunks_refs {
+            Ok(chunks_refs) => chunks_refs,
+            Err(missing) => {
+                trace!("Chunk {world_pos} can't be meshed yet: {missing}");
+                mesh_pipeline.mesh_blocked_on_neighbor += 1;
+                continue;
+            }
+        };
+        // the chunks read have to be done receiving structure blocks too, not only loaded.
+        if config.mesh_finalized_only && ADJACENT_CHUNK_DIRECTIONS.iter()
+            .filter(|dir| all_neighbors || dir.abs().element_sum() <= 1)
+            .map(|dir| world_pos + *dir)
+            .any(|chunk| !voxel_engine.is_finalized(chunk) && config.world_bounds.is_none_or(|bounds| bounds.contains(chunk)))
+        {
+            trace!("Chunk {world_pos} can't be meshed yet: neighbors aren't finalized");
+            mesh_pipeline.mesh_blocked_on_neighbor += 1;
+            continue;
+        }
+        mesh_pipeline.load_mesh_queue.swap_remove(&world_pos);
+
+        let llod = chunk_lod(world_pos);
+        chunks_refs.neighbor_lods = FACE_ADJACENT_CHUNK_DIRECTIONS.map(|dir| chunk_lod(world_pos + dir));
+        chunks_refs.face_cull_rule = face_cull_rule.clone();
+        chunks_refs.merge_orders = *merge_orders;
+        if mesh_pipeline.chunk_lods.insert(world_pos, llod).is_some_and(|previous| previous != llod) {
+            // Slices meshed at another LOD can't be reused.
+            mesh_pipeline.mesh_slices.remove(&world_pos);
+        }
+
+        // Fully air & fully enclosed chunks don't need a task.
+        let registry = &block_registry.0;
+        let render_empty = match meshing_method {
+            MeshingMethod::SmoothDensity => render_layers.0.first().is_none_or(|layer| is_smooth_mesh_empty(&chunks_refs, registry, layer.flags)),
+            _ => render_layers.0.iter().all(|layer| chunks_refs.is_mesh_empty(registry, layer.flags)),
+        };
+        let is_empty = (!outputs.render || render_empty)
+            && (!outputs.collision || chunks_refs.is_mesh_empty(registry, BlockFlags::COLLISION));
+        if is_empty {
+            mesh_pipeline.mesh_slices.remove(&world_pos);
+            mesh_pipeline.dirty_slices.remove(&world_pos);
+            mesh_pipeline.empty_meshes.push(world_pos);
+            mesh_pipeline.skipped_mesh_tasks += 1;
+            continue;
+        }
+        
+        let generate_skirts = *generate_skirts;
+        let ambient_occlusion = *ambient_occlusion;
+        let block_light = *block_light;
+        let block_registry = block_registry.0.clone();
+        let ChunkMeshOutputs { render, collision } = *outputs;
+        let layer_flags: Vec<BlockFlags> = if render { render_layers.0.iter().map(|layer| layer.flags).collect() } else { Vec::new() };
+        let layer_windings: Vec<(FaceWinding, bool)> = render_layers.0.iter().map(|layer| (layer.winding, layer.double_sided_indices)).collect();
+        let apply_windings = move |layers: &mut Vec<(usize, ChunkMesh)>| {
+            for (layer, mesh) in layers.iter_mut() {
+                let (winding, double_sided) = layer_windings[*layer];
+                if winding != FaceWinding::CounterClockwise || double_sided {
+                    mesh.set_winding(winding, double_sided);
+                }
+            }
+        };
+        
+        let task = match meshing_method {
+            MeshingMethod::BinaryGreedyMeshing => ChunkTask::spawn(config.threading, async move {
+                if block_light {
+                    chunks_refs.block_light = Some(ChunkLight::compute(&chunks_refs, &block_registry));
+                }
+                let mut stats = MeshStats::default();
+                let mut layers: Vec<(usize, ChunkMesh)> = layer_flags.iter().enumerate()
+                    .filter_map(|(layer, flags)| {
+                        let (mesh, layer_stats) = build_chunk_mesh_with_stats(&chunks_refs, llod, block_registry.clone(), *flags, ambient_occlusion, false, generate_skirts, None);
+                        stats = stats.combine(layer_stats);
+                        Some((layer, mesh?))
+                    })
+                    .collect();
+                if let Some(light) = &chunks_refs.block_light {
+                    layers.iter_mut().for_each(|(_, mesh)| mesh.apply_block_light(light));
+                }
+                apply_windings(&mut layers);
+
+                MeshTask {
+                    layers,
+                    collision: collision.then(|| build_chunk_mesh(&chunks_refs, llod, block_registry.clone(), BlockFlags::COLLISION, false, true, false, None)).flatten(),
+                    smooth: None,
+                    slices: None,
+                    stats,
+                }
+            }),
+            MeshingMethod::IncrementalBinaryGreedy => {
+                let previous = mesh_pipeline.mesh_slices.remove(&world_pos).unwrap_or_default();
+                // without dirty marks we can't tell what changed, e.g. a neighbor generated or the LOD changed, so everything is rebuilt.
+                let dirty = mesh_pipeline.dirty_slices.remove(&world_pos).unwrap_or(DirtySlices::ALL);
+
+                ChunkTask::spawn(config.threading, async move {
+                    let start = Instant::now();
+                    if block_light {
+                        chunks_refs.block_light = Some(ChunkLight::compute(&chunks_refs, &block_registry));
+                    }
+                    let build = |build: bool, previous: Option<ChunkMeshSlices>, flag_to_build, calculate_ao, ignore_block_type, generate_skirts| {
+                        if !build {
+                            return None;
+                        }
+
+                        Some(match previous {
+                            Some(mut slices) => {
+                                rebuild_chunk_mesh_slices(&mut slices, &chunks_refs, llod, block_registry.clone(), flag_to_build, calculate_ao, ignore_block_type, generate_skirts, &dirty);
+                                slices
+                            }
+                            None => build_chunk_mesh_slices(&chunks_refs, llod, block_registry.clone(), flag_to_build, calculate_ao, ignore_block_type, generate_skirts, &DirtySlices::ALL, None),
+                        })
+                    };
+
+                    // slices of layers that didn't exist when the chunk was last meshed are built from scratch.
+                    let mut previous_layers = previous.layers.into_iter();
+                    let slices = IncrementalChunkMesh {
+                        layers: layer_flags.iter()
+                            .map(|flags| build(true, previous_layers.next().flatten(), *flags, ambient_occlusion, false, generate_skirts))
+                            .collect(),
+                        collision: build(collision, previous.collision, BlockFlags::COLLISION, false, true, false),
+                    };
+
+                    let mut layers: Vec<(usize, ChunkMesh)> = slices.layers.iter().enumerate()
+                        .filter_map(|(layer, slices)| Some((layer, slices.as_ref()?.to_chunk_mesh()?)))
+                        .collect();
+                    if let Some(light) = &chunks_refs.block_light {
+                        layers.iter_mut().for_each(|(_, mesh)| mesh.apply_block_light(light));
+                    }
+                    apply_windings(&mut layers);
+                    let stats = layers.iter()
+                        .map(|(_, mesh)| MeshStats::from_vertices(&mesh.vertices, 0))
+                        .fold(MeshStats::default(), MeshStats::combine);
+
+                    MeshTask {
+                        layers,
+                        collision: slices.collision.as_ref().and_then(ChunkMeshSlices::to_chunk_mesh),
+                        smooth: None,
+                        slices: Some(slices),
+                        stats: MeshStats { build_micros: start.elapsed().as_micros() as u64, ..stats },
+                    }
+                })
+            }
+            MeshingMethod::SmoothDensity => ChunkTask::spawn(config.threading, async move {
+                let start = Instant::now();
+                // a single surface, from the blocks of the first layer.
+                let smooth = layer_flags.first().and_then(|flags| build_smooth_mesh(&chunks_refs, &block_registry, *flags));
+                // surface nets quads are never merged.
+                let quads = smooth.as_ref().map_or(0, |mesh| mesh.indices.len() / 6);
+
+                MeshTask {
+                    layers: Vec::new(),
+                    collision: collision.then(|| build_chunk_mesh(&chunks_refs, llod, block_registry.clone(), BlockFlags::COLLISION, false, true, false, None)).flatten(),
+                    smooth,
+                    slices: None,
+                    stats: MeshStats { quads, unit_faces_covered: quads, merge_ratio: 0.0, build_micros: start.elapsed().as_micros() as u64 },
+                }
+            }),
+        };
+
+        mesh_pipeline.mesh_tasks.push((world_pos, Some(task)));
+    }
+}
+
+/// clear meshing state of enqueued chunks
+///
+/// Chunks losing mesh relevance within a [`VisualScanner`] are frozen instead of sending [`ChunkMeshUnloaded`],
+/// which is sent once they leave it too.
+pub fn unload_mesh(
+    mut mesh_pipeline: ResMut<MeshingPipeline>,
+    mut events: EventWriter<ChunkMeshUnloaded>,
+    mut chunk_lost_mesh_relevance: EventReader<ChunkLostScannerRelevance<MeshScanner>>,
+    mut chunk_gained_mesh_relevance: EventReader<ChunkGainedScannerRelevance<MeshScanner>>,
+    mut chunk_lost_visual_relevance: EventReader<ChunkLostScannerRelevance<VisualScanner>>,
+    global_visual_scanner_chunks: Res<GlobalScannerDesiredChunks<VisualScanner>>,
+) {
+    let MeshingPipeline {
+        unload_mesh_queue,
+        load_mesh_queue,
+        vertex_diagnostic,
+        mesh_slices,
+        dirty_slices,
+        chunk_lods,
+        meshed,
+        frozen,
+        finished_meshes,
+        mesh_stats,
+        ..
+    } = mesh_pipeline.as_mut();
+
+    // gained chunks are queued for meshing, which replaces the frozen mesh.
+    for e in chunk_gained_mesh_relevance.read() {
+        frozen.remove(&e.chunk);
+    }
+    for e in chunk_lost_visual_relevance.read() {
+        if frozen.remove(&e.chunk) {
+            events.send(ChunkMeshUnloaded(e.chunk));
+        }
+    }
+
+    let mut freezing = Vec::new();
+    for e in chunk_lost_mesh_relevance.read() {
+        if global_visual_scanner_chunks.chunks.contains(&e.chunk) {
+            frozen.insert(e.chunk);
+            freezing.push(e.chunk);
+        } else {
+            unload_mesh_queue.push(e.chunk);
+        }
+    }
+
+    events.send_batch(unload_mesh_queue.iter().copied().map(ChunkMeshUnloaded));
+
+    for chunk_pos in unload_mesh_queue.drain(..).chain(freezing) {
+        mesh_slices.remove(&chunk_pos);
+        dirty_slices.remove(&chunk_pos);
+        chunk_lods.remove(&chunk_pos);
+        meshed.remove(&chunk_pos);
+        vertex_diagnostic.remove(&chunk_pos);
+        mesh_stats.remove(&chunk_pos);
+        load_mesh_queue.swap_remove(&chunk_pos);
+        finished_meshes.remove(&chunk_pos);
+    }
+}
+
+/// Drop queued & running mesh work for chunks whose data was unloaded.
+///
+/// Mesh scanners may reach further than data scanners, so these chunks don't necessarily lose mesh relevance
+/// & would otherwise wait in the queue for data that isn't coming back.
+pub fn drop_unloaded_chunks(
+    mut mesh_pipeline: ResMut<MeshingPipeline>,
+    mut chunk_unloaded: EventReader<ChunkUnloaded>,
+) {
+    let MeshingPipeline {
+        load_mesh_queue,
+        mesh_tasks,
+        finished_meshes,
+        mesh_slices,
+        dirty_slices,
+        ..
+    } = mesh_pipeline.as_mut();
+
+    for ChunkUnloaded(chunk_pos) in chunk_unloaded.read() {
+        load_mesh_queue.swap_remove(chunk_pos);
+        // Dropping a task cancels it.
+        mesh_tasks.retain(|(pos, _)| pos != chunk_pos);
+        finished_meshes.remove(chunk_pos);
+        // Regenerated data may differ from what the slices were built from.
+        mesh_slices.remove(chunk_pos);
+        dirty_slices.remove(chunk_pos);
+    }
+}
+
+/// join the multithreaded chunk mesh tasks & send their meshes out
+/// Empty meshes are sent first, then finished meshes nearest to a mesh scanner first, ties broken by chunk position.
+pub fn join_mesh(
+    mut mesh_pipeline: ResMut<MeshingPipeline>,
+    mut events: EventWriter<ChunkMeshed>,
+    scanners: Query<&ChunkPos, With<Scanner<MeshScanner>>>,
+) {
+    let MeshingPipeline {
+        mesh_tasks,
+        finished_meshes,
+        max_meshes_per_frame,
+        meshes_finalized,
+        vertex_diagnostic,
+        mesh_stats,
+        mesh_slices,
+        empty_meshes,
+        meshed,
+        chunk_lods,
+        ..
+    } = mesh_pipeline.as_mut();
+
+    for world_pos in empty_meshes.drain(..) {
+        vertex_diagnostic.insert(world_pos, 0);
+        mesh_stats.remove(&world_pos);
+        meshed.insert(world_pos);
+
+        events.send(ChunkMeshed {
+            chunk: world_pos,
+            layers: Vec::new(),
+            collision: None,
+            smooth: None,
+            lod: chunk_lods.get(&world_pos).copied().unwrap_or(Lod::L32),
+        });
+    }
+
+    for (world_pos, task_option) in mesh_tasks.iter_mut() {
+        let Some(mut task) = task_option.take() else {
+            // should never happend, because we drop None values later
+            warn!("someone modified task?");
+            continue;
+        };
+        let Some(chunk_mesh_task) = task.poll() else {
+            // failed polling, keep task alive
+            *task_option = Some(task);
+            continue;
+        };
+        finished_meshes.insert(*world_pos, chunk_mesh_task);
+    }
+    mesh_tasks.retain(|(_p, op)| op.is_some());
+
+    // Nearest to a scanner first, ties broken by position so the order doesn't depend on task completion or hash map order.
+    let mut to_finalize: Vec<IVec3> = finished_meshes.keys().copied().collect();
+    to_finalize.sort_by_cached_key(|pos| {
+        let distance = scanners.iter().map(|scan_pos| pos.distance_squared(scan_pos.0)).min().unwrap_or(i32::MAX);
+        (distance, pos.to_array())
+    });
+    if let Some(max_meshes) = *max_meshes_per_frame {
+        to_finalize.truncate(max_meshes);
+    }
+    *meshes_finalized = to_finalize.len();
+
+    for world_pos in to_finalize {
+        let Some(MeshTask { layers, collision, smooth, slices, stats }) = finished_meshes.remove(&world_pos) else {
+            continue;
+        };
+
+        if let Some(slices) = slices {
+            mesh_slices.insert(world_pos, slices);
+        }
+
+        let total_vertex_count = layers.iter().map(|(_, mesh)| mesh.vertices.len()).sum::<usize>()
+            + smooth.as_ref().map_or(0, |mesh| mesh.positions.len());
+        vertex_diagnostic.insert(world_pos, total_vertex_count as i32);
+        mesh_stats.insert(world_pos, stats);
+        meshed.insert(world_pos);
+
+        events.send(ChunkMeshed {
+            chunk: world_pos,
+            layers,
+            collision,
+            smooth,
+            lod: chunk_lods.get(&world_pos).copied().unwrap_or(Lod::L32),
+        });
+    }
+}
+
+/// App with the engine & meshing plugins running [inline](crate::voxel_engine::Threading::InlineImmediate),
+/// so tests can step it a fixed number of updates. `blocks` are registered after air, which is block 0.
+#[cfg(test)]
+pub(crate) fn test_app(blocks: &[(&str, crate::voxel::Block)], generate: impl Fn(IVec3) -> crate::chunk::GeneratedChunk + Send + Sync + 'static) -> App {
+    use std::sync::Arc;
+    use crate::{
+        chunk::ChunkGenerator,
+        voxel::{Block, BlockRegistryBuilder, BlockStringIdentifier, BlockVisibilty},
+        voxel_engine::{Threading, VoxelEnginePlugin},
+    };
+
+    let mut registry = BlockRegistryBuilder::new();
+    registry.add_block(BlockStringIdentifier(Box::from("air")), &Block { visibility: BlockVisibilty::Invisible, collision: false, ..default() }).unwrap();
+    for (name, block) in blocks {
+        registry.add_block(BlockStringIdentifier(Box::from(*name)), block).unwrap();
+    }
+
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, VoxelEnginePlugin, MeshingPlugin))
+        .insert_resource(BlockRegistryResource(registry.build()))
+        .insert_resource(VoxelEngineConfig { threading: Threading::InlineImmediate, ..default() })
+        .insert_resource(ChunkGenerator { generate: Arc::new(generate) });
+    app.finish();
+    app.cleanup();
+    app
+}
+
+#[test]
+fn unloaded_chunks_leave_the_mesh_queue() {
+    use crate::{chunk::ChunkData, scanner::DataScanner, voxel::BlockData};
+
+    let mut app = test_app(&[], |_| ChunkData::Filled(BlockData::default()).into());
+
+    let settle = |app: &mut App| {
+        for _ in 0..4 {
+            app.update();
+        }
+    };
+
+    // Data only reaches x = -2, so that chunk waits in the mesh queue for its -X neighbor.
+    let scanner = app.world_mut().spawn((Scanner::<DataScanner>::new(1, None), Scanner::<MeshScanner>::new(3, None))).id();
+    settle(&mut app);
+    let edge = IVec3::new(-2, 0, 0);
+    assert!(app.world().resource::<VoxelEngine>().is_data_loaded(edge));
+    assert!(app.world().resource::<MeshingPipeline>().load_mesh_queue.contains(&edge));
+
+    // Moving unloads its data, while it stays in range of the mesh scanner.
+    app.world_mut().entity_mut(scanner).insert(ChunkPos(IVec3::X));
+    settle(&mut app);
+    assert!(!app.world().resource::<VoxelEngine>().is_data_loaded(edge));
+    assert!(app.world().resource::<GlobalScannerDesiredChunks<MeshScanner>>().chunks.contains(&edge));
+    assert!(!app.world().resource::<MeshingPipeline>().load_mesh_queue.contains(&edge));
+
+    // Its data coming back queues it again.
+    app.world_mut().entity_mut(scanner).insert(ChunkPos(IVec3::ZERO));
+    settle(&mut app);
+    assert!(app.world().resource::<MeshingPipeline>().load_mesh_queue.contains(&edge));
+}
+
+#[test]
+fn chunks_in_visual_range_keep_their_meshes() {
+    use crate::{chunk::ChunkData, scanner::DataScanner, voxel::BlockData};
+
+    let mut app = test_app(&[], |_| ChunkData::Filled(BlockData::default()).into());
+    let mut unloaded = app.world().resource::<Events<ChunkMeshUnloaded>>().get_cursor();
+    let mut move_to = |app: &mut App, scanner: Entity, chunk: IVec3| {
+        app.world_mut().entity_mut(scanner).insert(ChunkPos(chunk));
+        for _ in 0..3 {
+            app.update();
+        }
+        let events = app.world().resource::<Events<ChunkMeshUnloaded>>();
+        unloaded.read(events).map(|event| event.0).collect::<Vec<_>>()
+    };
+
+    // meshes cover x -1..=0, visuals x -2..=1.
+    let scanner = app.world_mut().spawn((
+        Scanner::<DataScanner>::new(1, None),
+        Scanner::<MeshScanner>::new(0, None),
+        Scanner::<VisualScanner>::new(1, None),
+        ChunkPos(IVec3::ZERO),
+    )).id();
+    move_to(&mut app, scanner, IVec3::ZERO);
+
+    // x = 0 leaves the mesh scanner but not the visual one.
+    let chunk = IVec3::ZERO;
+    let unloaded_chunks = move_to(&mut app, scanner, IVec3::new(2, 0, 0));
+    assert!(!unloaded_chunks.contains(&chunk));
+    assert!(unloaded_chunks.contains(&IVec3::new(-1, 0, 0)));
+    assert!(app.world().resource::<MeshingPipeline>().is_frozen(chunk));
+
+    // back in range of the mesh scanner it's meshed as usual.
+    move_to(&mut app, scanner, IVec3::new(1, 0, 0));
+    assert!(!app.world().resource::<MeshingPipeline>().is_frozen(chunk));
+    move_to(&mut app, scanner, IVec3::new(2, 0, 0));
+    assert!(app.world().resource::<MeshingPipeline>().is_frozen(chunk));
+
+    // leaving the visual scanner unloads the mesh.
+    let unloaded_chunks = move_to(&mut app, scanner, IVec3::new(4, 0, 0));
+    assert!(unloaded_chunks.contains(&chunk));
+    assert!(!app.world().resource::<MeshingPipeline>().is_frozen(chunk));
+}
+
+#[test]
+fn chunks_are_remeshed_when_their_lod_changes() {
+    use crate::{chunk::ChunkData, scanner::DataScanner, voxel::BlockData};
+
+    let mut app = test_app(&[], |_| ChunkData::Filled(BlockData::default()).into());
+    let update = |app: &mut App| {
+        for _ in 0..4 {
+            app.update();
+        }
+    };
+
+    let scanner = app.world_mut().spawn((Scanner::<DataScanner>::new(1, None), Scanner::<MeshScanner>::new(0, None), ChunkPos(IVec3::ZERO))).id();
+    update(&mut app);
+    assert_eq!(app.world().resource::<MeshingPipeline>().meshed_lod(IVec3::ZERO), Some(Lod::L32));
+
+    // new bands without the scanner moving.
+    app.world_mut().entity_mut(scanner).insert(Scanner::<MeshScanner>::new(0, None).with_lod_bands(vec![(0, Lod::L16)]));
+    update(&mut app);
+    let pipeline = app.world().resource::<MeshingPipeline>();
+    assert_eq!(pipeline.meshed_lod(IVec3::ZERO), Some(Lod::L16));
+    // outside the band chunks use the default.
+    assert_eq!(pipeline.meshed_lod(IVec3::NEG_ONE), Some(Lod::L32));
+
+    app.world_mut().resource_mut::<VoxelEngine>().lod = Lod::L8;
+    update(&mut app);
+    let pipeline = app.world().resource::<MeshingPipeline>();
+    assert_eq!(pipeline.meshed_lod(IVec3::ZERO), Some(Lod::L16));
+    assert_eq!(pipeline.meshed_lod(IVec3::NEG_ONE), Some(Lod::L8));
+}
+
+#[test]
+fn blocks_are_meshed_into_their_render_layers() {
+    use crate::{
+        chunk::ChunkData,
+        scanner::DataScanner,
+        voxel::{Block, BlockData, BlockId, BlockVisibilty},
+    };
+
+    let glass = Block { visibility: BlockVisibilty::Transparent, ..default() };
+    let leaves = Block { visibility: BlockVisibilty::Cutout, ..default() };
+    let mut app = test_app(&[("glass", glass), ("leaves", leaves)], |chunk_pos| {
+        if chunk_pos != IVec3::ZERO {
+            return ChunkData::Filled(BlockData::default()).into();
+        }
+        let mut voxels = vec![BlockData::default(); crate::constants::CHUNK_SIZE3];
+        voxels[0].block_type = BlockId(1);
+        voxels[2].block_type = BlockId(1);
+        voxels[100].block_type = BlockId(2);
+        voxels[101].block_type = BlockId(2);
+        ChunkData::Dense(voxels).into()
+    });
+    app.insert_resource(ChunkRenderLayers(vec![
+        ChunkRenderLayer::new("Opaque", BlockFlags::SOLID),
+        ChunkRenderLayer::new("Foliage", BlockFlags::CUTOUT),
+        ChunkRenderLayer::new("Transparent", BlockFlags::TRANSPARENT),
+    ]));
+    app.world_mut().spawn((Scanner::<DataScanner>::new(1, None), Scanner::<MeshScanner>::new(0, None)));
+
+    let mut cursor = app.world().resource::<Events<ChunkMeshed>>().get_cursor();
+    let mut layers = None;
+    for _ in 0..4 {
+        app.update();
+        let events = app.world().resource::<Events<ChunkMeshed>>();
+        if let Some(meshed) = cursor.read(events).find(|meshed| meshed.chunk == IVec3::ZERO) {
+            layers = Some(meshed.layers.iter().map(|(layer, mesh)| (*layer, mesh.vertices.len())).collect::<Vec<_>>());
+        }
+    }
+
+    // The two leaves are greedy meshed into a single box, the glass blocks aren't touching.
+    assert_eq!(layers, Some(vec![(1, 6 * 4), (2, 12 * 4)]));
+}
+
+#[test]
+fn finished_meshes_are_sent_nearest_first_within_budget() {
+    use crate::{
+        chunk::ChunkData,
+        scanner::DataScanner,
+        voxel::{Block, BlockData, BlockId},
+    };
+
+    // A single block in every chunk, so none of them are skipped as empty.
+    let mut app = test_app(&[("stone", Block::default())], |_| {
+        let mut voxels = vec![BlockData::default(); crate::constants::CHUNK_SIZE3];
+        voxels[0].block_type = BlockId(1);
+        ChunkData::Dense(voxels).into()
+    });
+    // Hold back every mesh until all of them have finished.
+    app.world_mut().resource_mut::<MeshingPipeline>().max_meshes_per_frame = Some(0);
+    app.world_mut().spawn((Scanner::<DataScanner>::new(2, None), Scanner::<MeshScanner>::new(1, None)));
+
+    for _ in 0..6 {
+        app.update();
+    }
+    let (engine, pipeline) = (app.world().resource::<VoxelEngine>(), app.world().resource::<MeshingPipeline>());
+    assert!(engine.data_tasks.is_empty() && engine.load_data_queue.is_empty() && pipeline.mesh_tasks.is_empty() && pipeline.load_mesh_queue.is_empty());
+    let finished = app.world().resource::<MeshingPipeline>().finished_meshes.len();
+    assert!(finished > 1);
+
+    app.world_mut().resource_mut::<MeshingPipeline>().max_meshes_per_frame = Some(1);
+    let mut cursor = app.world().resource::<Events<ChunkMeshed>>().get_cursor();
+    let mut sent = Vec::new();
+    for _ in 0..finished {
+        app.update();
+        assert_eq!(app.world().resource::<MeshingPipeline>().meshes_finalized, 1);
+        let events = app.world().resource::<Events<ChunkMeshed>>();
+        sent.extend(cursor.read(events).map(|meshed| meshed.chunk));
+    }
+
+    assert_eq!(sent.len(), finished);
+    assert_eq!(sent[0], IVec3::ZERO);
+    assert!(sent.windows(2).all(|pair| pair[0].length_squared() <= pair[1].length_squared()));
+}
+
+#[test]
+fn neighbors_of_regenerated_chunks_are_remeshed() {
+    use crate::{chunk::ChunkData, scanner::DataScanner, voxel::{Block, BlockData, BlockId}};
+
+    let mut app = test_app(&[("stone", Block::default())], |chunk_pos| ChunkData::Filled(BlockData::new(BlockId(if chunk_pos.y < 0 { 1 } else { 0 }))).into());
+    // meshes cover -1..=0 on every axis.
+    app.world_mut().spawn((Scanner::<DataScanner>::new(1, None), Scanner::<MeshScanner>::new(0, None)));
+    for _ in 0..4 {
+        app.update();
+    }
+    let pipeline = app.world().resource::<MeshingPipeline>();
+    assert_eq!(pipeline.meshed_chunk_positions().count(), 8);
+    assert!(pipeline.load_mesh_queue.is_empty() && pipeline.mesh_tasks.is_empty());
+
+    let mut cursor = app.world().resource::<Events<ChunkMeshed>>().get_cursor_current();
+    app.world_mut().send_event(ChunkGenerated(IVec3::X));
+    let mut remeshed = HashSet::new();
+    for _ in 0..4 {
+        app.update();
+        let events = app.world().resource::<Events<ChunkMeshed>>();
+        remeshed.extend(cursor.read(events).map(|meshed| meshed.chunk));
+    }
+    // the meshed chunks touching x = 1, it isn't meshed itself.
+    let expected: HashSet<IVec3> = [IVec3::ZERO, IVec3::NEG_Y, IVec3::NEG_Z, IVec3::new(0, -1, -1)].into();
+    assert_eq!(remeshed, expected);
+}
@@ -1,3 +1,54 @@
+use bevy::math::{Vec2, Vec3};
+
+use crate::{
+    chunk_mesh::FACE_NORMALS,
+    face_direction::FaceDir,
+    utils::{get_ao_from_vertex_u32, get_block_type_from_vertex_u32, get_normal_index_from_vertex_u32, get_offset_pos_from_vertex_u32},
+    voxel::BlockId,
+};
+
+/// A quad of a chunk mesh in plain fields, for consumers other than the chunk renderer like navmeshes or lightmappers.
+/// See [`crate::greedy_mesher_optimized::build_chunk_quads`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quad {
+    /// Chunk local corner with the smallest coordinates, slab & fluid tops are lowered by half a voxel.
+    pub position: Vec3,
+    /// Size along the face's two axes in x, y, z order, e.g. x & z for up & down faces.
+    /// Cross quads are as wide as their horizontal diagonal.
+    pub size: Vec2,
+    /// `None` for the diagonal quads of [`crate::voxel::BlockShape::Cross`].
+    pub face: Option<FaceDir>,
+    pub normal: Vec3,
+    pub block: BlockId,
+    /// Ambient occlusion of each corner in vertex order, from 0 (none) to 3.
+    pub ambient_occlusion: [u32; 4],
+}
+impl Quad {
+    /// Unpacks the four vertices of a quad of a [`crate::chunk_mesh::ChunkMesh`].
+    pub fn from_vertices(vertices: [u32; 4]) -> Self {
+        let positions = vertices.map(get_offset_pos_from_vertex_u32);
+        let min = positions.into_iter().reduce(Vec3::min).unwrap();
+        let extent = positions.into_iter().reduce(Vec3::max).unwrap() - min;
+        let normal_index = get_normal_index_from_vertex_u32(vertices[0]);
+        let face = FaceDir::from_normal_index(normal_index);
+        let size = match face {
+            Some(FaceDir::Left | FaceDir::Right) => Vec2::new(extent.y, extent.z),
+            Some(FaceDir::Down | FaceDir::Up) => Vec2::new(extent.x, extent.z),
+            Some(FaceDir::Forward | FaceDir::Back) => Vec2::new(extent.x, extent.y),
+            None => Vec2::new(Vec2::new(extent.x, extent.z).length(), extent.y),
+        };
+
+        Self {
+            position: min,
+            size,
+            face,
+            normal: Vec3::from_array(FACE_NORMALS[normal_index as usize]),
+            block: BlockId(get_block_type_from_vertex_u32(vertices[0]) as u16),
+            ambient_occlusion: vertices.map(get_ao_from_vertex_u32),
+        }
+    }
+}
+
 // helper
 #[derive(Copy, Clone)]
 pub enum Direction {
@@ -1,15 +1,18 @@
 use bevy::{
+    color::palettes::css,
     asset::load_internal_asset, pbr::{MaterialPipeline, MaterialPipelineKey}, prelude::*, render::{
         mesh::MeshVertexBufferLayoutRef,
         render_resource::{
-            AsBindGroup, PolygonMode, RenderPipelineDescriptor, ShaderRef,
+            AsBindGroup, FrontFace, PolygonMode, RenderPipelineDescriptor, ShaderRef,
             SpecializedMeshPipelineError,
         }, storage::ShaderStorageBuffer,
-    }, tasks::{block_on, poll_once, AsyncComputeTaskPool, Task}, utils::HashMap
+        view::{NoFrustumCulling, VisibilitySystems},
+    }, utils::{HashMap, HashSet}
 };
-use indexmap::IndexSet;
+use indexmap::IndexMap;
+use std::{collections::VecDeque, time::Duration};
 
-use crate::{chunk_mesh::{ChunkMesh, ATTRIBUTE_VOXEL}, chunks_refs::ChunksRefs, constants::ADJACENT_CHUNK_DIRECTIONS, events::ChunkModified, scanner::{ChunkGainedScannerRelevance, ChunkLostScannerRelevance, ChunkPos, GlobalScannerDesiredChunks, MeshScanner, Scanner}, voxel::{BlockFlags, BlockRegistryResource}, voxel_engine::{join_data, MeshingMethod, VoxelEngine}};
+use crate::{break_overlay::BreakOverlayPlugin, chunk::FaceSolidity, chunk_mesh::{ATTRIBUTE_BLOCK_LIGHT, ATTRIBUTE_VOXEL}, constants::CHUNK_SIZE, events::{ChunkMeshUnloaded, ChunkMeshed, ChunkVoxelsModified}, face_direction::FaceDir, lod::Lod, meshing::{join_mesh, ChunkRenderLayers, MeshingPipeline, MeshingPlugin}, utils::{world_to_chunk, FaceWinding}, voxel::{BlockId, BlockRegistryResource}, voxel_engine::{MeshingMethod, VoxelEngine}};
 
 
 pub const CHUNK_SHADER_HANDLE: Handle<Shader> =
@@ -22,18 +25,90 @@ pub enum ChunkMaterialWireframeMode {
     Off,
 }
 
+/// Draws the bounds of every chunk with gizmos when enabled.
+/// Green chunks have a mesh, yellow ones only have data & red ones are waiting for data.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkBoundsGizmos {
+    pub enabled: bool,
+}
+
+/// Briefly outlines each modified chunk & the blocks in it that changed with gizmos when enabled, to check what was remeshed.
+/// Aqua outlines are remeshed incrementally (see [`MeshingMethod::IncrementalBinaryGreedy`]), red ones from scratch.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct RemeshGizmos {
+    pub enabled: bool,
+    /// Seconds each outline stays drawn.
+    pub duration: f32,
+}
+impl Default for RemeshGizmos {
+    fn default() -> Self {
+        Self { enabled: false, duration: 1.0 }
+    }
+}
+
+/// Hides the opaque layer meshes of chunks that no [`Camera3d`] can see into because fully solid chunk faces are in the way, see [`FaceSolidity`].
+///
+/// This is coarse & conservative: a chunk is only hidden if every path of chunks from a camera's chunk to it
+/// passes through a solid face, it doesn't look at what's inside the chunks.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkOcclusionCulling {
+    pub enabled: bool,
+}
+impl Default for ChunkOcclusionCulling {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// Settings for the chunk mesh entities.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRenderConfig {
+    /// Cull chunk meshes outside the view using their [`bevy::render::primitives::Aabb`].
+    /// Turning it off adds [`NoFrustumCulling`] to every chunk mesh, for debugging or when the bounds can't be trusted.
+    pub frustum_culling: bool,
+    /// Spawn each layer of a chunk as one mesh per face direction, each with a [`ChunkMeshFace`],
+    /// so the faces pointing away from every camera can be hidden instead of being drawn & culled on the GPU.
+    /// Costs more entities & draw calls, changing it only affects chunks meshed afterwards.
+    pub split_by_face: bool,
+    /// Crossfade chunks meshed at a new LOD from their old meshes over this long instead of swapping them at once,
+    /// drawing both with complementary dither patterns, see [`ChunkLodFade`].
+    pub lod_crossfade: Option<Duration>,
+}
+impl Default for ChunkRenderConfig {
+    fn default() -> Self {
+        Self { frustum_culling: true, split_by_face: false, lod_crossfade: None }
+    }
+}
+
 pub struct RenderingPlugin;
 
 impl Plugin for RenderingPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(MaterialPlugin::<ChunkMaterial>::default());
         app.add_plugins(MaterialPlugin::<ChunkMaterialWireframe>::default());
+        app.add_plugins(BreakOverlayPlugin);
         app.insert_resource(ChunkMaterialWireframeMode::Off);
+        app.init_resource::<ChunkBoundsGizmos>();
+        app.init_resource::<RemeshGizmos>();
+        app.init_resource::<ChunkOcclusionCulling>();
+        app.init_resource::<VisibleChunks>();
+        app.init_resource::<ChunkRenderConfig>();
 
-        app.init_resource::<MeshingPipeline>().init_resource::<ChunkMeshEntities>();
+        if !app.is_plugin_added::<MeshingPlugin>() {
+            app.add_plugins(MeshingPlugin);
+        }
+        app.init_resource::<ChunkMeshEntities>();
 
-        app.add_systems(Startup, initialize_global_chunk_materials);
-        app.add_systems(Update, apply_chunk_material);
+        // The registry may be loaded from an asset after startup.
+        app.add_systems(Update, initialize_global_chunk_materials.run_if(
+            resource_exists_and_changed::<BlockRegistryResource>.or(resource_exists::<BlockRegistryResource>.and(resource_changed::<ChunkRenderLayers>))
+        ));
+        app.add_systems(Update, initialize_smooth_chunk_material.run_if(not(resource_exists::<SmoothChunkMaterial>)));
+        app.add_systems(Update, apply_chunk_material.run_if(resource_exists::<GlobalChunkMaterial>));
+        app.add_systems(Update, update_chunk_material_time);
+        app.add_systems(Update, apply_chunk_frustum_culling.run_if(resource_changed::<ChunkRenderConfig>));
+        app.add_systems(Update, draw_chunk_bounds.run_if(|bounds: Res<ChunkBoundsGizmos>| bounds.enabled));
+        app.add_systems(Update, draw_remesh_gizmos.run_if(|remesh: Res<RemeshGizmos>| remesh.enabled));
 
         load_internal_asset!(
             app,
@@ -49,11 +124,14 @@ impl Plugin for RenderingPlugin {
             Shader::from_wgsl
         );
 
-        app.add_systems(PostUpdate, (
-            join_mesh,
-            unload_mesh,
-            start_mesh_tasks.after(join_data),
-        ).chain());
+        app.add_systems(PostUpdate, (despawn_chunk_meshes, spawn_chunk_meshes.run_if(resource_exists::<GlobalChunkMaterial>)).chain().after(join_mesh));
+        app.add_systems(PostUpdate, update_lod_crossfade.after(spawn_chunk_meshes).run_if(resource_exists::<GlobalChunkMaterial>));
+        app.add_systems(PostUpdate, (update_visible_chunks, cull_occluded_chunks).chain().after(spawn_chunk_meshes).before(VisibilitySystems::VisibilityPropagate));
+        app.add_systems(PostUpdate, cull_back_facing_faces
+            .after(update_visible_chunks)
+            .after(spawn_chunk_meshes)
+            .before(VisibilitySystems::VisibilityPropagate)
+            .run_if(|config: Res<ChunkRenderConfig>| config.split_by_face));
     }
 }
 
@@ -63,6 +141,7 @@ fn initialize_global_chunk_materials(
     mut chunk_materials: ResMut<Assets<ChunkMaterial>>,
     mut commands: Commands,
     block_registry: Res<BlockRegistryResource>,
+    render_layers: Res<ChunkRenderLayers>,
 ) {
     let colors = block_registry.0.block_color.iter().map(|color| color.to_linear().to_f32_array()).collect::<Vec<_>>();
     let colors = buffers.add(ShaderStorageBuffer::from(colors));
@@ -70,25 +149,31 @@ fn initialize_global_chunk_materials(
     let emissive = block_registry.0.block_emissive.iter().map(|color| color.to_linear().to_f32_array()).collect::<Vec<_>>();
     let emissive = buffers.add(ShaderStorageBuffer::from(emissive));
 
-    // TODO: Add transparent material.
-    
+    let flags = block_registry.0.block_flags.iter().map(|flags| flags.bits() as u32).collect::<Vec<_>>();
+    let flags = buffers.add(ShaderStorageBuffer::from(flags));
+
+    let lowering = (0..block_registry.0.block_flags.len()).map(|id| block_registry.0.shape(BlockId(id as u16)).vertex_lowering()).collect::<Vec<_>>();
+    let lowering = buffers.add(ShaderStorageBuffer::from(lowering));
+
     commands.insert_resource(GlobalChunkMaterial {
-        opaque: chunk_materials.add(ChunkMaterial {
-            reflectance: 0.5,
-            perceptual_roughness: 1.0,
-            metallic: 0.01,
-            block_colors: colors.clone(),
-            block_emissive: emissive.clone(),
-            alpha_mode: AlphaMode::Opaque
-        }),
-        transparent: chunk_materials.add(ChunkMaterial {
+        layers: render_layers.0.iter().map(|layer| chunk_materials.add(ChunkMaterial {
             reflectance: 0.5,
             perceptual_roughness: 1.0,
             metallic: 0.01,
             block_colors: colors.clone(),
             block_emissive: emissive.clone(),
-            alpha_mode: AlphaMode::Premultiplied
-        }),   
+            block_flags: flags.clone(),
+            block_lowering: lowering.clone(),
+            time: 0.0,
+            fade: 1.0,
+            alpha_cutoff: match layer.alpha_mode {
+                AlphaMode::Mask(cutoff) => cutoff,
+                _ => 0.0,
+            },
+            alpha_mode: layer.alpha_mode,
+            double_sided: layer.double_sided,
+            winding: layer.winding,
+        })).collect(),
     });
 
     
@@ -99,13 +184,62 @@ fn initialize_global_chunk_materials(
             metallic: 0.01,
             block_colors: colors.clone(),
             block_emissive: emissive.clone(),
+            block_flags: flags.clone(),
+            block_lowering: lowering.clone(),
+            time: 0.0,
+            fade: 1.0,
+            alpha_cutoff: 0.0,
         },
     )));
 }
 
+fn initialize_smooth_chunk_material(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    commands.insert_resource(SmoothChunkMaterial(materials.add(StandardMaterial {
+        base_color: Color::srgb(0.45, 0.42, 0.36),
+        perceptual_roughness: 1.0,
+        ..default()
+    })));
+}
+
+/// Keeps the `time` of the chunk materials up to date for [`crate::voxel::BlockFlags::ANIMATED_EMISSIVE`].
+fn update_chunk_material_time(
+    time: Res<Time>,
+    chunk_mat: Option<Res<GlobalChunkMaterial>>,
+    chunk_mat_wireframe: Option<Res<GlobalChunkWireframeMaterial>>,
+    mut chunk_materials: ResMut<Assets<ChunkMaterial>>,
+    mut chunk_materials_wireframe: ResMut<Assets<ChunkMaterialWireframe>>,
+) {
+    let elapsed = time.elapsed_secs();
+    if let Some(chunk_mat) = chunk_mat {
+        for handle in &chunk_mat.layers {
+            if let Some(material) = chunk_materials.get_mut(handle) {
+                material.time = elapsed;
+            }
+        }
+    }
+    if let Some(material) = chunk_mat_wireframe.and_then(|handle| chunk_materials_wireframe.get_mut(&handle.0)) {
+        material.time = elapsed;
+    }
+}
+
+/// Updates already spawned chunk meshes when [`ChunkRenderConfig::frustum_culling`] is toggled.
+fn apply_chunk_frustum_culling(
+    mut commands: Commands,
+    chunk_meshes: Query<Entity, With<ChunkMeshLayer>>,
+    render_config: Res<ChunkRenderConfig>,
+) {
+    for entity in chunk_meshes.iter() {
+        if render_config.frustum_culling {
+            commands.entity(entity).remove::<NoFrustumCulling>();
+        } else {
+            commands.entity(entity).insert(NoFrustumCulling);
+        }
+    }
+}
+
 fn apply_chunk_material(
     no_wireframe: Query<Entity, With<MeshMaterial3d<ChunkMaterial>>>,
-    wireframe: Query<(Entity, &ChunkEntityType), With<MeshMaterial3d<ChunkMaterialWireframe>>>,
+    wireframe: Query<(Entity, &ChunkMeshLayer), With<MeshMaterial3d<ChunkMaterialWireframe>>>,
     input: Res<ButtonInput<KeyCode>>,
     mut mode: ResMut<ChunkMaterialWireframeMode>,
     mut commands: Commands,
@@ -130,35 +264,253 @@ fn apply_chunk_material(
             }
         }
         F::Off => {
-            for (entity, chunk_type) in wireframe.iter() {
+            for (entity, layer) in wireframe.iter() {
+                let Some(material) = chunk_mat.layers.get(layer.0) else {
+                    continue;
+                };
                 commands
                     .entity(entity)
-                    .insert(MeshMaterial3d(match chunk_type {
-                        ChunkEntityType::Opaque => chunk_mat.opaque.clone(),
-                        ChunkEntityType::Transparent => chunk_mat.transparent.clone(),
-                    }))
+                    .insert(MeshMaterial3d(material.clone()))
                     .remove::<MeshMaterial3d<ChunkMaterialWireframe>>();
             }
         }
     }
 }
 
+fn draw_chunk_bounds(
+    mut gizmos: Gizmos,
+    voxel_engine: Res<VoxelEngine>,
+    chunk_mesh_entities: Res<ChunkMeshEntities>,
+) {
+    let chunk_size = CHUNK_SIZE as f32;
+    let mut draw = |chunk_pos: IVec3, color: Srgba| {
+        let center = (chunk_pos.as_vec3() + Vec3::splat(0.5)) * chunk_size;
+        gizmos.cuboid(Transform::from_translation(center).with_scale(Vec3::splat(chunk_size)), color);
+    };
+
+    for chunk_pos in voxel_engine.world_data.keys() {
+        let color = if chunk_mesh_entities.0.contains_key(chunk_pos) {
+            css::LIME
+        } else {
+            css::YELLOW
+        };
+        draw(*chunk_pos, color);
+    }
+
+    for chunk_pos in voxel_engine.load_data_queue.iter().chain(voxel_engine.data_tasks.keys()) {
+        draw(*chunk_pos, css::RED);
+    }
+}
+
+/// An outline drawn by [`draw_remesh_gizmos`], in world space voxels.
+struct RemeshOutline {
+    chunk: IVec3,
+    /// Bounds of the changed voxels, `None` if the whole chunk changed.
+    voxels: Option<(IVec3, IVec3)>,
+    incremental: bool,
+    expires: f32,
+}
+
+fn draw_remesh_gizmos(
+    mut gizmos: Gizmos,
+    mut outlines: Local<Vec<RemeshOutline>>,
+    mut chunk_voxels_modified: EventReader<ChunkVoxelsModified>,
+    voxel_engine: Res<VoxelEngine>,
+    mesh_pipeline: Res<MeshingPipeline>,
+    remesh: Res<RemeshGizmos>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_secs();
+    for ChunkVoxelsModified { chunk, voxels } in chunk_voxels_modified.read() {
+        let origin = *chunk * CHUNK_SIZE as i32;
+        let bounds = voxels.iter().fold(None, |bounds: Option<(IVec3, IVec3)>, voxel| {
+            let voxel = origin + *voxel;
+            Some(bounds.map_or((voxel, voxel), |(min, max)| (min.min(voxel), max.max(voxel))))
+        });
+        // slices are only kept for meshed chunks, & changes without voxels dirty every slice.
+        let incremental = voxel_engine.meshing_method == MeshingMethod::IncrementalBinaryGreedy
+            && bounds.is_some()
+            && mesh_pipeline.is_meshed(*chunk);
+        outlines.push(RemeshOutline { chunk: *chunk, voxels: bounds, incremental, expires: now + remesh.duration });
+    }
+    outlines.retain(|outline| outline.expires > now);
+
+    let chunk_size = CHUNK_SIZE as f32;
+    for outline in outlines.iter() {
+        let color = if outline.incremental { css::AQUA } else { css::RED };
+        let center = (outline.chunk.as_vec3() + Vec3::splat(0.5)) * chunk_size;
+        gizmos.cuboid(Transform::from_translation(center).with_scale(Vec3::splat(chunk_size)), color);
+        if let Some((min, max)) = outline.voxels {
+            let (min, max) = (min.as_vec3(), (max + IVec3::ONE).as_vec3());
+            gizmos.cuboid(Transform::from_translation((min + max) * 0.5).with_scale(max - min), color);
+        }
+    }
+}
+
+/// Chunks that may be visible from a camera in chunk `from`, found by flood filling through chunk faces that aren't solid.
+/// Chunks behind a solid face are visible, as the face itself is, but nothing past them through that face is.
+/// Only chunks in `face_solidity` are visited.
+pub fn visible_chunks(face_solidity: &HashMap<IVec3, FaceSolidity>, from: IVec3) -> HashSet<IVec3> {
+    let mut visible = HashSet::from_iter([from]);
+    let mut entered = HashSet::from_iter([from]);
+    let mut queue = VecDeque::from([from]);
+    while let Some(chunk) = queue.pop_front() {
+        let solidity = face_solidity.get(&chunk).copied().unwrap_or_default();
+        for face in FaceDir::ALL {
+            // The camera may be inside the solid layer along its own chunk's faces.
+            if chunk != from && solidity.is_solid(face) {
+                continue;
+            }
+            let neighbor = chunk + face.air_sample_dir();
+            let Some(neighbor_solidity) = face_solidity.get(&neighbor) else {
+                continue;
+            };
+            visible.insert(neighbor);
+            if !neighbor_solidity.is_solid(face.opposite()) && entered.insert(neighbor) {
+                queue.push_back(neighbor);
+            }
+        }
+    }
+    visible
+}
+
+/// Chunks visible from the [`Camera3d`]s' chunks, see [`ChunkOcclusionCulling`].
+#[derive(Resource, Default)]
+struct VisibleChunks {
+    camera_chunks: Vec<IVec3>,
+    chunks: HashSet<IVec3>,
+    face_solidity_version: u32,
+}
+impl VisibleChunks {
+    fn is_visible(&self, chunk_pos: IVec3) -> bool {
+        // Nothing is culled without a camera to cull for.
+        self.camera_chunks.is_empty() || self.chunks.contains(&chunk_pos)
+    }
+}
+
+/// Whether nothing of the layer can be seen behind a solid face, so it can be occlusion culled.
+fn is_opaque_layer(render_layers: &ChunkRenderLayers, layer: usize) -> bool {
+    render_layers.0.get(layer).is_some_and(|layer| layer.alpha_mode == AlphaMode::Opaque)
+}
+
+/// Recomputes the [`VisibleChunks`] when a camera moves to another chunk or the [`VoxelEngine::face_solidity`] changes.
+fn update_visible_chunks(
+    culling: Res<ChunkOcclusionCulling>,
+    voxel_engine: Res<VoxelEngine>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    mut visible: ResMut<VisibleChunks>,
+) {
+    let camera_chunks: Vec<IVec3> = if culling.enabled {
+        cameras.iter().map(|transform| world_to_chunk(transform.translation())).collect()
+    } else {
+        Vec::new()
+    };
+    if visible.camera_chunks == camera_chunks && visible.face_solidity_version == voxel_engine.face_solidity_version() {
+        return;
+    }
+    let _span = info_span!("Finding chunks visible from cameras").entered();
+    let chunks = camera_chunks.iter().flat_map(|camera_chunk| visible_chunks(&voxel_engine.face_solidity, *camera_chunk)).collect();
+    *visible = VisibleChunks { camera_chunks, chunks, face_solidity_version: voxel_engine.face_solidity_version() };
+}
+
+/// Hides the opaque layer meshes of chunks no camera can see, see [`ChunkOcclusionCulling`].
+/// Meshes split by face are left to [`cull_back_facing_faces`].
+fn cull_occluded_chunks(
+    visible: Res<VisibleChunks>,
+    render_layers: Res<ChunkRenderLayers>,
+    chunk_mesh_entities: Res<ChunkMeshEntities>,
+    children: Query<&Children>,
+    mut layers: Query<(&ChunkMeshLayer, &mut Visibility), Without<ChunkMeshFace>>,
+) {
+    for (chunk_pos, entity) in chunk_mesh_entities.0.iter() {
+        let shown = visible.is_visible(*chunk_pos);
+        for child in children.get(*entity).into_iter().flatten() {
+            let Ok((layer, mut visibility)) = layers.get_mut(*child) else {
+                continue;
+            };
+            if is_opaque_layer(&render_layers, layer.0) {
+                visibility.set_if_neq(if shown { Visibility::Inherited } else { Visibility::Hidden });
+            }
+        }
+    }
+}
+
+/// Returns true if faces in `face` direction of the chunk at `chunk_origin` can face `camera`.
+/// Faces on the far side of every plane they can lie on point away from the camera.
+pub fn face_may_be_visible(face: FaceDir, chunk_origin: Vec3, camera: Vec3) -> bool {
+    let normal = face.air_sample_dir().as_vec3();
+    let nearest_plane = Vec3::select(normal.cmplt(Vec3::ZERO), chunk_origin + Vec3::splat(CHUNK_SIZE as f32), chunk_origin);
+    normal.dot(camera - nearest_plane) > 0.0
+}
+
+/// Hides the face meshes of split chunks pointing away from every [`Camera3d`], see [`ChunkRenderConfig::split_by_face`].
+/// Double sided layers are always shown, unless they're opaque & the chunk is occluded.
+fn cull_back_facing_faces(
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    render_layers: Res<ChunkRenderLayers>,
+    visible: Res<VisibleChunks>,
+    mut faces: Query<(&ChunkMeshFace, &ChunkMeshLayer, &GlobalTransform, &mut Visibility)>,
+) {
+    let cameras: Vec<Vec3> = cameras.iter().map(GlobalTransform::translation).collect();
+    for (face, layer, transform, mut visibility) in faces.iter_mut() {
+        let occluded = is_opaque_layer(&render_layers, layer.0) && !visible.is_visible(world_to_chunk(transform.translation()));
+        let double_sided = render_layers.0.get(layer.0).is_some_and(|layer| layer.double_sided || layer.double_sided_indices);
+        // Nothing is culled without a camera to cull for.
+        let shown = !occluded && (double_sided || cameras.is_empty() || cameras.iter().any(|camera| face_may_be_visible(face.0, transform.translation(), *camera)));
+        visibility.set_if_neq(if shown { Visibility::Inherited } else { Visibility::Hidden });
+    }
+}
+
+/// Material of each of the [`ChunkRenderLayers`], recreated when the layers change.
 #[derive(Resource, Reflect)]
 pub struct GlobalChunkMaterial {
-    pub opaque: Handle<ChunkMaterial>,
-    pub transparent: Handle<ChunkMaterial>,
+    pub layers: Vec<Handle<ChunkMaterial>>,
 }
 #[derive(Resource, Reflect)]
 pub struct GlobalChunkWireframeMaterial(pub Handle<ChunkMaterialWireframe>);
+/// The single material [`ChunkMeshed::smooth`] meshes are drawn with, insert your own before the rendering plugin's to replace it.
+#[derive(Resource, Reflect)]
+pub struct SmoothChunkMaterial(pub Handle<StandardMaterial>);
+
+/// Marks a chunk's child entity drawing its [`ChunkMeshed::smooth`] mesh.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkSmoothMesh;
+
+/// Index of the [`ChunkRenderLayers`] layer a chunk's child mesh entity belongs to.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkMeshLayer(pub usize);
+
+/// Direction the faces of a chunk's child mesh entity point in, only on meshes split with [`ChunkRenderConfig::split_by_face`].
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkMeshFace(pub FaceDir);
 
-#[derive(Component)]
-pub enum ChunkEntityType {
-    Opaque,
-    Transparent,
+/// LOD a chunk entity's meshes were built at, see [`ChunkMeshed::lod`].
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkMeshLod(pub Lod);
+
+/// Dithered crossfade of a chunk's child mesh entity after its chunk was meshed at a new LOD, see [`ChunkRenderConfig::lod_crossfade`].
+/// The entity draws with its own copy of the layer's material until the fade is done, its [`ChunkMaterial::fade`] following the timer.
+#[derive(Component, Debug, Clone)]
+pub struct ChunkLodFade {
+    pub timer: Timer,
+    /// The new mesh fading in, or the old one fading out, which is despawned once it's done.
+    pub fading_in: bool,
+}
+impl ChunkLodFade {
+    pub fn new(duration: Duration, fading_in: bool) -> Self {
+        Self { timer: Timer::new(duration, TimerMode::Once), fading_in }
+    }
+
+    /// [`ChunkMaterial::fade`] of the entity, the old & new meshes of a chunk draw complementary fragments.
+    pub fn material_fade(&self) -> f32 {
+        let fraction = self.timer.fraction();
+        if self.fading_in { fraction } else { fraction - 1.0 }
+    }
 }
 
 // This is the struct that will be passed to your shader
 #[derive(Asset, Reflect, AsBindGroup, Debug, Clone)]
+#[bind_group_data(ChunkMaterialKey)]
 pub struct ChunkMaterial {
     #[uniform(0)]
     pub reflectance: f32,
@@ -166,6 +518,17 @@ pub struct ChunkMaterial {
     pub perceptual_roughness: f32,
     #[uniform(0)]
     pub metallic: f32,
+    /// Seconds since startup, used to animate emissive blocks.
+    #[uniform(0)]
+    pub time: f32,
+    /// Alpha below which fragments are discarded with [`AlphaMode::Mask`].
+    #[uniform(0)]
+    pub alpha_cutoff: f32,
+    /// Share of the fragments drawn during a LOD crossfade, picked by a 4x4 screen space dither pattern, see [`ChunkLodFade`].
+    /// From -1 to 0 it draws the fragments left out at `fade + 1` instead, so meshes at `f` & `f - 1` never overlap.
+    /// 1 draws everything, as the shared layer materials do.
+    #[uniform(0)]
+    pub fade: f32,
 
     #[storage(1,read_only)]
     pub block_colors: Handle<ShaderStorageBuffer>,
@@ -173,7 +536,34 @@ pub struct ChunkMaterial {
     #[storage(2,read_only)]
     pub block_emissive: Handle<ShaderStorageBuffer>,
 
+    /// [`crate::voxel::BlockFlags`] of each block type.
+    #[storage(3,read_only)]
+    pub block_flags: Handle<ShaderStorageBuffer>,
+
+    /// [`crate::voxel::BlockShape::vertex_lowering`] of each block type.
+    #[storage(4,read_only)]
+    pub block_lowering: Handle<ShaderStorageBuffer>,
+
     pub alpha_mode: AlphaMode,
+    /// Disables back face culling, see [`crate::meshing::ChunkRenderLayer::double_sided`].
+    pub double_sided: bool,
+    /// Front face of the meshes' triangles, see [`crate::meshing::ChunkRenderLayer::winding`].
+    pub winding: FaceWinding,
+}
+
+/// Pipeline key of a [`ChunkMaterial`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkMaterialKey {
+    double_sided: bool,
+    winding: FaceWinding,
+}
+impl From<&ChunkMaterial> for ChunkMaterialKey {
+    fn from(material: &ChunkMaterial) -> Self {
+        Self {
+            double_sided: material.double_sided,
+            winding: material.winding,
+        }
+    }
 }
 
 impl Material for ChunkMaterial {
@@ -192,10 +582,27 @@ impl Material for ChunkMaterial {
         _pipeline: &MaterialPipeline<Self>,
         descriptor: &mut RenderPipelineDescriptor,
         layout: &MeshVertexBufferLayoutRef,
-        _key: MaterialPipelineKey<Self>,
+        key: MaterialPipelineKey<Self>,
     ) -> Result<(), SpecializedMeshPipelineError> {
-        let vertex_layout = layout.0.get_layout(&[ATTRIBUTE_VOXEL.at_shader_location(0)])?;
+        // chunks meshed with block light, see `VoxelEngine::block_light`.
+        let vertex_layout = if layout.0.contains(ATTRIBUTE_BLOCK_LIGHT) {
+            descriptor.vertex.shader_defs.push("BLOCK_LIGHT".into());
+            layout.0.get_layout(&[ATTRIBUTE_VOXEL.at_shader_location(0), ATTRIBUTE_BLOCK_LIGHT.at_shader_location(1)])?
+        } else {
+            layout.0.get_layout(&[ATTRIBUTE_VOXEL.at_shader_location(0)])?
+        };
         descriptor.vertex.buffers = vec![vertex_layout];
+        descriptor.primitive.front_face = match key.bind_group_data.winding {
+            FaceWinding::CounterClockwise => FrontFace::Ccw,
+            FaceWinding::Clockwise => FrontFace::Cw,
+        };
+        if key.bind_group_data.double_sided {
+            descriptor.primitive.cull_mode = None;
+            // so the back faces are lit with flipped normals
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("CHUNK_DOUBLE_SIDED".into());
+            }
+        }
         Ok(())
     }
 
@@ -216,12 +623,29 @@ pub struct ChunkMaterialWireframe {
     pub perceptual_roughness: f32,
     #[uniform(0)]
     pub metallic: f32,
-    
+    /// Seconds since startup, used to animate emissive blocks.
+    #[uniform(0)]
+    pub time: f32,
+    /// Alpha below which fragments are discarded with [`AlphaMode::Mask`].
+    #[uniform(0)]
+    pub alpha_cutoff: f32,
+    /// Same as [`ChunkMaterial::fade`], wireframes don't crossfade.
+    #[uniform(0)]
+    pub fade: f32,
+
     #[storage(1,read_only)]
     pub block_colors: Handle<ShaderStorageBuffer>,
     
     #[storage(2,read_only)]
     pub block_emissive: Handle<ShaderStorageBuffer>,
+
+    /// [`crate::voxel::BlockFlags`] of each block type.
+    #[storage(3,read_only)]
+    pub block_flags: Handle<ShaderStorageBuffer>,
+
+    /// [`crate::voxel::BlockShape::vertex_lowering`] of each block type.
+    #[storage(4,read_only)]
+    pub block_lowering: Handle<ShaderStorageBuffer>,
 }
 
 impl Material for ChunkMaterialWireframe {
@@ -257,213 +681,469 @@ impl Material for ChunkMaterialWireframe {
     }
 }
 
-pub const MAX_MESH_TASKS: usize = 32;
-
-#[derive(Resource, Default)]
-pub struct MeshingPipeline {
-    pub load_mesh_queue: IndexSet<IVec3>,
-    pub unload_mesh_queue: Vec<IVec3>,
-    pub mesh_tasks: Vec<(IVec3, Option<Task<MeshTask>>)>,
-
-    pub vertex_diagnostic: HashMap<IVec3, i32>,
-}
-
+/// Entity of each chunk with a mesh.
+/// Chunks whose meshes are empty have no entity, use [`crate::meshing::MeshingPipeline::is_meshed`] to check if a chunk has been meshed.
 #[derive(Resource, Default)]
 pub struct ChunkMeshEntities(pub HashMap<IVec3, Entity>);
 
-pub struct MeshTask {
-    opaque: Option<ChunkMesh>,
-    transparent: Option<ChunkMesh>,
-}
-
-/// begin mesh building tasks for chunks in range
-pub fn start_mesh_tasks(
-    mut mesh_pipeline: ResMut<MeshingPipeline>,
-    voxel_engine: Res<VoxelEngine>,
-    scanners: Query<&ChunkPos, With<Scanner<MeshScanner>>>,
-    block_registry: Res<BlockRegistryResource>,
-    mut chunk_gained_mesh_relevance: EventReader<ChunkGainedScannerRelevance<MeshScanner>>,
-    mut chunk_modified: EventReader<ChunkModified>,
-    global_mesh_scanner_chunks: Res<GlobalScannerDesiredChunks<MeshScanner>>
-) {
-    let task_pool = AsyncComputeTaskPool::get();
-
-    let VoxelEngine {
-        world_data,
-        lod,
-        meshing_method,
-        ..
-    } = voxel_engine.as_ref();
-    
-    // Order by FURTHEST distance to any scanner.
-    // Closest chunks are at the end.
-    // We do this so we can pop from the end of the list.
-    if !chunk_gained_mesh_relevance.is_empty() || !chunk_modified.is_empty() {
-        mesh_pipeline.load_mesh_queue.extend(chunk_gained_mesh_relevance.read().map(|e| e.chunk));
-
-        mesh_pipeline.load_mesh_queue.extend(chunk_modified.read().map(|e| e.0).filter(|chunk| global_mesh_scanner_chunks.chunks.contains(chunk)));
-
-        // TODO: With many chunks in queue, this is SLOW.
-        let _span = info_span!("Sorting meshing queue by distance to scanners").entered();
-        mesh_pipeline.load_mesh_queue.sort_by_cached_key(|pos| {
-            let mut closest_distance = i32::MAX;
-            // TODO: This could use bevy_spatial for better performance.
-            for scan_pos in scanners.iter() {
-                let distance = pos.distance_squared(scan_pos.0);
-                if distance < closest_distance {
-                    closest_distance = distance;
-                }
-            }
-
-            -closest_distance
-        });
-    }
-
-    let mut i = mesh_pipeline.load_mesh_queue.len();
-    while i > 0 && mesh_pipeline.mesh_tasks.len() < MAX_MESH_TASKS {
-        i -= 1;
-
-        let world_pos = mesh_pipeline.load_mesh_queue[i];
-
-        // We can only generate a mesh if all neighbors are available.
-        let all_neighbors_available = ADJACENT_CHUNK_DIRECTIONS.iter().all(|&dir| {
-            world_data.contains_key(&(world_pos + dir))
-        });
-
-        if !all_neighbors_available {
-            continue;
-        }
-        mesh_pipeline.load_mesh_queue.swap_remove(&world_pos);
-
-        let Some(chunks_refs) = ChunksRefs::try_new(world_data, world_pos) else {
-            continue;
-        };
-        
-        let llod = *lod;
-        let block_registry = block_registry.0.clone();
-        
-        let task = match meshing_method {
-            MeshingMethod::BinaryGreedyMeshing => task_pool.spawn(async move {
-                MeshTask {
-                    opaque: crate::greedy_mesher_optimized::build_chunk_mesh(&chunks_refs, llod, block_registry.clone(), BlockFlags::SOLID, true, false),
-                    transparent: crate::greedy_mesher_optimized::build_chunk_mesh(&chunks_refs, llod, block_registry, BlockFlags::TRANSPARENT, true, false)
-                }
-            }),
-        };
-
-        mesh_pipeline.mesh_tasks.push((world_pos, Some(task)));
-    }
-}
-
-/// destroy enqueued, chunk mesh entities
-pub fn unload_mesh(
+/// despawn the entities of chunks whose mesh was unloaded
+pub fn despawn_chunk_meshes(
     mut commands: Commands,
-    mut mesh_pipeline: ResMut<MeshingPipeline>,
     mut chunk_mesh_entities: ResMut<ChunkMeshEntities>,
-    mut chunk_lost_mesh_relevance: EventReader<ChunkLostScannerRelevance<MeshScanner>>
+    mut chunk_mesh_unloaded: EventReader<ChunkMeshUnloaded>,
 ) {
-    let MeshingPipeline {
-        unload_mesh_queue,
-        load_mesh_queue,
-        vertex_diagnostic,
-        ..
-    } = mesh_pipeline.as_mut();
-
-    unload_mesh_queue.extend(chunk_lost_mesh_relevance.read().map(|e| e.chunk));
-
-    for chunk_pos in unload_mesh_queue.drain(..) {
-        let Some(chunk_id) = chunk_mesh_entities.0.remove(&chunk_pos) else {
+    for ChunkMeshUnloaded(chunk_pos) in chunk_mesh_unloaded.read() {
+        let Some(chunk_id) = chunk_mesh_entities.0.remove(chunk_pos) else {
             continue;
         };
 
-        vertex_diagnostic.remove(&chunk_pos);
-        
         if let Some(entity_commands) = commands.get_entity(chunk_id) {
             entity_commands.despawn_recursive();
         }
-
-        load_mesh_queue.swap_remove(&chunk_pos);
     }
 }
 
-/// join the multithreaded chunk mesh tasks, and construct a finalized chunk entity
-pub fn join_mesh(
-    mut mesh_pipeline: ResMut<MeshingPipeline>,
+/// construct finalized chunk entities from the joined chunk meshes
+///
+/// Chunk entities are placed at their corner, `chunk_pos * 32`, with the mesh vertices local to it,
+/// the same origin as [`crate::chunk::ChunkGenerator`] & [`world_to_chunk`] use, so rendering lines up with picking.
+///
+/// Remeshed chunks keep their entities & mesh assets, the new mesh replaces the old one under the same handle.
+/// Unless the chunk was meshed at a new LOD with [`ChunkRenderConfig::lod_crossfade`] on, then the old meshes fade out next to the new ones.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_chunk_meshes(
     mut chunk_mesh_entities: ResMut<ChunkMeshEntities>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
     global_chunk_material: Res<GlobalChunkMaterial>,
+    render_layers: Res<ChunkRenderLayers>,
+    render_config: Res<ChunkRenderConfig>,
+    children: Query<&Children>,
+    chunk_lods: Query<&ChunkMeshLod>,
+    layer_meshes: Query<(&ChunkMeshLayer, Option<&ChunkMeshFace>, &Mesh3d, Option<&ChunkLodFade>)>,
+    smooth_meshes: Query<&Mesh3d, With<ChunkSmoothMesh>>,
+    smooth_material: Option<Res<SmoothChunkMaterial>>,
+    mut chunk_meshed: EventMutator<ChunkMeshed>,
 ) {
-    let MeshingPipeline {
-        mesh_tasks,
-        vertex_diagnostic,
-        ..
-    } = mesh_pipeline.as_mut();
-
-    for (world_pos, task_option) in mesh_tasks.iter_mut() {
-        let Some(mut task) = task_option.take() else {
-            // should never happend, because we drop None values later
-            warn!("someone modified task?");
-            continue;
-        };
-        let Some(mut chunk_mesh_task) = block_on(poll_once(&mut task)) else {
-            // failed polling, keep task alive
-            *task_option = Some(task);
-            continue;
-        };
-        
-        // Despawn the old chunk entity if it exists.
+    // Only the newest mesh of a chunk counts, the entity of a chunk spawned this frame has no children to reuse yet.
+    let mut meshed = IndexMap::new();
+    for ChunkMeshed { chunk, layers, smooth, lod, .. } in chunk_meshed.read() {
+        meshed.insert(*chunk, (std::mem::take(layers), smooth.take(), *lod));
+    }
+
+    // copy of a layer's material to fade a single entity with.
+    let mut fade_material = |layer: usize, fade: f32| {
+        let mut material = materials.get(global_chunk_material.layers.get(layer)?)?.clone();
+        material.fade = fade;
+        Some(materials.add(material))
+    };
+
+    for (world_pos, (layers, smooth, lod)) in meshed {
         // Checking before we check the mesh because we may not get a mesh.
-        if let Some(entity) = chunk_mesh_entities.0.remove(world_pos) {
-            commands.entity(entity).despawn_recursive();
+        if layers.is_empty() && smooth.is_none() {
+            if let Some(entity) = chunk_mesh_entities.0.remove(&world_pos) {
+                commands.entity(entity).despawn_recursive();
+            }
+            continue;
         }
 
-        let mut total_vertex_count = 0;
-        if chunk_mesh_task.opaque.is_some() || chunk_mesh_task.transparent.is_some() {
-            // spawn chunk entity
-            let mut chunk_entity = commands
+        let chunk_entity = *chunk_mesh_entities.0.entry(world_pos).or_insert_with(|| {
+            commands
                 .spawn((
                     Transform::from_translation(world_pos.as_vec3() * Vec3::splat(32.0)),
                     Visibility::Inherited,
                     Name::new(format!("Chunk: {:?}", world_pos)),
-                ));
-            chunk_mesh_entities.0.insert(*world_pos, chunk_entity.id());
+                ))
+                .id()
+        });
+        let crossfade = render_config.lod_crossfade.filter(|_| chunk_lods.get(chunk_entity).is_ok_and(|previous| previous.0 != lod));
+        commands.entity(chunk_entity).insert(ChunkMeshLod(lod));
+        // meshes already fading out are left to finish.
+        let mut old_layers: Vec<(Entity, usize, Option<FaceDir>, Handle<Mesh>)> = children.get(chunk_entity).into_iter()
+            .flatten()
+            .filter_map(|child| layer_meshes.get(*child).ok()
+                .filter(|(_, _, _, fade)| fade.is_none_or(|fade| fade.fading_in))
+                .map(|(layer, face, mesh, _)| (*child, layer.0, face.map(|face| face.0), mesh.0.clone())))
+            .collect();
+        if let Some(duration) = crossfade {
+            // the new meshes get entities & assets of their own.
+            for (mesh_entity, layer, _, _) in old_layers.drain(..) {
+                match fade_material(layer, -1.0) {
+                    Some(material) => {
+                        commands.entity(mesh_entity).insert((ChunkLodFade::new(duration, false), MeshMaterial3d(material)));
+                    }
+                    None => commands.entity(mesh_entity).despawn_recursive(),
+                }
+            }
+        }
 
-            if let Some(mesh) = chunk_mesh_task.opaque.take() {
-                total_vertex_count += mesh.vertices.len();
+        for (layer, mesh) in layers {
+            // Layers may have changed since the chunk was meshed.
+            let (Some(material), Some(render_layer)) = (global_chunk_material.layers.get(layer), render_layers.0.get(layer)) else {
+                continue;
+            };
+            let parts = if render_config.split_by_face { mesh.split_by_face() } else { vec![(None, mesh)] };
 
-                let aabb = mesh.calculate_aabb();
+            for (face, mesh) in parts {
+                let Some(aabb) = mesh.calculate_aabb() else {
+                    continue;
+                };
                 let bevy_mesh = mesh.to_bevy_mesh();
+
+                if let Some(index) = old_layers.iter().position(|(_, old_layer, old_face, _)| *old_layer == layer && *old_face == face) {
+                    let (mesh_entity, _, _, mesh_handle) = old_layers.swap_remove(index);
+                    meshes.insert(&mesh_handle, bevy_mesh);
+                    commands.entity(mesh_entity).insert(aabb);
+                    continue;
+                }
+
                 let mesh_handle = meshes.add(bevy_mesh);
-                
-                chunk_entity.with_child((
-                    aabb,
-                    Mesh3d(mesh_handle),
-                    MeshMaterial3d(global_chunk_material.opaque.clone()),
-                    ChunkEntityType::Opaque,
-                    Name::new("Opaque")
-                ));
+                let fade_in = crossfade.and_then(|duration| Some((ChunkLodFade::new(duration, true), fade_material(layer, 0.0)?)));
+                commands.entity(chunk_entity).with_children(|parent| {
+                    let mut mesh_entity = parent.spawn((
+                        aabb,
+                        Mesh3d(mesh_handle),
+                        MeshMaterial3d(material.clone()),
+                        ChunkMeshLayer(layer),
+                        Name::new(render_layer.name.clone())
+                    ));
+                    if let Some((fade, material)) = fade_in {
+                        mesh_entity.insert((fade, MeshMaterial3d(material)));
+                    }
+                    if let Some(face) = face {
+                        mesh_entity.insert((ChunkMeshFace(face), Name::new(format!("{} {face:?}", render_layer.name))));
+                    }
+                    if !render_config.frustum_culling {
+                        mesh_entity.insert(NoFrustumCulling);
+                    }
+                });
             }
+        }
 
-            if let Some(mesh) = chunk_mesh_task.transparent.take() {
-                total_vertex_count += mesh.vertices.len();
+        // layers that ended up empty this time.
+        for (mesh_entity, _, _, _) in old_layers {
+            commands.entity(mesh_entity).despawn_recursive();
+        }
 
-                let aabb = mesh.calculate_aabb();
-                let bevy_mesh = mesh.to_bevy_mesh();
-                let mesh_handle = meshes.add(bevy_mesh);
-                
-                chunk_entity.with_child((
-                    aabb,
-                    Mesh3d(mesh_handle),
-                    MeshMaterial3d(global_chunk_material.transparent.clone()),
-                    ChunkEntityType::Transparent,
-                    Name::new("Transparent")
-                ));
+        // smooth meshes are always full detail, so they're replaced in place without crossfading.
+        let old_smooth = children.get(chunk_entity).into_iter().flatten().find_map(|child| Some((*child, smooth_meshes.get(*child).ok()?.0.clone())));
+        match (smooth.and_then(|mesh| Some((mesh.calculate_aabb()?, mesh))), old_smooth, &smooth_material) {
+            (Some((aabb, mesh)), Some((mesh_entity, mesh_handle)), _) => {
+                meshes.insert(&mesh_handle, mesh.to_bevy_mesh());
+                commands.entity(mesh_entity).insert(aabb);
+            }
+            (Some((aabb, mesh)), None, Some(material)) => {
+                let mesh_handle = meshes.add(mesh.to_bevy_mesh());
+                commands.entity(chunk_entity).with_children(|parent| {
+                    let mut mesh_entity = parent.spawn((aabb, Mesh3d(mesh_handle), MeshMaterial3d(material.0.clone()), ChunkSmoothMesh, Name::new("Smooth")));
+                    if !render_config.frustum_culling {
+                        mesh_entity.insert(NoFrustumCulling);
+                    }
+                });
+            }
+            (None, Some((mesh_entity, _)), _) => commands.entity(mesh_entity).despawn_recursive(),
+            _ => {}
+        }
+    }
+}
+
+/// Steps the [`ChunkLodFade`] of chunk meshes, the old meshes are despawned once they've faded out
+/// & the new ones go back to the shared layer material.
+fn update_lod_crossfade(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut fading: Query<(Entity, &mut ChunkLodFade, &ChunkMeshLayer, Option<&MeshMaterial3d<ChunkMaterial>>)>,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
+    global_chunk_material: Res<GlobalChunkMaterial>,
+) {
+    for (entity, mut fade, layer, material) in fading.iter_mut() {
+        fade.timer.tick(time.delta());
+        if fade.timer.finished() {
+            if !fade.fading_in {
+                commands.entity(entity).despawn_recursive();
+                continue;
             }
+            let mut entity_commands = commands.entity(entity);
+            entity_commands.remove::<ChunkLodFade>();
+            // meshes toggled to wireframe keep it.
+            if let (Some(_), Some(shared)) = (material, global_chunk_material.layers.get(layer.0)) {
+                entity_commands.insert(MeshMaterial3d(shared.clone()));
+            }
+            continue;
         }
+        if let Some(material) = material.and_then(|material| materials.get_mut(&material.0)) {
+            material.fade = fade.material_fade();
+            material.time = time.elapsed_secs();
+        }
+    }
+}
+
+#[test]
+fn chunks_behind_solid_faces_are_not_visible() {
+    // A row of chunks along x, with a wall at x = 2 whose faces are solid on both sides.
+    let mut face_solidity: HashMap<IVec3, FaceSolidity> = (-1..6).map(|x| (IVec3::new(x, 0, 0), FaceSolidity::NONE)).collect();
+    face_solidity.insert(IVec3::new(2, 0, 0), FaceSolidity::ALL);
+
+    let visible = visible_chunks(&face_solidity, IVec3::ZERO);
+    assert!(visible.contains(&IVec3::new(-1, 0, 0)));
+    // The wall itself can be seen, but not past it.
+    assert!(visible.contains(&IVec3::new(2, 0, 0)));
+    assert!(!visible.contains(&IVec3::new(3, 0, 0)));
+
+    // Only the wall's far side being solid doesn't hide anything, it can be entered but not left.
+    face_solidity.insert(IVec3::new(2, 0, 0), FaceSolidity(1 << FaceDir::Right.normal_index()));
+    assert!(!visible_chunks(&face_solidity, IVec3::ZERO).contains(&IVec3::new(3, 0, 0)));
+    // Unless there's a way around.
+    face_solidity.insert(IVec3::new(2, 1, 0), FaceSolidity::NONE);
+    face_solidity.insert(IVec3::new(1, 1, 0), FaceSolidity::NONE);
+    face_solidity.insert(IVec3::new(3, 1, 0), FaceSolidity::NONE);
+    assert!(visible_chunks(&face_solidity, IVec3::ZERO).contains(&IVec3::new(3, 0, 0)));
+}
+
+#[test]
+fn only_the_opaque_layers_of_occluded_chunks_are_hidden() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = World::new();
+    world.init_resource::<ChunkOcclusionCulling>();
+    world.init_resource::<VisibleChunks>();
+    world.init_resource::<ChunkRenderLayers>();
+    world.init_resource::<ChunkMeshEntities>();
+    // a wall at x = 1 between the camera & the chunk at x = 2.
+    let mut voxel_engine = VoxelEngine::default();
+    voxel_engine.face_solidity = (0..3).map(|x| (IVec3::new(x, 0, 0), FaceSolidity::NONE)).collect();
+    voxel_engine.face_solidity.insert(IVec3::X, FaceSolidity::ALL);
+    world.insert_resource(voxel_engine);
+    world.spawn((Camera3d::default(), GlobalTransform::from_translation(Vec3::splat(16.0))));
+
+    let chunk = world.spawn(Visibility::Inherited).id();
+    let opaque = world.spawn((ChunkMeshLayer(0), Visibility::Inherited)).set_parent(chunk).id();
+    let transparent = world.spawn((ChunkMeshLayer(1), Visibility::Inherited)).set_parent(chunk).id();
+    world.resource_mut::<ChunkMeshEntities>().0.insert(IVec3::new(2, 0, 0), chunk);
+
+    world.run_system_once(update_visible_chunks).unwrap();
+    world.run_system_once(cull_occluded_chunks).unwrap();
+    assert_eq!(world.get::<Visibility>(chunk), Some(&Visibility::Inherited));
+    assert_eq!(world.get::<Visibility>(opaque), Some(&Visibility::Hidden));
+    assert_eq!(world.get::<Visibility>(transparent), Some(&Visibility::Inherited));
+}
+
+#[test]
+fn remeshed_chunks_reuse_their_mesh_assets() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use crate::{chunk_mesh::ChunkMesh, utils::generate_indices};
+
+    let mut world = World::new();
+    world.init_resource::<Assets<Mesh>>();
+    world.init_resource::<Assets<ChunkMaterial>>();
+    world.init_resource::<Events<ChunkMeshed>>();
+    world.init_resource::<ChunkMeshEntities>();
+    world.init_resource::<ChunkRenderLayers>();
+    world.init_resource::<ChunkRenderConfig>();
+    world.insert_resource(GlobalChunkMaterial { layers: vec![Handle::default(), Handle::default()] });
+
+    let mesh_chunk = |world: &mut World, quads: usize, layers: Vec<usize>| {
+        world.send_event(ChunkMeshed {
+            chunk: IVec3::ZERO,
+            layers: layers.into_iter().map(|layer| (layer, ChunkMesh { indices: generate_indices(quads * 4), vertices: vec![0; quads * 4], light: Vec::new() })).collect(),
+            collision: None,
+            smooth: None,
+            lod: Lod::L32,
+        });
+        world.run_system_once(spawn_chunk_meshes).unwrap();
+        let chunk_entity = world.resource::<ChunkMeshEntities>().0.get(&IVec3::ZERO).copied();
+        let layer_meshes: Vec<(usize, AssetId<Mesh>)> = world.query::<(&ChunkMeshLayer, &Mesh3d)>().iter(world).map(|(layer, mesh)| (layer.0, mesh.id())).collect();
+        (chunk_entity, layer_meshes)
+    };
+
+    let (entity, meshes) = mesh_chunk(&mut world, 1, vec![0, 1]);
+    assert_eq!(meshes.len(), 2);
+    let opaque = meshes.iter().find(|(layer, _)| *layer == 0).unwrap().1;
+
+    // the opaque mesh is replaced in place, the transparent layer is gone.
+    let (remeshed_entity, remeshed) = mesh_chunk(&mut world, 3, vec![0]);
+    assert_eq!(remeshed_entity, entity);
+    assert_eq!(remeshed, [(0, opaque)]);
+    assert_eq!(world.resource::<Assets<Mesh>>().get(opaque).unwrap().count_vertices(), 3 * 4);
+
+    assert_eq!(mesh_chunk(&mut world, 0, Vec::new()).0, None);
+}
+
+/// Columns of stone in a 4x4 checkerboard, filling the bottom half of every chunk.
+#[cfg(test)]
+fn checkerboard_chunk(_chunk_pos: IVec3) -> crate::chunk::GeneratedChunk {
+    use crate::{chunk::ChunkData, constants::CHUNK_SIZE3, utils::index_to_ivec3, voxel::BlockData};
 
-        vertex_diagnostic.insert(*world_pos, total_vertex_count as i32);
+    let voxels = (0..CHUNK_SIZE3)
+        .map(|i| {
+            let pos = index_to_ivec3(i);
+            let solid = pos.y < 16 && (pos.x / 4 + pos.z / 4) % 2 == 0;
+            BlockData::new(BlockId(if solid { 1 } else { 0 }))
+        })
+        .collect();
+    ChunkData::Dense(voxels).into()
+}
+
+#[test]
+fn scanned_chunks_end_up_as_mesh_entities() {
+    use crate::{
+        meshing::test_app,
+        scanner::{DataScanner, MeshScanner, Scanner},
+        voxel::Block,
+    };
+
+    // the parts of the rendering plugin that don't need a renderer.
+    let mut app = test_app(&[("stone", Block::default())], checkerboard_chunk);
+    app.init_resource::<Assets<Mesh>>()
+        .init_resource::<Assets<ChunkMaterial>>()
+        .init_resource::<ChunkMeshEntities>()
+        .init_resource::<ChunkRenderConfig>()
+        .insert_resource(GlobalChunkMaterial { layers: vec![Handle::default(), Handle::default()] })
+        .add_systems(PostUpdate, (despawn_chunk_meshes, spawn_chunk_meshes).chain().after(join_mesh));
+    app.world_mut().spawn((Scanner::<DataScanner>::new(1, None), Scanner::<MeshScanner>::new(0, None)));
+
+    // scanned, generated, meshed & spawned.
+    for _ in 0..4 {
+        app.update();
+    }
+
+    // every chunk of the mesh scanner's 2x2x2 box, each with a non-empty opaque mesh.
+    let world = app.world_mut();
+    let chunk_entities = world.resource::<ChunkMeshEntities>().0.clone();
+    assert_eq!(chunk_entities.len(), 8);
+    assert!(chunk_entities.keys().all(|chunk_pos| chunk_pos.cmpge(IVec3::NEG_ONE).all() && chunk_pos.cmple(IVec3::ZERO).all()));
+    let layer_meshes: Vec<(Entity, AssetId<Mesh>)> = world.query::<(&Parent, &Mesh3d)>().iter(world).map(|(parent, mesh)| (parent.get(), mesh.id())).collect();
+    assert_eq!(layer_meshes.len(), 8);
+    let meshes = world.resource::<Assets<Mesh>>();
+    for (chunk_entity, mesh) in layer_meshes {
+        assert!(chunk_entities.values().any(|entity| *entity == chunk_entity));
+        assert!(meshes.get(mesh).unwrap().count_vertices() > 0);
     }
+}
+
+#[test]
+fn faces_pointing_away_from_the_camera_are_not_visible() {
+    let origin = Vec3::new(32.0, 0.0, 0.0);
+    // looking at the chunk from -X.
+    let camera = Vec3::new(0.0, 16.0, 16.0);
+    assert!(face_may_be_visible(FaceDir::Left, origin, camera));
+    assert!(!face_may_be_visible(FaceDir::Right, origin, camera));
+    // the camera is between the chunk's top & bottom, so both can be seen.
+    assert!(face_may_be_visible(FaceDir::Up, origin, camera) && face_may_be_visible(FaceDir::Down, origin, camera));
+    // inside the chunk every face can be seen.
+    assert!(FaceDir::ALL.iter().all(|face| face_may_be_visible(*face, origin, origin + Vec3::splat(16.0))));
+}
+
+#[test]
+fn lod_changes_crossfade_the_old_meshes() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use crate::{chunk_mesh::ChunkMesh, utils::generate_indices};
+
+    let mut world = World::new();
+    world.init_resource::<Assets<Mesh>>();
+    world.init_resource::<Events<ChunkMeshed>>();
+    world.init_resource::<ChunkMeshEntities>();
+    world.init_resource::<ChunkRenderLayers>();
+    world.init_resource::<Time>();
+    world.insert_resource(ChunkRenderConfig { lod_crossfade: Some(Duration::from_secs(1)), ..default() });
+    let mut materials = Assets::<ChunkMaterial>::default();
+    let shared = materials.add(ChunkMaterial {
+        reflectance: 0.5,
+        perceptual_roughness: 1.0,
+        metallic: 0.0,
+        time: 0.0,
+        alpha_cutoff: 0.0,
+        fade: 1.0,
+        block_colors: Handle::default(),
+        block_emissive: Handle::default(),
+        block_flags: Handle::default(),
+        block_lowering: Handle::default(),
+        alpha_mode: AlphaMode::Opaque,
+        double_sided: false,
+        winding: FaceWinding::CounterClockwise,
+    });
+    world.insert_resource(materials);
+    world.insert_resource(GlobalChunkMaterial { layers: vec![shared.clone()] });
+
+    let mesh_chunk = |world: &mut World, lod: Lod| {
+        world.send_event(ChunkMeshed {
+            chunk: IVec3::ZERO,
+            layers: vec![(0, ChunkMesh { indices: generate_indices(4), vertices: vec![0; 4], light: Vec::new() })],
+            collision: None,
+            smooth: None,
+            lod,
+        });
+        world.run_system_once(spawn_chunk_meshes).unwrap();
+    };
+    let fades = |world: &mut World| {
+        let mut fades: Vec<(bool, f32)> = world.query::<(&ChunkLodFade, &MeshMaterial3d<ChunkMaterial>)>().iter(world)
+            .map(|(fade, material)| (fade.fading_in, world.resource::<Assets<ChunkMaterial>>().get(&material.0).unwrap().fade))
+            .collect();
+        fades.sort_by_key(|(fading_in, _)| *fading_in);
+        fades
+    };
+
+    // remeshing at the same LOD swaps the mesh at once.
+    mesh_chunk(&mut world, Lod::L32);
+    mesh_chunk(&mut world, Lod::L32);
+    assert!(fades(&mut world).is_empty());
+
+    // the old & new meshes get their own materials, drawing complementary fragments.
+    mesh_chunk(&mut world, Lod::L16);
+    assert_eq!(world.query::<&ChunkMeshLayer>().iter(&world).count(), 2);
+    assert_eq!(fades(&mut world), [(false, -1.0), (true, 0.0)]);
+
+    world.resource_mut::<Time>().advance_by(Duration::from_millis(250));
+    world.run_system_once(update_lod_crossfade).unwrap();
+    assert_eq!(fades(&mut world), [(false, -0.75), (true, 0.25)]);
+
+    // once done only the new mesh is left, back on the shared material.
+    world.resource_mut::<Time>().advance_by(Duration::from_secs(1));
+    world.run_system_once(update_lod_crossfade).unwrap();
+    let meshes: Vec<Handle<ChunkMaterial>> = world.query_filtered::<&MeshMaterial3d<ChunkMaterial>, With<ChunkMeshLayer>>().iter(&world).map(|material| material.0.clone()).collect();
+    assert_eq!(meshes, [shared]);
+    assert!(fades(&mut world).is_empty());
+}
 
-    mesh_pipeline.mesh_tasks.retain(|(_p, op)| op.is_some());
-}
\ No newline at end of file
+#[test]
+fn meshes_line_up_with_world_to_chunk() {
+    use std::sync::Arc;
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    use crate::{
+        chunk::ChunkData, chunks_refs::ChunksRefs, greedy_mesher_optimized::build_chunk_mesh,
+        utils::{get_pos_from_vertex_u32, world_to_chunk_local_voxel}, voxel::{BlockData, BlockFlags, BlockRegistry},
+    };
+
+    // a single block at the generator's local (0, 0, 0) of a chunk below & behind the origin.
+    let chunk_pos = IVec3::new(-2, 1, -1);
+    let mut chunk = ChunkData::Filled(BlockData::default());
+    chunk.set_block(IVec3::ZERO, BlockId(1));
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(chunk);
+    let registry = Arc::new(BlockRegistry { block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID], ..Default::default() });
+    let mesh = build_chunk_mesh(&ChunksRefs::new(chunks), Lod::L32, registry, BlockFlags::SOLID, false, false, false, None).unwrap();
+    let block_min = mesh.vertices.iter().map(|vertex| get_pos_from_vertex_u32(*vertex)).reduce(IVec3::min).unwrap();
+
+    let mut world = World::new();
+    world.init_resource::<Assets<Mesh>>();
+    world.init_resource::<Assets<ChunkMaterial>>();
+    world.init_resource::<Events<ChunkMeshed>>();
+    world.init_resource::<ChunkMeshEntities>();
+    world.init_resource::<ChunkRenderLayers>();
+    world.init_resource::<ChunkRenderConfig>();
+    world.insert_resource(GlobalChunkMaterial { layers: vec![Handle::default()] });
+    world.send_event(ChunkMeshed { chunk: chunk_pos, layers: vec![(0, mesh)], collision: None, smooth: None, lod: Lod::L32 });
+    world.run_system_once(spawn_chunk_meshes).unwrap();
+
+    let entity = world.resource::<ChunkMeshEntities>().0[&chunk_pos];
+    let rendered = world.get::<Transform>(entity).unwrap().translation + block_min.as_vec3();
+    assert_eq!(rendered, (chunk_pos * 32).as_vec3());
+    // the middle of the rendered block maps back to the chunk & local voxel it was generated at.
+    assert_eq!(world_to_chunk(rendered + Vec3::splat(0.5)), chunk_pos);
+    assert_eq!(world_to_chunk_local_voxel(rendered.as_ivec3()), IVec3::ZERO);
+}
@@ -2,14 +2,9 @@ use std::marker::PhantomData;
 
 use bevy::{prelude::*, utils::HashSet};
 
-use crate::
-    utils::world_to_chunk
-;
-
-pub const MAX_DATA_TASKS: usize = 9;
-pub const MAX_MESH_TASKS: usize = 3;
-
-pub const MAX_SCANS: usize = 26000;
+use crate::{
+    lod::Lod, utils::world_to_chunk, voxel_engine::voxel_engine_running
+};
 
 pub struct ChunkTrackerPlugin;
 
@@ -31,11 +26,14 @@ pub struct ScannerPlugin<T: Send + Sync + Default + 'static> {
 
 impl<T: Send + Sync + Default + 'static> Plugin for ScannerPlugin<T> {
     fn build(&self, app: &mut App) {
-        app.init_resource::<GlobalScannerDesiredChunks<T>>();
+        app.init_resource::<GlobalScannerDesiredChunks<T>>()
+            .init_resource::<ForceLoadedChunks<T>>();
 
         app.add_systems(
             PreUpdate,
-            scan::<T>.after(update_chunk_pos).run_if(any_with_component::<Scanner<T>>.or(any_component_removed::<Scanner<T>>)),
+            scan::<T>.after(update_chunk_pos).run_if(voxel_engine_running.and(
+                any_with_component::<Scanner<T>>.or(any_component_removed::<Scanner<T>>).or(resource_changed::<ForceLoadedChunks<T>>)
+            )),
         );
 
         app.add_event::<ChunkGainedScannerRelevance<T>>()
@@ -51,17 +49,77 @@ pub struct TrackChunkPos;
 #[reflect(Component)]
 pub struct ChunkPos(pub IVec3);
 
-/// Iterates over chunks in a box around the center, within the given radius.
-fn iter_chunks_around(center: IVec3, horizontal_radius: i32, vertical_radius: i32) -> impl Iterator<Item = IVec3> {
+/// Shape of the region a [`Scanner`] keeps loaded.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub enum ScanShape {
+    #[default]
+    Box,
+    /// Circular horizontally, with the vertical radius as a separate clamp.
+    Cylinder,
+    /// Ellipsoid using the horizontal & vertical radius.
+    Sphere,
+}
+impl ScanShape {
+    /// Fraction of the bounding box the shape covers.
+    fn volume_factor(&self) -> f32 {
+        match self {
+            ScanShape::Box => 1.0,
+            ScanShape::Cylinder => std::f32::consts::FRAC_PI_4,
+            ScanShape::Sphere => std::f32::consts::FRAC_PI_6,
+        }
+    }
+}
+
+/// Breaks ties between chunks the same distance from a [`Scanner<DataScanner>`] when ordering generation.
+#[derive(Default, Clone, Copy, Debug)]
+pub enum PriorityBias {
+    /// Only the distance matters.
+    #[default]
+    None,
+    /// Chunks below the scanner first, like the ground under a falling player.
+    PreferBelow,
+    PreferAbove,
+    /// Called with the chunk's offset from the scanner, lower keys are generated first.
+    Custom(fn(IVec3) -> i32),
+}
+impl PriorityBias {
+    /// Sort key of the chunk at `offset` from the scanner, lower goes first.
+    pub fn key(&self, offset: IVec3) -> i32 {
+        match self {
+            PriorityBias::None => 0,
+            PriorityBias::PreferBelow => offset.y,
+            PriorityBias::PreferAbove => -offset.y,
+            PriorityBias::Custom(key) => key(offset),
+        }
+    }
+}
+
+/// Iterates over chunks in a shape around the center, within the given radius.
+/// `extra_depth` extends the shape further downwards.
+fn iter_chunks_around(center: IVec3, horizontal_radius: i32, vertical_radius: i32, extra_depth: i32, shape: ScanShape) -> impl Iterator<Item = IVec3> {
     let r = horizontal_radius + 1;
     let v_r = vertical_radius + 1;
     (-r..r).flat_map(move |x| {
-        (-v_r..v_r).flat_map(move |y| {
+        (-v_r - extra_depth..v_r).flat_map(move |y| {
             (-r..r).map(move |z| {
-                IVec3::new(x, y, z) + center
+                IVec3::new(x, y, z)
             })
         })
     })
+    .filter(move |offset| in_shape(*offset, r, v_r, extra_depth, shape))
+    .map(move |offset| offset + center)
+}
+
+/// Whether the offset from the center of a box `2 * r` chunks wide & `2 * v_r + extra_depth` high is within the shape.
+fn in_shape(offset: IVec3, r: i32, v_r: i32, extra_depth: i32, shape: ScanShape) -> bool {
+    let horizontal = (offset.x * offset.x + offset.z * offset.z) as f32 / (r * r) as f32;
+    let v_r = if offset.y < 0 { v_r + extra_depth } else { v_r };
+    let vertical = (offset.y * offset.y) as f32 / (v_r * v_r) as f32;
+    match shape {
+        ScanShape::Box => true,
+        ScanShape::Cylinder => horizontal <= 1.0,
+        ScanShape::Sphere => horizontal + vertical <= 1.0,
+    }
 }
 
 fn update_chunk_pos(
@@ -72,12 +130,25 @@ fn update_chunk_pos(
     }
 }
 
-#[derive(Component)]
+/// Editable at runtime, e.g. from an inspector. Chunks in range are recomputed & meshes change LOD the next time the scanners run.
+#[derive(Component, Reflect)]
+#[reflect(Component)]
 #[require(TrackChunkPos)]
 pub struct Scanner<T: Send + Sync + 'static> {
     horizontal_radius: u8,
     vertical_radius: u8,
+    /// Extra chunks below the scanner, so caves underneath are loaded further than the vertical radius.
+    extra_depth: u8,
+    shape: ScanShape,
+    /// `(radius, lod)` pairs sorted by radius, see [`Scanner::with_lod_bands`].
+    lod_bands: Vec<(u8, Lod)>,
+    // custom biases are function pointers, which can't be reflected.
+    #[reflect(ignore)]
+    priority_bias: PriorityBias,
+    /// Extra radius chunks have to leave before losing relevance, see [`Scanner::with_hysteresis`].
+    hysteresis: u8,
 
+    #[reflect(ignore)]
     phantom_data: PhantomData<T>
 }
 impl<T: Send + Sync + 'static> Scanner::<T> {
@@ -85,9 +156,94 @@ impl<T: Send + Sync + 'static> Scanner::<T> {
         Self {
             horizontal_radius,
             vertical_radius: vertical_radius.unwrap_or(horizontal_radius),
+            extra_depth: 0,
+            shape: ScanShape::Box,
+            lod_bands: Vec::new(),
+            priority_bias: PriorityBias::None,
+            hysteresis: 0,
             phantom_data: PhantomData
         }
     }
+
+    /// Changes the radius in place, the chunks in range are recomputed the next time the scanners run.
+    /// Cheaper than replacing the component, for things like a render distance setting.
+    pub fn set_radius(&mut self, horizontal_radius: u8, vertical_radius: Option<u8>) {
+        self.horizontal_radius = horizontal_radius;
+        self.vertical_radius = vertical_radius.unwrap_or(horizontal_radius);
+    }
+
+    pub fn horizontal_radius(&self) -> u8 {
+        self.horizontal_radius
+    }
+
+    pub fn vertical_radius(&self) -> u8 {
+        self.vertical_radius
+    }
+
+    /// Loads `extra_depth` more chunks below the scanner than above it.
+    pub fn with_extra_depth(mut self, extra_depth: u8) -> Self {
+        self.extra_depth = extra_depth;
+        self
+    }
+
+    pub fn with_shape(mut self, shape: ScanShape) -> Self {
+        self.shape = shape;
+        self
+    }
+
+    /// Sets the LOD chunks are meshed at by distance to the scanner, as `(radius, lod)` bands.
+    /// A chunk uses the first band whose radius (in chunks, per axis) it's within, chunks outside every band use [`crate::voxel_engine::VoxelEngine::lod`].
+    /// With multiple scanners the finest LOD wins.
+    ///
+    /// Only affects [`MeshScanner`]s. The bands don't change which chunks are loaded.
+    /// Chunks next to a coarser band get skirts along that border if [`crate::voxel_engine::VoxelEngine::generate_skirts`] is set,
+    /// to hide the cracks between the LODs.
+    pub fn with_lod_bands(mut self, mut lod_bands: Vec<(u8, Lod)>) -> Self {
+        lod_bands.sort_by_key(|(radius, _)| *radius);
+        self.lod_bands = lod_bands;
+        self
+    }
+
+    /// Sets how chunks the same distance away are ordered for generation.
+    ///
+    /// Only affects [`DataScanner`]s.
+    pub fn with_priority_bias(mut self, priority_bias: PriorityBias) -> Self {
+        self.priority_bias = priority_bias;
+        self
+    }
+
+    pub fn priority_bias(&self) -> PriorityBias {
+        self.priority_bias
+    }
+
+    /// Chunks gain relevance within the radius but only lose it once they're `margin` chunks further out,
+    /// so a scanner moving back & forth over a chunk border doesn't keep loading & unloading the chunks at the edge.
+    pub fn with_hysteresis(mut self, margin: u8) -> Self {
+        self.hysteresis = margin;
+        self
+    }
+
+    /// LOD of the band the chunk at `offset` from the scanner falls into, if any.
+    pub fn lod_at(&self, offset: IVec3) -> Option<Lod> {
+        let distance = offset.abs().max_element();
+        // the bands may have been edited out of order through reflection.
+        self.lod_bands.iter().filter(|(radius, _)| distance <= *radius as i32).min_by_key(|(radius, _)| *radius).map(|(_, lod)| *lod)
+    }
+
+    /// Whether a scanner at `scanner_pos` covers the chunk, not counting the hysteresis margin.
+    pub fn covers(&self, scanner_pos: IVec3, chunk: IVec3) -> bool {
+        let (r, v_r) = (self.horizontal_radius as i32 + 1, self.vertical_radius as i32 + 1);
+        let offset = chunk - scanner_pos;
+        let in_box = (-r..r).contains(&offset.x) && (-r..r).contains(&offset.z) && (-v_r - self.extra_depth as i32..v_r).contains(&offset.y);
+        in_box && in_shape(offset, r, v_r, self.extra_depth as i32, self.shape)
+    }
+
+    /// Estimated number of chunks this scanner wants loaded.
+    fn chunk_count_hint(&self) -> usize {
+        let width = 2 * (self.horizontal_radius as usize + 1);
+        let height = 2 * (self.vertical_radius as usize + 1) + self.extra_depth as usize;
+        ((width * width * height) as f32 * self.shape.volume_factor()) as usize
+    }
 }
 
 #[derive(Resource, Default)]
@@ -96,10 +252,34 @@ pub struct GlobalScannerDesiredChunks<T: Send + Sync + 'static> {
     phantom_data: PhantomData<T>
 }
 
-#[derive(Default)]
+/// Chunks kept relevant to `T` as if a [`Scanner<T>`] covered them, whether or not any scanner is nearby.
+/// For spawn areas, teleport targets & the like.
+///
+/// Chunks unload once they're removed, unless a scanner still covers them.
+/// Meshes need the data of the chunk & its neighbors, so force loading a [`MeshScanner`] chunk needs the [`DataScanner`] chunks around it too.
+#[derive(Resource)]
+pub struct ForceLoadedChunks<T: Send + Sync + 'static> {
+    pub chunks: HashSet<IVec3>,
+    phantom_data: PhantomData<T>
+}
+impl<T: Send + Sync + 'static> Default for ForceLoadedChunks<T> {
+    fn default() -> Self {
+        Self {
+            chunks: HashSet::default(),
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+#[derive(Default, TypePath)]
 pub struct MeshScanner;
-#[derive(Default)]
+#[derive(Default, TypePath)]
 pub struct DataScanner;
+/// Chunks within a visual scanner keep their meshes after losing [`MeshScanner`] relevance, frozen as static geometry.
+/// Frozen chunks aren't remeshed, so their data can unload, until a mesh scanner reaches them again.
+/// See [`crate::meshing::MeshingPipeline::is_frozen`].
+#[derive(Default, TypePath)]
+pub struct VisualScanner;
 
 #[derive(Event)]
 pub struct ChunkGainedScannerRelevance<T: Send + Sync + Default + 'static> {
@@ -113,16 +293,19 @@ pub struct ChunkLostScannerRelevance<T: Send + Sync + Default + 'static> {
     phantom_data: PhantomData<T>
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn scan<T: Send + Sync + Default + 'static>(
-    any_changed_query: Query<(), (With<Scanner<T>>, Changed<ChunkPos>)>,
+    any_changed_query: Query<(), (With<Scanner<T>>, Or<(Changed<ChunkPos>, Changed<Scanner<T>>)>)>,
     scanners: Query<(&Scanner<T>, &ChunkPos)>,
     mut global_desired_chunks: ResMut<GlobalScannerDesiredChunks<T>>,
     mut current_desired_chunks: Local<HashSet<IVec3>>,
+    mut kept_chunks: Local<HashSet<IVec3>>,
+    force_loaded_chunks: Res<ForceLoadedChunks<T>>,
     mut gained_relevance_events: EventWriter<ChunkGainedScannerRelevance<T>>,
     mut lost_relevance_events: EventWriter<ChunkLostScannerRelevance<T>>,
     mut removed_scanners: RemovedComponents<Scanner<T>>,
 ) {
-    if any_changed_query.is_empty() && removed_scanners.read().next().is_none() {
+    if any_changed_query.is_empty() && removed_scanners.read().next().is_none() && !force_loaded_chunks.is_changed() {
         return;
     }
 
@@ -130,11 +313,26 @@ pub fn scan<T: Send + Sync + Default + 'static>(
     {
         let _span = info_span!("Filling globally desired chunks.").entered();
         current_desired_chunks.clear();
+        // Scanners usually overlap, so the largest is a decent lower bound.
+        current_desired_chunks.reserve(scanners.iter().map(|(scanner, _)| scanner.chunk_count_hint()).max().unwrap_or(0));
+        for (scanner, chunk_pos) in scanners.iter() {
+            current_desired_chunks.extend(iter_chunks_around(chunk_pos.0, scanner.horizontal_radius as i32, scanner.vertical_radius as i32, scanner.extra_depth as i32, scanner.shape));
+        }
+    }
+
+    // Previously desired chunks stay desired while they're within the hysteresis margin.
+    if scanners.iter().any(|(scanner, _)| scanner.hysteresis > 0) {
+        let _span = info_span!("Keeping chunks within hysteresis margin.").entered();
+        kept_chunks.clear();
         for (scanner, chunk_pos) in scanners.iter() {
-            current_desired_chunks.extend(iter_chunks_around(chunk_pos.0, scanner.horizontal_radius as i32, scanner.vertical_radius as i32));
+            let margin = scanner.hysteresis as i32;
+            kept_chunks.extend(iter_chunks_around(chunk_pos.0, scanner.horizontal_radius as i32 + margin, scanner.vertical_radius as i32 + margin, scanner.extra_depth as i32, scanner.shape));
         }
+        current_desired_chunks.extend(global_desired_chunks.chunks.intersection(&kept_chunks));
     }
 
+    current_desired_chunks.extend(&force_loaded_chunks.chunks);
+
     {
         let _span = info_span!("Finding newly desired chunks.").entered();
         let newly_desired_chunks = current_desired_chunks.difference(&global_desired_chunks.chunks);
@@ -150,3 +348,163 @@ pub fn scan<T: Send + Sync + Default + 'static>(
     // Swap the lists because it's faster than copying.
     std::mem::swap(&mut global_desired_chunks.chunks, &mut current_desired_chunks);
 }
+
+#[test]
+fn extra_depth_loads_below() {
+    let vertical_radius = 2;
+    let extra_depth = 6;
+
+    // Descend & check the column below the scanner is always loaded.
+    for y in (-40..=0).rev() {
+        let center = IVec3::new(3, y, -7);
+        let chunks: HashSet<IVec3> = iter_chunks_around(center, 1, vertical_radius, extra_depth, ScanShape::Box).collect();
+
+        for depth in 1..=vertical_radius + extra_depth {
+            assert!(chunks.contains(&(center - IVec3::Y * depth)));
+        }
+        assert!(chunks.contains(&(center + IVec3::Y * vertical_radius)));
+        assert!(!chunks.contains(&(center + IVec3::Y * (vertical_radius + 1))));
+    }
+}
+
+#[test]
+fn scan_shapes() {
+    let center = IVec3::new(-4, 2, 9);
+    let scan = |shape| iter_chunks_around(center, 8, 4, 2, shape).collect::<HashSet<IVec3>>();
+
+    let cuboid = scan(ScanShape::Box);
+    let cylinder = scan(ScanShape::Cylinder);
+    let sphere = scan(ScanShape::Sphere);
+
+    assert!(sphere.is_subset(&cylinder));
+    assert!(cylinder.is_subset(&cuboid));
+    assert!(sphere.len() < cylinder.len() && cylinder.len() < cuboid.len());
+
+    // The axes are fully covered by every shape.
+    for shape in [&cuboid, &cylinder, &sphere] {
+        for i in -8..=8 {
+            assert!(shape.contains(&(center + IVec3::new(i, 0, 0))));
+            assert!(shape.contains(&(center + IVec3::new(0, 0, i))));
+        }
+        for y in -6..=4 {
+            assert!(shape.contains(&(center + IVec3::new(0, y, 0))));
+        }
+    }
+
+    // Corners are cut off.
+    assert!(cuboid.contains(&(center + IVec3::new(8, 4, 8))));
+    assert!(!cylinder.contains(&(center + IVec3::new(8, 0, 8))));
+    assert!(!sphere.contains(&(center + IVec3::new(6, 4, 0))));
+}
+
+#[test]
+fn lod_bands() {
+    let scanner = Scanner::<MeshScanner>::new(16, None).with_lod_bands(vec![(8, Lod::L8), (2, Lod::L32), (4, Lod::L16)]);
+
+    assert_eq!(scanner.lod_at(IVec3::ZERO), Some(Lod::L32));
+    assert_eq!(scanner.lod_at(IVec3::new(-2, 1, 2)), Some(Lod::L32));
+    assert_eq!(scanner.lod_at(IVec3::new(0, -3, 0)), Some(Lod::L16));
+    assert_eq!(scanner.lod_at(IVec3::new(8, 0, -5)), Some(Lod::L8));
+    assert_eq!(scanner.lod_at(IVec3::new(9, 0, 0)), None);
+    assert_eq!(Scanner::<MeshScanner>::new(16, None).lod_at(IVec3::ZERO), None);
+}
+
+#[test]
+fn hysteresis_stops_border_thrashing() {
+    let mut app = App::new();
+    app.add_event::<ChunkGainedScannerRelevance<DataScanner>>()
+        .add_event::<ChunkLostScannerRelevance<DataScanner>>()
+        .init_resource::<GlobalScannerDesiredChunks<DataScanner>>()
+        .init_resource::<ForceLoadedChunks<DataScanner>>()
+        .add_systems(Update, scan::<DataScanner>);
+    let scanner = app.world_mut().spawn((Scanner::<DataScanner>::new(2, None).with_hysteresis(1), ChunkPos(IVec3::ZERO))).id();
+
+    let mut gained = app.world().resource::<Events<ChunkGainedScannerRelevance<DataScanner>>>().get_cursor();
+    let mut lost = app.world().resource::<Events<ChunkLostScannerRelevance<DataScanner>>>().get_cursor();
+    let mut gained_count = bevy::utils::HashMap::<IVec3, u32>::default();
+    let mut lost_count = 0;
+    // Jitter back & forth over the border.
+    for i in 0..10 {
+        app.world_mut().get_mut::<ChunkPos>(scanner).unwrap().0 = IVec3::new(i % 2, 0, 0);
+        app.update();
+
+        for event in gained.read(app.world().resource()) {
+            *gained_count.entry(event.chunk).or_default() += 1;
+        }
+        lost_count += lost.read(app.world().resource::<Events<ChunkLostScannerRelevance<DataScanner>>>()).count();
+    }
+    assert!(gained_count.values().all(|&count| count == 1));
+    assert_eq!(lost_count, 0);
+
+    // Moving past the margin still unloads.
+    app.world_mut().get_mut::<ChunkPos>(scanner).unwrap().0 = IVec3::new(3, 0, 0);
+    app.update();
+    assert!(lost.read(app.world().resource()).any(|event| event.chunk.x == -3));
+    assert!(app.world().resource::<GlobalScannerDesiredChunks<DataScanner>>().chunks.iter().all(|chunk| chunk.x >= -1));
+}
+
+#[test]
+fn force_loaded_chunks_stay_loaded() {
+    let mut app = App::new();
+    app.add_event::<ChunkGainedScannerRelevance<DataScanner>>()
+        .add_event::<ChunkLostScannerRelevance<DataScanner>>()
+        .init_resource::<GlobalScannerDesiredChunks<DataScanner>>()
+        .init_resource::<ForceLoadedChunks<DataScanner>>()
+        .add_systems(Update, scan::<DataScanner>);
+    let spawn = IVec3::new(100, 0, -20);
+    app.world_mut().resource_mut::<ForceLoadedChunks<DataScanner>>().chunks.insert(spawn);
+    let scanner = app.world_mut().spawn((Scanner::<DataScanner>::new(1, None), ChunkPos(IVec3::ZERO))).id();
+
+    let mut lost = app.world().resource::<Events<ChunkLostScannerRelevance<DataScanner>>>().get_cursor();
+    for x in 0..10 {
+        app.world_mut().get_mut::<ChunkPos>(scanner).unwrap().0 = IVec3::new(-x * 4, 0, 0);
+        app.update();
+
+        assert!(app.world().resource::<GlobalScannerDesiredChunks<DataScanner>>().chunks.contains(&spawn));
+        assert!(lost.read(app.world().resource()).all(|event| event.chunk != spawn));
+    }
+
+    // Unpinning unloads it, even though the scanner didn't move.
+    app.world_mut().resource_mut::<ForceLoadedChunks<DataScanner>>().chunks.remove(&spawn);
+    app.update();
+    assert!(lost.read(app.world().resource()).any(|event| event.chunk == spawn));
+    assert!(!app.world().resource::<GlobalScannerDesiredChunks<DataScanner>>().chunks.contains(&spawn));
+}
+
+#[test]
+fn shrinking_the_radius_drops_outer_chunks() {
+    let mut app = App::new();
+    app.add_event::<ChunkGainedScannerRelevance<DataScanner>>()
+        .add_event::<ChunkLostScannerRelevance<DataScanner>>()
+        .init_resource::<GlobalScannerDesiredChunks<DataScanner>>()
+        .init_resource::<ForceLoadedChunks<DataScanner>>()
+        .add_systems(Update, scan::<DataScanner>);
+    let scanner = app.world_mut().spawn((Scanner::<DataScanner>::new(3, Some(1)), ChunkPos(IVec3::ZERO))).id();
+    app.update();
+    let before = app.world().resource::<GlobalScannerDesiredChunks<DataScanner>>().chunks.clone();
+
+    let mut lost = app.world().resource::<Events<ChunkLostScannerRelevance<DataScanner>>>().get_cursor();
+    app.world_mut().get_mut::<Scanner<DataScanner>>(scanner).unwrap().set_radius(1, None);
+    app.update();
+
+    let after: HashSet<IVec3> = iter_chunks_around(IVec3::ZERO, 1, 1, 0, ScanShape::Box).collect();
+    assert_eq!(app.world().resource::<GlobalScannerDesiredChunks<DataScanner>>().chunks, after);
+    let lost: HashSet<IVec3> = lost.read(app.world().resource()).map(|event| event.chunk).collect();
+    assert_eq!(lost, before.difference(&after).copied().collect());
+}
+
+#[test]
+fn scanners_are_editable_through_reflection() {
+    use bevy::reflect::GetPath;
+
+    let mut scanner = Scanner::<MeshScanner>::new(4, None).with_lod_bands(vec![(1, Lod::L32), (3, Lod::L8)]);
+    *scanner.path_mut::<u8>("horizontal_radius").unwrap() = 6;
+    *scanner.path_mut::<Lod>("lod_bands[1].1").unwrap() = Lod::L16;
+    // edited out of order, the closest band still wins.
+    *scanner.path_mut::<u8>("lod_bands[0].0").unwrap() = 5;
+
+    assert_eq!(scanner.horizontal_radius(), 6);
+    assert_eq!(scanner.lod_at(IVec3::new(2, 0, 0)), Some(Lod::L16));
+    assert_eq!(scanner.lod_at(IVec3::new(0, -4, 0)), Some(Lod::L32));
+    assert_eq!(scanner.lod_at(IVec3::new(0, 0, 6)), None);
+}
@@ -0,0 +1,213 @@
+use bevy::math::{ivec3, IVec3, Vec3};
+#[cfg(feature = "rendering")]
+use bevy::{asset::RenderAssetUsages, render::{mesh::{Indices, Mesh, PrimitiveTopology}, primitives::Aabb}};
+
+use crate::{
+    chunks_refs::ChunksRefs,
+    constants::{CHUNK_SIZE_I32, CHUNK_SIZE_P},
+    utils::vec3_to_index,
+    voxel::{BlockFlags, BlockRegistry},
+};
+
+/// Density above which a sample is inside the surface.
+const ISO_LEVEL: f32 = 0.5;
+/// Cells span two neighboring samples, from -1 to [`CHUNK_SIZE_I32`] - 1 along each axis.
+const CELLS: i32 = CHUNK_SIZE_I32 + 1;
+
+/// Smooth isosurface of a chunk built by [`build_smooth_mesh`], with positions local to the chunk's corner like [`crate::chunk_mesh::ChunkMesh`].
+#[derive(Clone, Default, Debug)]
+pub struct SmoothMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+impl SmoothMesh {
+    #[cfg(feature = "rendering")]
+    pub fn to_bevy_mesh(self) -> Mesh {
+        let mut bevy_mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        bevy_mesh.insert_indices(Indices::U32(self.indices));
+
+        bevy_mesh
+    }
+
+    /// Bounds of the vertices, `None` for a mesh without any.
+    #[cfg(feature = "rendering")]
+    pub fn calculate_aabb(&self) -> Option<Aabb> {
+        if self.positions.is_empty() {
+            return None;
+        }
+        let (min, max) = self.positions.iter().fold((Vec3::MAX, Vec3::MIN), |(min, max), pos| (min.min(*pos), max.max(*pos)));
+        Some(Aabb::from_min_max(min, max))
+    }
+
+    /// Triangles of the mesh in chunk local positions, from the index triples.
+    pub fn triangles(&self) -> impl Iterator<Item = [Vec3; 3]> + '_ {
+        self.indices.chunks_exact(3).map(|triangle| [0, 1, 2].map(|i| self.positions[triangle[i] as usize]))
+    }
+}
+
+/// Density of a block, 1 for full blocks with `flags` & 0 for everything else.
+fn block_density(chunks_refs: &ChunksRefs, registry: &BlockRegistry, flags: BlockFlags, pos: IVec3) -> f32 {
+    if registry.occludes(chunks_refs.get_block(pos).block_type, flags) { 1.0 } else { 0.0 }
+}
+
+/// Returns true if the middle chunk has no smooth surface for `flags`,
+/// because it & the neighbors its surface reaches into are all filled with blocks of the same density.
+pub fn is_smooth_mesh_empty(chunks_refs: &ChunksRefs, registry: &BlockRegistry, flags: BlockFlags) -> bool {
+    let inside = |chunk: IVec3| chunks_refs.chunks[vec3_to_index(IVec3::ONE + chunk, 3)]
+        .get_block_if_filled()
+        .map(|block| registry.occludes(block.block_type, flags));
+
+    let Some(middle) = inside(IVec3::ZERO) else {
+        return false;
+    };
+    // the chunk's quads only cross into its positive neighbors.
+    [IVec3::X, IVec3::Y, IVec3::Z].into_iter().all(|dir| inside(dir) == Some(middle))
+}
+
+/// Meshes the surface between the full blocks with `flags` & everything else in the middle chunk with naive surface nets,
+/// one material for the whole surface.
+///
+/// The density is sampled at the voxel centers from the blocks, see [`block_density`], & the surface is placed where it crosses [`ISO_LEVEL`].
+/// Each cell between 8 samples the surface passes through gets one vertex, averaged from the crossings on its edges,
+/// & every sample edge the surface crosses gets a quad connecting the 4 cells around it. Staircases of blocks end up as slopes.
+///
+/// A chunk meshes the edges starting at its own samples, so neighboring chunks share their border vertices without overlapping.
+/// Always meshed at full detail, `None` if there is no surface.
+pub fn build_smooth_mesh(chunks_refs: &ChunksRefs, registry: &BlockRegistry, flags: BlockFlags) -> Option<SmoothMesh> {
+    // samples of the padded chunk, from -1 to CHUNK_SIZE inclusive.
+    let sample_index = |pos: IVec3| vec3_to_index(pos + IVec3::ONE, CHUNK_SIZE_P as i32);
+    let mut densities = vec![0.0; CHUNK_SIZE_P * CHUNK_SIZE_P * CHUNK_SIZE_P];
+    for z in -1..=CHUNK_SIZE_I32 {
+        for y in -1..=CHUNK_SIZE_I32 {
+            for x in -1..=CHUNK_SIZE_I32 {
+                let pos = ivec3(x, y, z);
+                densities[sample_index(pos)] = block_density(chunks_refs, registry, flags, pos);
+            }
+        }
+    }
+    let density = |pos: IVec3| densities[sample_index(pos)];
+    let corners = |cell: IVec3| [0, 1, 2, 3, 4, 5, 6, 7].map(|i: i32| cell + ivec3(i & 1, (i >> 1) & 1, i >> 2));
+
+    let mut mesh = SmoothMesh::default();
+    let cell_index = |cell: IVec3| vec3_to_index(cell + IVec3::ONE, CELLS);
+    let mut cell_vertices = vec![u32::MAX; (CELLS * CELLS * CELLS) as usize];
+    for z in -1..CHUNK_SIZE_I32 {
+        for y in -1..CHUNK_SIZE_I32 {
+            for x in -1..CHUNK_SIZE_I32 {
+                let cell = ivec3(x, y, z);
+                let corners = corners(cell).map(|corner| (corner, density(corner)));
+                if corners.iter().all(|(_, d)| *d > ISO_LEVEL) || corners.iter().all(|(_, d)| *d <= ISO_LEVEL) {
+                    continue;
+                }
+
+                let mut crossings = Vec3::ZERO;
+                let mut crossing_count = 0;
+                let mut gradient = Vec3::ZERO;
+                for (a, (pos_a, density_a)) in corners.iter().enumerate() {
+                    for (pos_b, density_b) in corners[a + 1..].iter() {
+                        let dir = *pos_b - *pos_a;
+                        if dir.abs().element_sum() != 1 {
+                            continue;
+                        }
+                        gradient += dir.as_vec3() * (density_b - density_a);
+                        if (*density_a > ISO_LEVEL) == (*density_b > ISO_LEVEL) {
+                            continue;
+                        }
+                        let t = (ISO_LEVEL - density_a) / (density_b - density_a);
+                        crossings += (*pos_a - cell).as_vec3() + dir.as_vec3() * t;
+                        crossing_count += 1;
+                    }
+                }
+
+                cell_vertices[cell_index(cell)] = mesh.positions.len() as u32;
+                // samples are at the voxel centers.
+                mesh.positions.push(cell.as_vec3() + Vec3::splat(0.5) + crossings / crossing_count as f32);
+                // density is highest inside, so the normal points down the gradient.
+                mesh.normals.push((-gradient).normalize_or(Vec3::Y));
+            }
+        }
+    }
+
+    for z in 0..CHUNK_SIZE_I32 {
+        for y in 0..CHUNK_SIZE_I32 {
+            for x in 0..CHUNK_SIZE_I32 {
+                let pos = ivec3(x, y, z);
+                let inside = density(pos) > ISO_LEVEL;
+                for (axis, u, v) in [(IVec3::X, IVec3::Y, IVec3::Z), (IVec3::Y, IVec3::Z, IVec3::X), (IVec3::Z, IVec3::X, IVec3::Y)] {
+                    if inside == (density(pos + axis) > ISO_LEVEL) {
+                        continue;
+                    }
+                    // counter clockwise around `axis`, flipped when the surface faces the other way.
+                    let mut quad = [pos - u - v, pos - v, pos, pos - u].map(|cell| cell_vertices[cell_index(cell)]);
+                    if !inside {
+                        quad.reverse();
+                    }
+                    mesh.indices.extend([quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+                }
+            }
+        }
+    }
+
+    if mesh.indices.is_empty() {
+        return None;
+    }
+    Some(mesh)
+}
+
+#[test]
+fn smooth_surfaces_are_closed_and_face_outwards() {
+    use std::sync::Arc;
+
+    use crate::{chunk::ChunkData, constants::CHUNK_SIZE3, utils::index_to_ivec3_bounds, voxel::{BlockData, BlockId}};
+
+    let registry = BlockRegistry {
+        block_flags: vec![BlockFlags::empty(), BlockFlags::SOLID],
+        ..Default::default()
+    };
+    // a ball of stone in the middle of the chunk.
+    let center = Vec3::splat(16.0);
+    let voxels: Vec<BlockData> = (0..CHUNK_SIZE3)
+        .map(|i| {
+            let pos = index_to_ivec3_bounds(i as i32, CHUNK_SIZE_I32).as_vec3() + Vec3::splat(0.5);
+            BlockData::new(BlockId(if pos.distance(center) < 8.0 { 1 } else { 0 }))
+        })
+        .collect();
+    let mut chunks: Vec<_> = (0..27).map(|_| Arc::new(ChunkData::Filled(BlockData::default()))).collect();
+    chunks[13] = Arc::new(ChunkData::Dense(voxels));
+    let chunks_refs = ChunksRefs::new(chunks);
+    assert!(!is_smooth_mesh_empty(&chunks_refs, &registry, BlockFlags::SOLID));
+    let mesh = build_smooth_mesh(&chunks_refs, &registry, BlockFlags::SOLID).unwrap();
+
+    // every edge of a closed surface is shared by exactly two triangles, once in each direction.
+    let mut edges = bevy::utils::HashMap::new();
+    for triangle in mesh.indices.chunks_exact(3) {
+        for i in 0..3 {
+            *edges.entry((triangle[i], triangle[(i + 1) % 3])).or_insert(0) += 1;
+        }
+    }
+    assert!(edges.iter().all(|((a, b), count)| *count == 1 && edges.get(&(*b, *a)) == Some(&1)));
+
+    for (position, normal) in mesh.positions.iter().zip(&mesh.normals) {
+        assert!((position.distance(center) - 8.0).abs() < 1.0, "{position} is off the ball");
+        assert!(normal.dot(*position - center) > 0.0);
+    }
+    for [a, b, c] in mesh.triangles() {
+        let middle = (a + b + c) / 3.0;
+        assert!((b - a).cross(c - a).dot(middle - center) > 0.0, "triangle at {middle} faces inwards");
+    }
+
+    // solid ground with air above, the surface is on the chunk above's side of the border.
+    let chunks = (0..27).map(|i| Arc::new(ChunkData::Filled(BlockData::new(BlockId(if i / 3 % 3 == 2 { 0 } else { 1 }))))).collect();
+    let chunks_refs = ChunksRefs::new(chunks);
+    assert!(!is_smooth_mesh_empty(&chunks_refs, &registry, BlockFlags::SOLID));
+    let mesh = build_smooth_mesh(&chunks_refs, &registry, BlockFlags::SOLID).unwrap();
+    assert_eq!(mesh.indices.len(), 32 * 32 * 6);
+    assert!(mesh.normals.iter().all(|normal| *normal == Vec3::Y));
+}
@@ -1,5 +1,7 @@
 use bevy::prelude::*;
 
+use crate::constants::CHUNK_SIZE_I32;
+
 pub const CHUNK_POWER: i32 = 5;
 
 #[inline]
@@ -68,9 +70,10 @@ pub fn get_edging_chunk(pos: IVec3) -> Option<IVec3> {
 
 /// Vertex format:
 /// position: 6 bits each, 18 bits total
-/// ao: 3 bits
+/// ao: 2 bits
+/// half down: 1 bit, lowers the vertex by half a voxel or to a fluid's level (see [`VERTEX_HALF_DOWN_BIT`])
 /// normal: 3 bits (Original comment said 4 but shader only uses 3?)
-/// block type: 8 bits (256 block types max :/)
+/// block type: 8 bits (256 block types max :/, see [`MAX_BLOCK_TYPES`])
 /// total: 32 bits
 #[inline]
 pub fn make_vertex_u32(
@@ -80,6 +83,7 @@ pub fn make_vertex_u32(
     normal: u32,
     block_type: u32,
 ) -> u32 {
+    debug_assert!(block_type < MAX_BLOCK_TYPES as u32, "block type {block_type} doesn't fit in the 8 bits of a vertex");
     pos.x as u32
         | (pos.y as u32) << 6u32
         | (pos.z as u32) << 12u32
@@ -90,6 +94,16 @@ pub fn make_vertex_u32(
     // | (texture_id) << 21u32
 }
 
+/// Block types that fit in a vertex, [`crate::voxel::BlockRegistryBuilder`] refuses to add more blocks.
+pub const MAX_BLOCK_TYPES: usize = 256;
+
+/// Set on vertices which are lowered by half a voxel for slabs,
+/// or to the fluid's level for fluids, which the shader looks up by block type (see [`crate::voxel::BlockShape::vertex_lowering`]).
+pub const VERTEX_HALF_DOWN_BIT: u32 = 1 << 20;
+
+/// Normal indices of the two diagonal quads of [`crate::voxel::BlockShape::Cross`], after the six [`crate::face_direction::FaceDir`] normals.
+pub const CROSS_NORMAL_INDICES: [u32; 2] = [6, 7];
+
 #[inline]
 fn x_positive_bits(bits: u32) -> u32{
     (1 << bits) - 1
@@ -104,9 +118,35 @@ pub fn get_pos_from_vertex_u32(vertex: u32) -> IVec3 {
     )
 }
 
+/// Position of the vertex including the half voxel offset, see [`VERTEX_HALF_DOWN_BIT`].
+/// Lowered fluid vertices are off, as the offset depends on the block.
+#[inline]
+pub fn get_offset_pos_from_vertex_u32(vertex: u32) -> Vec3 {
+    let half_down = if vertex & VERTEX_HALF_DOWN_BIT != 0 { 0.5 } else { 0.0 };
+    get_pos_from_vertex_u32(vertex).as_vec3() - Vec3::Y * half_down
+}
+
+#[inline]
+pub fn get_ao_from_vertex_u32(vertex: u32) -> u32 {
+    (vertex >> 18) & x_positive_bits(2)
+}
+
+#[inline]
+pub fn get_normal_index_from_vertex_u32(vertex: u32) -> u32 {
+    (vertex >> 21) & x_positive_bits(3)
+}
+
+#[inline]
+pub fn get_block_type_from_vertex_u32(vertex: u32) -> u32 {
+    (vertex >> 24) & x_positive_bits(8)
+}
+
+/// Convert a world space position to the position of the chunk containing it.
+/// Chunk `c` spans `c * 32..(c + 1) * 32`, on both sides of the origin.
 #[inline]
 pub fn world_to_chunk(pos: Vec3) -> IVec3 {
-    pos.as_ivec3() >> CHUNK_POWER
+    // as_ivec3 truncates towards zero, which would put -0.5 in chunk 0. The shift floors.
+    pos.floor().as_ivec3() >> CHUNK_POWER
 }
 
 /// Convert a world space voxel position to a chunk-local voxel position (0-31).
@@ -114,20 +154,58 @@ pub fn world_to_chunk_local_voxel(voxel: IVec3) -> IVec3 {
     voxel & ((1 << CHUNK_POWER) - 1) 
 }
 
+/// Splits a world space voxel position into its chunk & its chunk-local position (0-31).
+/// Negative positions are floored, so voxel -1 is the last voxel of chunk -1.
+pub fn world_to_chunk_local(world: IVec3) -> (IVec3, IVec3) {
+    let chunk_size = IVec3::splat(CHUNK_SIZE_I32);
+    (world.div_euclid(chunk_size), world.rem_euclid(chunk_size))
+}
+
+/// Iterates over the chunks overlapping the box between the world space voxel positions `min` & `max` (inclusive), in x, then y, then z order.
+pub fn chunks_in_aabb(min: IVec3, max: IVec3) -> impl Iterator<Item = IVec3> {
+    let (min_chunk, _) = world_to_chunk_local(min.min(max));
+    let (max_chunk, _) = world_to_chunk_local(min.max(max));
+    (min_chunk.z..=max_chunk.z).flat_map(move |z| {
+        (min_chunk.y..=max_chunk.y).flat_map(move |y| {
+            (min_chunk.x..=max_chunk.x).map(move |x| IVec3::new(x, y, z))
+        })
+    })
+}
+
+/// Which way round the corners of a quad's front face go when looking at it, see [`generate_indices_with`].
+/// The mesher emits quad corners counter clockwise.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub enum FaceWinding {
+    #[default]
+    CounterClockwise,
+    Clockwise,
+}
+
 /// generate a vec of indices
 /// assumes vertices are made of quads, and counter clockwise ordered
 #[inline]
 pub fn generate_indices(vertex_count: usize) -> Vec<u32> {
-    let indices_count = vertex_count / 4;
-    let mut indices = Vec::<u32>::with_capacity(indices_count * 6);
-    (0..indices_count).for_each(|vert_index| {
-        let vert_index = vert_index as u32 * 4u32;
-        indices.push(vert_index);
-        indices.push(vert_index + 1);
-        indices.push(vert_index + 2);
-        indices.push(vert_index);
-        indices.push(vert_index + 2);
-        indices.push(vert_index + 3);
+    generate_indices_with(vertex_count, FaceWinding::CounterClockwise, false)
+}
+
+/// Like [`generate_indices`], with the triangles wound so `winding` is their front face.
+/// `double_sided` adds every triangle a second time with the opposite winding, so quads survive back face culling from either side.
+pub fn generate_indices_with(vertex_count: usize, winding: FaceWinding, double_sided: bool) -> Vec<u32> {
+    const COUNTER_CLOCKWISE: [u32; 6] = [0, 1, 2, 0, 2, 3];
+    const CLOCKWISE: [u32; 6] = [0, 2, 1, 0, 3, 2];
+    let (front, back) = match winding {
+        FaceWinding::CounterClockwise => (COUNTER_CLOCKWISE, CLOCKWISE),
+        FaceWinding::Clockwise => (CLOCKWISE, COUNTER_CLOCKWISE),
+    };
+
+    let quad_count = vertex_count / 4;
+    let mut indices = Vec::<u32>::with_capacity(quad_count * if double_sided { 12 } else { 6 });
+    (0..quad_count).for_each(|quad| {
+        let vert_index = quad as u32 * 4u32;
+        indices.extend(front.map(|corner| vert_index + corner));
+        if double_sided {
+            indices.extend(back.map(|corner| vert_index + corner));
+        }
     });
 
     indices
@@ -147,10 +225,171 @@ fn index_functions() {
     }
 }
 
+#[test]
+fn world_to_chunk_floors() {
+    for (x, chunk) in [(-48.0, -2), (-32.5, -2), (-32.0, -1), (-16.0, -1), (-1.0, -1), (-0.5, -1), (0.0, 0), (15.0, 0), (31.9, 0), (32.0, 1), (48.0, 1)] {
+        assert_eq!(world_to_chunk(Vec3::new(x, x, x)), IVec3::splat(chunk), "{x}");
+    }
+    // Every voxel maps to the chunk its local position is relative to.
+    for voxel in -70..70 {
+        let chunk = world_to_chunk(Vec3::splat(voxel as f32 + 0.5));
+        assert_eq!(chunk * CHUNK_SIZE_I32 + world_to_chunk_local_voxel(IVec3::splat(voxel)), IVec3::splat(voxel));
+    }
+}
+
+#[test]
+fn chunk_coords_across_the_origin() {
+    for (voxel, chunk, local) in [(-33, -2, 31), (-32, -1, 0), (-1, -1, 31), (0, 0, 0), (31, 0, 31), (32, 1, 0)] {
+        assert_eq!(world_to_chunk_local(IVec3::splat(voxel)), (IVec3::splat(chunk), IVec3::splat(local)), "{voxel}");
+    }
+    assert_eq!(world_to_chunk_local(IVec3::new(-1, 40, -70)), (IVec3::new(-1, 1, -3), IVec3::new(31, 8, 26)));
+
+    let chunks: Vec<_> = chunks_in_aabb(IVec3::new(-1, -40, 5), IVec3::new(0, 2, -33)).collect();
+    assert_eq!(chunks.len(), 2 * 3 * 3);
+    for x in -1..=0 {
+        for y in -2..=0 {
+            for z in -2..=0 {
+                assert!(chunks.contains(&IVec3::new(x, y, z)));
+            }
+        }
+    }
+    assert_eq!(chunks_in_aabb(IVec3::splat(-32), IVec3::splat(-1)).collect::<Vec<_>>(), [IVec3::NEG_ONE]);
+}
+
+#[test]
+fn index_functions_edges() {
+    assert_eq!(vec3_to_index(IVec3::ZERO, 32), 0);
+    assert_eq!(vec3_to_index(IVec3::new(31, 0, 0), 32), 31);
+    assert_eq!(vec3_to_index(IVec3::new(0, 1, 0), 32), 32);
+    assert_eq!(vec3_to_index(IVec3::new(0, 0, 1), 32), 32 * 32);
+    assert_eq!(vec3_to_index(IVec3::splat(31), 32), 32 * 32 * 32 - 1);
+    // Non chunk sized bounds take the slow path.
+    assert_eq!(vec3_to_index(IVec3::new(2, 1, 1), 3), 2 + 3 + 9);
+    assert_eq!(vec3_to_index(IVec3::splat(2), 3), 26);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn index_functions_out_of_range() {
+    // Used to silently wrap x into the next row instead.
+    vec3_to_index(IVec3::new(32, 0, 0), 32);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn index_functions_negative() {
+    vec3_to_index(IVec3::new(0, -1, 0), 32);
+}
+
+/// Converts a position to an index into an x, then y, then z ordered array with `bounds` elements per axis.
+/// The position must already be within `0..bounds` on every axis, this is only checked in debug builds.
 #[inline]
 pub fn vec3_to_index(pos: IVec3, bounds: i32) -> usize {
-    let x_i = pos.x % bounds;
+    debug_assert!(
+        pos.cmpge(IVec3::ZERO).all() && pos.cmplt(IVec3::splat(bounds)).all(),
+        "{pos} is out of bounds 0..{bounds}"
+    );
+
+    // Fast path for chunk local positions.
+    if bounds == CHUNK_SIZE_I32 {
+        return (pos.x | pos.y << CHUNK_POWER | pos.z << (CHUNK_POWER * 2)) as usize;
+    }
+
+    let x_i = pos.x;
     let y_i = pos.y * bounds;
     let z_i = pos.z * (bounds * bounds);
     (x_i + y_i + z_i) as usize
 }
+
+/// Which axis points up in positions & voxel arrays coming from outside the engine, which is always Y-up.
+/// Z-up is right handed like Blender & MagicaVoxel: x is the same, z is up & y points into the screen of a Y-up camera looking down -Z.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub enum CoordinateConvention {
+    #[default]
+    YUp,
+    ZUp,
+}
+impl CoordinateConvention {
+    /// Converts a voxel or chunk position in this convention to the engine's.
+    /// Positions are cells, so the cell at y 0 in Z-up is the one at z -1 in Y-up.
+    pub fn to_engine(self, pos: IVec3) -> IVec3 {
+        match self {
+            CoordinateConvention::YUp => pos,
+            CoordinateConvention::ZUp => IVec3::new(pos.x, pos.z, -1 - pos.y),
+        }
+    }
+
+    /// Converts a voxel or chunk position in the engine's convention to this one, inverse of [`CoordinateConvention::to_engine`].
+    pub fn from_engine(self, pos: IVec3) -> IVec3 {
+        match self {
+            CoordinateConvention::YUp => pos,
+            CoordinateConvention::ZUp => IVec3::new(pos.x, -1 - pos.z, pos.y),
+        }
+    }
+
+    /// Converts a direction or offset between voxels in this convention to the engine's.
+    pub fn offset_to_engine(self, offset: IVec3) -> IVec3 {
+        match self {
+            CoordinateConvention::YUp => offset,
+            CoordinateConvention::ZUp => IVec3::new(offset.x, offset.z, -offset.y),
+        }
+    }
+
+    /// Converts a position within `0..bounds` on every axis, like a voxel in a chunk, in this convention to the engine's.
+    /// Chunk local positions stay consistent with [`CoordinateConvention::to_engine`] of the chunk & world positions.
+    pub fn local_to_engine(self, pos: IVec3, bounds: i32) -> IVec3 {
+        match self {
+            CoordinateConvention::YUp => pos,
+            CoordinateConvention::ZUp => IVec3::new(pos.x, pos.z, bounds - 1 - pos.y),
+        }
+    }
+
+    /// Inverse of [`CoordinateConvention::local_to_engine`].
+    pub fn local_from_engine(self, pos: IVec3, bounds: i32) -> IVec3 {
+        match self {
+            CoordinateConvention::YUp => pos,
+            CoordinateConvention::ZUp => IVec3::new(pos.x, bounds - 1 - pos.z, pos.y),
+        }
+    }
+
+    /// Engine position of index `i` into an array ordered x, then y, then z in this convention, with `bounds` elements per axis.
+    pub fn index_to_ivec3(self, i: i32, bounds: i32) -> IVec3 {
+        self.local_to_engine(index_to_ivec3_bounds(i, bounds), bounds)
+    }
+
+    /// Index of an engine position into an array ordered x, then y, then z in this convention, inverse of [`CoordinateConvention::index_to_ivec3`].
+    pub fn vec3_to_index(self, pos: IVec3, bounds: i32) -> usize {
+        vec3_to_index(self.local_from_engine(pos, bounds), bounds)
+    }
+}
+
+#[test]
+fn index_order_follows_winding() {
+    assert_eq!(generate_indices(8), [0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7]);
+    assert_eq!(generate_indices_with(8, FaceWinding::Clockwise, false), [0, 2, 1, 0, 3, 2, 4, 6, 5, 4, 7, 6]);
+    assert_eq!(generate_indices_with(4, FaceWinding::CounterClockwise, true), [0, 1, 2, 0, 2, 3, 0, 2, 1, 0, 3, 2]);
+    assert_eq!(generate_indices_with(4, FaceWinding::Clockwise, true), [0, 2, 1, 0, 3, 2, 0, 1, 2, 0, 2, 3]);
+}
+
+#[test]
+fn coordinate_conventions_round_trip() {
+    for convention in [CoordinateConvention::YUp, CoordinateConvention::ZUp] {
+        for i in [0, 1, 31, 32, 1000, 32 * 32 * 32 - 1] {
+            let pos = convention.index_to_ivec3(i, 32);
+            assert!(pos.cmpge(IVec3::ZERO).all() && pos.cmplt(IVec3::splat(32)).all(), "{convention:?} {pos}");
+            assert_eq!(convention.vec3_to_index(pos, 32), i as usize, "{convention:?}");
+        }
+        for voxel in [IVec3::ZERO, IVec3::new(-1, 40, -70), IVec3::new(33, -33, 5)] {
+            assert_eq!(convention.from_engine(convention.to_engine(voxel)), voxel);
+            // converting the chunk & local position lands on the same voxel as converting the world position.
+            let (chunk, local) = world_to_chunk_local(voxel);
+            let engine = convention.to_engine(chunk) * CHUNK_SIZE_I32 + convention.local_to_engine(local, CHUNK_SIZE_I32);
+            assert_eq!(engine, convention.to_engine(voxel), "{convention:?} {voxel}");
+        }
+    }
+    // Z-up arrays are x, then the horizontal y, then up.
+    assert_eq!(CoordinateConvention::ZUp.index_to_ivec3(32 * 32, 32), IVec3::new(0, 1, 31));
+    assert_eq!(CoordinateConvention::ZUp.offset_to_engine(IVec3::Z), IVec3::Y);
+}
@@ -1,17 +1,25 @@
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
-use bevy::{color::Color, ecs::system::Resource, utils::HashMap};
+use bevy::{color::Color, ecs::system::Resource, reflect::Reflect, utils::HashMap};
+
+use crate::{greedy_mesher_optimized::OcclusionTable, utils::MAX_BLOCK_TYPES};
 
 /// The on disk identifier for a block.
 /// Consistent between adding & removing block types.
 #[derive(Default, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BlockStringIdentifier(pub Box<str>);
+impl std::borrow::Borrow<str> for BlockStringIdentifier {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
 
 /// The in memory identifier for a block.
 /// Not consistent between adding & removing block types.
 /// 
 /// These ids do not have gaps.
-#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect)]
+#[repr(transparent)]
 pub struct BlockId(pub u16);
 
 bitflags::bitflags! {
@@ -21,14 +29,69 @@ bitflags::bitflags! {
         /// This is a solid block which appears in the mesh.
         const SOLID = 1 << 0;
         /// The is a transparent block which should appear in the transparent mesh.
+        /// Faces between two of the same transparent block are culled, faces between different ones are kept so they can be seen through each other.
         const TRANSPARENT = 1 << 1;
         /// The block has collision and should affect the collision mesh.
         const COLLISION = 1 << 2;
+        /// The block's emissive color pulses over time.
+        /// `chunk.wgsl` scales the emissive color by `0.5 + 0.5 * sin(time)` for these blocks.
+        const ANIMATED_EMISSIVE = 1 << 3;
+        /// The block is drawn with parts cut out, such as foliage. Needs a [`crate::meshing::ChunkRenderLayer`] to be rendered.
+        const CUTOUT = 1 << 4;
+        /// Faces of the block aren't greedy merged with their neighbors, so per block textures (such as ores) aren't stretched.
+        /// Collision meshes still merge them.
+        const NO_GREEDY_MERGE = 1 << 5;
     }
 }
 
+/// Geometry of a block.
+///
+/// Only full blocks occlude their neighbors' faces & get greedy meshed, other shapes are meshed block by block, except for fluids.
+/// Stairs aren't supported, the vertex format can only lower vertices vertically by a fixed amount per block, see [`BlockShape::vertex_lowering`].
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "block_registry_asset", derive(serde::Deserialize))]
+pub enum BlockShape {
+    #[default]
+    Full,
+    /// Lower half of the block.
+    SlabBottom,
+    /// Upper half of the block.
+    SlabTop,
+    /// A fluid with its top surface `level` eighths of a block high, 1 to 8.
+    ///
+    /// Fluids are greedy meshed like full blocks, but only hide the faces of the same block, so each level of a fluid is its own block.
+    /// Their top faces are lowered to the level & the side faces shortened to match, they never hide the block above.
+    /// Collision meshes treat them as full blocks.
+    Fluid { level: u8 },
+    /// Two quads crossing along the block's diagonals, for foliage such as grass tufts & flowers.
+    ///
+    /// They're never merged & never hide their neighbors' faces, so they're best drawn double sided in an `AlphaMode::Mask` [`crate::meshing::ChunkRenderLayer`].
+    /// They're meshed for collision too, so plants should usually not have collision.
+    Cross,
+}
+impl BlockShape {
+    /// How far vertices of the block with [`crate::utils::VERTEX_HALF_DOWN_BIT`] are lowered, in voxels.
+    pub fn vertex_lowering(&self) -> f32 {
+        match self {
+            BlockShape::Fluid { level } => 1.0 - (*level).clamp(1, 8) as f32 / 8.0,
+            _ => 0.5,
+        }
+    }
+
+    /// Returns true for fluids with their top below the top of the block.
+    pub fn is_lowered_fluid(&self) -> bool {
+        matches!(self, BlockShape::Fluid { level } if *level < 8)
+    }
+}
+
+/// Block data indexed by [`BlockId`].
+///
+/// Nothing assumes a particular id is air, code that needs to place or recognize air uses [`BlockRegistry::air`].
 #[derive(Default, Debug)]
 pub struct BlockRegistry {
+    /// Block placed when removing blocks, see [`BlockRegistry::air`].
+    /// Set by [`BlockRegistryBuilder::build`], registries built by hand default to block 0.
+    pub air_block: BlockId,
     pub block_string_identifier_to_id: HashMap<BlockStringIdentifier, BlockId>,
 
     /// Maps block id to block string identifier.
@@ -37,9 +100,24 @@ pub struct BlockRegistry {
     pub block_flags: Vec<BlockFlags>,
     /// Maps block id to block color.
     pub block_color: Vec<Color>,
-    pub block_emissive: Vec<Color>
+    pub block_emissive: Vec<Color>,
+    /// Maps block id to the light it absorbs, see [`BlockRegistry::light_opacity`].
+    pub block_opacity: Vec<u8>,
+    /// Maps block id to block shape.
+    pub block_shape: Vec<BlockShape>,
+    /// See [`BlockRegistry::occlusion_table`].
+    pub occlusion_tables: OcclusionTables,
 }
+
+/// [`OcclusionTable`]s of a registry by flag, built as they're needed. Left empty when building a registry.
+#[derive(Default, Debug)]
+pub struct OcclusionTables(RwLock<HashMap<BlockFlags, Arc<OcclusionTable>>>);
 impl BlockRegistry {
+    /// The block which stands for empty space. It's invisible, so it never ends up in meshes.
+    #[inline]
+    pub fn air(&self) -> BlockId {
+        self.air_block
+    }
     #[inline]
     pub fn is_solid(&self, block_id: BlockId) -> bool {
         self.block_flags[block_id.0 as usize].contains(BlockFlags::SOLID)
@@ -48,45 +126,188 @@ impl BlockRegistry {
     pub fn has_flag(&self, block_id: BlockId, flag: BlockFlags) -> bool {
         self.block_flags[block_id.0 as usize].contains(flag)
     }
+    /// Blocks without a shape, such as in registries built by hand, are full blocks.
+    #[inline]
+    pub fn shape(&self, block_id: BlockId) -> BlockShape {
+        self.block_shape.get(block_id.0 as usize).copied().unwrap_or_default()
+    }
+    /// Returns true if the block is a full block with `flag`, hiding the faces of neighbors with `flag` behind it.
+    #[inline]
+    pub fn occludes(&self, block_id: BlockId, flag: BlockFlags) -> bool {
+        self.has_flag(block_id, flag) && self.shape(block_id) == BlockShape::Full
+    }
+    /// Returns true if the block is greedy meshed for `flag`, which are the blocks that [occlude](BlockRegistry::occludes) & fluids.
+    #[inline]
+    pub fn is_greedy_meshed(&self, block_id: BlockId, flag: BlockFlags) -> bool {
+        self.has_flag(block_id, flag) && matches!(self.shape(block_id), BlockShape::Full | BlockShape::Fluid { .. })
+    }
+    /// [`BlockRegistry::is_greedy_meshed`] of every block for `flag`, built the first time it's asked for.
+    pub fn occlusion_table(&self, flag: BlockFlags) -> Arc<OcclusionTable> {
+        if let Some(table) = self.occlusion_tables.0.read().unwrap().get(&flag) {
+            return table.clone();
+        }
+        self.occlusion_tables.0.write().unwrap().entry(flag).or_insert_with(|| Arc::new(OcclusionTable::new(self, flag))).clone()
+    }
+
+    /// Block light level the block emits, from the brightest channel of its emissive color, see [`crate::light::ChunkLight`].
+    /// Any emissive color gives at least level 1.
+    pub fn light_emission(&self, block_id: BlockId) -> u8 {
+        let Some(emissive) = self.block_emissive.get(block_id.0 as usize) else {
+            return 0;
+        };
+        let emissive = emissive.to_linear();
+        let brightest = emissive.red.max(emissive.green).max(emissive.blue).min(1.0);
+        if brightest <= 0.0 {
+            return 0;
+        }
+        ((brightest * crate::light::MAX_LIGHT as f32).round() as u8).max(1)
+    }
+
+    /// Light levels lost by block light entering the block, at least 1.
+    /// Blocks without an opacity, such as in registries built by hand, absorb 1 like air.
+    /// Full solid blocks stop light entirely regardless, see [`crate::light::ChunkLight`].
+    #[inline]
+    pub fn light_opacity(&self, block_id: BlockId) -> u8 {
+        self.block_opacity.get(block_id.0 as usize).copied().unwrap_or(1).max(1)
+    }
+
+    /// Returns the id of the block with `identifier`.
+    pub fn get_id(&self, identifier: &str) -> Option<BlockId> {
+        self.block_string_identifier_to_id.get(identifier).copied()
+    }
+
+    /// Returns the string identifier of a block.
+    pub fn get_identifier(&self, block_id: BlockId) -> Option<&str> {
+        self.block_id_to_string_identifier.get(block_id.0 as usize).map(|identifier| &*identifier.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    /// A block with the identifier has already been added.
+    DuplicateIdentifier(BlockStringIdentifier),
+    /// The registry already has [`MAX_BLOCK_TYPES`] blocks, the most the packed vertices can tell apart.
+    TooManyBlocks,
+}
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::DuplicateIdentifier(identifier) => write!(f, "block identifier '{}' is already registered", identifier.0),
+            RegistryError::TooManyBlocks => write!(f, "a block registry can't hold more than {MAX_BLOCK_TYPES} blocks"),
+        }
+    }
+}
+impl std::error::Error for RegistryError {}
+
+/// Adds blocks to a [`BlockRegistry`], keeping the identifier map & the vectors indexed by [`BlockId`] in sync.
+#[derive(Default, Debug)]
+pub struct BlockRegistryBuilder {
+    registry: BlockRegistry,
+    air_block: Option<BlockId>,
+}
+impl BlockRegistryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
+    /// Adds a block, ids are handed out in the order blocks are added.
     pub fn add_block(
         &mut self,
         identifier: BlockStringIdentifier,
         block: &Block,
-    ) -> BlockId{
+    ) -> Result<BlockId, RegistryError> {
+        let registry = &mut self.registry;
+        if registry.block_string_identifier_to_id.contains_key(&identifier) {
+            return Err(RegistryError::DuplicateIdentifier(identifier));
+        }
+        if registry.block_flags.len() >= MAX_BLOCK_TYPES {
+            return Err(RegistryError::TooManyBlocks);
+        }
+
         let mut flags = match block.visibility {
             BlockVisibilty::Solid => BlockFlags::SOLID,
             BlockVisibilty::Transparent => BlockFlags::TRANSPARENT,
+            BlockVisibilty::Cutout => BlockFlags::CUTOUT,
             BlockVisibilty::Invisible => BlockFlags::empty(),
         };
         if block.collision {
             flags |= BlockFlags::COLLISION;
         }
+        if block.animated_emissive {
+            flags |= BlockFlags::ANIMATED_EMISSIVE;
+        }
+        if block.no_greedy_merge {
+            flags |= BlockFlags::NO_GREEDY_MERGE;
+        }
 
-        let block_id = BlockId(self.block_id_to_string_identifier.len() as u16);
+        let block_id = BlockId(registry.block_id_to_string_identifier.len() as u16);
         
-        self.block_id_to_string_identifier.push(identifier.clone());
-        self.block_flags.push(flags); 
-        self.block_color.push(block.color);
-        self.block_emissive.push(block.emissive_color);
+        registry.block_id_to_string_identifier.push(identifier.clone());
+        registry.block_flags.push(flags); 
+        registry.block_color.push(block.color);
+        registry.block_emissive.push(block.emissive_color);
+        // light passes through invisible blocks like air.
+        registry.block_opacity.push(if block.visibility == BlockVisibilty::Invisible { 1 } else { block.opacity.max(1) });
+        registry.block_shape.push(block.shape);
+
+        registry.block_string_identifier_to_id.insert(identifier, block_id);
+
+        Ok(block_id)
+    }
 
-        self.block_string_identifier_to_id.insert(identifier, block_id);
+    /// Use `block_id` as [`BlockRegistry::air`], instead of the first invisible block.
+    pub fn set_air_block(&mut self, block_id: BlockId) {
+        self.air_block = Some(block_id);
+    }
 
-        block_id
+    /// Finishes the registry. Unless [`BlockRegistryBuilder::set_air_block`] was called air is the first invisible block,
+    /// or block 0 if there are none.
+    pub fn build(mut self) -> Arc<BlockRegistry> {
+        let first_invisible = self.registry.block_flags.iter()
+            .position(|flags| !flags.intersects(BlockFlags::SOLID | BlockFlags::TRANSPARENT | BlockFlags::CUTOUT))
+            .map(|id| BlockId(id as u16));
+        self.registry.air_block = self.air_block.or(first_invisible).unwrap_or_default();
+        Arc::new(self.registry)
     }
 }
 
+/// Data & mesh tasks aren't started until this exists.
 #[derive(Debug, Resource)]
 pub struct BlockRegistryResource(pub Arc<BlockRegistry>);
 
-#[derive(Default, Copy, Clone, Debug)]
+/// A single voxel.
+/// `repr(C)` keeps the id in the low half of the voxel, which [`crate::greedy_mesher_optimized::solid_row_mask`] relies on.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(C)]
 pub struct BlockData {
     pub block_type: BlockId,
+    /// Per voxel state like orientation, damage or fluid level, what it means is up to the block type.
+    /// Voxels only compress together if their states match too.
+    pub state: u16,
 }
+impl BlockData {
+    pub const fn new(block_type: BlockId) -> Self {
+        Self { block_type, state: 0 }
+    }
 
+    pub const fn with_state(mut self, state: u16) -> Self {
+        self.state = state;
+        self
+    }
+}
+impl From<BlockId> for BlockData {
+    fn from(block_type: BlockId) -> Self {
+        Self::new(block_type)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "block_registry_asset", derive(serde::Deserialize))]
 pub enum BlockVisibilty {
     Solid,
     Transparent,
+    /// See [`BlockFlags::CUTOUT`].
+    Cutout,
     Invisible
 }
 
@@ -95,6 +316,13 @@ pub struct Block {
     pub collision: bool,
     pub color: Color,
     pub emissive_color: Color,
+    /// Pulse the emissive color over time, see [`BlockFlags::ANIMATED_EMISSIVE`].
+    pub animated_emissive: bool,
+    pub shape: BlockShape,
+    /// Mesh every face of the block separately, see [`BlockFlags::NO_GREEDY_MERGE`].
+    pub no_greedy_merge: bool,
+    /// Light levels lost by block light passing through the block, see [`BlockRegistry::light_opacity`].
+    pub opacity: u8,
 }
 impl Default for Block {
     fn default() -> Self {
@@ -103,6 +331,61 @@ impl Default for Block {
             collision: true,
             color: Color::srgb(1.0, 0.0, 1.0),
             emissive_color: Color::NONE,
+            animated_emissive: false,
+            shape: BlockShape::Full,
+            no_greedy_merge: false,
+            opacity: 1,
         }
     }
 }
+
+#[test]
+fn duplicate_block_identifiers() {
+    let mut builder = BlockRegistryBuilder::new();
+    let air = builder.add_block(BlockStringIdentifier(Box::from("air")), &Block { visibility: BlockVisibilty::Invisible, ..Default::default() }).unwrap();
+    let stone = builder.add_block(BlockStringIdentifier(Box::from("stone")), &Block::default()).unwrap();
+    assert_eq!(
+        builder.add_block(BlockStringIdentifier(Box::from("air")), &Block::default()),
+        Err(RegistryError::DuplicateIdentifier(BlockStringIdentifier(Box::from("air"))))
+    );
+
+    let registry = builder.build();
+    assert_eq!(registry.block_flags.len(), 2);
+    assert_eq!(registry.get_id("air"), Some(air));
+    assert_eq!(registry.get_id("stone"), Some(stone));
+    assert_eq!(registry.get_id("dirt"), None);
+    assert_eq!(registry.get_identifier(stone), Some("stone"));
+    assert_eq!(registry.get_identifier(BlockId(2)), None);
+    assert!(!registry.is_solid(air));
+}
+
+#[test]
+fn air_is_the_first_invisible_block() {
+    let invisible = Block { visibility: BlockVisibilty::Invisible, collision: false, ..Default::default() };
+
+    let mut builder = BlockRegistryBuilder::new();
+    builder.add_block(BlockStringIdentifier(Box::from("stone")), &Block::default()).unwrap();
+    builder.add_block(BlockStringIdentifier(Box::from("glass")), &Block { visibility: BlockVisibilty::Transparent, ..Default::default() }).unwrap();
+    let air = builder.add_block(BlockStringIdentifier(Box::from("air")), &invisible).unwrap();
+    builder.add_block(BlockStringIdentifier(Box::from("void")), &invisible).unwrap();
+    assert_eq!(builder.build().air(), air);
+
+    let mut builder = BlockRegistryBuilder::new();
+    builder.add_block(BlockStringIdentifier(Box::from("air")), &invisible).unwrap();
+    let void = builder.add_block(BlockStringIdentifier(Box::from("void")), &invisible).unwrap();
+    builder.set_air_block(void);
+    assert_eq!(builder.build().air(), void);
+}
+
+#[test]
+fn registry_is_limited_to_vertex_block_types() {
+    let mut builder = BlockRegistryBuilder::new();
+    for i in 0..MAX_BLOCK_TYPES {
+        builder.add_block(BlockStringIdentifier(Box::from(format!("block_{i}"))), &Block::default()).unwrap();
+    }
+    assert_eq!(
+        builder.add_block(BlockStringIdentifier(Box::from("one_too_many")), &Block::default()),
+        Err(RegistryError::TooManyBlocks)
+    );
+    assert_eq!(builder.build().block_flags.len(), MAX_BLOCK_TYPES);
+}
@@ -1,59 +1,332 @@
-use std::sync::Arc;
+use std::{future::Future, sync::Arc, time::Duration};
 
 use bevy::{
     prelude::*,
     tasks::{block_on, poll_once, AsyncComputeTaskPool, Task},
-    utils::{HashMap, HashSet},
+    utils::{HashMap, HashSet, Instant},
 };
 use indexmap::IndexSet;
 
 use crate::{
-    chunk::{ChunkData, ChunkGenerator}, constants::{CHUNK_SIZE, CHUNK_SIZE3}, events::{ChunkEventsPlugin, ChunkGenerated, ChunkModified, ChunkUnloaded}, lod::Lod, scanner::{scan, ChunkGainedScannerRelevance, ChunkLostScannerRelevance, ChunkPos, ChunkTrackerPlugin, DataScanner, MeshScanner, Scanner, ScannerPlugin}, utils::{get_edging_chunk, vec3_to_index}, voxel::BlockId
+    chunk::{ChunkData, ChunkGenerator, ChunkGeneratorWithContext, FaceSolidity, GenerationContext, GeneratedChunk, PendingStructure}, constants::{ADJACENT_CHUNK_DIRECTIONS, CHUNK_SIZE, CHUNK_SIZE_I32}, events::{ChunkBlocksChanged, ChunkEventsPlugin, ChunkGenerated, ChunkModified, ChunkUnloaded, ChunkVoxelsModified, PrewarmCompleted, VoxelEngineSaturated}, face_direction::FaceDir, greedy_mesher_optimized::{FaceCullRule, GreedyMergeOrder}, lod::Lod, meshing::MeshingPipeline, scanner::{scan, ChunkGainedScannerRelevance, ChunkLostScannerRelevance, ChunkPos, ChunkTrackerPlugin, DataScanner, ForceLoadedChunks, MeshScanner, Scanner, ScannerPlugin, VisualScanner}, utils::{chunks_in_aabb, get_edging_chunk, vec3_to_index, world_to_chunk_local}, voxel::{BlockData, BlockFlags, BlockId, BlockRegistry, BlockRegistryResource}
 };
 
 pub struct VoxelEnginePlugin;
 
-pub const MAX_DATA_TASKS: usize = 64;
-
 impl Plugin for VoxelEnginePlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<VoxelEngine>();
+        app.init_resource::<VoxelEngine>()
+            .init_resource::<VoxelEngineState>()
+            .init_resource::<VoxelEngineConfig>()
+            .init_resource::<ChunkCompactionConfig>();
+
+        // for tweaking the engine from an inspector.
+        app.register_type::<VoxelEngineConfig>()
+            .register_type::<MeshingMethod>()
+            .register_type::<Lod>()
+            .register_type::<Scanner<DataScanner>>()
+            .register_type::<Scanner<MeshScanner>>()
+            .register_type::<Scanner<VisualScanner>>();
 
         app.add_plugins((
             ChunkEventsPlugin,
             ChunkTrackerPlugin,
             ScannerPlugin::<DataScanner>::default(),
             ScannerPlugin::<MeshScanner>::default(),
+            ScannerPlugin::<VisualScanner>::default(),
         ));
         
 
-        app.add_systems(Update, start_modifications);
+        app.add_systems(Update, (
+            join_modifications.run_if(voxel_engine_joining),
+            start_modifications.run_if(voxel_engine_running),
+        ).chain());
+        app.add_systems(
+            Update,
+            compact_chunks
+                .after(start_modifications)
+                .run_if(voxel_engine_running)
+                .run_if(|config: Res<ChunkCompactionConfig>| config.enabled),
+        );
+        app.add_systems(
+            Update,
+            (
+                join_data.run_if(voxel_engine_joining),
+                (
+                    limit_loaded_chunks,
+                    unload_data,
+                    regenerate_outdated_chunks.run_if(resource_changed::<VoxelEngineConfig>),
+                    start_data_tasks.run_if(resource_exists::<BlockRegistryResource>),
+                    detect_saturation,
+                ).chain().after(scan::<DataScanner>).run_if(voxel_engine_running)
+            ).chain(),
+        );
+        app.add_systems(Update, resolve_chunk_load_requests.after(join_data));
+        app.add_systems(Update, update_prewarms.after(join_data).run_if(voxel_engine_running));
         app.add_systems(
             Update,
-            (join_data, (unload_data, start_data_tasks).chain().after(scan::<DataScanner>)).chain(),
+            update_face_solidity
+                .after(join_data)
+                .after(start_modifications)
+                .run_if(resource_exists::<BlockRegistryResource>),
         );
+        app.add_systems(Update, update_finalized_chunks.after(join_data).after(start_modifications));
+    }
+}
+
+/// Controls whether the voxel engine's systems run.
+///
+/// While paused no scanning, modifications, unloading or new data/mesh tasks happen.
+/// Tasks already in flight keep running on the task pool regardless, since they can't be cancelled without being dropped.
+/// Whether their results are joined while paused depends on `join_in_flight`,
+/// if `false` they are left untouched & joined once the engine is resumed.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum VoxelEngineState {
+    #[default]
+    Running,
+    Paused {
+        join_in_flight: bool,
+    },
+}
+
+/// Run condition for systems that start new work.
+/// Also true if there is no [`VoxelEngineState`], so the scanners can be used on their own.
+pub fn voxel_engine_running(state: Option<Res<VoxelEngineState>>) -> bool {
+    state.is_none_or(|state| *state == VoxelEngineState::Running)
+}
+
+/// Run condition for systems that join finished tasks.
+pub fn voxel_engine_joining(state: Option<Res<VoxelEngineState>>) -> bool {
+    state.is_none_or(|state| !matches!(*state, VoxelEngineState::Paused { join_in_flight: false }))
+}
+
+/// Limits on how much work the engine schedules at once.
+/// Changes take effect the next time tasks are scheduled.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+#[reflect(Resource)]
+pub struct VoxelEngineConfig {
+    /// Max chunk meshing tasks running at once.
+    pub max_mesh_tasks: usize,
+    /// Max chunk generation tasks running at once.
+    pub max_data_tasks: usize,
+    /// Block that chunks are filled with when their generator panics.
+    /// Set it to something distinctive to make failed chunks stand out.
+    pub generator_error_block: BlockId,
+    /// Warn about chunks taking longer than this to generate, as each one keeps a task pool thread busy the whole time.
+    /// Expensive generators should be split up or simplified.
+    pub slow_generation_warning: Option<Duration>,
+    /// Where generation, meshing & modifications run.
+    pub threading: Threading,
+    /// Most chunks of data to keep loaded, including chunks being generated.
+    /// Past it the chunks furthest from every [`Scanner<DataScanner>`] are unloaded, even if a scanner still covers them,
+    /// as a safety valve against scanner radii too big for the memory available. [`ForceLoadedChunks`] are never unloaded for it.
+    ///
+    /// Chunks cut this way, unloaded or never loaded, are loaded again once they're within the limit, e.g. after the scanners moved.
+    pub max_loaded_chunks: Option<usize>,
+    /// Frames in a row the data tasks have to stay at `max_data_tasks` with chunks still queued before the engine is saturated,
+    /// see [`VoxelEngine::is_saturated`].
+    pub saturation_frames: u32,
+    /// How long a [`VoxelEngine::prewarm`] region stays loaded after it was made, whether or not it completed.
+    /// A completed region unloads sooner if a [`Scanner<MeshScanner>`] reaches its center first.
+    pub prewarm_timeout: Duration,
+    /// Only mesh chunks once they & the neighbors they're meshed with are [`VoxelEngine::is_finalized`],
+    /// rather than as soon as their data exists, so structures of chunks generated later can't leave seams at chunk borders.
+    ///
+    /// Meshes lag behind the data by `structure_reach` more chunks, as the neighbors need every chunk within that reach generated.
+    /// Scanners need a [`Scanner<DataScanner>`] radius at least `structure_reach + 1` bigger than the [`Scanner<MeshScanner>`] radius,
+    /// or the chunks at the edge of the mesh radius are never meshed.
+    pub mesh_finalized_only: bool,
+    /// Furthest, in chunks along each axis, the structures of a generated chunk place blocks from it.
+    pub structure_reach: u8,
+    /// Limits of a fixed size world, chunks outside it are never generated or meshed.
+    pub world_bounds: Option<WorldBounds>,
+    /// Version of the generator, bump it after changing the generator to regenerate the loaded chunks an older version made.
+    /// Chunks that were modified since they generated are kept, see [`VoxelEngine::is_modified`].
+    pub generation_version: u32,
+    /// Most unloaded chunks to keep [`VoxelEngine::deferred_modifications`] & fills for,
+    /// so edits of chunks that never load don't pile up. Past it the ones furthest from every [`Scanner<DataScanner>`] are dropped.
+    pub max_deferred_chunks: usize,
+}
+impl Default for VoxelEngineConfig {
+    fn default() -> Self {
+        Self {
+            max_mesh_tasks: 32,
+            max_data_tasks: 64,
+            generator_error_block: BlockId::default(),
+            slow_generation_warning: Some(Duration::from_millis(50)),
+            threading: Threading::Threaded,
+            max_loaded_chunks: None,
+            saturation_frames: 30,
+            prewarm_timeout: Duration::from_secs(10),
+            mesh_finalized_only: false,
+            structure_reach: 1,
+            world_bounds: None,
+            generation_version: 0,
+            max_deferred_chunks: 4096,
+        }
+    }
+}
+
+/// See [`VoxelEngineConfig::world_bounds`].
+/// Chunks at the edge of the bounds are meshed as if everything outside of it were the `outside` block,
+/// rather than waiting on neighbors that are never generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub struct WorldBounds {
+    /// Lowest chunk of the world.
+    pub min: IVec3,
+    /// Highest chunk of the world, inclusive.
+    pub max: IVec3,
+    /// Air for open edges, a solid block to close the world off with faces along its edge.
+    pub outside: BlockId,
+}
+impl WorldBounds {
+    pub fn contains(&self, chunk_pos: IVec3) -> bool {
+        chunk_pos.cmpge(self.min).all() && chunk_pos.cmple(self.max).all()
+    }
+}
+
+/// See [`VoxelEngineConfig::threading`].
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum Threading {
+    /// Tasks run on the [`AsyncComputeTaskPool`].
+    #[default]
+    Threaded,
+    /// Work runs to completion inside the systems that would've spawned the tasks,
+    /// for single threaded targets such as wasm where tasks may not make progress.
+    /// [`VoxelEngineConfig::max_data_tasks`] & [`VoxelEngineConfig::max_mesh_tasks`] become the number of chunks done each frame.
+    InlineImmediate,
+}
+
+/// A task spawned according to [`Threading`], which already holds its result if it ran inline.
+pub struct ChunkTask<T>(ChunkTaskState<T>);
+enum ChunkTaskState<T> {
+    Running(Task<T>),
+    Done(Option<T>),
+}
+impl<T: Send + 'static> ChunkTask<T> {
+    pub fn spawn(threading: Threading, future: impl Future<Output = T> + Send + 'static) -> Self {
+        match threading {
+            Threading::Threaded => Self(ChunkTaskState::Running(AsyncComputeTaskPool::get().spawn(future))),
+            Threading::InlineImmediate => Self(ChunkTaskState::Done(Some(block_on(future)))),
+        }
+    }
+
+    /// Takes the result if the task has finished, it's only returned once.
+    pub fn poll(&mut self) -> Option<T> {
+        match &mut self.0 {
+            ChunkTaskState::Running(task) => block_on(poll_once(task)),
+            ChunkTaskState::Done(result) => result.take(),
+        }
+    }
+}
+
+/// Settings for the background pass which compresses edited chunks again, see [`ChunkData::compress`].
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkCompactionConfig {
+    pub enabled: bool,
+    /// How many chunks are checked each frame.
+    pub chunks_per_frame: usize,
+}
+impl Default for ChunkCompactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            chunks_per_frame: 4,
+        }
     }
 }
 
 #[derive(Debug, Reflect, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum MeshingMethod {
     BinaryGreedyMeshing,
+    /// Binary greedy meshing which keeps each chunk's mesh split into slices,
+    /// only rebuilding the slices affected by a [`ChunkModification`] when remeshing.
+    IncrementalBinaryGreedy,
+    /// Smooth surface nets over the density of the first [`crate::meshing::ChunkRenderLayers`] layer's blocks,
+    /// sent as [`crate::events::ChunkMeshed::smooth`] instead of the layer meshes, see [`crate::surface_nets::build_smooth_mesh`].
+    /// Collision meshes stay blocky.
+    SmoothDensity,
 }
 
 /// holds all voxel world data
 #[derive(Resource)]
 pub struct VoxelEngine {
+    /// Data of each loaded chunk. Prefer [`VoxelEngine::iter_loaded`] & [`VoxelEngine::chunk_data`] for reading it,
+    /// they don't depend on how the chunks are stored.
     pub world_data: HashMap<IVec3, Arc<ChunkData>>,
     // Using index map to only load a chunk once & still be able to sort.
     pub load_data_queue: IndexSet<IVec3>,
     pub unload_data_queue: Vec<IVec3>,
-    pub data_tasks: HashMap<IVec3, Option<Task<ChunkData>>>,
+    pub data_tasks: HashMap<IVec3, Option<ChunkTask<GeneratedChunk>>>,
     pub lod: Lod,
+    /// Generate skirts along borders with chunks meshed at a coarser LOD to hide cracks.
+    pub generate_skirts: bool,
+    /// Calculate ambient occlusion for the render meshes.
+    /// Without it chunks are meshed as soon as their face neighbors are loaded, rather than all 26 neighbors.
+    /// Changing this only affects chunks meshed afterwards.
+    pub ambient_occlusion: bool,
+    /// Light the render meshes with block light flood filled from emissive blocks, see [`crate::light::ChunkLight`].
+    /// Like ambient occlusion it needs all 26 neighbors of a chunk to mesh it.
+    /// Changing this only affects chunks meshed afterwards.
+    pub block_light: bool,
+    /// Which faces between neighboring blocks are meshed, `None` culls them with [`crate::greedy_mesher_optimized::StandardCull`].
+    /// Changing this only affects chunks meshed afterwards.
+    pub face_cull_rule: Option<Arc<dyn FaceCullRule>>,
+    /// How the faces of each direction are merged into quads, see [`crate::chunks_refs::ChunksRefs::merge_orders`].
+    /// Changing this only affects chunks meshed afterwards.
+    pub merge_orders: [GreedyMergeOrder; 6],
     pub meshing_method: MeshingMethod,
+    /// Blocks to set in each chunk, applied on the task pool in the order they're queued.
+    ///
+    /// Reads like [`VoxelEngine::get_block`] don't see them until [`join_modifications`] has swapped in the modified chunk,
+    /// which is a frame later at the earliest. The same goes for [`VoxelEngine::fill_region`] & [`VoxelEngine::fill_sphere`],
+    /// except for chunks they cover entirely.
+    ///
+    /// Modifications of chunks that aren't loaded are moved to `deferred_modifications`.
     pub chunk_modifications: HashMap<IVec3, Vec<ChunkModification>>,
+    /// Modifications of chunks that weren't loaded, applied when the chunk generates, after its structures & `deferred_fills`.
+    /// Past [`VoxelEngineConfig::max_deferred_chunks`] the ones furthest from every [`Scanner<DataScanner>`] are dropped.
+    pub deferred_modifications: HashMap<IVec3, Vec<ChunkModification>>,
+    /// `chunk_fills` of chunks that weren't loaded, applied when the chunk generates like `deferred_modifications`.
+    pub deferred_fills: HashMap<IVec3, BlockData>,
+    /// Modifications being applied, chunks with a task wait for it to finish before their next batch is started.
+    pub modification_tasks: HashMap<IVec3, ChunkTask<ModifiedChunk>>,
+    /// Chunks to be entirely replaced by a single block, applied before `chunk_modifications`.
+    pub chunk_fills: HashMap<IVec3, BlockData>,
+    /// Block types to replace in every loaded chunk, in order, see [`VoxelEngine::replace_block_global`].
+    pub block_replacements: Vec<(BlockId, BlockId)>,
+    /// Which faces of each loaded chunk are entirely opaque, kept up to date as chunks generate & are modified.
+    pub face_solidity: HashMap<IVec3, FaceSolidity>,
+    /// See [`VoxelEngine::face_solidity_version`].
+    face_solidity_version: u32,
+    /// Structure blocks waiting for their chunk to generate, as chunk -> (local position, block).
+    pub pending_structure_blocks: HashMap<IVec3, Vec<(IVec3, BlockId)>>,
+    /// Frames each queued chunk has waited on its neighbors, see [`ChunkGeneratorWithContext::max_wait_frames`].
+    neighbor_waits: HashMap<IVec3, u32>,
+    /// Frames in a row generation has been at its task limit, see [`VoxelEngine::is_saturated`].
+    saturated_frames: u32,
+    saturated: bool,
+    /// Regions being loaded ahead of time with when they were first updated & whether they completed, see [`VoxelEngine::prewarm`].
+    prewarms: Vec<(Prewarm, Option<Duration>, bool)>,
+    /// Chunks force loaded for the prewarms, they're generated & meshed before any other queued chunk.
+    prewarm_data_chunks: HashSet<IVec3>,
+    prewarm_mesh_chunks: HashSet<IVec3>,
+    /// See [`VoxelEngine::is_finalized`].
+    finalized_chunks: HashSet<IVec3>,
+    /// See [`VoxelEngine::generation_version`].
+    generation_versions: HashMap<IVec3, u32>,
+    /// Version each of the `data_tasks` generates with, stamped into `generation_versions` once its data is inserted.
+    generating_versions: HashMap<IVec3, u32>,
+    /// See [`VoxelEngine::is_modified`].
+    modified_chunks: HashSet<IVec3>,
+    /// Chunks a scanner wants which were cut by [`VoxelEngineConfig::max_loaded_chunks`], queued again once there's room.
+    trimmed_chunks: HashSet<IVec3>,
+    /// Structure blocks among each chunk's `chunk_modifications`, which don't make it count as modified.
+    structure_modifications: HashMap<IVec3, usize>,
 }
 
-pub struct ChunkModification(pub IVec3, pub BlockId);
+/// Sets the block at a chunk local position.
+pub struct ChunkModification(pub IVec3, pub BlockData);
 
 
 impl VoxelEngine {
@@ -78,8 +351,33 @@ impl Default for VoxelEngine {
             unload_data_queue: Vec::new(),
             data_tasks: HashMap::new(),
             lod: Lod::L32,
+            generate_skirts: true,
+            ambient_occlusion: true,
+            block_light: false,
+            face_cull_rule: None,
+            merge_orders: [GreedyMergeOrder::HeightFirst; 6],
             meshing_method: MeshingMethod::BinaryGreedyMeshing,
             chunk_modifications: HashMap::new(),
+            deferred_modifications: HashMap::new(),
+            deferred_fills: HashMap::new(),
+            modification_tasks: HashMap::new(),
+            chunk_fills: HashMap::new(),
+            block_replacements: Vec::new(),
+            face_solidity: HashMap::new(),
+            face_solidity_version: 0,
+            pending_structure_blocks: HashMap::new(),
+            neighbor_waits: HashMap::new(),
+            saturated_frames: 0,
+            saturated: false,
+            prewarms: Vec::new(),
+            prewarm_data_chunks: HashSet::new(),
+            prewarm_mesh_chunks: HashSet::new(),
+            finalized_chunks: HashSet::new(),
+            generation_versions: HashMap::new(),
+            generating_versions: HashMap::new(),
+            modified_chunks: HashSet::new(),
+            trimmed_chunks: HashSet::new(),
+            structure_modifications: HashMap::new(),
         }
     }
 }
@@ -87,47 +385,331 @@ impl Default for VoxelEngine {
 /// begin data building tasks for chunks in range
 pub fn start_data_tasks(
     mut voxel_engine: ResMut<VoxelEngine>,
-    scanners: Query<&ChunkPos, With<Scanner<DataScanner>>>,
+    scanners: Query<(&Scanner<DataScanner>, &ChunkPos)>,
     mut chunk_gained_data_relevance: EventReader<ChunkGainedScannerRelevance<DataScanner>>,
-    chunk_generator: Res<ChunkGenerator>,
+    chunk_generator: Option<Res<ChunkGenerator>>,
+    context_generator: Option<Res<ChunkGeneratorWithContext>>,
+    config: Res<VoxelEngineConfig>,
 ) {
-    let task_pool = AsyncComputeTaskPool::get();
-
     let VoxelEngine {
+        world_data,
         load_data_queue,
         data_tasks,
+        neighbor_waits,
+        prewarm_data_chunks,
+        generating_versions,
         ..
     } = voxel_engine.as_mut();
 
     
     // Order by closest distance to any scanner.
     if !chunk_gained_data_relevance.is_empty() {
-        load_data_queue.extend(chunk_gained_data_relevance.read().map(|e| e.chunk));
+        load_data_queue.extend(chunk_gained_data_relevance.read().map(|e| e.chunk).filter(|chunk| config.world_bounds.is_none_or(|bounds| bounds.contains(*chunk))));
         
         // TODO: With many chunks in queue, this is SLOW.
         let _span = info_span!("Sorting data queue by distance to scanners").entered();
-        load_data_queue.sort_by_cached_key(|pos| {
-            let mut closest_distance = i32::MAX;
-            
-            for scan_pos in scanners.iter() {
-                let distance = pos.distance_squared(scan_pos.0);
-                if distance < closest_distance {
-                    closest_distance = distance;
+        load_data_queue.sort_by_cached_key(|pos| (!prewarm_data_chunks.contains(pos), data_priority(*pos, scanners.iter())));
+    }
+
+    let tasks_left = config.max_data_tasks.saturating_sub(data_tasks.len()).min(load_data_queue.len());
+
+    if let Some(context_generator) = context_generator {
+        neighbor_waits.retain(|chunk_pos, _| load_data_queue.contains(chunk_pos));
+
+        let mut started = Vec::with_capacity(tasks_left);
+        for world_pos in load_data_queue.iter().copied() {
+            if started.len() == tasks_left {
+                break;
+            }
+            // neighbors being generated will be loaded soon, wait for them to be readable.
+            let neighbor_generating = ADJACENT_CHUNK_DIRECTIONS.iter().any(|dir| *dir != IVec3::ZERO && data_tasks.contains_key(&(world_pos + *dir)));
+            if neighbor_generating {
+                let waited = neighbor_waits.entry(world_pos).or_default();
+                if *waited < context_generator.max_wait_frames {
+                    *waited += 1;
+                    continue;
                 }
             }
-    
-            closest_distance
-        });
+            neighbor_waits.remove(&world_pos);
+
+            let generate = context_generator.generate.clone();
+            let context = GenerationContext::new(world_data, world_pos);
+            data_tasks.insert(world_pos, Some(spawn_data_task(&config, world_pos, move |chunk_pos| generate(chunk_pos, &context))));
+            generating_versions.insert(world_pos, config.generation_version);
+            started.push(world_pos);
+        }
+        load_data_queue.retain(|chunk_pos| !started.contains(chunk_pos));
+        return;
     }
 
-    let tasks_left = MAX_DATA_TASKS.saturating_sub(data_tasks.len()).min(load_data_queue.len());
+    let Some(chunk_generator) = chunk_generator else {
+        return;
+    };
     for world_pos in load_data_queue.drain(0..tasks_left) {
-        let k = world_pos;
         let generate = chunk_generator.generate.clone();
-        let task = task_pool.spawn(async move {
-            generate(k)
-        });
-        data_tasks.insert(world_pos, Some(task));
+        data_tasks.insert(world_pos, Some(spawn_data_task(&config, world_pos, move |chunk_pos| generate(chunk_pos))));
+        generating_versions.insert(world_pos, config.generation_version);
+    }
+}
+
+/// Queues the loaded chunks made by another [`VoxelEngineConfig::generation_version`] to generate again,
+/// unless they were [modified](VoxelEngine::is_modified) since.
+///
+/// Their old data stays loaded until the new data replaces it, with a [`ChunkGenerated`] like any other chunk.
+/// Structure blocks neighbors placed in a regenerated chunk are lost, unless the neighbor regenerates after it.
+pub fn regenerate_outdated_chunks(mut voxel_engine: ResMut<VoxelEngine>, config: Res<VoxelEngineConfig>) {
+    let VoxelEngine {
+        world_data,
+        load_data_queue,
+        generation_versions,
+        modified_chunks,
+        ..
+    } = voxel_engine.as_mut();
+
+    let outdated = generation_versions.iter()
+        .filter(|(chunk, version)| **version != config.generation_version && world_data.contains_key(*chunk) && !modified_chunks.contains(*chunk))
+        .map(|(chunk, _)| *chunk);
+    load_data_queue.extend(outdated);
+}
+
+/// Counts the frames generation stays at [`VoxelEngineConfig::max_data_tasks`] with chunks left in the queue,
+/// sending [`VoxelEngineSaturated`] once it has for [`VoxelEngineConfig::saturation_frames`].
+fn detect_saturation(
+    mut voxel_engine: ResMut<VoxelEngine>,
+    config: Res<VoxelEngineConfig>,
+    mut saturated: EventWriter<VoxelEngineSaturated>,
+) {
+    let at_limit = voxel_engine.data_tasks.len() >= config.max_data_tasks && !voxel_engine.load_data_queue.is_empty();
+    if !at_limit {
+        voxel_engine.saturated_frames = 0;
+        voxel_engine.saturated = false;
+        return;
+    }
+    voxel_engine.saturated_frames += 1;
+    if !voxel_engine.saturated && voxel_engine.saturated_frames >= config.saturation_frames {
+        voxel_engine.saturated = true;
+        saturated.send(VoxelEngineSaturated { queued: voxel_engine.load_data_queue.len() });
+    }
+}
+
+fn spawn_data_task(config: &VoxelEngineConfig, chunk_pos: IVec3, generate: impl Fn(IVec3) -> GeneratedChunk + Send + 'static) -> ChunkTask<GeneratedChunk> {
+    let error_block = config.generator_error_block;
+    let slow_generation_warning = config.slow_generation_warning;
+    ChunkTask::spawn(config.threading, async move {
+        let start = Instant::now();
+        let generated = generate_or_error_chunk(&generate, chunk_pos, error_block);
+        let elapsed = start.elapsed();
+        if let Some(threshold) = slow_generation_warning.filter(|threshold| elapsed > *threshold) {
+            warn!("Generating chunk {chunk_pos} took {elapsed:?}, more than the slow generation warning of {threshold:?}.");
+        }
+        generated
+    })
+}
+
+/// Sort key of a chunk in the data queue.
+/// Closest to any scanner first, with that scanner's [`crate::scanner::PriorityBias`] breaking ties.
+fn data_priority<'a>(chunk: IVec3, scanners: impl Iterator<Item = (&'a Scanner<DataScanner>, &'a ChunkPos)>) -> (i32, i32) {
+    scanners
+        .map(|(scanner, scan_pos)| (chunk.distance_squared(scan_pos.0), scanner.priority_bias().key(chunk - scan_pos.0)))
+        .min()
+        .unwrap_or((i32::MAX, 0))
+}
+
+/// Runs the generator, returning a chunk filled with `error_block` if it panics
+/// so a broken generator doesn't take down the task pool thread.
+/// Generated data is compressed here so it happens on the task pool.
+fn generate_or_error_chunk(generate: &dyn Fn(IVec3) -> GeneratedChunk, chunk_pos: IVec3, error_block: BlockId) -> GeneratedChunk {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| generate(chunk_pos))) {
+        Ok(mut generated) => {
+            generated.data.compress();
+            generated
+        }
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            error!("Chunk generator panicked for chunk {chunk_pos}: {message}");
+
+            ChunkData::Filled(BlockData::new(error_block)).into()
+        }
+    }
+}
+
+/// Waits for the data of a chunk to exist, [`ChunkReady`] is inserted on the same entity once it does.
+///
+/// This doesn't load the chunk, it still has to be in range of a [`Scanner<DataScanner>`].
+/// Changing the requested chunk removes [`ChunkReady`] until the new chunk exists.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLoadRequest(pub IVec3);
+
+/// The data of the chunk in [`ChunkLoadRequest`] exists.
+/// Not removed if the chunk is unloaded again.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkReady(pub IVec3);
+
+pub fn resolve_chunk_load_requests(
+    mut commands: Commands,
+    voxel_engine: Res<VoxelEngine>,
+    requests: Query<(Entity, &ChunkLoadRequest, Option<&ChunkReady>)>,
+) {
+    for (entity, ChunkLoadRequest(chunk_pos), ready) in requests.iter() {
+        if ready.is_some_and(|ready| ready.0 == *chunk_pos) {
+            continue;
+        }
+
+        if voxel_engine.world_data.contains_key(chunk_pos) {
+            commands.entity(entity).insert(ChunkReady(*chunk_pos));
+        } else if ready.is_some() {
+            commands.entity(entity).remove::<ChunkReady>();
+        }
+    }
+}
+
+/// A cube of chunks around `center` loaded ahead of time, see [`VoxelEngine::prewarm`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prewarm {
+    pub center: IVec3,
+    pub radius: i32,
+}
+impl Prewarm {
+    /// Chunks meshed for the region.
+    pub fn chunks(&self) -> impl Iterator<Item = IVec3> {
+        chunks_in_cube(self.center, self.radius)
+    }
+
+    /// Chunks loaded for the region, one further out so the outermost chunks can be meshed.
+    pub fn data_chunks(&self) -> impl Iterator<Item = IVec3> {
+        chunks_in_cube(self.center, self.radius + 1)
+    }
+}
+
+fn chunks_in_cube(center: IVec3, radius: i32) -> impl Iterator<Item = IVec3> {
+    (-radius..=radius).flat_map(move |x| (-radius..=radius).flat_map(move |y| (-radius..=radius).map(move |z| center + IVec3::new(x, y, z))))
+}
+
+/// Force loads the [`VoxelEngine::prewarm`] regions, sends [`PrewarmCompleted`] once each is meshed
+/// & lets its chunks unload like any other once a [`Scanner<MeshScanner>`] covers its center or [`VoxelEngineConfig::prewarm_timeout`] has passed.
+#[allow(clippy::too_many_arguments)]
+pub fn update_prewarms(
+    mut voxel_engine: ResMut<VoxelEngine>,
+    mut force_loaded_data: ResMut<ForceLoadedChunks<DataScanner>>,
+    mut force_loaded_meshes: ResMut<ForceLoadedChunks<MeshScanner>>,
+    mesh_scanners: Query<(&Scanner<MeshScanner>, &ChunkPos)>,
+    meshing_pipeline: Option<Res<MeshingPipeline>>,
+    config: Res<VoxelEngineConfig>,
+    time: Res<Time>,
+    mut completed: EventWriter<PrewarmCompleted>,
+) {
+    if voxel_engine.prewarms.is_empty() && voxel_engine.prewarm_data_chunks.is_empty() && voxel_engine.prewarm_mesh_chunks.is_empty() {
+        return;
+    }
+    let now = time.elapsed();
+    let VoxelEngine {
+        world_data,
+        prewarms,
+        prewarm_data_chunks,
+        prewarm_mesh_chunks,
+        ..
+    } = voxel_engine.as_mut();
+    // chunks outside of the world are never loaded or meshed.
+    let in_bounds = |chunk: &IVec3| config.world_bounds.is_none_or(|bounds| bounds.contains(*chunk));
+
+    for (prewarm, created_at, done) in prewarms.iter_mut() {
+        // `VoxelEngine::prewarm` has no clock, so the timeout starts the first time it's updated.
+        created_at.get_or_insert(now);
+        if *done {
+            continue;
+        }
+        *done = match &meshing_pipeline {
+            Some(meshing_pipeline) => prewarm.chunks().filter(in_bounds).all(|chunk| meshing_pipeline.is_meshed(chunk)),
+            None => prewarm.data_chunks().filter(in_bounds).all(|chunk| world_data.contains_key(&chunk)),
+        };
+        if *done {
+            completed.send(PrewarmCompleted(*prewarm));
+        }
+    }
+
+    prewarms.retain(|(prewarm, created_at, done)| {
+        let timed_out = created_at.is_some_and(|created_at| now.saturating_sub(created_at) >= config.prewarm_timeout);
+        let taken_over = *done && mesh_scanners.iter().any(|(scanner, scan_pos)| scanner.covers(scan_pos.0, prewarm.center));
+        !timed_out && !taken_over
+    });
+
+    sync_prewarm_chunks(&mut force_loaded_data, prewarm_data_chunks, prewarms.iter().flat_map(|(prewarm, ..)| prewarm.data_chunks()).filter(in_bounds).collect());
+    sync_prewarm_chunks(&mut force_loaded_meshes, prewarm_mesh_chunks, prewarms.iter().flat_map(|(prewarm, ..)| prewarm.chunks()).filter(in_bounds).collect());
+}
+
+/// Force loads the `wanted` chunks & releases the ones no longer wanted.
+/// `added` are the chunks the prewarms force loaded, so chunks force loaded by others are left alone.
+fn sync_prewarm_chunks<T: Send + Sync + 'static>(force_loaded: &mut ResMut<ForceLoadedChunks<T>>, added: &mut HashSet<IVec3>, wanted: HashSet<IVec3>) {
+    let released: Vec<IVec3> = added.difference(&wanted).copied().collect();
+    let new: Vec<IVec3> = wanted.iter().filter(|chunk| !added.contains(*chunk) && !force_loaded.chunks.contains(*chunk)).copied().collect();
+    // only touch the resource when something changed, changes to it make the scanners run.
+    if released.is_empty() && new.is_empty() {
+        return;
+    }
+    for chunk in released {
+        added.remove(&chunk);
+        force_loaded.chunks.remove(&chunk);
+    }
+    for chunk in new {
+        added.insert(chunk);
+        force_loaded.chunks.insert(chunk);
+    }
+}
+
+/// Cuts the chunks past [`VoxelEngineConfig::max_loaded_chunks`], furthest from the scanners first.
+/// Loaded ones are queued for unloading, queued ones are only taken out of the queue.
+/// Queued chunks count towards the limit too, so chunks closer than loaded ones still get loaded.
+/// Cut chunks are queued again once they're within the limit.
+pub fn limit_loaded_chunks(
+    mut voxel_engine: ResMut<VoxelEngine>,
+    scanners: Query<(&Scanner<DataScanner>, &ChunkPos)>,
+    force_loaded_chunks: Res<ForceLoadedChunks<DataScanner>>,
+    config: Res<VoxelEngineConfig>,
+) {
+    let VoxelEngine {
+        world_data,
+        load_data_queue,
+        data_tasks,
+        unload_data_queue,
+        trimmed_chunks,
+        ..
+    } = voxel_engine.as_mut();
+    let Some(max_loaded_chunks) = config.max_loaded_chunks else {
+        load_data_queue.extend(trimmed_chunks.drain());
+        return;
+    };
+    // loaded chunks queued or generating again to regenerate are only counted once.
+    let queued = || load_data_queue.iter().filter(|chunk| !world_data.contains_key(*chunk));
+    let generating = data_tasks.keys().filter(|chunk| !world_data.contains_key(*chunk)).count();
+    if world_data.len() + queued().count() + generating + trimmed_chunks.len() <= max_loaded_chunks {
+        load_data_queue.extend(trimmed_chunks.drain());
+        return;
+    }
+
+    // chunks being generated can't be stopped, they're unloaded once they've finished if they're still too far.
+    let budget = max_loaded_chunks.saturating_sub(generating + force_loaded_chunks.chunks.len());
+    let mut chunks: Vec<IVec3> = world_data.keys()
+        .chain(queued())
+        .chain(trimmed_chunks.iter())
+        .filter(|chunk| !force_loaded_chunks.chunks.contains(*chunk))
+        .copied()
+        .collect();
+    chunks.sort_by_cached_key(|chunk| (data_priority(*chunk, scanners.iter()), chunk.to_array()));
+    let (kept, cut) = chunks.split_at(budget.min(chunks.len()));
+    for chunk in kept {
+        if trimmed_chunks.remove(chunk) {
+            load_data_queue.insert(*chunk);
+        }
+    }
+    for chunk in cut {
+        if world_data.contains_key(chunk) {
+            unload_data_queue.push(*chunk);
+        } else {
+            load_data_queue.swap_remove(chunk);
+        }
+        trimmed_chunks.insert(*chunk);
     }
 }
 
@@ -141,97 +723,1772 @@ pub fn unload_data(
         unload_data_queue,
         world_data,
         load_data_queue,
+        modification_tasks,
+        generation_versions,
+        generating_versions,
+        modified_chunks,
+        structure_modifications,
+        trimmed_chunks,
         ..
     } = voxel_engine.as_mut();
 
-    unload_data_queue.extend(chunk_lost_data_relevance.read().map(|e| e.chunk));
+    // chunks no scanner wants anymore aren't loaded again.
+    unload_data_queue.extend(chunk_lost_data_relevance.read().map(|e| e.chunk).inspect(|chunk| {
+        trimmed_chunks.remove(chunk);
+    }));
 
     events.send_batch(unload_data_queue.iter().copied().map(ChunkUnloaded));
 
     for chunk_pos in unload_data_queue.drain(..) {
         load_data_queue.swap_remove(&chunk_pos);
         world_data.remove(&chunk_pos);
+        modification_tasks.remove(&chunk_pos);
+        generation_versions.remove(&chunk_pos);
+        generating_versions.remove(&chunk_pos);
+        modified_chunks.remove(&chunk_pos);
+        structure_modifications.remove(&chunk_pos);
     }
 }
 
 
-// start
+/// Applies queued fills right away & starts tasks applying the queued [`ChunkModification`]s, see [`join_modifications`].
 pub fn start_modifications(
     mut voxel_engine: ResMut<VoxelEngine>,
     mut events: EventWriter<ChunkModified>,
-    mut updated_and_adjecant_chunks_set: Local<HashSet<IVec3>>,
+    mut voxel_events: EventWriter<ChunkVoxelsModified>,
+    mut blocks_changed_events: EventWriter<ChunkBlocksChanged>,
+    config: Res<VoxelEngineConfig>,
+    scanners: Query<(&Scanner<DataScanner>, &ChunkPos)>,
 ) {
     let VoxelEngine {
         world_data,
         chunk_modifications,
+        deferred_modifications,
+        deferred_fills,
+        chunk_fills,
+        block_replacements,
+        modification_tasks,
+        modified_chunks: player_modified_chunks,
+        structure_modifications,
         ..
     } = voxel_engine.as_mut();
-    for (chunk_pos, mods) in chunk_modifications.drain() {
-        // say i want to load mesh now :)
+
+    // Chunks that changed as a whole because they or a neighbor were filled.
+    let mut modified_chunks = HashSet::new();
+    for (chunk_pos, block) in chunk_fills.drain() {
         let Some(chunk_data) = world_data.get_mut(&chunk_pos) else {
+            // The fill overwrites the modifications deferred before it.
+            deferred_modifications.remove(&chunk_pos);
+            deferred_fills.insert(chunk_pos, block);
             continue;
         };
-        let new_chunk_data = Arc::make_mut(chunk_data);
-        for ChunkModification(local_pos, block_type) in mods.into_iter() {
-            let i = vec3_to_index(local_pos, 32);
-            if new_chunk_data.voxels.len() == 1 {
-                let value = new_chunk_data.voxels[0];
-                new_chunk_data.voxels.resize(CHUNK_SIZE3, value);
-            }
-            new_chunk_data.voxels[i].block_type = block_type;
+        let changed: Vec<_> = chunk_data.iter_blocks()
+            .filter(|(_, old)| *old != block.block_type)
+            .map(|(local_pos, old)| (local_pos, old, block.block_type))
+            .collect();
+        if !changed.is_empty() {
+            blocks_changed_events.send(ChunkBlocksChanged { pos: chunk_pos, changed });
+        }
+        *chunk_data = Arc::new(ChunkData::Filled(block));
+        player_modified_chunks.insert(chunk_pos);
+        // The fill overwrites whatever the task would've written, dropping it cancels it.
+        modification_tasks.remove(&chunk_pos);
+        modified_chunks.extend(ADJACENT_CHUNK_DIRECTIONS.iter().map(|offset| chunk_pos + *offset));
+    }
+
+    // Running tasks would swap back in chunks copied before the replacement,
+    // so no new ones are started until they're joined & the replacement is done.
+    let replacing = !block_replacements.is_empty();
+    if replacing && modification_tasks.is_empty() {
+        for (chunk_pos, chunk_data) in world_data.iter_mut() {
+            let mut changed = Vec::new();
+            for (from, to) in block_replacements.iter().copied() {
+                if from == to || !chunk_data.contains_block(from) {
+                    continue;
+                }
+                changed.extend(chunk_data.iter_blocks().filter(|(_, block)| *block == from).map(|(local_pos, _)| (local_pos, from, to)));
+                Arc::make_mut(chunk_data).replace_block(from, to);
+            }
+            if !changed.is_empty() {
+                blocks_changed_events.send(ChunkBlocksChanged { pos: *chunk_pos, changed });
+                modified_chunks.extend(ADJACENT_CHUNK_DIRECTIONS.iter().map(|offset| *chunk_pos + *offset));
+            }
+        }
+        block_replacements.clear();
+    }
+
+    chunk_modifications.retain(|chunk_pos, mods| {
+        // Wait for the previous batch to be joined, so batches are applied in order.
+        if replacing || modification_tasks.contains_key(chunk_pos) {
+            return true;
+        }
+        let from_structures = structure_modifications.remove(chunk_pos).unwrap_or(0);
+        let Some(chunk_data) = world_data.get(chunk_pos) else {
+            deferred_modifications.entry(*chunk_pos).or_default().append(mods);
+            return false;
+        };
+        if mods.len() > from_structures {
+            player_modified_chunks.insert(*chunk_pos);
+        }
+
+        let base = chunk_data.clone();
+        let mut chunk_data = chunk_data.clone();
+        let chunk_pos = *chunk_pos;
+        let mods = std::mem::take(mods);
+        let task = ChunkTask::spawn(config.threading, async move {
+            // Copies the chunk, the original is still shared with the world.
+            let new_chunk_data = Arc::make_mut(&mut chunk_data);
+            let mut voxels = Vec::with_capacity(mods.len());
+            let mut changed = Vec::new();
+            for &ChunkModification(local_pos, block) in &mods {
+                if local_pos.cmplt(IVec3::ZERO).any() || local_pos.cmpge(IVec3::splat(CHUNK_SIZE as i32)).any() {
+                    warn!("Ignoring modification of {local_pos} in chunk {chunk_pos}, position isn't local to the chunk.");
+                    continue;
+                }
+                let old = new_chunk_data.get_block(vec3_to_index(local_pos, CHUNK_SIZE_I32)).block_type;
+                if old != block.block_type {
+                    changed.push((local_pos, old, block.block_type));
+                }
+                new_chunk_data.set_block(local_pos, block);
+                voxels.push(local_pos);
+            }
+            // Cleared chunks go straight back to a single voxel.
+            new_chunk_data.compact();
+
+            ModifiedChunk { data: chunk_data, base, mods, from_structures, voxels, changed }
+        });
+        modification_tasks.insert(chunk_pos, task);
+        false
+    });
+
+    let deferred_chunks = deferred_modifications.len() + deferred_fills.keys().filter(|chunk_pos| !deferred_modifications.contains_key(*chunk_pos)).count();
+    if deferred_chunks > config.max_deferred_chunks {
+        let mut deferred: Vec<IVec3> = deferred_modifications.keys()
+            .chain(deferred_fills.keys().filter(|chunk_pos| !deferred_modifications.contains_key(*chunk_pos)))
+            .copied()
+            .collect();
+        deferred.sort_by_cached_key(|chunk_pos| (data_priority(*chunk_pos, scanners.iter()), chunk_pos.to_array()));
+        for chunk_pos in &deferred[config.max_deferred_chunks..] {
+            deferred_modifications.remove(chunk_pos);
+            deferred_fills.remove(chunk_pos);
+        }
+        warn!("Dropped the deferred modifications of {} unloaded chunks, past the max of {}.", deferred.len() - config.max_deferred_chunks, config.max_deferred_chunks);
+    }
+
+    events.send_batch(modified_chunks.iter().copied().map(ChunkModified));
+    voxel_events.send_batch(modified_chunks.into_iter().map(|chunk| ChunkVoxelsModified { chunk, voxels: Vec::new() }));
+}
+
+/// Chunk data with a batch of [`ChunkModification`]s applied, made on the task pool by [`start_modifications`].
+pub struct ModifiedChunk {
+    data: Arc<ChunkData>,
+    /// The chunk the modifications were applied to, if the world's has been replaced since they're applied again.
+    base: Arc<ChunkData>,
+    mods: Vec<ChunkModification>,
+    /// Structure blocks among `mods`, see [`VoxelEngine::is_modified`].
+    from_structures: usize,
+    /// Chunk local positions that were modified.
+    voxels: Vec<IVec3>,
+    /// Modifications that changed the block type, for [`ChunkBlocksChanged`].
+    changed: Vec<(IVec3, BlockId, BlockId)>,
+}
+
+/// Swaps in the chunks modified by finished modification tasks
+/// & sends [`ChunkModified`] for them & the neighbors sharing the modified voxels.
+/// [`ChunkBlocksChanged`] is only sent for the modified chunks themselves.
+pub fn join_modifications(
+    mut voxel_engine: ResMut<VoxelEngine>,
+    mut events: EventWriter<ChunkModified>,
+    mut voxel_events: EventWriter<ChunkVoxelsModified>,
+    mut blocks_changed_events: EventWriter<ChunkBlocksChanged>,
+    // Updated & adjecant chunks -> modified positions local to them.
+    mut modified_voxels: Local<HashMap<IVec3, Vec<IVec3>>>,
+) {
+    let VoxelEngine {
+        world_data,
+        modification_tasks,
+        chunk_modifications,
+        structure_modifications,
+        ..
+    } = voxel_engine.as_mut();
+
+    modification_tasks.retain(|chunk_pos, task| {
+        let Some(ModifiedChunk { data, base, mods, from_structures, voxels, changed }) = task.poll() else {
+            return true;
+        };
+        let chunk_pos = *chunk_pos;
+        // Unloading drops the task, but be safe.
+        let Some(chunk_data) = world_data.get_mut(&chunk_pos) else {
+            return false;
+        };
+        // The chunk was replaced while the task ran, e.g. regenerated, so the result is stale.
+        // The batch goes back to the front of the queue to be applied to the new data.
+        if !Arc::ptr_eq(chunk_data, &base) {
+            chunk_modifications.entry(chunk_pos).or_default().splice(0..0, mods);
+            *structure_modifications.entry(chunk_pos).or_default() += from_structures;
+            return false;
+        }
+        *chunk_data = data;
+        if !changed.is_empty() {
+            blocks_changed_events.send(ChunkBlocksChanged { pos: chunk_pos, changed });
+        }
+
+        for local_pos in voxels {
+            let mut add_modified = |offset: IVec3| {
+                modified_voxels.entry(chunk_pos + offset).or_default().push(local_pos - offset * CHUNK_SIZE as i32);
+            };
+            add_modified(IVec3::ZERO);
             if let Some(edge_chunk) = get_edging_chunk(local_pos) {
-                updated_and_adjecant_chunks_set.insert(chunk_pos + edge_chunk);
+                add_modified(edge_chunk);
             }
 
             // Add pos chunks to the modified list.
             if local_pos.x == 0 {
-                updated_and_adjecant_chunks_set.insert(chunk_pos - IVec3::new(1, 0, 0));
+                add_modified(IVec3::new(-1, 0, 0));
             } else if local_pos.x == CHUNK_SIZE as i32 - 1 {
-                updated_and_adjecant_chunks_set.insert(chunk_pos + IVec3::new(1, 0, 0));
+                add_modified(IVec3::new(1, 0, 0));
             }
 
             if local_pos.y == 0 {
-                updated_and_adjecant_chunks_set.insert(chunk_pos - IVec3::new(0, 1, 0));
+                add_modified(IVec3::new(0, -1, 0));
             } else if local_pos.y == CHUNK_SIZE as i32 - 1 {
-                updated_and_adjecant_chunks_set.insert(chunk_pos + IVec3::new(0, 1, 0));
+                add_modified(IVec3::new(0, 1, 0));
             }
         
             if local_pos.z == 0 {
-                updated_and_adjecant_chunks_set.insert(chunk_pos - IVec3::new(0, 0, 1));
+                add_modified(IVec3::new(0, 0, -1));
             } else if local_pos.z == CHUNK_SIZE as i32 - 1 {
-                updated_and_adjecant_chunks_set.insert(chunk_pos + IVec3::new(0, 0, 1));
+                add_modified(IVec3::new(0, 0, 1));
             }
         }
-        updated_and_adjecant_chunks_set.insert(chunk_pos);
+        modified_voxels.entry(chunk_pos).or_default();
+        false
+    });
+
+    events.send_batch(modified_voxels.keys().copied().map(ChunkModified));
+    voxel_events.send_batch(modified_voxels.drain().map(|(chunk, voxels)| ChunkVoxelsModified { chunk, voxels }));
+}
+
+/// Recomputes the [`FaceSolidity`] of generated & modified chunks, forgetting that of unloaded ones.
+pub fn update_face_solidity(
+    mut voxel_engine: ResMut<VoxelEngine>,
+    block_registry: Res<BlockRegistryResource>,
+    mut chunk_generated: EventReader<ChunkGenerated>,
+    mut chunk_modified: EventReader<ChunkModified>,
+    mut chunk_unloaded: EventReader<ChunkUnloaded>,
+) {
+    if chunk_unloaded.is_empty() && chunk_generated.is_empty() && chunk_modified.is_empty() {
+        return;
+    }
+    let VoxelEngine {
+        world_data,
+        face_solidity,
+        face_solidity_version,
+        ..
+    } = voxel_engine.as_mut();
+
+    let mut changed = false;
+    for ChunkUnloaded(chunk_pos) in chunk_unloaded.read() {
+        changed |= face_solidity.remove(chunk_pos).is_some();
     }
 
-    events.send_batch(updated_and_adjecant_chunks_set.iter().cloned().map(ChunkModified));
+    let updated = chunk_generated.read().map(|e| e.0).chain(chunk_modified.read().map(|e| e.0));
+    for chunk_pos in updated {
+        // Modified events include neighbors which may not be loaded.
+        if let Some(chunk_data) = world_data.get(&chunk_pos) {
+            let solidity = FaceSolidity::of_chunk(chunk_data, &block_registry.0);
+            changed |= face_solidity.insert(chunk_pos, solidity) != Some(solidity);
+        }
+    }
+    if changed {
+        *face_solidity_version = face_solidity_version.wrapping_add(1);
+    }
 }
 
-/// join the chunkdata threads
-pub fn join_data(
+/// Finalizes the chunks around generated chunks & modified chunks once nothing can place structure blocks in them anymore,
+/// forgetting unloaded ones, see [`VoxelEngine::is_finalized`].
+pub fn update_finalized_chunks(
     mut voxel_engine: ResMut<VoxelEngine>,
-    mut events: EventWriter<ChunkGenerated>
+    config: Res<VoxelEngineConfig>,
+    mut chunk_generated: EventReader<ChunkGenerated>,
+    mut chunk_modified: EventReader<ChunkModified>,
+    mut chunk_unloaded: EventReader<ChunkUnloaded>,
 ) {
     let VoxelEngine {
         world_data,
-        data_tasks,
+        chunk_modifications,
+        modification_tasks,
+        chunk_fills,
+        finalized_chunks,
         ..
     } = voxel_engine.as_mut();
+
+    for ChunkUnloaded(chunk_pos) in chunk_unloaded.read() {
+        finalized_chunks.remove(chunk_pos);
+    }
+
+    let reach = config.structure_reach as i32;
+    // nothing is generated outside the world bounds.
+    let exists = |chunk: IVec3| world_data.contains_key(&chunk) || config.world_bounds.is_some_and(|bounds| !bounds.contains(chunk));
+    // a generated chunk may be the last one within reach of those around it,
+    // a modified one may have just had the structure blocks of a neighbor applied.
+    let candidates: HashSet<IVec3> = chunk_generated.read().flat_map(|e| chunks_in_cube(e.0, reach))
+        .chain(chunk_modified.read().map(|e| e.0))
+        .collect();
+    for chunk_pos in candidates {
+        if finalized_chunks.contains(&chunk_pos) || !world_data.contains_key(&chunk_pos) {
+            continue;
+        }
+        let applied = !chunk_modifications.contains_key(&chunk_pos) && !modification_tasks.contains_key(&chunk_pos) && !chunk_fills.contains_key(&chunk_pos);
+        if applied && chunks_in_cube(chunk_pos, reach).all(exists) {
+            finalized_chunks.insert(chunk_pos);
+        }
+    }
+}
+
+/// Checks a few loaded chunks per frame & compresses the ones that were expanded to be edited.
+/// Cycles through every loaded chunk before starting over.
+pub fn compact_chunks(
+    mut voxel_engine: ResMut<VoxelEngine>,
+    config: Res<ChunkCompactionConfig>,
+    mut queue: Local<Vec<IVec3>>,
+) {
+    // Compacting doesn't change what the chunks contain.
+    let world_data = &mut voxel_engine.bypass_change_detection().world_data;
+    if queue.is_empty() {
+        queue.extend(world_data.keys().copied());
+    }
+
+    for _ in 0..config.chunks_per_frame {
+        let Some(chunk_pos) = queue.pop() else {
+            break;
+        };
+        let Some(chunk_data) = world_data.get_mut(&chunk_pos) else {
+            continue;
+        };
+        // Chunks shared with a running task are skipped, compacting them would mean copying them.
+        if let Some(chunk_data) = Arc::get_mut(chunk_data) {
+            chunk_data.compress();
+        }
+    }
+}
+
+/// join the chunkdata threads
+/// Inserts finished chunks & sends their [`ChunkGenerated`] events.
+/// Chunks finishing the same frame are joined in [`data_priority`] order, ties broken by position,
+/// so events & overlapping structures don't depend on task completion or hash map order.
+pub fn join_data(
+    mut voxel_engine: ResMut<VoxelEngine>,
+    mut events: EventWriter<ChunkGenerated>,
+    scanners: Query<(&Scanner<DataScanner>, &ChunkPos)>,
+    config: Res<VoxelEngineConfig>,
+    mut finished: Local<Vec<(IVec3, GeneratedChunk)>>,
+) {
+    let data_tasks = &mut voxel_engine.data_tasks;
     for (world_pos, task_option) in data_tasks.iter_mut() {
         let Some(mut task) = task_option.take() else {
             // should never happend, because we drop None values later
             warn!("someone modified task?");
             continue;
         };
-        let Some(chunk_data) = block_on(poll_once(&mut task)) else {
+        let Some(generated) = task.poll() else {
             *task_option = Some(task);
             continue;
         };
 
-        world_data.insert(*world_pos, Arc::new(chunk_data));
-        events.send(ChunkGenerated(*world_pos));
+        finished.push((*world_pos, generated));
     }
     data_tasks.retain(|_k, op| op.is_some());
+
+    finished.sort_by_cached_key(|(world_pos, _)| (data_priority(*world_pos, scanners.iter()), world_pos.to_array()));
+    for (world_pos, generated) in finished.drain(..) {
+        // modified while it was regenerating, the modifications win & it keeps the version it had.
+        if voxel_engine.is_modified(world_pos) && voxel_engine.world_data.contains_key(&world_pos) {
+            voxel_engine.generating_versions.remove(&world_pos);
+            continue;
+        }
+        voxel_engine.insert_generated_chunk(world_pos, generated);
+        events.send(ChunkGenerated(world_pos));
+        // the version was bumped while it generated, `regenerate_outdated_chunks` only runs when it changes.
+        if voxel_engine.generation_version(world_pos).is_some_and(|version| version != config.generation_version) {
+            voxel_engine.load_data_queue.insert(world_pos);
+        }
+    }
 }
 
+impl VoxelEngine {
+    /// Inserts a freshly generated chunk into the world.
+    ///
+    /// Structure blocks from neighbors that were waiting on this chunk are applied on top of the generated data,
+    /// so the result is the same whether the neighbor's structure was generated before or after this chunk.
+    /// Structure blocks for loaded neighbors are queued as [`ChunkModification`]s, those for ungenerated ones are stashed.
+    ///
+    /// Overlapping structures from different chunks are applied in generation order.
+    /// [`VoxelEngine::deferred_fills`] & [`VoxelEngine::deferred_modifications`] of the chunk are applied last.
+    ///
+    /// The chunk gets the [`VoxelEngine::generation_version`] its data task was started with, none if it wasn't generated by one.
+    pub fn insert_generated_chunk(&mut self, chunk_pos: IVec3, generated: GeneratedChunk) {
+        let GeneratedChunk { data: mut chunk_data, structures } = generated;
+        match self.generating_versions.remove(&chunk_pos) {
+            Some(version) => self.generation_versions.insert(chunk_pos, version),
+            None => self.generation_versions.remove(&chunk_pos),
+        };
+
+        if let Some(blocks) = self.pending_structure_blocks.remove(&chunk_pos) {
+            for (local_pos, block_type) in blocks {
+                chunk_data.set_block(local_pos, block_type);
+            }
+        }
+
+        for PendingStructure { world_pos, blocks } in structures {
+            for (offset, block_type) in blocks {
+                let (target_chunk, local_pos) = world_to_chunk_local(world_pos + offset);
+
+                if target_chunk == chunk_pos {
+                    chunk_data.set_block(local_pos, block_type);
+                } else if self.world_data.contains_key(&target_chunk) {
+                    self.chunk_modifications.entry(target_chunk).or_default().push(ChunkModification(local_pos, block_type.into()));
+                    *self.structure_modifications.entry(target_chunk).or_default() += 1;
+                } else {
+                    self.pending_structure_blocks.entry(target_chunk).or_default().push((local_pos, block_type));
+                }
+            }
+        }
+
+        self.modified_chunks.remove(&chunk_pos);
+        if let Some(block) = self.deferred_fills.remove(&chunk_pos) {
+            chunk_data = ChunkData::Filled(block);
+            self.modified_chunks.insert(chunk_pos);
+        }
+        for ChunkModification(local_pos, block) in self.deferred_modifications.remove(&chunk_pos).unwrap_or_default() {
+            if local_pos.cmplt(IVec3::ZERO).any() || local_pos.cmpge(IVec3::splat(CHUNK_SIZE_I32)).any() {
+                warn!("Ignoring modification of {local_pos} in chunk {chunk_pos}, position isn't local to the chunk.");
+                continue;
+            }
+            chunk_data.set_block(local_pos, block);
+            self.modified_chunks.insert(chunk_pos);
+        }
+
+        self.world_data.insert(chunk_pos, Arc::new(chunk_data));
+    }
+
+    /// Sets every voxel between `min` & `max` (inclusive, world space) to `block`.
+    ///
+    /// Chunks entirely inside the region are replaced by a single compressed voxel,
+    /// the rest are queued as [`ChunkModification`]s. Chunks that aren't loaded are filled once they generate.
+    pub fn fill_region(&mut self, min: IVec3, max: IVec3, block: impl Into<BlockData>) {
+        let (min, max) = (min.min(max), min.max(max));
+        self.fill_chunks(min, max, block.into(), |_| true);
+    }
+
+    /// Sets every voxel within `radius` of `center` (world space) to `block`.
+    /// See [`VoxelEngine::fill_region`].
+    pub fn fill_sphere(&mut self, center: IVec3, radius: i32, block: impl Into<BlockData>) {
+        let radius = radius.abs();
+        let radius_squared = radius * radius;
+        self.fill_chunks(center - radius, center + radius, block.into(), |voxel| voxel.distance_squared(center) <= radius_squared);
+    }
+
+    /// Fills the voxels in the `min`..=`max` bounds which `contains` accepts.
+    /// `contains` must describe a convex shape, so a chunk with all its corners inside is entirely inside.
+    fn fill_chunks(&mut self, min: IVec3, max: IVec3, block: BlockData, contains: impl Fn(IVec3) -> bool) {
+        for chunk_pos in chunks_in_aabb(min, max) {
+            let chunk_min = chunk_pos * CHUNK_SIZE_I32;
+            let chunk_max = chunk_min + IVec3::splat(CHUNK_SIZE_I32 - 1);
+            let fully_inside = min.cmple(chunk_min).all() && max.cmpge(chunk_max).all() && (0..8).all(|corner| {
+                contains(IVec3::select(
+                    BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                    chunk_max,
+                    chunk_min,
+                ))
+            });
+            if fully_inside {
+                // Earlier modifications would be overwritten anyway.
+                self.chunk_modifications.remove(&chunk_pos);
+                self.chunk_fills.insert(chunk_pos, block);
+                continue;
+            }
+
+            let from = min.max(chunk_min);
+            let to = max.min(chunk_max);
+            let modifications = self.chunk_modifications.entry(chunk_pos).or_default();
+            for z in from.z..=to.z {
+                for y in from.y..=to.y {
+                    for x in from.x..=to.x {
+                        let voxel = IVec3::new(x, y, z);
+                        if contains(voxel) {
+                            modifications.push(ChunkModification(voxel - chunk_min, block));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces every `from` block in the loaded chunks with `to`, keeping their states.
+    ///
+    /// Applied by [`start_modifications`] once the running modification tasks are joined,
+    /// which sends [`ChunkModified`] for every chunk that contained `from` & its neighbors.
+    /// Modifications queued before this are applied afterwards & may place `from` again.
+    pub fn replace_block_global(&mut self, from: BlockId, to: BlockId) {
+        self.block_replacements.push((from, to));
+    }
+
+    /// Returns the block at a world space voxel position, or `None` if its chunk isn't loaded.
+    /// Queued modifications aren't visible until they've been joined, see [`VoxelEngine::chunk_modifications`].
+    pub fn get_block(&self, voxel: IVec3) -> Option<BlockData> {
+        let (chunk_pos, local_pos) = world_to_chunk_local(voxel);
+        let chunk_data = self.world_data.get(&chunk_pos)?;
+        let i = vec3_to_index(local_pos, 32);
+        Some(chunk_data.get_block(i))
+    }
+
+    /// Sets the block at a world space voxel position, queued as a [`ChunkModification`] of its chunk.
+    /// Chunks that aren't loaded get it once they generate, see [`VoxelEngine::deferred_modifications`].
+    pub fn set_block(&mut self, voxel: IVec3, block: impl Into<BlockData>) {
+        let (chunk_pos, local_pos) = world_to_chunk_local(voxel);
+        self.chunk_modifications.entry(chunk_pos).or_default().push(ChunkModification(local_pos, block.into()));
+    }
+
+    /// Number of chunks with generated data.
+    pub fn loaded_chunk_count(&self) -> usize {
+        self.world_data.len()
+    }
+
+    /// Returns true if the chunk's data has been generated.
+    pub fn is_data_loaded(&self, chunk_pos: IVec3) -> bool {
+        self.world_data.contains_key(&chunk_pos)
+    }
+
+    /// Every loaded chunk & its data, in no particular order.
+    pub fn iter_loaded(&self) -> impl Iterator<Item = (IVec3, &ChunkData)> {
+        self.world_data.iter().map(|(chunk_pos, data)| (*chunk_pos, data.as_ref()))
+    }
+
+    /// Number of voxels of each block type across every loaded chunk, see [`ChunkData::block_histogram`].
+    /// Queued modifications aren't counted until they've been joined.
+    pub fn global_histogram(&self) -> HashMap<BlockId, u64> {
+        let mut histogram = HashMap::new();
+        for chunk_data in self.world_data.values() {
+            for (block, count) in chunk_data.block_histogram() {
+                *histogram.entry(block).or_default() += count as u64;
+            }
+        }
+        histogram
+    }
+
+    /// The data of a loaded chunk, or `None` if it isn't loaded.
+    /// The data is shared, it's a snapshot that won't see later modifications.
+    pub fn chunk_data(&self, chunk_pos: IVec3) -> Option<Arc<ChunkData>> {
+        self.world_data.get(&chunk_pos).cloned()
+    }
+
+    /// Loads the chunks within `radius` of `center` ahead of time & meshes them, before any chunk the scanners queue.
+    /// For teleports & level loads, where the chunks should be ready before the camera gets there.
+    ///
+    /// [`PrewarmCompleted`] is sent once the region is meshed. Its chunks stay loaded until it's meshed & a [`Scanner<MeshScanner>`] covers `center`,
+    /// or [`VoxelEngineConfig::prewarm_timeout`] has passed since it was made, then they unload like any other chunk out of range of the scanners.
+    pub fn prewarm(&mut self, center: IVec3, radius: i32) -> Prewarm {
+        let prewarm = Prewarm { center, radius };
+        self.prewarms.push((prewarm, None, false));
+        prewarm
+    }
+
+    /// Bumped whenever [`VoxelEngine::face_solidity`] changes as chunks generate, are modified or unload,
+    /// so anything derived from it only has to be recomputed when this differs.
+    pub fn face_solidity_version(&self) -> u32 {
+        self.face_solidity_version
+    }
+
+    /// Whether every chunk within [`VoxelEngineConfig::structure_reach`] of the chunk has generated
+    /// & the structure blocks they placed in it have been applied, so its blocks only change from modifications from now on.
+    /// Stays set until the chunk unloads.
+    pub fn is_finalized(&self, chunk_pos: IVec3) -> bool {
+        self.finalized_chunks.contains(&chunk_pos)
+    }
+
+    /// [`VoxelEngineConfig::generation_version`] the chunk's data was generated with,
+    /// `None` if it isn't loaded or wasn't generated by the engine.
+    pub fn generation_version(&self, chunk_pos: IVec3) -> Option<u32> {
+        self.world_data.contains_key(&chunk_pos).then(|| self.generation_versions.get(&chunk_pos).copied()).flatten()
+    }
+
+    /// Whether the chunk was modified or filled since it generated, these are kept when the generator version changes.
+    /// Structure blocks placed by neighbors & [`VoxelEngine::replace_block_global`] don't count.
+    pub fn is_modified(&self, chunk_pos: IVec3) -> bool {
+        self.modified_chunks.contains(&chunk_pos)
+    }
+
+    /// Whether the chunk is being meshed for a [`VoxelEngine::prewarm`] region.
+    pub fn is_prewarming(&self, chunk_pos: IVec3) -> bool {
+        self.prewarm_mesh_chunks.contains(&chunk_pos)
+    }
+
+    /// Whether generation has been at its task limit for at least [`VoxelEngineConfig::saturation_frames`] frames in a row,
+    /// meaning it can't keep up with the scanners. Games can slow down the player or show a loading indicator until it clears.
+    pub fn is_saturated(&self) -> bool {
+        self.saturated
+    }
+
+    /// Frames in a row generation has been at its task limit with chunks left in the queue.
+    pub fn saturated_frames(&self) -> u32 {
+        self.saturated_frames
+    }
+}
+
+/// Result of a successful [`raycast`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    /// World space voxel position of the block that was hit.
+    pub position: IVec3,
+    /// World space voxel position in front of the hit face, where a block would be placed.
+    pub adjacent: IVec3,
+    pub block: BlockId,
+    /// The face of the hit block the ray entered through.
+    pub face: FaceDir,
+    /// Distance along the ray to the hit face.
+    pub distance: f32,
+}
+
+/// Amanatides-Woo voxel traversal.
+/// Yields every voxel the ray passes through, starting with the one containing the origin.
+struct VoxelTraversal {
+    voxel: IVec3,
+    step: IVec3,
+    t_max: Vec3,
+    t_delta: Vec3,
+    max_dist: f32,
+    distance: f32,
+    entered: Option<FaceDir>,
+    done: bool,
+}
+
+impl VoxelTraversal {
+    fn new(origin: Vec3, dir: Vec3, max_dist: f32) -> Self {
+        let dir = dir.normalize_or_zero();
+        let voxel = origin.floor().as_ivec3();
+
+        let mut step = IVec3::ZERO;
+        let mut t_max = Vec3::INFINITY;
+        let mut t_delta = Vec3::INFINITY;
+        for axis in 0..3 {
+            if dir[axis] > 0.0 {
+                step[axis] = 1;
+                t_max[axis] = (voxel[axis] as f32 + 1.0 - origin[axis]) / dir[axis];
+                t_delta[axis] = 1.0 / dir[axis];
+            } else if dir[axis] < 0.0 {
+                step[axis] = -1;
+                t_max[axis] = (origin[axis] - voxel[axis] as f32) / -dir[axis];
+                t_delta[axis] = 1.0 / -dir[axis];
+            }
+        }
+
+        Self {
+            voxel,
+            step,
+            t_max,
+            t_delta,
+            max_dist,
+            distance: 0.0,
+            entered: None,
+            // A zero direction never leaves the origin voxel.
+            done: dir == Vec3::ZERO,
+        }
+    }
+}
+
+impl Iterator for VoxelTraversal {
+    /// (voxel, face entered through, distance to entry)
+    type Item = (IVec3, Option<FaceDir>, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let current = (self.voxel, self.entered, self.distance);
+
+        let axis = if self.t_max.x < self.t_max.y {
+            if self.t_max.x < self.t_max.z { 0 } else { 2 }
+        } else if self.t_max.y < self.t_max.z {
+            1
+        } else {
+            2
+        };
+
+        if self.t_max[axis] > self.max_dist {
+            self.done = true;
+        } else {
+            self.voxel[axis] += self.step[axis];
+            self.distance = self.t_max[axis];
+            self.t_max[axis] += self.t_delta[axis];
+            // Stepping in the positive direction enters through the negative face & vice versa.
+            self.entered = Some(match (axis, self.step[axis] > 0) {
+                (0, true) => FaceDir::Left,
+                (0, false) => FaceDir::Right,
+                (1, true) => FaceDir::Down,
+                (1, false) => FaceDir::Up,
+                (_, true) => FaceDir::Forward,
+                (_, false) => FaceDir::Back,
+            });
+        }
+
+        Some(current)
+    }
+}
+
+/// Casts a ray into the world & returns the first block which has any of `flags` set.
+///
+/// The voxel containing `origin` is never reported as a hit.
+/// Returns `None` if nothing was hit within `max_dist` or the ray entered an unloaded chunk first.
+pub fn raycast_with_flags(
+    engine: &VoxelEngine,
+    registry: &BlockRegistry,
+    origin: Vec3,
+    dir: Vec3,
+    max_dist: f32,
+    flags: BlockFlags,
+) -> Option<RaycastHit> {
+    for (voxel, entered, distance) in VoxelTraversal::new(origin, dir, max_dist) {
+        let block = engine.get_block(voxel)?;
+        let Some(face) = entered else {
+            continue;
+        };
+
+        if registry.block_flags[block.block_type.0 as usize].intersects(flags) {
+            return Some(RaycastHit {
+                position: voxel,
+                adjacent: voxel + face.air_sample_dir(),
+                block: block.block_type,
+                face,
+                distance,
+            });
+        }
+    }
+
+    None
+}
+
+/// Casts a ray into the world & returns the first [`BlockFlags::SOLID`] block.
+/// See [`raycast_with_flags`].
+pub fn raycast(
+    engine: &VoxelEngine,
+    registry: &BlockRegistry,
+    origin: Vec3,
+    dir: Vec3,
+    max_dist: f32,
+) -> Option<RaycastHit> {
+    raycast_with_flags(engine, registry, origin, dir, max_dist, BlockFlags::SOLID)
+}
+
+/// A voxel a ray passed through, see [`VoxelEngine::raycast_voxels`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoxelStep {
+    /// World space voxel position.
+    pub voxel: IVec3,
+    /// The face of the voxel the ray entered through.
+    pub entered: FaceDir,
+    pub block: BlockId,
+    /// Distance along the ray to the entered face.
+    pub distance: f32,
+}
+
+impl VoxelEngine {
+    /// Every voxel a ray passes through within `max_dist`, whatever its block, for tools editing along a ray.
+    /// Uses the same traversal as [`raycast`].
+    ///
+    /// Like [`raycast`] the voxel containing `origin` is skipped. Stops at the first voxel in an unloaded chunk.
+    pub fn raycast_voxels(&self, origin: Vec3, dir: Vec3, max_dist: f32) -> impl Iterator<Item = VoxelStep> + '_ {
+        VoxelTraversal::new(origin, dir, max_dist)
+            .map_while(|(voxel, entered, distance)| Some((voxel, entered, distance, self.get_block(voxel)?)))
+            .filter_map(|(voxel, entered, distance, block)| Some(VoxelStep { voxel, entered: entered?, block: block.block_type, distance }))
+    }
+}
+
+/// Read access to the voxel world for gameplay systems, in world space voxel positions.
+/// See [`VoxelWorldMut`] for setting blocks.
+///
+/// Systems using this are skipped until the [`BlockRegistryResource`] exists.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use new_voxel_testing::voxel_engine::VoxelWorld;
+/// #[derive(Component)]
+/// struct Falling;
+///
+/// fn gravity(world: VoxelWorld, time: Res<Time>, mut falling: Query<&mut Transform, With<Falling>>) {
+///     for mut transform in &mut falling {
+///         let below = (transform.translation - Vec3::Y).floor().as_ivec3();
+///         // Don't fall through chunks that haven't loaded yet.
+///         if world.get_block(below).is_some() && !world.is_solid(below) {
+///             transform.translation.y -= 9.81 * time.delta_secs();
+///         }
+///     }
+/// }
+/// # App::new().add_systems(Update, gravity);
+/// ```
+#[derive(bevy::ecs::system::SystemParam)]
+pub struct VoxelWorld<'w> {
+    pub engine: Res<'w, VoxelEngine>,
+    pub registry: Res<'w, BlockRegistryResource>,
+}
+
+impl VoxelWorld<'_> {
+    /// Returns the block at a voxel position, or `None` if its chunk isn't loaded.
+    pub fn get_block(&self, voxel: IVec3) -> Option<BlockData> {
+        self.engine.get_block(voxel)
+    }
+
+    /// Returns true if the block at a voxel position is [`BlockFlags::SOLID`], false if its chunk isn't loaded.
+    pub fn is_solid(&self, voxel: IVec3) -> bool {
+        self.get_block(voxel).is_some_and(|block| self.registry.0.is_solid(block.block_type))
+    }
+
+    /// See [`raycast`].
+    pub fn raycast(&self, origin: Vec3, dir: Vec3, max_dist: f32) -> Option<RaycastHit> {
+        raycast(&self.engine, &self.registry.0, origin, dir, max_dist)
+    }
+
+    /// See [`raycast_with_flags`].
+    pub fn raycast_with_flags(&self, origin: Vec3, dir: Vec3, max_dist: f32, flags: BlockFlags) -> Option<RaycastHit> {
+        raycast_with_flags(&self.engine, &self.registry.0, origin, dir, max_dist, flags)
+    }
+}
+
+/// [`VoxelWorld`] for systems that also set blocks, which can't run in parallel with other systems reading the [`VoxelEngine`].
+#[derive(bevy::ecs::system::SystemParam)]
+pub struct VoxelWorldMut<'w> {
+    pub engine: ResMut<'w, VoxelEngine>,
+    pub registry: Res<'w, BlockRegistryResource>,
+}
+
+impl VoxelWorldMut<'_> {
+    /// See [`VoxelWorld::get_block`].
+    pub fn get_block(&self, voxel: IVec3) -> Option<BlockData> {
+        self.engine.get_block(voxel)
+    }
+
+    /// See [`VoxelWorld::is_solid`].
+    pub fn is_solid(&self, voxel: IVec3) -> bool {
+        self.get_block(voxel).is_some_and(|block| self.registry.0.is_solid(block.block_type))
+    }
+
+    /// See [`VoxelEngine::set_block`].
+    pub fn set_block(&mut self, voxel: IVec3, block: impl Into<BlockData>) {
+        self.engine.set_block(voxel, block);
+    }
+}
+
+#[cfg(test)]
+fn raycast_test_world() -> (VoxelEngine, Arc<BlockRegistry>) {
+    use crate::voxel::{Block, BlockRegistryBuilder, BlockStringIdentifier, BlockVisibilty};
+
+    let mut registry = BlockRegistryBuilder::new();
+    registry.add_block(BlockStringIdentifier(Box::from("air")), &Block { visibility: BlockVisibilty::Invisible, collision: false, ..default() }).unwrap();
+    let stone = registry.add_block(BlockStringIdentifier(Box::from("stone")), &Block::default()).unwrap();
+    let registry = registry.build();
+
+    let mut engine = VoxelEngine::default();
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                engine.world_data.insert(IVec3::new(x, y, z), Arc::new(ChunkData::Filled(BlockData::default())));
+            }
+        }
+    }
+
+    // Single stone block at world voxel (5, 5, 5).
+    let mut voxels = vec![BlockData::default(); crate::constants::CHUNK_SIZE3];
+    voxels[vec3_to_index(IVec3::splat(5), 32)].block_type = stone;
+    engine.world_data.insert(IVec3::ZERO, Arc::new(ChunkData::Dense(voxels)));
+
+    (engine, registry)
+}
+
+/// World with the events & resources the engine's systems use, `config` & a generator filling chunks with the default block.
+/// Tests insert their own [`VoxelEngine`].
+#[cfg(test)]
+fn test_world(config: VoxelEngineConfig) -> World {
+    let mut world = World::new();
+    world.init_resource::<Events<ChunkModified>>();
+    world.init_resource::<Events<ChunkVoxelsModified>>();
+    world.init_resource::<Events<ChunkBlocksChanged>>();
+    world.init_resource::<Events<ChunkGainedScannerRelevance<DataScanner>>>();
+    world.init_resource::<Events<ChunkLostScannerRelevance<DataScanner>>>();
+    world.init_resource::<Events<ChunkGenerated>>();
+    world.init_resource::<Events<ChunkUnloaded>>();
+    world.init_resource::<Events<VoxelEngineSaturated>>();
+    world.init_resource::<Events<PrewarmCompleted>>();
+    world.init_resource::<ForceLoadedChunks<DataScanner>>();
+    world.init_resource::<ForceLoadedChunks<MeshScanner>>();
+    world.init_resource::<Time>();
+    world.insert_resource(config);
+    world.insert_resource(ChunkGenerator {
+        generate: Arc::new(|_| ChunkData::Filled(BlockData::default()).into()),
+    });
+    world
+}
+
+#[test]
+fn raycast_axis_aligned() {
+    let (engine, registry) = raycast_test_world();
+
+    let hit = raycast(&engine, &registry, Vec3::new(0.5, 5.5, 5.5), Vec3::X, 16.0).unwrap();
+    assert_eq!(hit.position, IVec3::splat(5));
+    assert_eq!(hit.adjacent, IVec3::new(4, 5, 5));
+    assert_eq!(hit.face, FaceDir::Left);
+    assert!((hit.distance - 4.5).abs() < 1e-5);
+
+    let hit = raycast(&engine, &registry, Vec3::new(5.5, 10.5, 5.5), Vec3::NEG_Y, 16.0).unwrap();
+    assert_eq!(hit.position, IVec3::splat(5));
+    assert_eq!(hit.adjacent, IVec3::new(5, 6, 5));
+    assert_eq!(hit.face, FaceDir::Up);
+
+    // Too short to reach the block.
+    assert!(raycast(&engine, &registry, Vec3::new(0.5, 5.5, 5.5), Vec3::X, 4.0).is_none());
+    // Misses the block entirely & runs into unloaded chunks.
+    assert!(raycast(&engine, &registry, Vec3::new(0.5, 6.5, 5.5), Vec3::X, 200.0).is_none());
+}
+
+#[test]
+fn raycast_diagonal() {
+    let (engine, registry) = raycast_test_world();
+
+    let hit = raycast(&engine, &registry, Vec3::new(0.5, 0.5, 0.5), Vec3::ONE, 16.0).unwrap();
+    assert_eq!(hit.position, IVec3::splat(5));
+    assert_eq!(hit.block, BlockId(1));
+    assert_eq!(hit.adjacent, hit.position + hit.face.air_sample_dir());
+
+    let hit = raycast(&engine, &registry, Vec3::new(9.5, 9.5, 5.5), Vec3::new(-1.0, -1.0, 0.0), 16.0).unwrap();
+    assert_eq!(hit.position, IVec3::splat(5));
+
+    // Negative coordinates are traversed through the neighbouring chunks.
+    let hit = raycast(&engine, &registry, Vec3::new(-3.5, -3.5, 5.5), Vec3::new(1.0, 1.0, 0.0), 16.0).unwrap();
+    assert_eq!(hit.position, IVec3::splat(5));
+}
+
+#[test]
+fn raycast_voxels_steps_through_blocks() {
+    let (engine, _) = raycast_test_world();
+
+    // Straight through the stone block, without stopping at it.
+    let steps: Vec<VoxelStep> = engine.raycast_voxels(Vec3::new(0.5, 5.5, 5.5), Vec3::X, 8.0).collect();
+    assert_eq!(steps.iter().map(|step| step.voxel.x).collect::<Vec<_>>(), (1..=8).collect::<Vec<_>>());
+    assert!(steps.iter().all(|step| step.entered == FaceDir::Left));
+    assert_eq!(steps[4].block, BlockId(1));
+    assert!((steps[4].distance - 4.5).abs() < 1e-5);
+
+    // Stops once it reaches the unloaded chunks past x = 63.
+    let last = engine.raycast_voxels(Vec3::new(0.5, 6.5, 5.5), Vec3::X, 200.0).last().unwrap();
+    assert_eq!(last.voxel.x, 63);
+}
+
+#[test]
+fn structures_are_order_independent() {
+    let generate = |chunk_pos: IVec3| -> GeneratedChunk {
+        let mut voxels = vec![BlockData::default(); crate::constants::CHUNK_SIZE3];
+        // Ground level at the bottom of every chunk.
+        for z in 0..CHUNK_SIZE as i32 {
+            for x in 0..CHUNK_SIZE as i32 {
+                voxels[vec3_to_index(IVec3::new(x, 0, z), 32)].block_type = BlockId(1);
+            }
+        }
+
+        // A "tree" at the +x edge of chunk zero, reaching into the neighbor at x = 1.
+        let structures = if chunk_pos == IVec3::ZERO {
+            vec![PendingStructure {
+                world_pos: IVec3::new(31, 1, 10),
+                blocks: (-2..=2).flat_map(|x| (0..3).map(move |y| (IVec3::new(x, y, 0), BlockId(2)))).collect(),
+            }]
+        } else {
+            Vec::new()
+        };
+
+        GeneratedChunk {
+            data: ChunkData::Dense(voxels),
+            structures,
+        }
+    };
+
+    let neighbor = IVec3::X;
+
+    let mut structure_first = VoxelEngine::default();
+    structure_first.insert_generated_chunk(IVec3::ZERO, generate(IVec3::ZERO));
+    structure_first.insert_generated_chunk(neighbor, generate(neighbor));
+
+    let mut neighbor_first = VoxelEngine::default();
+    neighbor_first.insert_generated_chunk(neighbor, generate(neighbor));
+    neighbor_first.insert_generated_chunk(IVec3::ZERO, generate(IVec3::ZERO));
+    // Loaded neighbors are modified through the usual modification path.
+    for (chunk_pos, mods) in neighbor_first.chunk_modifications.drain() {
+        let chunk_data = Arc::make_mut(neighbor_first.world_data.get_mut(&chunk_pos).unwrap());
+        for ChunkModification(local_pos, block_type) in mods {
+            chunk_data.set_block(local_pos, block_type);
+        }
+    }
+
+    assert!(structure_first.pending_structure_blocks.is_empty());
+    for engine in [&structure_first, &neighbor_first] {
+        assert_eq!(engine.get_block(IVec3::new(33, 2, 10)).unwrap().block_type, BlockId(2));
+        assert_eq!(engine.get_block(IVec3::new(29, 3, 10)).unwrap().block_type, BlockId(2));
+        assert_eq!(engine.get_block(IVec3::new(34, 1, 10)).unwrap().block_type, BlockId(0));
+    }
+    for chunk_pos in [IVec3::ZERO, neighbor] {
+        let a = structure_first.world_data[&chunk_pos].iter_blocks();
+        let b = neighbor_first.world_data[&chunk_pos].iter_blocks();
+        assert!(a.eq(b));
+    }
+}
+
+#[test]
+fn fill_region_collapses_covered_chunks() {
+    use bevy::{ecs::system::RunSystemOnce, tasks::TaskPool};
+
+    let (mut engine, _) = raycast_test_world();
+    let stone = BlockId(1);
+    // Spans 3 chunks on each axis, only chunk (0, 0, 0) is entirely covered.
+    engine.fill_region(IVec3::splat(-16), IVec3::splat(47), stone);
+    assert_eq!(engine.chunk_fills.len(), 1);
+
+    let mut world = test_world(default());
+    world.insert_resource(engine);
+    AsyncComputeTaskPool::get_or_init(TaskPool::new);
+    world.run_system_once(start_modifications).unwrap();
+
+    // The covered chunk is filled right away, the rest wait for their tasks.
+    let engine = world.resource::<VoxelEngine>();
+    assert_eq!(engine.world_data[&IVec3::ZERO].get_block_if_filled(), Some(&BlockData::new(stone)));
+    assert_eq!(engine.get_block(IVec3::splat(-16)).unwrap().block_type, BlockId(0));
+
+    // Every chunk touching the filled one has to be remeshed, as a whole.
+    let events = world.resource::<Events<ChunkVoxelsModified>>();
+    let mut cursor = events.get_cursor();
+    let modified: Vec<_> = cursor.read(events).collect();
+    assert_eq!(modified.len(), 27);
+    assert!(modified.iter().all(|event| event.voxels.is_empty()));
+    // Only the filled chunk changed so far, except for the stone that was already there.
+    let events = world.resource::<Events<ChunkBlocksChanged>>();
+    let mut cursor = events.get_cursor();
+    let changed: Vec<_> = cursor.read(events).collect();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].changed.len(), crate::constants::CHUNK_SIZE3 - 1);
+
+    while !world.resource::<VoxelEngine>().modification_tasks.is_empty() {
+        world.run_system_once(join_modifications).unwrap();
+    }
+
+    let engine = world.resource::<VoxelEngine>();
+    for chunk_pos in ADJACENT_CHUNK_DIRECTIONS.iter().skip(1) {
+        assert!(matches!(*engine.world_data[chunk_pos], ChunkData::Dense(_)));
+    }
+    assert_eq!(engine.get_block(IVec3::splat(-16)).unwrap().block_type, stone);
+    assert_eq!(engine.get_block(IVec3::splat(-17)).unwrap().block_type, BlockId(0));
+    assert_eq!(engine.get_block(IVec3::new(47, 0, 0)).unwrap().block_type, stone);
+    assert_eq!(engine.get_block(IVec3::new(48, 0, 0)).unwrap().block_type, BlockId(0));
+}
+
+#[test]
+fn generator_panics_become_error_chunks() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let error_block = BlockId(7);
+    let mut world = test_world(VoxelEngineConfig { generator_error_block: error_block, threading: Threading::InlineImmediate, ..default() });
+    world.insert_resource(ChunkGenerator {
+        generate: Arc::new(|chunk_pos| {
+            if chunk_pos.x == 1 {
+                panic!("bad generator");
+            }
+            ChunkData::Filled(BlockData::default()).into()
+        }),
+    });
+    let mut engine = VoxelEngine::default();
+    engine.load_data_queue.extend([IVec3::ZERO, IVec3::X, IVec3::NEG_X]);
+    world.insert_resource(engine);
+
+    // the generator runs inline, so the panic is caught while starting the tasks.
+    world.run_system_once(start_data_tasks).unwrap();
+    world.run_system_once(join_data).unwrap();
+
+    let engine = world.resource::<VoxelEngine>();
+    assert!(engine.data_tasks.is_empty());
+    assert_eq!(engine.world_data.len(), 3);
+    let fill = |chunk_pos| engine.world_data[&chunk_pos].get_block_if_filled().map(|block| block.block_type);
+    assert_eq!(fill(IVec3::X), Some(error_block));
+    assert_eq!(fill(IVec3::ZERO), Some(BlockId::default()));
+    assert_eq!(fill(IVec3::NEG_X), Some(BlockId::default()));
+}
+
+/// Generates `positions` spread over `threads` threads, inserting the chunks into an engine in whatever order they finish.
+/// Returns the hash of every resulting chunk.
+#[cfg(test)]
+fn generate_world_hashes(generate: &(dyn Fn(IVec3) -> GeneratedChunk + Send + Sync), positions: &[IVec3], threads: usize) -> HashMap<IVec3, u64> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        for part in positions.chunks(positions.len().div_ceil(threads)) {
+            let sender = sender.clone();
+            scope.spawn(move || {
+                for chunk_pos in part {
+                    sender.send((*chunk_pos, generate(*chunk_pos))).unwrap();
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    let mut engine = VoxelEngine::default();
+    for (chunk_pos, generated) in receiver {
+        engine.insert_generated_chunk(chunk_pos, generated);
+    }
+    for (chunk_pos, mods) in engine.chunk_modifications.drain() {
+        let chunk_data = Arc::make_mut(engine.world_data.get_mut(&chunk_pos).unwrap());
+        for ChunkModification(local_pos, block_type) in mods {
+            chunk_data.set_block(local_pos, block_type);
+        }
+    }
+
+    engine.world_data.iter().map(|(chunk_pos, chunk_data)| (*chunk_pos, crate::chunk::hash_chunk(chunk_data))).collect()
+}
+
+#[test]
+fn generation_is_deterministic() {
+    use bracket_noise::prelude::FastNoise;
+
+    let generate = |chunk_pos: IVec3| -> GeneratedChunk {
+        let mut noise = FastNoise::seeded(1234);
+        noise.set_frequency(0.05);
+
+        let chunk_origin = chunk_pos * CHUNK_SIZE_I32;
+        let mut voxels = vec![BlockData::default(); crate::constants::CHUNK_SIZE3];
+        for (i, voxel) in voxels.iter_mut().enumerate() {
+            let pos = chunk_origin + crate::utils::index_to_ivec3(i);
+            let height = noise.get_noise(pos.x as f32, pos.z as f32) * 20.0;
+            if (pos.y as f32) < height {
+                voxel.block_type = BlockId(1);
+            }
+        }
+
+        // One "tree" per chunk crossing into the +x neighbor, placed from the noise.
+        let z = (noise.get_noise(chunk_origin.x as f32, chunk_origin.z as f32).abs() * 31.0) as i32;
+        let structures = vec![PendingStructure {
+            world_pos: chunk_origin + IVec3::new(31, 10, z),
+            blocks: (-2..=2).flat_map(|x| (0..3).map(move |y| (IVec3::new(x, y, 0), BlockId(2)))).collect(),
+        }];
+
+        GeneratedChunk {
+            data: ChunkData::Dense(voxels),
+            structures,
+        }
+    };
+
+    let positions: Vec<IVec3> = (-2..2).flat_map(|x| (-1..1).flat_map(move |y| (-2..2).map(move |z| IVec3::new(x, y, z)))).collect();
+    let mut reversed = positions.clone();
+    reversed.reverse();
+
+    let expected = generate_world_hashes(&generate, &positions, 1);
+    assert_eq!(expected.len(), positions.len());
+    assert_eq!(generate_world_hashes(&generate, &reversed, 1), expected);
+    assert_eq!(generate_world_hashes(&generate, &positions, 4), expected);
+    assert_eq!(generate_world_hashes(&generate, &reversed, 7), expected);
+}
+
+#[test]
+fn chunk_load_requests_resolve_once_loaded() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = World::new();
+    world.init_resource::<VoxelEngine>();
+    let entity = world.spawn(ChunkLoadRequest(IVec3::new(1, 0, 0))).id();
+
+    world.run_system_once(resolve_chunk_load_requests).unwrap();
+    assert!(world.get::<ChunkReady>(entity).is_none());
+
+    let air = Arc::new(ChunkData::Filled(BlockData::default()));
+    world.resource_mut::<VoxelEngine>().world_data.insert(IVec3::new(1, 0, 0), air);
+    world.run_system_once(resolve_chunk_load_requests).unwrap();
+    assert_eq!(world.get::<ChunkReady>(entity), Some(&ChunkReady(IVec3::new(1, 0, 0))));
+
+    // Requesting another chunk waits for that one.
+    world.entity_mut(entity).insert(ChunkLoadRequest(IVec3::new(2, 0, 0)));
+    world.run_system_once(resolve_chunk_load_requests).unwrap();
+    assert!(world.get::<ChunkReady>(entity).is_none());
+}
+
+#[test]
+fn inline_threading_finishes_in_one_frame() {
+    let mut world = test_world(VoxelEngineConfig { threading: Threading::InlineImmediate, max_data_tasks: 2, ..default() });
+    let mut engine = VoxelEngine::default();
+    engine.load_data_queue.extend([IVec3::ZERO, IVec3::X, IVec3::NEG_X]);
+    world.insert_resource(engine);
+
+    let mut start_data_tasks = IntoSystem::into_system(start_data_tasks);
+    let mut join_data = IntoSystem::into_system(join_data);
+    start_data_tasks.initialize(&mut world);
+    join_data.initialize(&mut world);
+
+    // The task limit is a per frame budget.
+    for loaded in [2, 3] {
+        start_data_tasks.run((), &mut world);
+        join_data.run((), &mut world);
+        let engine = world.resource::<VoxelEngine>();
+        assert!(engine.data_tasks.is_empty());
+        assert_eq!(engine.world_data.len(), loaded);
+    }
+}
+
+#[test]
+fn sustained_task_limit_saturates_the_engine() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world(VoxelEngineConfig { max_data_tasks: 2, saturation_frames: 3, ..default() });
+    let mut engine = VoxelEngine::default();
+    engine.data_tasks.extend([(IVec3::ZERO, None), (IVec3::X, None)]);
+    engine.load_data_queue.insert(IVec3::Y);
+    world.insert_resource(engine);
+
+    for frame in 1..=4 {
+        world.run_system_once(detect_saturation).unwrap();
+        assert_eq!(world.resource::<VoxelEngine>().is_saturated(), frame >= 3);
+    }
+    // sent once, when it became saturated.
+    assert_eq!(world.resource_mut::<Events<VoxelEngineSaturated>>().drain().count(), 1);
+
+    // a free task slot clears it.
+    world.resource_mut::<VoxelEngine>().data_tasks.remove(&IVec3::X);
+    world.run_system_once(detect_saturation).unwrap();
+    assert!(!world.resource::<VoxelEngine>().is_saturated());
+    assert_eq!(world.resource::<VoxelEngine>().saturated_frames(), 0);
+}
+
+#[test]
+fn chunks_finishing_together_are_joined_nearest_first() {
+    let mut world = test_world(VoxelEngineConfig { threading: Threading::InlineImmediate, ..default() });
+    world.spawn((Scanner::<DataScanner>::new(16, None), ChunkPos(IVec3::ZERO)));
+    let mut engine = VoxelEngine::default();
+    engine.load_data_queue.extend([IVec3::new(0, 3, 0), IVec3::X, IVec3::NEG_Z, IVec3::ZERO, IVec3::new(2, 0, 0), IVec3::NEG_X]);
+    world.insert_resource(engine);
+
+    let mut frame = Schedule::default();
+    frame.add_systems((start_data_tasks, join_data).chain());
+    frame.run(&mut world);
+
+    let events = world.resource::<Events<ChunkGenerated>>();
+    let joined: Vec<IVec3> = events.get_cursor().read(events).map(|event| event.0).collect();
+    assert_eq!(joined, [IVec3::ZERO, IVec3::NEG_X, IVec3::NEG_Z, IVec3::X, IVec3::new(2, 0, 0), IVec3::new(0, 3, 0)]);
+}
+
+#[test]
+fn context_generator_waits_for_generating_neighbors() {
+    use std::sync::Mutex;
+
+    let run = |max_wait_frames| {
+        let mut world = test_world(VoxelEngineConfig { threading: Threading::InlineImmediate, ..default() });
+        let generated = Arc::new(Mutex::new(Vec::new()));
+        let log = generated.clone();
+        world.insert_resource(ChunkGeneratorWithContext {
+            generate: Arc::new(move |chunk_pos, context: &GenerationContext| {
+                log.lock().unwrap().push((chunk_pos, context.neighbor_count()));
+                ChunkData::Filled(BlockData::default()).into()
+            }),
+            max_wait_frames,
+        });
+        let mut engine = VoxelEngine::default();
+        engine.load_data_queue.extend([IVec3::ZERO, IVec3::X, IVec3::new(5, 0, 0)]);
+        world.insert_resource(engine);
+
+        let mut frame = Schedule::default();
+        frame.add_systems((start_data_tasks, join_data).chain());
+        frame.run(&mut world);
+        frame.run(&mut world);
+        let generated = generated.lock().unwrap().clone();
+        generated
+    };
+
+    // the chunk next to the first one waits a frame to read it, the one further away doesn't.
+    assert_eq!(run(4), [(IVec3::ZERO, 0), (IVec3::new(5, 0, 0), 0), (IVec3::X, 1)]);
+    // without waiting both are generated at once.
+    assert_eq!(run(0), [(IVec3::ZERO, 0), (IVec3::X, 0), (IVec3::new(5, 0, 0), 0)]);
+}
+
+#[test]
+fn loaded_chunks_settle_at_the_limit() {
+    let mut world = test_world(VoxelEngineConfig { threading: Threading::InlineImmediate, ..default() });
+    world.spawn((Scanner::<DataScanner>::new(16, None), ChunkPos(IVec3::ZERO)));
+    let mut engine = VoxelEngine::default();
+    engine.load_data_queue.extend((-10..10).map(|x| IVec3::new(x, 0, 0)));
+    world.insert_resource(engine);
+
+    let mut frame = Schedule::default();
+    frame.add_systems((limit_loaded_chunks, unload_data, start_data_tasks, join_data).chain());
+    frame.run(&mut world);
+    assert_eq!(world.resource::<VoxelEngine>().world_data.len(), 20);
+
+    // the scanner still covers every chunk, the furthest ones are unloaded anyway.
+    world.resource_mut::<VoxelEngineConfig>().max_loaded_chunks = Some(7);
+    for _ in 0..3 {
+        frame.run(&mut world);
+        let engine = world.resource::<VoxelEngine>();
+        assert_eq!(engine.world_data.len(), 7);
+        assert!((-3..=3).all(|x| engine.is_data_loaded(IVec3::new(x, 0, 0))));
+    }
+    assert_eq!(world.resource::<Events<ChunkUnloaded>>().len(), 13);
+
+    // raising the limit loads the cut chunks again, nearest first.
+    world.resource_mut::<VoxelEngineConfig>().max_loaded_chunks = Some(9);
+    frame.run(&mut world);
+    let engine = world.resource::<VoxelEngine>();
+    assert_eq!(engine.world_data.len(), 9);
+    assert!((-4..=4).all(|x| engine.is_data_loaded(IVec3::new(x, 0, 0))));
+    world.resource_mut::<VoxelEngineConfig>().max_loaded_chunks = None;
+    frame.run(&mut world);
+    assert_eq!(world.resource::<VoxelEngine>().world_data.len(), 20);
+}
+
+#[test]
+fn chunks_cut_from_the_load_queue_are_not_unloaded() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world(VoxelEngineConfig { threading: Threading::InlineImmediate, max_loaded_chunks: Some(7), ..default() });
+    world.spawn((Scanner::<DataScanner>::new(16, None), ChunkPos(IVec3::ZERO)));
+    let mut engine = VoxelEngine::default();
+    engine.load_data_queue.extend((-10..10).map(|x| IVec3::new(x, 0, 0)));
+    world.insert_resource(engine);
+
+    let mut frame = Schedule::default();
+    frame.add_systems((limit_loaded_chunks, unload_data, start_data_tasks, join_data).chain());
+    frame.run(&mut world);
+    let engine = world.resource::<VoxelEngine>();
+    assert_eq!(engine.world_data.len(), 7);
+    assert!(engine.load_data_queue.is_empty());
+    assert!(world.resource::<Events<ChunkUnloaded>>().is_empty());
+
+    // loaded chunks queued to regenerate still count once.
+    let mut engine = world.resource_mut::<VoxelEngine>();
+    let loaded: Vec<IVec3> = engine.world_data.keys().copied().collect();
+    engine.load_data_queue.extend(loaded);
+    world.run_system_once(limit_loaded_chunks).unwrap();
+    let engine = world.resource::<VoxelEngine>();
+    assert!(engine.unload_data_queue.is_empty());
+    assert_eq!(engine.load_data_queue.len(), 7);
+}
+
+#[test]
+fn loaded_chunks_are_iterable() {
+    let mut engine = VoxelEngine::default();
+    engine.world_data.insert(IVec3::ZERO, Arc::new(ChunkData::Filled(BlockData::default())));
+    engine.world_data.insert(IVec3::X, Arc::new(ChunkData::Filled(BlockData::new(BlockId(1)))));
+
+    let mut loaded: Vec<(IVec3, BlockId)> = engine.iter_loaded().map(|(pos, data)| (pos, data.get_block(0).block_type)).collect();
+    loaded.sort_by_key(|(pos, _)| pos.to_array());
+    assert_eq!(loaded, [(IVec3::ZERO, BlockId(0)), (IVec3::X, BlockId(1))]);
+    assert_eq!(engine.chunk_data(IVec3::X).map(|data| data.get_block(0).block_type), Some(BlockId(1)));
+    assert!(engine.chunk_data(IVec3::Y).is_none());
+}
+
+#[test]
+fn voxel_world_reads_blocks() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let (engine, registry) = raycast_test_world();
+    let mut world = World::new();
+    world.insert_resource(engine);
+    world.insert_resource(BlockRegistryResource(registry));
+
+    world.run_system_once(|voxel_world: VoxelWorld| {
+        assert_eq!(voxel_world.get_block(IVec3::splat(5)).map(|block| block.block_type), Some(BlockId(1)));
+        assert!(voxel_world.is_solid(IVec3::splat(5)));
+        assert!(!voxel_world.is_solid(IVec3::splat(4)));
+        assert!(!voxel_world.is_solid(IVec3::splat(500)));
+        assert_eq!(voxel_world.raycast(Vec3::new(0.5, 5.5, 5.5), Vec3::X, 16.0).map(|hit| hit.position), Some(IVec3::splat(5)));
+    }).unwrap();
+}
+
+#[test]
+fn priority_bias_breaks_distance_ties() {
+    use crate::scanner::PriorityBias;
+
+    let scan_pos = ChunkPos(IVec3::new(0, 5, 0));
+    let sorted = |bias| {
+        let scanner = Scanner::<DataScanner>::new(2, None).with_priority_bias(bias);
+        let mut chunks = vec![IVec3::new(0, 6, 0), IVec3::new(2, 5, 0), IVec3::new(0, 4, 0), IVec3::new(0, 5, 0)];
+        chunks.sort_by_cached_key(|chunk| data_priority(*chunk, [(&scanner, &scan_pos)].into_iter()));
+        chunks
+    };
+
+    assert_eq!(sorted(PriorityBias::PreferBelow), [IVec3::new(0, 5, 0), IVec3::new(0, 4, 0), IVec3::new(0, 6, 0), IVec3::new(2, 5, 0)]);
+    assert_eq!(sorted(PriorityBias::PreferAbove), [IVec3::new(0, 5, 0), IVec3::new(0, 6, 0), IVec3::new(0, 4, 0), IVec3::new(2, 5, 0)]);
+    // Distance still comes first.
+    assert_eq!(sorted(PriorityBias::Custom(|offset| -offset.x))[3], IVec3::new(2, 5, 0));
+}
+
+#[test]
+fn blocks_changed_only_for_changed_chunks() {
+    use bevy::{ecs::system::RunSystemOnce, tasks::TaskPool};
+
+    let (mut engine, _) = raycast_test_world();
+    let stone = BlockId(1);
+    // On the chunk's edge, so its neighbor has to be remeshed too. Setting the existing stone changes nothing.
+    engine.chunk_modifications.insert(IVec3::ZERO, vec![
+        ChunkModification(IVec3::new(0, 3, 3), stone.into()),
+        ChunkModification(IVec3::splat(5), stone.into()),
+    ]);
+
+    let mut world = test_world(default());
+    world.insert_resource(engine);
+    AsyncComputeTaskPool::get_or_init(TaskPool::new);
+    world.run_system_once(start_modifications).unwrap();
+    while !world.resource::<VoxelEngine>().modification_tasks.is_empty() {
+        world.run_system_once(join_modifications).unwrap();
+    }
+
+    let events = world.resource::<Events<ChunkModified>>();
+    let mut cursor = events.get_cursor();
+    assert_eq!(cursor.read(events).count(), 2);
+    let events = world.resource::<Events<ChunkBlocksChanged>>();
+    let mut cursor = events.get_cursor();
+    let changed: Vec<_> = cursor.read(events).collect();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].pos, IVec3::ZERO);
+    assert_eq!(changed[0].changed, [(IVec3::new(0, 3, 3), BlockId(0), stone)]);
+}
+
+#[test]
+fn replace_block_global_keeps_uniform_chunks_uniform() {
+    use bevy::{ecs::system::RunSystemOnce, tasks::TaskPool};
+
+    let (mut engine, _) = raycast_test_world();
+    let (stone, dirt) = (BlockId(1), BlockId(2));
+    engine.world_data.insert(IVec3::new(0, 1, 0), Arc::new(ChunkData::Filled(stone.into())));
+    // a run of stone below a run of dirt, which merge into one.
+    let mut runs = ChunkData::Dense((0..crate::constants::CHUNK_SIZE3).map(|i| BlockData::new(if i < 100 { stone } else { dirt })).collect());
+    runs.compress();
+    assert!(matches!(runs, ChunkData::Runs(_)));
+    engine.world_data.insert(IVec3::new(0, -1, 0), Arc::new(runs));
+    engine.replace_block_global(stone, dirt);
+
+    let mut world = test_world(default());
+    world.insert_resource(engine);
+    AsyncComputeTaskPool::get_or_init(TaskPool::new);
+    world.run_system_once(start_modifications).unwrap();
+
+    let engine = world.resource::<VoxelEngine>();
+    assert!(engine.block_replacements.is_empty());
+    assert_eq!(engine.world_data[&IVec3::new(0, 1, 0)].get_block_if_filled(), Some(&BlockData::new(dirt)));
+    assert_eq!(engine.world_data[&IVec3::new(0, -1, 0)].get_block_if_filled(), Some(&BlockData::new(dirt)));
+    assert!(matches!(*engine.world_data[&IVec3::ZERO], ChunkData::Dense(_)));
+    assert_eq!(engine.get_block(IVec3::splat(5)).unwrap().block_type, dirt);
+    assert_eq!(engine.get_block(IVec3::splat(6)).unwrap().block_type, BlockId(0));
+
+    // both changed chunks & everything around them.
+    let events = world.resource::<Events<ChunkModified>>();
+    let mut cursor = events.get_cursor();
+    let modified: HashSet<IVec3> = cursor.read(events).map(|event| event.0).collect();
+    assert_eq!(modified.len(), 3 * 5 * 3);
+    assert!(modified.contains(&IVec3::new(1, 2, -1)) && modified.contains(&IVec3::new(-1, -2, -1)));
+    let events = world.resource::<Events<ChunkBlocksChanged>>();
+    let mut cursor = events.get_cursor();
+    let changed: Vec<_> = cursor.read(events).map(|event| (event.pos, event.changed.len())).collect();
+    assert_eq!(changed.len(), 3);
+    assert!(changed.contains(&(IVec3::ZERO, 1)) && changed.contains(&(IVec3::new(0, -1, 0), 100)) && changed.contains(&(IVec3::new(0, 1, 0), crate::constants::CHUNK_SIZE3)));
+}
+
+#[test]
+fn block_histograms_count_every_voxel() {
+    // stone below y 10 & a layer of dirt, with one ore block.
+    let generate = |_| {
+        let mut chunk = ChunkData::Filled(BlockData::default());
+        for (pos, _) in ChunkData::Filled(BlockData::default()).iter_blocks() {
+            if pos.y < 10 {
+                chunk.set_block(pos, BlockId(1));
+            } else if pos.y == 10 {
+                chunk.set_block(pos, BlockId(2));
+            }
+        }
+        chunk.set_block(IVec3::new(3, 4, 5), BlockId(3));
+        chunk
+    };
+    let layer = (CHUNK_SIZE * CHUNK_SIZE) as u32;
+    let expected: HashMap<BlockId, u32> = [(BlockId(0), 21 * layer), (BlockId(1), 10 * layer - 1), (BlockId(2), layer), (BlockId(3), 1)].into();
+
+    let dense = generate(IVec3::ZERO);
+    assert_eq!(dense.block_histogram(), expected);
+    let mut runs = dense.clone();
+    runs.compress();
+    assert!(matches!(runs, ChunkData::Runs(_)));
+    assert_eq!(runs.block_histogram(), expected);
+
+    let mut engine = VoxelEngine::default();
+    engine.insert_generated_chunk(IVec3::ZERO, generate(IVec3::ZERO).into());
+    engine.insert_generated_chunk(IVec3::Y, runs.into());
+    engine.insert_generated_chunk(IVec3::NEG_Y, ChunkData::Filled(BlockData::new(BlockId(1))).into());
+    let global = engine.global_histogram();
+    assert_eq!(global[&BlockId(0)], 2 * 21 * layer as u64);
+    assert_eq!(global[&BlockId(1)], 2 * (10 * layer as u64 - 1) + crate::constants::CHUNK_SIZE3 as u64);
+    assert_eq!(global[&BlockId(3)], 2);
+    assert_eq!(global.values().sum::<u64>(), 3 * crate::constants::CHUNK_SIZE3 as u64);
+}
+
+#[test]
+fn prewarms_force_load_until_timeout() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world(VoxelEngineConfig { prewarm_timeout: Duration::from_secs(5), ..default() });
+    // a chunk someone else force loaded stays loaded.
+    world.resource_mut::<ForceLoadedChunks<DataScanner>>().chunks.insert(IVec3::ZERO);
+    let mut engine = VoxelEngine::default();
+    let prewarm = engine.prewarm(IVec3::new(10, 0, 0), 1);
+    // one that never completes times out all the same.
+    engine.prewarm(IVec3::new(-10, 0, 0), 0);
+    world.insert_resource(engine);
+
+    world.run_system_once(update_prewarms).unwrap();
+    assert_eq!(world.resource::<ForceLoadedChunks<DataScanner>>().chunks.len(), 5 * 5 * 5 + 3 * 3 * 3 + 1);
+    assert_eq!(world.resource::<ForceLoadedChunks<MeshScanner>>().chunks.len(), 3 * 3 * 3 + 1);
+    assert!(world.resource::<VoxelEngine>().is_prewarming(IVec3::new(11, 1, -1)));
+
+    // without meshing it completes once the data is loaded.
+    let mut engine = world.resource_mut::<VoxelEngine>();
+    let chunks: Vec<IVec3> = prewarm.data_chunks().collect();
+    for chunk in chunks {
+        engine.world_data.insert(chunk, Arc::new(ChunkData::Filled(BlockData::default())));
+    }
+    world.run_system_once(update_prewarms).unwrap();
+    world.run_system_once(update_prewarms).unwrap();
+    let completed: Vec<Prewarm> = world.resource_mut::<Events<PrewarmCompleted>>().drain().map(|event| event.0).collect();
+    assert_eq!(completed, vec![prewarm]);
+
+    world.resource_mut::<Time>().advance_by(Duration::from_secs(6));
+    world.run_system_once(update_prewarms).unwrap();
+    assert_eq!(world.resource::<ForceLoadedChunks<DataScanner>>().chunks.iter().copied().collect::<Vec<_>>(), vec![IVec3::ZERO]);
+    assert!(world.resource::<ForceLoadedChunks<MeshScanner>>().chunks.is_empty());
+    assert!(!world.resource::<VoxelEngine>().is_prewarming(IVec3::new(11, 1, -1)));
+    assert!(!world.resource::<VoxelEngine>().is_prewarming(IVec3::new(-10, 0, 0)));
+}
+
+#[test]
+fn prewarms_at_the_world_edge_complete() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let bounds = WorldBounds { min: IVec3::ZERO, max: IVec3::splat(3), outside: BlockId(0) };
+    let mut world = test_world(VoxelEngineConfig { world_bounds: Some(bounds), ..default() });
+    let mut engine = VoxelEngine::default();
+    let prewarm = engine.prewarm(IVec3::ZERO, 1);
+    world.insert_resource(engine);
+
+    world.run_system_once(update_prewarms).unwrap();
+    assert_eq!(world.resource::<ForceLoadedChunks<DataScanner>>().chunks.len(), 3 * 3 * 3);
+    assert_eq!(world.resource::<ForceLoadedChunks<MeshScanner>>().chunks.len(), 2 * 2 * 2);
+
+    let mut engine = world.resource_mut::<VoxelEngine>();
+    let chunks: Vec<IVec3> = prewarm.data_chunks().filter(|chunk| bounds.contains(*chunk)).collect();
+    for chunk in chunks {
+        engine.world_data.insert(chunk, Arc::new(ChunkData::Filled(BlockData::default())));
+    }
+    world.run_system_once(update_prewarms).unwrap();
+    let completed: Vec<Prewarm> = world.resource_mut::<Events<PrewarmCompleted>>().drain().map(|event| event.0).collect();
+    assert_eq!(completed, vec![prewarm]);
+}
+
+#[test]
+fn chunks_finalize_once_structures_around_them_are_applied() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world(VoxelEngineConfig { structure_reach: 1, ..default() });
+    world.init_resource::<VoxelEngine>();
+    let generate = |world: &mut World, chunks: &[IVec3]| {
+        for chunk in chunks {
+            world.resource_mut::<VoxelEngine>().world_data.insert(*chunk, Arc::new(ChunkData::Filled(BlockData::default())));
+            world.send_event(ChunkGenerated(*chunk));
+        }
+        world.run_system_once(update_finalized_chunks).unwrap();
+    };
+
+    // all but one neighbor, & a structure block waiting to be applied.
+    let neighbors: Vec<IVec3> = ADJACENT_CHUNK_DIRECTIONS.into_iter().filter(|dir| *dir != IVec3::ONE).collect();
+    world.resource_mut::<VoxelEngine>().chunk_modifications.insert(IVec3::ZERO, vec![ChunkModification(IVec3::ZERO, BlockData::default())]);
+    generate(&mut world, &neighbors);
+    assert!(!world.resource::<VoxelEngine>().is_finalized(IVec3::ZERO));
+    generate(&mut world, &[IVec3::ONE]);
+    assert!(!world.resource::<VoxelEngine>().is_finalized(IVec3::ZERO));
+
+    world.resource_mut::<VoxelEngine>().chunk_modifications.clear();
+    world.send_event(ChunkModified(IVec3::ZERO));
+    world.run_system_once(update_finalized_chunks).unwrap();
+    assert!(world.resource::<VoxelEngine>().is_finalized(IVec3::ZERO));
+    // the neighbors are at the edge of the loaded chunks.
+    assert!(!world.resource::<VoxelEngine>().is_finalized(IVec3::ONE));
+
+    world.resource_mut::<VoxelEngine>().world_data.remove(&IVec3::ZERO);
+    world.send_event(ChunkUnloaded(IVec3::ZERO));
+    world.run_system_once(update_finalized_chunks).unwrap();
+    assert!(!world.resource::<VoxelEngine>().is_finalized(IVec3::ZERO));
+}
+
+#[test]
+fn modifications_of_unloaded_chunks_apply_once_generated() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world(VoxelEngineConfig { threading: Threading::InlineImmediate, ..default() });
+    let chunk = IVec3::new(5, 0, 0);
+    let mut engine = VoxelEngine::default();
+    engine.chunk_modifications.insert(chunk, vec![ChunkModification(IVec3::splat(3), BlockId(1).into())]);
+    world.insert_resource(engine);
+
+    // not loaded yet, so it waits.
+    world.run_system_once(start_modifications).unwrap();
+    let engine = world.resource::<VoxelEngine>();
+    assert!(engine.modification_tasks.is_empty() && engine.chunk_modifications.is_empty());
+    assert_eq!(engine.deferred_modifications[&chunk].len(), 1);
+
+    world.resource_mut::<VoxelEngine>().load_data_queue.insert(chunk);
+    world.run_system_once(start_data_tasks).unwrap();
+    world.run_system_once(join_data).unwrap();
+    let engine = world.resource::<VoxelEngine>();
+    assert_eq!(engine.get_block(chunk * CHUNK_SIZE_I32 + IVec3::splat(3)).unwrap().block_type, BlockId(1));
+    assert!(engine.deferred_modifications.is_empty());
+}
+
+#[test]
+fn bumping_the_generation_version_regenerates_unmodified_chunks() {
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world(VoxelEngineConfig { threading: Threading::InlineImmediate, ..default() });
+    // the generator fills chunks with whichever block is current.
+    let block = Arc::new(AtomicU16::new(1));
+    let generator_block = block.clone();
+    world.insert_resource(ChunkGenerator {
+        generate: Arc::new(move |_| ChunkData::Filled(BlockId(generator_block.load(Ordering::Relaxed)).into()).into()),
+    });
+    world.init_resource::<VoxelEngine>();
+    let (unmodified, modified) = (IVec3::ZERO, IVec3::new(3, 0, 0));
+    world.resource_mut::<VoxelEngine>().load_data_queue.extend([unmodified, modified]);
+    world.run_system_once(start_data_tasks).unwrap();
+    world.run_system_once(join_data).unwrap();
+
+    world.resource_mut::<VoxelEngine>().chunk_modifications.insert(modified, vec![ChunkModification(IVec3::splat(3), BlockId(5).into())]);
+    world.run_system_once(start_modifications).unwrap();
+    world.run_system_once(join_modifications).unwrap();
+    let engine = world.resource::<VoxelEngine>();
+    assert!(engine.is_modified(modified) && !engine.is_modified(unmodified));
+    assert_eq!(engine.generation_version(unmodified), Some(0));
+
+    block.store(2, Ordering::Relaxed);
+    world.resource_mut::<VoxelEngineConfig>().generation_version = 1;
+    world.run_system_once(regenerate_outdated_chunks).unwrap();
+    world.run_system_once(start_data_tasks).unwrap();
+    world.run_system_once(join_data).unwrap();
+
+    let engine = world.resource::<VoxelEngine>();
+    assert_eq!(engine.get_block(unmodified * CHUNK_SIZE_I32).unwrap().block_type, BlockId(2));
+    assert_eq!(engine.generation_version(unmodified), Some(1));
+    // the modified chunk keeps both its old terrain & the modification.
+    assert_eq!(engine.get_block(modified * CHUNK_SIZE_I32).unwrap().block_type, BlockId(1));
+    assert_eq!(engine.get_block(modified * CHUNK_SIZE_I32 + IVec3::splat(3)).unwrap().block_type, BlockId(5));
+    assert_eq!(engine.generation_version(modified), Some(0));
+
+    // modified while regenerating, the new data is dropped so the old version stays.
+    world.resource_mut::<VoxelEngineConfig>().generation_version = 2;
+    world.run_system_once(regenerate_outdated_chunks).unwrap();
+    world.run_system_once(start_data_tasks).unwrap();
+    world.resource_mut::<VoxelEngine>().chunk_modifications.insert(unmodified, vec![ChunkModification(IVec3::splat(3), BlockId(5).into())]);
+    world.run_system_once(start_modifications).unwrap();
+    world.run_system_once(join_modifications).unwrap();
+    world.run_system_once(join_data).unwrap();
+    let engine = world.resource::<VoxelEngine>();
+    assert_eq!(engine.get_block(unmodified * CHUNK_SIZE_I32 + IVec3::splat(3)).unwrap().block_type, BlockId(5));
+    assert_eq!(engine.generation_version(unmodified), Some(1));
+
+    // bumped while generating, it's generated again with the new version.
+    let late = IVec3::new(0, 5, 0);
+    world.resource_mut::<VoxelEngine>().load_data_queue.insert(late);
+    world.run_system_once(start_data_tasks).unwrap();
+    world.resource_mut::<VoxelEngineConfig>().generation_version = 3;
+    world.run_system_once(join_data).unwrap();
+    assert!(world.resource::<VoxelEngine>().load_data_queue.contains(&late));
+    world.run_system_once(start_data_tasks).unwrap();
+    world.run_system_once(join_data).unwrap();
+    assert_eq!(world.resource::<VoxelEngine>().generation_version(late), Some(3));
+}
+
+#[test]
+fn modifications_of_replaced_chunks_are_applied_again() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world(VoxelEngineConfig { threading: Threading::InlineImmediate, ..default() });
+    let chunk = IVec3::ZERO;
+    let mut engine = VoxelEngine::default();
+    engine.world_data.insert(chunk, Arc::new(ChunkData::Filled(BlockData::default())));
+    engine.chunk_modifications.insert(chunk, vec![ChunkModification(IVec3::splat(3), BlockId(1).into())]);
+    world.insert_resource(engine);
+
+    world.run_system_once(start_modifications).unwrap();
+    // e.g. regenerated while the task ran.
+    world.resource_mut::<VoxelEngine>().world_data.insert(chunk, Arc::new(ChunkData::Filled(BlockId(2).into())));
+    world.run_system_once(join_modifications).unwrap();
+    let engine = world.resource::<VoxelEngine>();
+    assert_eq!(engine.get_block(IVec3::splat(3)).unwrap().block_type, BlockId(2));
+    assert_eq!(engine.chunk_modifications[&chunk].len(), 1);
+
+    world.run_system_once(start_modifications).unwrap();
+    world.run_system_once(join_modifications).unwrap();
+    let engine = world.resource::<VoxelEngine>();
+    assert_eq!(engine.get_block(IVec3::splat(3)).unwrap().block_type, BlockId(1));
+    assert_eq!(engine.get_block(IVec3::splat(4)).unwrap().block_type, BlockId(2));
+}
+
+#[test]
+fn fills_of_unloaded_chunks_apply_once_generated() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world(VoxelEngineConfig { threading: Threading::InlineImmediate, ..default() });
+    let chunk = IVec3::new(5, 0, 0);
+    let mut engine = VoxelEngine::default();
+    // covers the chunk, with a block set on top of the fill.
+    engine.fill_region(chunk * CHUNK_SIZE_I32, chunk * CHUNK_SIZE_I32 + IVec3::splat(CHUNK_SIZE_I32 - 1), BlockId(1));
+    engine.set_block(chunk * CHUNK_SIZE_I32 + IVec3::splat(3), BlockId(2));
+    world.insert_resource(engine);
+
+    world.run_system_once(start_modifications).unwrap();
+    let engine = world.resource::<VoxelEngine>();
+    assert!(engine.chunk_fills.is_empty() && engine.chunk_modifications.is_empty());
+    assert_eq!(engine.deferred_fills[&chunk], BlockId(1).into());
+
+    world.resource_mut::<VoxelEngine>().load_data_queue.insert(chunk);
+    world.run_system_once(start_data_tasks).unwrap();
+    world.run_system_once(join_data).unwrap();
+    let engine = world.resource::<VoxelEngine>();
+    assert_eq!(engine.get_block(chunk * CHUNK_SIZE_I32).unwrap().block_type, BlockId(1));
+    assert_eq!(engine.get_block(chunk * CHUNK_SIZE_I32 + IVec3::splat(3)).unwrap().block_type, BlockId(2));
+    assert!(engine.is_modified(chunk) && engine.deferred_fills.is_empty());
+}
+
+#[test]
+fn deferred_modifications_are_capped_furthest_first() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world(VoxelEngineConfig { max_deferred_chunks: 2, ..default() });
+    world.spawn((Scanner::<DataScanner>::new(1, None), ChunkPos(IVec3::ZERO)));
+    let mut engine = VoxelEngine::default();
+    for x in [3, 1, 2] {
+        engine.set_block(IVec3::new(x * CHUNK_SIZE_I32, 0, 0), BlockId(1));
+    }
+    world.insert_resource(engine);
+
+    world.run_system_once(start_modifications).unwrap();
+    let deferred = &world.resource::<VoxelEngine>().deferred_modifications;
+    assert_eq!(deferred.len(), 2);
+    assert!(deferred.contains_key(&IVec3::X) && deferred.contains_key(&IVec3::new(2, 0, 0)));
+}
+
+#[test]
+fn voxel_world_sets_blocks() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let (engine, registry) = raycast_test_world();
+    let mut world = World::new();
+    world.insert_resource(engine);
+    world.insert_resource(BlockRegistryResource(registry));
+    world.run_system_once(|mut voxel_world: VoxelWorldMut| voxel_world.set_block(IVec3::new(-1, 40, 5), BlockId(1))).unwrap();
+    let mods = &world.resource::<VoxelEngine>().chunk_modifications[&IVec3::new(-1, 1, 0)];
+    assert!(matches!(mods[..], [ChunkModification(local_pos, block)] if local_pos == IVec3::new(31, 8, 5) && block.block_type == BlockId(1)));
+}